@@ -0,0 +1,100 @@
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// `statfs` magic number of tmpfs.
+const TMPFS_MAGIC: i64 = 0x0102_1994;
+
+/// Let the user edit `text` in `$VISUAL`/`$EDITOR`. The plaintext only ever
+/// lands on a tmpfs, in a file only we can read, and is overwritten before
+/// it is removed. Returns the new text if it changed.
+pub fn edit_in_editor(name: &str, text: &str) -> Result<Option<String>, String> {
+    let dir = scratch_dir()?;
+    let file_name: String = name
+        .chars()
+        .map(|c| if c == '/' || c.is_control() { '_' } else { c })
+        .collect();
+    let path = dir.path.join(format!("{file_name}.md"));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map_err(|e| format!("Could not write {}: {e}", path.display()))?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    // Through the shell, so editors given with arguments ("code --wait") work
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(&path)
+        .status()
+        .map_err(|e| format!("Could not run {editor}: {e}"))?;
+    if !status.success() {
+        return Err(format!(
+            "{editor} exited with {status}; the note is unchanged"
+        ));
+    }
+
+    let edited =
+        fs::read_to_string(&path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    Ok((edited != text).then_some(edited))
+}
+
+/// A private directory on a tmpfs, wiped and removed when dropped.
+struct ScratchDir {
+    path: PathBuf,
+}
+
+fn scratch_dir() -> Result<ScratchDir, String> {
+    // XDG_RUNTIME_DIR is a per-user tmpfs under systemd; /dev/shm otherwise
+    let base = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain([PathBuf::from("/dev/shm")])
+        .find(|dir| is_tmpfs(dir))
+        .ok_or("No tmpfs to edit notes on; mount /dev/shm or set XDG_RUNTIME_DIR")?;
+    let path = base.join(format!("pithos-edit-{}", std::process::id()));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&path)
+        .map_err(|e| format!("Could not create {}: {e}", path.display()))?;
+    Ok(ScratchDir { path })
+}
+
+fn is_tmpfs(dir: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(c_path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: statfs is plain data, filled in by the call for a valid C string.
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    unsafe { libc::statfs(c_path.as_ptr(), &mut stat) == 0 && stat.f_type as i64 == TMPFS_MAGIC }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        // Editors may leave backup or swap files next to the note
+        if let Ok(entries) = fs::read_dir(&self.path) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if let Ok(len) = entry.metadata().map(|m| m.len()) {
+                    if let Ok(mut file) = OpenOptions::new().write(true).open(&path) {
+                        let _ = file.write_all(&vec![0; len as usize]);
+                        let _ = file.sync_data();
+                    }
+                }
+                let _ = fs::remove_file(&path);
+            }
+        }
+        let _ = fs::remove_dir(&self.path);
+    }
+}
@@ -0,0 +1,652 @@
+mod edit;
+mod passphrase;
+mod session;
+
+use passphrase::PassphraseSource;
+use pithos_core::crypto::{KdfParams, SlotInfo, SlotKind};
+use pithos_core::export::markdown_to_html;
+use pithos_core::markdown_tree::TreeReport;
+use pithos_core::notes::push_snapshot;
+use pithos_core::search::Query;
+use pithos_core::state::unix_now;
+use pithos_core::vault;
+use session::{OpenOptions, Session};
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: pithos [OPTIONS] COMMAND [ARGS]
+
+Commands:
+  ls [FOLDER] [--tag TAG]...        List notes as ID, path and tags
+  cat NOTE                          Print a note's Markdown
+  new NAME [--folder F] [--tag T]...
+                                    Add a note, its text read from stdin
+  edit NOTE                         Edit a note in $VISUAL or $EDITOR
+  search QUERY... [--tag TAG]...    List notes matching QUERY, best match
+                                    first: words, \"phrases\", /regex/,
+                                    -exclusions, OR, tag:, folder:, updated:,
+                                    created:, pinned:, has:image|task, is:todo
+  tag NOTE [--remove] [TAG...]      Add or remove tags, or list them
+  export NOTE [--html] [-o FILE]    Write a note as Markdown or HTML
+  import FILE... [--folder F] [--tag T]...
+                                    Add Markdown files as notes
+  export-tree DIR                   Write every note to DIR as Markdown files,
+                                    one directory per folder
+  import-tree DIR [--folder F]      Add a tree written by export-tree, or any
+                                    directory of Markdown files
+  audit                             Report how the vault, notes and assets are
+                                    encrypted, without unlocking; fails unless
+                                    all use the current format
+
+NOTE is a note ID, a path such as \"Work/Standup\", or a name that is unique.
+
+Options:
+  --vault DIR            Vault folder; defaults to $PITHOS_VAULT, then the
+                         vault Pithos Notebook last opened
+  --key-file FILE        Key file, for vaults that need one
+  --passphrase-stdin     Read the passphrase from the first line of stdin
+  --passphrase-fd N      Read the passphrase from file descriptor N
+  -h, --help             Show this help
+
+The passphrase is asked for on the terminal unless given another way.
+Commands that change the vault take its lock, and fail while Pithos
+Notebook has it open for writing.";
+
+/// Options that take a value; every other option is a flag.
+const VALUE_OPTIONS: &[&str] = &[
+    "--vault",
+    "--key-file",
+    "--passphrase-fd",
+    "--folder",
+    "--tag",
+    "-o",
+    "--output",
+];
+const GLOBAL_OPTIONS: &[&str] = &[
+    "--vault",
+    "--key-file",
+    "--passphrase-stdin",
+    "--passphrase-fd",
+];
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => return usage_error(&e),
+    };
+    if args.flag("-h") || args.flag("--help") || args.positional.is_empty() {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Usage(e)) => usage_error(&e),
+        Err(Failure::Error(e)) => {
+            eprintln!("pithos: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("pithos: {message}\nTry 'pithos --help' for more information.");
+    ExitCode::from(2)
+}
+
+enum Failure {
+    Usage(String),
+    Error(String),
+}
+
+/// The options `command` takes besides the global ones, and how many
+/// operands (see [`Args::check`]); `None` for an unknown command.
+fn command_syntax(command: &str) -> Option<(&'static [&'static str], &'static str)> {
+    Some(match command {
+        "ls" => (&["--tag"], "0-1"),
+        "cat" | "edit" => (&[], "1"),
+        "new" => (&["--folder", "--tag"], "1"),
+        "search" => (&["--tag"], "1+"),
+        "tag" => (&["--remove"], "1+"),
+        "export" => (&["--html", "-o", "--output"], "1"),
+        "import" => (&["--folder", "--tag"], "1+"),
+        "export-tree" => (&[], "1"),
+        "import-tree" => (&["--folder"], "1"),
+        "audit" => (&[], "0"),
+        _ => return None,
+    })
+}
+
+fn run(mut args: Args) -> Result<(), Failure> {
+    let command = args.positional.remove(0);
+    let Some((allowed, operands)) = command_syntax(&command) else {
+        return Err(Failure::Usage(format!("unknown command '{command}'")));
+    };
+    args.check(&command, allowed, operands)
+        .map_err(Failure::Usage)?;
+    let options = open_options(&args).map_err(Failure::Usage)?;
+
+    match command.as_str() {
+        "ls" => list(&args, &options),
+        "cat" => cat(&args, &options),
+        "new" => new(&args, &options),
+        "edit" => edit(&args, &options),
+        "search" => search(&args, &options),
+        "tag" => tag(&args, &options),
+        "export" => export(&args, &options),
+        "import" => import(&args, &options),
+        "export-tree" => export_tree(&args, &options),
+        "audit" => audit(&options),
+        _ => import_tree(&args, &options),
+    }
+    .map_err(Failure::Error)
+}
+
+fn open_options(args: &Args) -> Result<OpenOptions, String> {
+    let passphrase = match (
+        args.flag("--passphrase-stdin"),
+        args.value("--passphrase-fd"),
+    ) {
+        (true, Some(_)) => {
+            return Err("--passphrase-stdin and --passphrase-fd can't be combined".into())
+        }
+        (true, None) => PassphraseSource::Stdin,
+        (false, Some(fd)) => match fd.parse() {
+            Ok(n) if n > 2 => PassphraseSource::Fd(n),
+            // Reading closes the descriptor, and stdin may still be needed
+            Ok(0..=2) => {
+                return Err(
+                    "--passphrase-fd can't read a standard stream; use --passphrase-stdin".into(),
+                )
+            }
+            _ => {
+                return Err(format!(
+                    "--passphrase-fd needs a file descriptor, not '{fd}'"
+                ))
+            }
+        },
+        (false, None) => PassphraseSource::Prompt,
+    };
+    Ok(OpenOptions {
+        vault_folder: args.value("--vault").map(str::to_string),
+        keyfile: args.value("--key-file").map(PathBuf::from),
+        passphrase,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Commands
+// ---------------------------------------------------------------------------
+
+fn list(args: &Args, options: &OpenOptions) -> Result<(), String> {
+    let session = Session::open(options, false)?;
+    let prefix = match args.positional.first() {
+        Some(folder) => {
+            let id = session.find_folder(folder)?;
+            format!("{}/", session.folder_path(Some(&id)))
+        }
+        None => String::new(),
+    };
+    let tags = args.values("--tag");
+    let mut rows: Vec<(String, &str, String)> = session
+        .state
+        .notes
+        .iter()
+        .filter(|note| tags.iter().all(|t| note.tags.iter().any(|nt| nt == t)))
+        .map(|note| {
+            (
+                session.note_path(note),
+                note.id.as_str(),
+                tag_list(&note.tags),
+            )
+        })
+        .filter(|(path, _, _)| path.starts_with(&prefix))
+        .collect();
+    rows.sort();
+    for (path, id, tags) in rows {
+        println!("{id}\t{path}\t{tags}");
+    }
+    Ok(())
+}
+
+fn cat(args: &Args, options: &OpenOptions) -> Result<(), String> {
+    let mut session = Session::open(options, false)?;
+    let index = session.find_note(&args.positional[0])?;
+    print!("{}", session.load_body(index)?.content);
+    Ok(())
+}
+
+fn new(args: &Args, options: &OpenOptions) -> Result<(), String> {
+    let mut session = Session::open(options, true)?;
+    let folder = args
+        .value("--folder")
+        .map(|f| session.find_folder(f))
+        .transpose()?;
+    // Read the text once unlocked: with --passphrase-stdin it follows the passphrase
+    let mut content = String::new();
+    if !std::io::stdin().is_terminal() {
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .map_err(|e| format!("Could not read the note from stdin: {e}"))?;
+    }
+    let tags = args
+        .values("--tag")
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let id = session.add_note(&args.positional[0], content, tags, folder);
+    session.save()?;
+    println!("{id}");
+    Ok(())
+}
+
+fn edit(args: &Args, options: &OpenOptions) -> Result<(), String> {
+    let mut session = Session::open(options, true)?;
+    let index = session.find_note(&args.positional[0])?;
+    let note = session.load_body(index)?;
+    let Some(content) = edit::edit_in_editor(&note.name, &note.content)? else {
+        eprintln!("pithos: no changes");
+        return Ok(());
+    };
+    let note = &mut session.state.notes[index];
+    push_snapshot(note, content.clone());
+    note.content = content;
+    note.updated_at = unix_now();
+    session.save()
+}
+
+fn search(args: &Args, options: &OpenOptions) -> Result<(), String> {
+    let session = Session::open(options, false)?;
+    let mut query = Query::parse(&args.positional.join(" ")).map_err(|e| e.to_string())?;
+    query.add_tag_filter(&args.values("--tag"), true);
+    let mut rows = Vec::new();
+    for hit in session.search(&query) {
+        if let Some(note) = session.state.notes.iter().find(|n| n.id == hit.id) {
+            rows.push((hit.score, session.note_path(note), hit.id));
+        }
+    }
+    // Best match first; notes that only matched filters score alike, so by path
+    rows.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    for (_, path, id) in rows {
+        println!("{id}\t{path}");
+    }
+    Ok(())
+}
+
+fn tag(args: &Args, options: &OpenOptions) -> Result<(), String> {
+    let (query, tags) = args.positional.split_first().expect("checked operands");
+    let remove = args.flag("--remove");
+    if tags.is_empty() && remove {
+        return Err("Name the tags to remove".into());
+    }
+    let mut session = Session::open(options, !tags.is_empty())?;
+    let index = session.find_note(query)?;
+    let note = &mut session.state.notes[index];
+    if tags.is_empty() {
+        for tag in &note.tags {
+            println!("{tag}");
+        }
+        return Ok(());
+    }
+
+    let before = note.tags.clone();
+    for tag in tags.iter().map(|t| t.trim_start_matches('#').trim()) {
+        if remove {
+            note.tags.retain(|existing| existing != tag);
+        } else if !tag.is_empty() && !note.tags.iter().any(|existing| existing == tag) {
+            note.tags.push(tag.to_string());
+        }
+    }
+    if note.tags == before {
+        return Ok(());
+    }
+    note.updated_at = unix_now();
+    session.save()
+}
+
+fn export(args: &Args, options: &OpenOptions) -> Result<(), String> {
+    let mut session = Session::open(options, false)?;
+    let index = session.find_note(&args.positional[0])?;
+    let note = session.load_body(index)?;
+    let html = args.flag("--html");
+    let output = if html {
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n{}</body>\n</html>\n",
+            escape_html(&note.name),
+            markdown_to_html(&note.content)
+        )
+    } else {
+        note.content.clone()
+    };
+    match args.value("-o").or(args.value("--output")) {
+        Some(path) => {
+            std::fs::write(path, output).map_err(|e| format!("Could not write {path}: {e}"))
+        }
+        None => {
+            print!("{output}");
+            Ok(())
+        }
+    }
+}
+
+fn import(args: &Args, options: &OpenOptions) -> Result<(), String> {
+    let mut files = Vec::new();
+    for path in &args.positional {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+        let name = Path::new(path)
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or("Imported Note")
+            .to_string();
+        files.push((name, text));
+    }
+    let mut tags: Vec<String> = args
+        .values("--tag")
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    if tags.is_empty() {
+        tags.push("imported".to_string());
+    }
+
+    let mut session = Session::open(options, true)?;
+    let folder = args
+        .value("--folder")
+        .map(|f| session.find_folder(f))
+        .transpose()?;
+    let ids: Vec<String> = files
+        .into_iter()
+        .map(|(name, text)| session.add_note(&name, text, tags.clone(), folder.clone()))
+        .collect();
+    session.save()?;
+    for id in ids {
+        println!("{id}");
+    }
+    Ok(())
+}
+
+fn export_tree(args: &Args, options: &OpenOptions) -> Result<(), String> {
+    let mut session = Session::open(options, false)?;
+    let report = session.export_tree(Path::new(&args.positional[0]))?;
+    print_tree_report(&report, "exported");
+    Ok(())
+}
+
+fn import_tree(args: &Args, options: &OpenOptions) -> Result<(), String> {
+    let dir = Path::new(&args.positional[0]);
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+    let mut session = Session::open(options, true)?;
+    let folder = args
+        .value("--folder")
+        .map(|f| session.find_folder(f))
+        .transpose()?;
+    let report = session.import_tree(dir, folder)?;
+    session.save()?;
+    print_tree_report(&report, "imported");
+    Ok(())
+}
+
+fn audit(options: &OpenOptions) -> Result<(), String> {
+    let folder = options.folder()?;
+    let audit = vault::audit_encryption(&folder)
+        .map_err(|e| format!("Could not audit the vault in {folder}: {e}"))?;
+    match &audit.vault {
+        Some(info) => {
+            let status = if info.is_current() {
+                "current"
+            } else {
+                "outdated"
+            };
+            println!("vault\tv{} {}\t{status}", info.version, info.cipher);
+            for slot in &info.slots {
+                println!("slot\t{}\t{}", slot_name(slot), kdf_name(&slot.kdf));
+            }
+            if let Some(kdf) = &info.kdf {
+                println!("kdf\t{}", kdf_name(kdf));
+            }
+        }
+        None => println!("vault\t-\tunencrypted"),
+    }
+    let blobs = [
+        ("asset", &audit.outdated_assets, "outdated"),
+        ("asset", &audit.unencrypted_assets, "unencrypted"),
+        ("note", &audit.outdated_notes, "outdated"),
+        ("note", &audit.unencrypted_notes, "unencrypted"),
+    ];
+    for (kind, ids, status) in blobs {
+        for id in ids {
+            println!("{kind}\t{id}\t{status}");
+        }
+    }
+    if audit.is_current() {
+        Ok(())
+    } else {
+        Err("the vault is not fully encrypted in the current format".into())
+    }
+}
+
+fn slot_name(slot: &SlotInfo) -> &'static str {
+    match slot.kind {
+        SlotKind::Passphrase if slot.keyfile => "passphrase+key-file",
+        SlotKind::Passphrase => "passphrase",
+        SlotKind::RecoveryCode => "recovery-code",
+        SlotKind::KeyFile => "key-file",
+    }
+}
+
+fn kdf_name(kdf: &KdfParams) -> String {
+    match kdf {
+        KdfParams::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => format!("argon2id m={memory_kib} t={iterations} p={parallelism}"),
+        KdfParams::Pbkdf2Sha256 { iterations } => format!("pbkdf2-sha256 i={iterations}"),
+    }
+}
+
+fn print_tree_report(report: &TreeReport, done: &str) {
+    for item in &report.skipped {
+        eprintln!("pithos: skipped {item}");
+    }
+    eprintln!(
+        "pithos: {done} {} notes, {} folders and {} attachments",
+        report.notes, report.folders, report.attachments
+    );
+}
+
+fn tag_list(tags: &[String]) -> String {
+    tags.iter()
+        .map(|t| format!("#{t}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// ---------------------------------------------------------------------------
+// Arguments
+// ---------------------------------------------------------------------------
+
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    fn parse(raw: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut args = Args {
+            positional: Vec::new(),
+            options: Vec::new(),
+        };
+        let mut raw = raw.peekable();
+        while let Some(arg) = raw.next() {
+            if arg == "--" {
+                args.positional.extend(raw.by_ref());
+                break;
+            }
+            if !arg.starts_with('-') || arg == "-" {
+                args.positional.push(arg);
+                continue;
+            }
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                }
+                _ => (arg, None),
+            };
+            let value = if VALUE_OPTIONS.contains(&name.as_str()) {
+                Some(match inline {
+                    Some(value) => value,
+                    None => raw
+                        .next()
+                        .ok_or_else(|| format!("option '{name}' needs a value"))?,
+                })
+            } else if inline.is_some() {
+                return Err(format!("option '{name}' doesn't take a value"));
+            } else {
+                None
+            };
+            args.options.push((name, value));
+        }
+        Ok(args)
+    }
+
+    /// Reject options `command` doesn't know and a wrong number of operands:
+    /// `operands` is "0", "1", "0-1" or "1+".
+    fn check(&self, command: &str, allowed: &[&str], operands: &str) -> Result<(), String> {
+        for (name, _) in &self.options {
+            if !GLOBAL_OPTIONS.contains(&name.as_str()) && !allowed.contains(&name.as_str()) {
+                return Err(format!("'{command}' has no option '{name}'"));
+            }
+        }
+        let count = self.positional.len();
+        let fits = match operands {
+            "0" => count == 0,
+            "0-1" => count <= 1,
+            "1+" => count >= 1,
+            _ => count == 1,
+        };
+        if !fits {
+            return Err(format!("wrong number of arguments for '{command}'"));
+        }
+        Ok(())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(n, _)| n == name)
+    }
+
+    /// The last value given for `name`.
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, v)| v.as_deref())
+    }
+
+    fn values(&self, name: &str) -> Vec<&str> {
+        self.options
+            .iter()
+            .filter(|(n, _)| n == name)
+            .filter_map(|(_, v)| v.as_deref())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let args = parse(&[
+            "--vault=/tmp/v",
+            "new",
+            "--tag",
+            "a",
+            "Draft",
+            "--tag=b",
+            "--passphrase-stdin",
+            "--",
+            "--not-an-option",
+        ])
+        .unwrap();
+        assert_eq!(args.positional, ["new", "Draft", "--not-an-option"]);
+        assert_eq!(args.value("--vault"), Some("/tmp/v"));
+        assert_eq!(args.values("--tag"), ["a", "b"]);
+        assert!(args.flag("--passphrase-stdin"));
+        assert!(!args.flag("--html"));
+        // The last value wins, and "-" is an operand
+        let args = parse(&["export", "-o", "a", "-o", "b", "-"]).unwrap();
+        assert_eq!(args.value("-o"), Some("b"));
+        assert_eq!(args.positional, ["export", "-"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse(&["ls", "--tag"]).err().unwrap(),
+            "option '--tag' needs a value"
+        );
+        assert_eq!(
+            parse(&["export", "--html=yes"]).err().unwrap(),
+            "option '--html' doesn't take a value"
+        );
+    }
+
+    #[test]
+    fn test_check_commands() {
+        assert!(command_syntax("frobnicate").is_none());
+        let check = |raw: &[&str]| {
+            let mut args = parse(raw).unwrap();
+            let command = args.positional.remove(0);
+            let (allowed, operands) = command_syntax(&command).unwrap();
+            args.check(&command, allowed, operands)
+        };
+        assert!(check(&["ls"]).is_ok());
+        assert!(check(&["ls", "Work", "--tag", "x", "--vault", "v"]).is_ok());
+        assert_eq!(
+            check(&["ls", "--html"]).unwrap_err(),
+            "'ls' has no option '--html'"
+        );
+        assert_eq!(
+            check(&["ls", "a", "b"]).unwrap_err(),
+            "wrong number of arguments for 'ls'"
+        );
+        assert!(check(&["cat"]).is_err());
+        assert!(check(&["search"]).is_err());
+        assert!(check(&["search", "two", "words"]).is_ok());
+        assert!(check(&["audit", "extra"]).is_err());
+    }
+
+    #[test]
+    fn test_passphrase_options() {
+        let options = |raw: &[&str]| open_options(&parse(raw).unwrap());
+        assert!(matches!(
+            options(&["--passphrase-fd", "3"]).unwrap().passphrase,
+            PassphraseSource::Fd(3)
+        ));
+        assert!(matches!(
+            options(&[]).unwrap().passphrase,
+            PassphraseSource::Prompt
+        ));
+        for fd in ["0", "2", "-1", "three"] {
+            assert!(options(&["--passphrase-fd", fd]).is_err(), "fd {fd}");
+        }
+        assert!(options(&["--passphrase-stdin", "--passphrase-fd", "3"]).is_err());
+    }
+}
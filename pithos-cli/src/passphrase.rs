@@ -0,0 +1,74 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use zeroize::Zeroizing;
+
+/// Where the passphrase comes from.
+pub enum PassphraseSource {
+    /// Ask on the controlling terminal, with echo turned off.
+    Prompt,
+    /// The first line of standard input.
+    Stdin,
+    /// The first line read from an inherited file descriptor above 2,
+    /// which is closed afterwards.
+    Fd(RawFd),
+}
+
+pub fn read_passphrase(source: &PassphraseSource) -> Result<Zeroizing<String>, String> {
+    let mut line = Zeroizing::new(String::new());
+    match *source {
+        PassphraseSource::Prompt => prompt("Passphrase: ", &mut line)?,
+        PassphraseSource::Stdin => {
+            io::stdin()
+                .read_line(&mut line)
+                .map_err(|e| format!("Could not read the passphrase: {e}"))?;
+        }
+        PassphraseSource::Fd(fd) => {
+            // SAFETY: the descriptor was handed to us with --passphrase-fd
+            // for exactly this, and isn't a standard stream; nothing else in
+            // the process uses it, so closing it once read is fine.
+            let file = unsafe { File::from_raw_fd(fd) };
+            BufReader::new(file)
+                .read_line(&mut line)
+                .map_err(|e| format!("Could not read the passphrase from fd {fd}: {e}"))?;
+        }
+    }
+    // Only the line ending goes; spaces may be part of the passphrase
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(line)
+}
+
+/// Read a line from /dev/tty rather than stdin, so stdin stays free for
+/// note text, without echoing it.
+fn prompt(message: &str, line: &mut String) -> Result<(), String> {
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|_| {
+            "No terminal to ask for the passphrase on; use --passphrase-stdin or --passphrase-fd"
+                .to_string()
+        })?;
+    tty.write_all(message.as_bytes())
+        .and_then(|_| tty.flush())
+        .map_err(|e| e.to_string())?;
+
+    let fd = tty.as_raw_fd();
+    // SAFETY: termios is plain data, and `fd` stays open for both calls.
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    let echo_off = unsafe { libc::tcgetattr(fd, &mut saved) } == 0;
+    if echo_off {
+        let mut quiet = saved;
+        quiet.c_lflag &= !libc::ECHO;
+        quiet.c_lflag |= libc::ECHONL;
+        unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &quiet) };
+    }
+    let result = BufReader::new(&tty).read_line(line);
+    if echo_off {
+        unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &saved) };
+    }
+    result
+        .map(|_| ())
+        .map_err(|e| format!("Could not read the passphrase: {e}"))
+}
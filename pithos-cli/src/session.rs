@@ -0,0 +1,280 @@
+use crate::passphrase::{read_passphrase, PassphraseSource};
+use pithos_core::crypto::{self, CachedKey, CryptoError};
+use pithos_core::markdown_tree::{self, TreeReport};
+use pithos_core::search::{Query, SearchHit};
+use pithos_core::state::*;
+use pithos_core::vault;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+/// How to find and unlock the vault, from the global options.
+pub struct OpenOptions {
+    pub vault_folder: Option<String>,
+    pub keyfile: Option<PathBuf>,
+    pub passphrase: PassphraseSource,
+}
+
+/// An unlocked vault. Sessions opened for writing hold the vault's lock
+/// until dropped, so the app and other commands open it read-only meanwhile.
+pub struct Session {
+    pub folder: String,
+    pub state: DocState,
+    key: CachedKey,
+    _lock: Option<LockGuard>,
+}
+
+impl OpenOptions {
+    /// The vault folder: --vault, then $PITHOS_VAULT, then the app's last vault.
+    pub fn folder(&self) -> Result<String, String> {
+        match &self.vault_folder {
+            Some(folder) => Ok(folder.clone()),
+            None => std::env::var("PITHOS_VAULT")
+                .ok()
+                .filter(|f| !f.is_empty())
+                .or_else(|| vault::load_config().vault_path)
+                .ok_or_else(|| "No vault given; use --vault or set PITHOS_VAULT".into()),
+        }
+    }
+}
+
+impl Session {
+    pub fn open(options: &OpenOptions, write: bool) -> Result<Self, String> {
+        let folder = options.folder()?;
+        let raw = vault::read_vault_raw(&folder)
+            .map_err(|e| format!("Could not read the vault in {folder}: {e}"))?
+            .ok_or_else(|| format!("No vault in {folder}"))?;
+
+        // Check before asking for the passphrase, which takes a while to derive
+        let mut lock = None;
+        if write {
+            if !vault::folder_writable(&folder) {
+                return Err(format!("Cannot write to {folder}"));
+            }
+            vault::acquire_lock(&folder).map_err(|e| e.to_string())?;
+            lock = Some(LockGuard(folder.clone()));
+        }
+
+        let keyfile = options
+            .keyfile
+            .as_deref()
+            .map(crypto::KeyFile::read)
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        let has_keyfile = keyfile.is_some();
+        let passphrase = read_passphrase(&options.passphrase)?;
+        if passphrase.is_empty() && !has_keyfile {
+            return Err("Empty passphrase".into());
+        }
+        let (mut json, key) = match crypto::decrypt_vault_with_keyfile(&raw, &passphrase, keyfile) {
+            Ok(unlocked) => unlocked,
+            Err(e @ CryptoError::KeyFileRequired) => return Err(format!("{e}; use --key-file")),
+            Err(CryptoError::Unencrypted(_)) => {
+                return Err("This vault is stored without encryption; \
+                            open it in Pithos Notebook to import it"
+                    .into())
+            }
+            Err(_) if has_keyfile => return Err("Wrong passphrase or key file".into()),
+            Err(_) => return Err("Wrong passphrase".into()),
+        };
+        let parsed = vault::parse_vault(&json);
+        json.zeroize();
+        let mut state = vault::vault_to_doc_state(parsed.map_err(|e| e.to_string())?);
+        if let Err(e) = vault::replay_journal(&folder, &key, &mut state) {
+            eprintln!("pithos: failed to replay the journal: {e}");
+        }
+
+        Ok(Self {
+            folder,
+            state,
+            key,
+            _lock: lock,
+        })
+    }
+
+    /// Decrypt the body of the note at `index` if it isn't in memory yet.
+    pub fn load_body(&mut self, index: usize) -> Result<&NoteItem, String> {
+        let vault_id = &self.state.vault_id;
+        let note = &mut self.state.notes[index];
+        vault::load_note_body(&self.folder, vault_id, &self.key, note)
+            .map_err(|e| e.to_string())?;
+        Ok(note)
+    }
+
+    /// Notes matching `query`, best match first. Uses the index the app
+    /// stores, so only notes changed since are decrypted, and notes whose
+    /// text the query looks at.
+    pub fn search(&self, query: &Query) -> Vec<SearchHit> {
+        let vault_id = &self.state.vault_id;
+        let read_body = |note: &NoteItem| match vault::read_note_body(
+            &self.folder,
+            vault_id,
+            &note.id,
+            &self.key,
+        ) {
+            Ok(body) => Some(body),
+            Err(e) => {
+                eprintln!("pithos: skipping a note: {e}");
+                None
+            }
+        };
+        let mut index = vault::read_search_index(&self.folder, vault_id, &self.key);
+        index.sync(&self.state.notes, read_body);
+        index.query(query, &self.state.notes, &self.state.folders, read_body)
+    }
+
+    /// Write the vault in full: note bodies in memory, then vault.json, as
+    /// the app's own saves do. The journal is folded in and cleared.
+    pub fn save(&mut self) -> Result<(), String> {
+        let vault_data = vault::doc_state_to_vault(&self.state);
+        let bodies = vault::loaded_note_bodies(&self.state, None);
+        if let Err(e) = vault::backup_vault(&self.folder) {
+            eprintln!("pithos: vault backup failed: {e}");
+        }
+        vault::write_note_bodies(&self.folder, &self.state.vault_id, &bodies, &self.key)
+            .map_err(|e| format!("Write: {e}"))?;
+        let mut json =
+            serde_json::to_string_pretty(&vault_data).map_err(|e| format!("Serialization: {e}"))?;
+        let result =
+            crypto::encrypt_vault_fast(&json, &self.key).map_err(|e| format!("Encryption: {e}"));
+        json.zeroize();
+        vault::write_vault_raw(&self.folder, &result?).map_err(|e| format!("Write: {e}"))?;
+        if let Err(e) = vault::clear_journal(&self.folder) {
+            eprintln!("pithos: failed to clear the journal: {e}");
+        }
+        if let Err(e) = vault::prune_note_bodies(&self.folder, &vault_data) {
+            eprintln!("pithos: failed to prune deleted note bodies: {e}");
+        }
+        Ok(())
+    }
+
+    /// Write the notes outside locked folders to `dir` as Markdown files.
+    pub fn export_tree(&mut self, dir: &Path) -> Result<TreeReport, String> {
+        markdown_tree::export_tree(&mut self.state, &self.folder, &self.key, dir)
+            .map_err(|e| format!("Export to {}: {e}", dir.display()))
+    }
+
+    /// Add the Markdown files under `dir` in `folder_id`, attachments
+    /// included; save afterwards.
+    pub fn import_tree(
+        &mut self,
+        dir: &Path,
+        folder_id: Option<String>,
+    ) -> Result<TreeReport, String> {
+        markdown_tree::import_tree(&mut self.state, &self.folder, &self.key, dir, folder_id)
+            .map_err(|e| format!("Import from {}: {e}", dir.display()))
+    }
+
+    /// "Folder/Subfolder" for a folder ID, empty at the top level.
+    pub fn folder_path(&self, folder_id: Option<&str>) -> String {
+        let mut names = Vec::new();
+        let mut current = folder_id;
+        while let Some(id) = current {
+            let Some(folder) = self.state.folders.iter().find(|f| f.id == id) else {
+                break;
+            };
+            names.push(folder.name.as_str());
+            current = folder.parent_id.as_deref();
+        }
+        names.reverse();
+        names.join("/")
+    }
+
+    /// "Folder/Subfolder/Note name" for a note.
+    pub fn note_path(&self, note: &NoteItem) -> String {
+        let folder = self.folder_path(note.parent_id.as_deref());
+        if folder.is_empty() {
+            note.name.clone()
+        } else {
+            format!("{folder}/{}", note.name)
+        }
+    }
+
+    /// Find a note by ID, by path, or by name if that is unique.
+    pub fn find_note(&self, query: &str) -> Result<usize, String> {
+        if let Some(index) = find_note_index(&self.state.notes, query) {
+            return Ok(index);
+        }
+        let lower = query.to_lowercase();
+        let notes = &self.state.notes;
+        let mut matches: Vec<usize> = (0..notes.len())
+            .filter(|&i| self.note_path(&notes[i]).to_lowercase() == lower)
+            .collect();
+        if matches.is_empty() {
+            matches = (0..notes.len())
+                .filter(|&i| notes[i].name.to_lowercase() == lower)
+                .collect();
+        }
+        match matches[..] {
+            [index] => Ok(index),
+            [] => Err(format!("No note \"{query}\"")),
+            _ => Err(format!(
+                "\"{query}\" matches several notes; use an ID: {}",
+                matches
+                    .iter()
+                    .map(|&i| notes[i].id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// Find a folder by ID or path. The contents of locked folders stay in
+    /// their sub-vaults, which the CLI doesn't open.
+    pub fn find_folder(&self, query: &str) -> Result<String, String> {
+        let lower = query.trim_matches('/').to_lowercase();
+        let folder = self
+            .state
+            .folders
+            .iter()
+            .find(|f| f.id == query)
+            .or_else(|| {
+                self.state
+                    .folders
+                    .iter()
+                    .find(|f| self.folder_path(Some(&f.id)).to_lowercase() == lower)
+            })
+            .ok_or_else(|| format!("No folder \"{query}\""))?;
+        if !self.state.is_folder_accessible(Some(&folder.id)) {
+            return Err(format!(
+                "Folder \"{query}\" is locked; its notes can only be opened in Pithos Notebook"
+            ));
+        }
+        Ok(folder.id.clone())
+    }
+
+    /// Add a note with a unique name in `folder_id`; returns its ID.
+    pub fn add_note(
+        &mut self,
+        name: &str,
+        content: String,
+        tags: Vec<String>,
+        folder_id: Option<String>,
+    ) -> String {
+        let state = &mut self.state;
+        let unique_name = deduplicate_note_name(&state.notes, name, &folder_id);
+        let id = format!("note-{}", state.next_note_seq);
+        state.next_note_seq += 1;
+        let mut note = NoteItem::new(id.clone(), unique_name, content, tags);
+        note.parent_id = folder_id;
+        state.notes.push(note);
+        id
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        vault::wipe_doc_state(&mut self.state);
+    }
+}
+
+/// Releases the vault's lock when dropped, also on the early returns while
+/// a session is being opened.
+struct LockGuard(String);
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if let Err(e) = vault::release_lock(&self.0) {
+            eprintln!("pithos: failed to remove the vault lock: {e}");
+        }
+    }
+}
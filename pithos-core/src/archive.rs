@@ -0,0 +1,680 @@
+//! Vault archives (`.pithos`): a whole vault folder in one file, for moving a
+//! vault to another machine or handing it to a colleague.
+//!
+//! An archive holds the folder's encrypted files as they are — vault.json,
+//! the journal, note bodies, assets, locked folders and the backups — so it
+//! is no easier to read than the folder itself. Exported to a different
+//! passphrase, the vault's own files are re-encrypted under a new data key;
+//! backups the vault's key can't open are then left out, and locked folders
+//! keep their own passphrases either way. Files that backups share with the
+//! vault are stored once.
+//!
+//! Layout: `MAGIC`, the format version (u32), the contents of the files one
+//! after another, then the entry table and its length (u64, big-endian). The
+//! table lists each file's path with where its contents are and their
+//! SHA-256, or the earlier file it has the same contents as. It is sealed
+//! like vault.json, to the archive's passphrase, and repeats the format
+//! version, so neither the paths, the contents nor the header can be changed
+//! without the passphrase.
+
+use crate::crypto::{self, CachedKey, KeyFile};
+use crate::vault::{self, VaultError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use zeroize::Zeroize;
+
+/// File extension of vault archives.
+pub const ARCHIVE_EXTENSION: &str = "pithos";
+
+const MAGIC: &[u8; 15] = b"PITHOS-ARCHIVE\n";
+const FORMAT_VERSION: u32 = 1;
+/// Where the contents start.
+const HEADER_LEN: u64 = MAGIC.len() as u64 + 4;
+/// Marks the decrypted table as an archive's, not a vault sealed the same way.
+const TABLE_KIND: &str = "pithos-archive";
+
+/// Top-level names an archive may contain; anything else is refused when
+/// extracting.
+const ARCHIVE_ROOTS: &[&str] = &[
+    "vault.json",
+    "journal",
+    "notes",
+    "assets",
+    "locked",
+    "backups",
+];
+
+#[derive(Debug, Default)]
+pub struct ArchiveReport {
+    pub files: usize,
+    /// Size of the archive's contents, before files stored once are copied.
+    pub bytes: u64,
+    pub backups: usize,
+    /// Backups left out of an archive to another passphrase because the
+    /// vault's key doesn't open them (made before a passphrase change).
+    pub skipped_backups: Vec<String>,
+}
+
+/// The sealed entry table.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Table {
+    kind: String,
+    version: u32,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    path: String,
+    #[serde(flatten)]
+    source: Source,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Source {
+    /// `len` bytes at `offset` into the contents, with this SHA-256 (hex).
+    File {
+        offset: u64,
+        len: u64,
+        sha256: String,
+    },
+    /// The same contents as the earlier entry with this path.
+    SameAs(String),
+}
+
+#[derive(Default)]
+pub struct ArchiveOptions<'a> {
+    /// Encrypt the archive to this key instead of the vault's own.
+    pub new_key: Option<&'a CachedKey>,
+    pub include_backups: bool,
+}
+
+// ---------------------------------------------------------------------------
+// Writing
+// ---------------------------------------------------------------------------
+
+/// Archive the vault in `vault_folder`, as it is on disk, to `out`. Save the
+/// vault first; `key` must open its vault.json.
+pub fn write_archive(
+    vault_folder: &str,
+    key: &CachedKey,
+    options: &ArchiveOptions,
+    out: &Path,
+) -> Result<ArchiveReport, VaultError> {
+    let raw = vault::read_vault_raw(vault_folder)?
+        .ok_or_else(|| VaultError::Invalid("no vault.json".into()))?;
+    // Fails early, with the usual error, if `key` is for another vault
+    crypto::decrypt_vault_fast(&raw, key)?.zeroize();
+
+    let tmp = out.with_extension("tmp");
+    let mut writer = ArchiveWriter {
+        out: BufWriter::new(File::create(&tmp)?),
+        key,
+        new_key: options.new_key,
+        seen: HashMap::new(),
+        entries: Vec::new(),
+        offset: 0,
+        report: ArchiveReport::default(),
+    };
+    let result = writer.header().and_then(|()| {
+        writer.vault(vault_folder, "", &raw)?;
+        if options.include_backups {
+            writer.backups(vault_folder)?;
+        }
+        writer.finish()
+    });
+    match result {
+        Ok(()) => {
+            fs::rename(&tmp, out)?;
+            Ok(writer.report)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+struct ArchiveWriter<'a> {
+    out: BufWriter<File>,
+    key: &'a CachedKey,
+    new_key: Option<&'a CachedKey>,
+    /// SHA-256 of the source file -> path it was stored under.
+    seen: HashMap<[u8; 32], String>,
+    entries: Vec<Entry>,
+    /// Bytes of contents written so far.
+    offset: u64,
+    report: ArchiveReport,
+}
+
+impl ArchiveWriter<'_> {
+    fn header(&mut self) -> Result<(), VaultError> {
+        self.out.write_all(MAGIC)?;
+        self.out.write_all(&FORMAT_VERSION.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Write a vault folder (the vault itself, or a backup) under `prefix`.
+    fn vault(&mut self, folder: &str, prefix: &str, raw: &str) -> Result<(), VaultError> {
+        let (key, new_key) = (self.key, self.new_key);
+        self.add(&format!("{prefix}vault.json"), raw.as_bytes(), |data| {
+            let Some(new_key) = new_key else {
+                return Ok(data.to_vec());
+            };
+            let mut json = crypto::decrypt_vault_fast(raw, key)?;
+            let encrypted = crypto::encrypt_vault_fast(&json, new_key);
+            json.zeroize();
+            Ok(encrypted?.into_bytes())
+        })?;
+
+        let journal = vault::journal_path(folder);
+        if journal.exists() {
+            let data = fs::read(&journal)?;
+            self.add(&format!("{prefix}journal"), &data, |data| {
+                let Some(new_key) = new_key else {
+                    return Ok(data.to_vec());
+                };
+                // Stops at a line cut short by a crash, as replaying does
+                let mut lines = String::new();
+                for line in data.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+                    let Ok(line) = crypto::reencrypt_asset(line, key, new_key) else {
+                        break;
+                    };
+                    lines.push_str(&line);
+                    lines.push('\n');
+                }
+                Ok(lines.into_bytes())
+            })?;
+        }
+
+        for (dir, error) in [
+            (
+                "notes",
+                VaultError::Note as fn(String, String) -> VaultError,
+            ),
+            ("assets", VaultError::Asset),
+        ] {
+            let path = Path::new(folder).join(dir);
+            for id in vault::list_blob_ids(&path)? {
+                let data = fs::read(path.join(&id))?;
+                self.add(
+                    &format!("{prefix}{dir}/{id}"),
+                    &data,
+                    |data| match new_key {
+                        Some(new_key) => crypto::reencrypt_asset(data, key, new_key)
+                            .map(String::into_bytes)
+                            .map_err(|e| error(id.clone(), format!("re-encrypt failed: {e}"))),
+                        None => Ok(data.to_vec()),
+                    },
+                )?;
+            }
+        }
+
+        // Locked folders have keys of their own and go in as they are
+        for (path, file) in files_under(&Path::new(folder).join("locked"))? {
+            let data = fs::read(&file)?;
+            self.add(&format!("{prefix}locked/{path}"), &data, |data| {
+                Ok(data.to_vec())
+            })?;
+        }
+        Ok(())
+    }
+
+    fn backups(&mut self, vault_folder: &str) -> Result<(), VaultError> {
+        for backup in vault::list_backups(vault_folder)? {
+            let folder = vault::backup_path(vault_folder, &backup.id)?;
+            let Some(raw) = vault::read_vault_raw(&folder)? else {
+                continue;
+            };
+            if self.new_key.is_some() {
+                match crypto::decrypt_vault_fast(&raw, self.key) {
+                    Ok(mut json) => json.zeroize(),
+                    Err(_) => {
+                        self.report.skipped_backups.push(backup.id);
+                        continue;
+                    }
+                }
+            }
+            self.vault(&folder, &format!("backups/{}/", backup.id), &raw)?;
+            self.report.backups += 1;
+        }
+        Ok(())
+    }
+
+    /// Store `data` under `path`, converted by `convert`, unless a file with
+    /// the same contents is already in the archive.
+    fn add(
+        &mut self,
+        path: &str,
+        data: &[u8],
+        convert: impl FnOnce(&[u8]) -> Result<Vec<u8>, VaultError>,
+    ) -> Result<(), VaultError> {
+        let hash: [u8; 32] = Sha256::digest(data).into();
+        self.report.files += 1;
+        let source = match self.seen.get(&hash) {
+            Some(earlier) => Source::SameAs(earlier.clone()),
+            None => {
+                let contents = convert(data)?;
+                self.out.write_all(&contents)?;
+                let len = contents.len() as u64;
+                let source = Source::File {
+                    offset: self.offset,
+                    len,
+                    sha256: hex(&Sha256::digest(&contents)),
+                };
+                self.offset += len;
+                self.report.bytes += len;
+                self.seen.insert(hash, path.to_string());
+                source
+            }
+        };
+        self.entries.push(Entry {
+            path: path.to_string(),
+            source,
+        });
+        Ok(())
+    }
+
+    /// Seal the entry table to the archive's key and write it last.
+    fn finish(&mut self) -> Result<(), VaultError> {
+        let table = Table {
+            kind: TABLE_KIND.to_string(),
+            version: FORMAT_VERSION,
+            entries: std::mem::take(&mut self.entries),
+        };
+        let json = serde_json::to_string(&table).map_err(|e| VaultError::Invalid(e.to_string()))?;
+        let sealed = crypto::encrypt_vault_fast(&json, self.new_key.unwrap_or(self.key))?;
+        self.out.write_all(sealed.as_bytes())?;
+        self.out.write_all(&(sealed.len() as u64).to_be_bytes())?;
+        self.out.flush()?;
+        self.out.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Every file under `dir` as (path relative to `dir`, full path), skipping
+/// leftovers from interrupted writes.
+fn files_under(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.ends_with(".tmp") {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            for (path, file) in files_under(&entry.path())? {
+                files.push((format!("{name}/{path}"), file));
+            }
+        } else {
+            files.push((name, entry.path()));
+        }
+    }
+    Ok(files)
+}
+
+// ---------------------------------------------------------------------------
+// Extracting
+// ---------------------------------------------------------------------------
+
+/// Whether opening the archive takes a key file, as its vault's does.
+pub fn archive_needs_keyfile(archive: &Path) -> Result<bool, VaultError> {
+    let (sealed, _, _) = read_sealed_table(&mut File::open(archive)?)?;
+    Ok(crypto::envelope_info(sealed.as_bytes())?.is_some_and(|info| info.keyfile))
+}
+
+/// Unpack an archive into `dest`, which must be empty or not exist yet. The
+/// result is a vault folder that opens with the returned key: the one the
+/// passphrase (and key file) the archive was written to unlock. Only the
+/// entry table is decrypted here; each file is checked against it.
+pub fn extract_archive(
+    archive: &Path,
+    dest: &str,
+    passphrase: &str,
+    keyfile: Option<KeyFile>,
+) -> Result<(ArchiveReport, CachedKey), VaultError> {
+    let mut input = File::open(archive)?;
+    let (sealed, version, contents_len) = read_sealed_table(&mut input)?;
+    let (mut json, key) = crypto::decrypt_vault_with_keyfile(&sealed, passphrase, keyfile)?;
+    let table: Result<Table, _> = serde_json::from_str(&json);
+    json.zeroize();
+    let table = table
+        .ok()
+        .filter(|t| t.kind == TABLE_KIND && t.version == version)
+        .ok_or_else(|| VaultError::Invalid("the archive's entry table is damaged".into()))?;
+
+    let dest_path = Path::new(dest);
+    let existed = dest_path.exists();
+    if existed && fs::read_dir(dest_path)?.next().is_some() {
+        return Err(VaultError::Invalid(format!("{dest} is not empty")));
+    }
+    fs::create_dir_all(dest_path)?;
+    let result = extract_entries(&mut input, contents_len, &table.entries, dest_path);
+    if result.is_err() {
+        // `dest` was empty, so everything in it is ours
+        let _ = fs::remove_dir_all(dest_path);
+        if existed {
+            let _ = fs::create_dir(dest_path);
+        }
+    }
+    Ok((result?, key))
+}
+
+/// Check the header and read the sealed entry table. Returns it with the
+/// format version and the length of the contents before it.
+fn read_sealed_table(input: &mut File) -> Result<(String, u32, u64), VaultError> {
+    let truncated = || VaultError::Invalid("the archive is truncated".into());
+    let mut magic = [0u8; MAGIC.len()];
+    input
+        .read_exact(&mut magic)
+        .ok()
+        .filter(|()| &magic == MAGIC)
+        .ok_or_else(|| VaultError::Invalid("not a Pithos vault archive".into()))?;
+    let mut version = [0u8; 4];
+    input.read_exact(&mut version).map_err(|_| truncated())?;
+    let version = u32::from_be_bytes(version);
+    if version > FORMAT_VERSION {
+        return Err(VaultError::Invalid(format!(
+            "archive format {version} is newer than this version of Pithos reads; update Pithos to open it"
+        )));
+    }
+
+    let file_len = input.metadata()?.len();
+    let trailer = file_len.checked_sub(8).filter(|&t| t >= HEADER_LEN);
+    let trailer = trailer.ok_or_else(truncated)?;
+    input.seek(SeekFrom::Start(trailer))?;
+    let mut len = [0u8; 8];
+    input.read_exact(&mut len)?;
+    let contents_len = (trailer - HEADER_LEN)
+        .checked_sub(u64::from_be_bytes(len))
+        .ok_or_else(truncated)?;
+    input.seek(SeekFrom::Start(HEADER_LEN + contents_len))?;
+    let mut sealed = String::new();
+    input
+        .take(u64::from_be_bytes(len))
+        .read_to_string(&mut sealed)
+        .map_err(|_| VaultError::Invalid("the archive's entry table is damaged".into()))?;
+    Ok((sealed, version, contents_len))
+}
+
+fn extract_entries(
+    input: &mut File,
+    contents_len: u64,
+    entries: &[Entry],
+    dest: &Path,
+) -> Result<ArchiveReport, VaultError> {
+    let damaged = |path: &str| VaultError::Invalid(format!("{path} is damaged in the archive"));
+    let mut report = ArchiveReport::default();
+    let mut written = HashSet::new();
+    let mut backups = HashSet::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    for entry in entries {
+        let path = checked_path(&entry.path)?;
+        // A second entry for a path would write through a link to another
+        if written.contains(path) {
+            return Err(VaultError::Invalid(format!(
+                "{path} is in the archive twice"
+            )));
+        }
+        let target = dest.join(path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match &entry.source {
+            Source::File {
+                offset,
+                len,
+                sha256,
+            } => {
+                if offset
+                    .checked_add(*len)
+                    .is_none_or(|end| end > contents_len)
+                {
+                    return Err(damaged(path));
+                }
+                input.seek(SeekFrom::Start(HEADER_LEN + offset))?;
+                let mut reader = (&mut *input).take(*len);
+                let mut file = File::create(&target)?;
+                let mut hasher = Sha256::new();
+                loop {
+                    let n = reader.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    hasher.update(&buf[..n]);
+                    file.write_all(&buf[..n])?;
+                }
+                if hex(&hasher.finalize()) != *sha256 {
+                    return Err(damaged(path));
+                }
+                report.bytes += len;
+            }
+            Source::SameAs(earlier) => {
+                if !written.contains(earlier.as_str()) {
+                    return Err(VaultError::Invalid(format!(
+                        "{path} refers to {earlier}, which is not in the archive"
+                    )));
+                }
+                vault::link_or_copy(&dest.join(earlier), &target)?;
+            }
+        }
+        if let Some(backup) = path
+            .strip_prefix("backups/")
+            .and_then(|p| p.split('/').next())
+        {
+            backups.insert(backup.to_string());
+        }
+        written.insert(path);
+        report.files += 1;
+    }
+    report.backups = backups.len();
+
+    let raw = vault::read_vault_raw(&dest.to_string_lossy())?
+        .ok_or_else(|| VaultError::Invalid("the archive has no vault.json".into()))?;
+    if crypto::envelope_info(raw.as_bytes())?.is_none() {
+        return Err(VaultError::Invalid(
+            "the archived vault is not encrypted".into(),
+        ));
+    }
+    Ok(report)
+}
+
+/// Check that an entry path stays inside the vault folder.
+fn checked_path(path: &str) -> Result<&str, VaultError> {
+    let safe = Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+        && path
+            .split('/')
+            .next()
+            .is_some_and(|root| ARCHIVE_ROOTS.contains(&root));
+    if !safe || path.contains('\\') {
+        return Err(VaultError::Invalid(format!(
+            "bad path in the archive: {path}"
+        )));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::*;
+
+    /// An archive of `entries` over `contents`, sealed to `key`.
+    fn forge(entries: Vec<Entry>, contents: &[u8], key: &CachedKey) -> Vec<u8> {
+        let table = Table {
+            kind: TABLE_KIND.into(),
+            version: FORMAT_VERSION,
+            entries,
+        };
+        let json = serde_json::to_string(&table).unwrap();
+        let sealed = crypto::encrypt_vault_fast(&json, key).unwrap();
+        let mut archive = MAGIC.to_vec();
+        archive.extend(FORMAT_VERSION.to_be_bytes());
+        archive.extend(contents);
+        archive.extend(sealed.as_bytes());
+        archive.extend((sealed.len() as u64).to_be_bytes());
+        archive
+    }
+
+    fn file(path: &str, offset: u64, contents: &[u8]) -> Entry {
+        Entry {
+            path: path.into(),
+            source: Source::File {
+                offset,
+                len: contents.len() as u64,
+                sha256: hex(&Sha256::digest(contents)),
+            },
+        }
+    }
+
+    #[test]
+    fn test_archive_round_trip() {
+        let root = PathBuf::from(vault::tests::temp_vault("archive"));
+        let folder = root.join("vault").to_string_lossy().to_string();
+        fs::create_dir_all(&folder).unwrap();
+        let key = CachedKey::derive_with("archive-pass", crypto::KdfParams::legacy()).unwrap();
+
+        let mut state = DocState::default();
+        state.notes[0].content = "archived body".to_string();
+        let data = vault::doc_state_to_vault(&state);
+        let bodies = vault::loaded_note_bodies(&state, None);
+        vault::write_note_bodies(&folder, &state.vault_id, &bodies, &key).unwrap();
+        let binding = vault::binding_for(&state.vault_id, None, "img1", 1);
+        let asset = crypto::encrypt_asset_bound(b"image", &key, &binding).unwrap();
+        vault::write_asset(&folder, "img1", asset.as_bytes()).unwrap();
+        let json = serde_json::to_string(&data).unwrap();
+        vault::write_vault_raw(&folder, &crypto::encrypt_vault_fast(&json, &key).unwrap()).unwrap();
+        vault::create_backup(&folder, 1_000).unwrap();
+
+        // Same passphrase: the files go in as they are, backups stored once
+        let out = root.join("copy.pithos");
+        let options = ArchiveOptions {
+            include_backups: true,
+            ..Default::default()
+        };
+        let report = write_archive(&folder, &key, &options, &out).expect("archive");
+        // The backup's five files match the vault's and are stored once
+        assert_eq!((report.files, report.backups), (10, 1));
+        let vault_bytes: u64 = files_under(Path::new(&folder))
+            .unwrap()
+            .iter()
+            .filter(|(path, _)| !path.starts_with("backups/"))
+            .map(|(_, file)| fs::metadata(file).unwrap().len())
+            .sum();
+        assert_eq!(report.bytes, vault_bytes);
+        let copy = root.join("copy").to_string_lossy().to_string();
+        assert!(!archive_needs_keyfile(&out).unwrap());
+        let wrong = extract_archive(&out, &copy, "wrong", None);
+        assert!(wrong.is_err() && !Path::new(&copy).exists());
+        let (extracted, opened) = extract_archive(&out, &copy, "archive-pass", None).unwrap();
+        assert_eq!((extracted.files, extracted.backups), (10, 1));
+        assert!(vault::read_note_body(&copy, &state.vault_id, &state.notes[0].id, &opened).is_ok());
+        assert_eq!(
+            fs::read(vault::assets_dir(&copy).join("img1")).unwrap(),
+            asset.as_bytes()
+        );
+        assert!(vault::open_backup(&copy, "1000", &key).is_ok());
+        assert!(
+            extract_archive(&out, &copy, "archive-pass", None).is_err(),
+            "not empty"
+        );
+
+        // Another passphrase: a new data key, and the old one opens nothing
+        let new_key = CachedKey::derive_with("colleague", crypto::KdfParams::legacy()).unwrap();
+        let out = root.join("shared.pithos");
+        let options = ArchiveOptions {
+            new_key: Some(&new_key),
+            include_backups: false,
+        };
+        write_archive(&folder, &key, &options, &out).expect("archive");
+        let shared = root.join("shared").to_string_lossy().to_string();
+        assert!(extract_archive(&out, &shared, "archive-pass", None).is_err());
+        let (extracted, _) = extract_archive(&out, &shared, "colleague", None).unwrap();
+        assert_eq!(extracted.backups, 0);
+        let raw = vault::read_vault_raw(&shared).unwrap().unwrap();
+        let (json, opened) = crypto::decrypt_vault_returning_key(&raw, "colleague").unwrap();
+        assert_eq!(vault::parse_vault(&json).unwrap().vault_id, state.vault_id);
+        assert!(crypto::decrypt_vault_fast(&raw, &key).is_err());
+        let body =
+            vault::read_note_body(&shared, &state.vault_id, &state.notes[0].id, &opened).unwrap();
+        assert_eq!(body.content, "archived body");
+        let meta = vault::AssetMeta {
+            id: "img1".into(),
+            original_name: "img.png".into(),
+            mime_type: "image/png".into(),
+            size: 5,
+            created_at: 0,
+            stored_in: None,
+            version: Some(1),
+        };
+        let binding = vault::asset_binding(&state.vault_id, &meta);
+        let image = vault::read_asset(&shared, "img1", &opened, binding.as_ref()).unwrap();
+        assert_eq!(image, b"image");
+
+        // Changed contents, header or table, and paths that would leave the
+        // vault folder or that come twice, are refused
+        let extract = |name: &str, archive: &[u8]| {
+            let path = root.join(format!("{name}.pithos"));
+            fs::write(&path, archive).unwrap();
+            let target = root.join(name).to_string_lossy().to_string();
+            let result = extract_archive(&path, &target, "archive-pass", None);
+            assert!(result.is_ok() || !Path::new(&target).exists());
+            result.map(|_| ())
+        };
+        let mut archive = fs::read(root.join("copy.pithos")).unwrap();
+        archive[HEADER_LEN as usize + 1] ^= 1;
+        assert!(extract("flipped", &archive).is_err());
+        let mut archive = fs::read(root.join("copy.pithos")).unwrap();
+        archive[MAGIC.len() + 3] = 0;
+        assert!(extract("downgraded", &archive).is_err());
+        let mut archive = fs::read(root.join("copy.pithos")).unwrap();
+        let table_byte = archive.len() - 20;
+        archive[table_byte] ^= 1;
+        assert!(extract("table", &archive).is_err());
+
+        let vault_json = fs::read(vault::vault_file_path(&folder)).unwrap();
+        let escape = forge(
+            vec![
+                file("vault.json", 0, &vault_json),
+                file("notes/../../escape", vault_json.len() as u64, b"x"),
+            ],
+            &[vault_json.as_slice(), b"x"].concat(),
+            &key,
+        );
+        assert!(extract("escape", &escape).is_err());
+        assert!(!root.join("escape").exists());
+        let twice = forge(
+            vec![
+                file("vault.json", 0, &vault_json),
+                Entry {
+                    path: "journal".into(),
+                    source: Source::SameAs("vault.json".into()),
+                },
+                file("journal", 0, &vault_json),
+            ],
+            &vault_json,
+            &key,
+        );
+        assert!(extract("twice", &twice).is_err());
+        let fine = forge(vec![file("vault.json", 0, &vault_json)], &vault_json, &key);
+        assert!(extract("fine", &fine).is_ok());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,547 @@
+//! A single note or folder shared on its own: its notes with their tags and
+//! history, its subfolders and the images they show, sealed in one file
+//! under a one-time passphrase. The passphrase is sent separately; the
+//! recipient's Pithos imports the bundle into their own vault, where
+//! everything gets new IDs.
+
+use crate::crypto::{self, CachedKey};
+use crate::state::*;
+use crate::vault::{self, AssetMeta, NoteBody, VaultError};
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use zeroize::{Zeroize, Zeroizing};
+
+/// File extension for bundles, without the dot.
+pub const BUNDLE_EXTENSION: &str = "pithosbundle";
+/// Marks the decrypted payload as a bundle, not a vault sealed the same way.
+const BUNDLE_KIND: &str = "pithos-bundle";
+const FORMAT_VERSION: u32 = 1;
+
+/// What to share.
+#[derive(Debug, Clone)]
+pub enum ShareTarget {
+    Note(String),
+    Folder(String),
+}
+
+/// Decrypted contents of a bundle. Note text and asset data are wiped when
+/// it is dropped.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bundle {
+    kind: String,
+    format: u32,
+    /// Name of the shared note or folder.
+    pub name: String,
+    pub created_at: i64,
+    /// Parents before their subfolders; the shared folder has no parent.
+    folders: Vec<BundleFolder>,
+    notes: Vec<BundleNote>,
+    assets: Vec<BundleAsset>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BundleFolder {
+    id: String,
+    name: String,
+    parent_id: Option<String>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BundleNote {
+    id: String,
+    name: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    created_at: i64,
+    updated_at: i64,
+    #[serde(default)]
+    pinned: bool,
+    parent_id: Option<String>,
+    #[serde(flatten)]
+    body: NoteBody,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BundleAsset {
+    id: String,
+    original_name: String,
+    mime_type: String,
+    /// Base64 of the decrypted asset.
+    data: String,
+}
+
+impl Drop for BundleAsset {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+
+impl Bundle {
+    pub fn note_count(&self) -> usize {
+        self.notes.len()
+    }
+
+    pub fn folder_count(&self) -> usize {
+        self.folders.len()
+    }
+
+    pub fn asset_count(&self) -> usize {
+        self.assets.len()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BundleReport {
+    /// Name of the imported note or folder, numbered if the name was taken.
+    pub name: String,
+    pub notes: usize,
+    pub folders: usize,
+    pub assets: usize,
+}
+
+// ---------------------------------------------------------------------------
+// Sharing
+// ---------------------------------------------------------------------------
+
+/// Collect `target` from `state` with the assets its notes link to. Bodies
+/// not in memory are decrypted from their blobs, and unsaved edits of
+/// loaded notes are included. Fails inside a locked folder that is not
+/// unlocked; locked subfolders that are not unlocked are left out.
+pub fn build_bundle(
+    state: &DocState,
+    vault_folder: &str,
+    key: &CachedKey,
+    target: &ShareTarget,
+) -> Result<Bundle, VaultError> {
+    let mut bundle = Bundle {
+        kind: BUNDLE_KIND.to_string(),
+        format: FORMAT_VERSION,
+        name: String::new(),
+        created_at: unix_now(),
+        folders: Vec::new(),
+        notes: Vec::new(),
+        assets: Vec::new(),
+    };
+    let note_indices: Vec<usize> = match target {
+        ShareTarget::Note(id) => {
+            let index = find_note_index(&state.notes, id)
+                .ok_or_else(|| VaultError::Note(id.clone(), "not found".into()))?;
+            let note = &state.notes[index];
+            if let Some(root) = state.locked_root(note.parent_id.as_deref()) {
+                if !state.is_folder_unlocked(root) {
+                    return Err(VaultError::Locked(root.to_string()));
+                }
+            }
+            bundle.name = note.name.clone();
+            vec![index]
+        }
+        ShareTarget::Folder(id) => {
+            let folder = state
+                .folders
+                .iter()
+                .find(|f| f.id == *id)
+                .ok_or_else(|| VaultError::Invalid(format!("folder {id} not found")))?;
+            if !state.is_folder_accessible(Some(id)) {
+                let root = state.locked_root(Some(id)).unwrap_or(id);
+                return Err(VaultError::Locked(root.to_string()));
+            }
+            bundle.name = folder.name.clone();
+            bundle.folders = subtree(state, id);
+            let included: HashSet<&str> = bundle.folders.iter().map(|f| f.id.as_str()).collect();
+            (0..state.notes.len())
+                .filter(|&i| {
+                    state.notes[i]
+                        .parent_id
+                        .as_deref()
+                        .is_some_and(|p| included.contains(p))
+                })
+                .collect()
+        }
+    };
+
+    let mut asset_ids = Vec::new();
+    for index in note_indices {
+        let note = &state.notes[index];
+        let body = if note.loaded {
+            NoteBody {
+                content: note.content.clone(),
+                versions: vault::versions_to_entries(&note.versions),
+            }
+        } else {
+            let (dir, key) = vault::blob_location(
+                vault_folder,
+                &state.folder_keys,
+                key,
+                note.stored_in.as_deref(),
+            )?;
+            let owner = vault::blob_owner(&state.vault_id, note.stored_in.as_deref());
+            vault::read_note_body(&dir, &owner, &note.id, key)?
+        };
+        let texts = std::iter::once(&body.content).chain(body.versions.iter().map(|v| &v.content));
+        for text in texts {
+            for id in vault::referenced_asset_ids(text) {
+                if !asset_ids.contains(&id) {
+                    asset_ids.push(id);
+                }
+            }
+        }
+        // A shared note lands at the top of the recipient's import
+        let parent_id = match target {
+            ShareTarget::Note(_) => None,
+            ShareTarget::Folder(_) => note.parent_id.clone(),
+        };
+        bundle.notes.push(BundleNote {
+            id: note.id.clone(),
+            name: note.name.clone(),
+            tags: note.tags.clone(),
+            created_at: note.created_at,
+            updated_at: note.updated_at,
+            pinned: note.pinned,
+            parent_id,
+            body,
+        });
+    }
+
+    let b64 = &base64::engine::general_purpose::STANDARD;
+    for id in asset_ids {
+        // Links to assets the vault doesn't have stay as they are
+        let Some(meta) = state.assets.get(&id) else {
+            continue;
+        };
+        let (dir, key) = vault::blob_location(
+            vault_folder,
+            &state.folder_keys,
+            key,
+            meta.stored_in.as_deref(),
+        )?;
+        let binding = vault::asset_binding(&state.vault_id, meta);
+        let data = Zeroizing::new(vault::read_asset(&dir, &id, key, binding.as_ref())?);
+        bundle.assets.push(BundleAsset {
+            id,
+            original_name: meta.original_name.clone(),
+            mime_type: meta.mime_type.clone(),
+            data: b64.encode(&*data),
+        });
+    }
+    Ok(bundle)
+}
+
+/// `root` and the folders below it that can be read, parents first, with
+/// `root` detached from its parent.
+fn subtree(state: &DocState, root: &str) -> Vec<BundleFolder> {
+    let mut folders = Vec::new();
+    let mut queue = vec![root.to_string()];
+    while let Some(id) = queue.pop() {
+        let Some(folder) = state.folders.iter().find(|f| f.id == id) else {
+            continue;
+        };
+        folders.push(BundleFolder {
+            id: folder.id.clone(),
+            name: folder.name.clone(),
+            parent_id: (folder.id != root)
+                .then(|| folder.parent_id.clone())
+                .flatten(),
+            created_at: folder.created_at,
+            updated_at: folder.updated_at,
+        });
+        queue.extend(
+            state
+                .folders
+                .iter()
+                .filter(|f| f.parent_id.as_deref() == Some(&id))
+                .filter(|f| state.is_folder_accessible(Some(&f.id)))
+                .map(|f| f.id.clone()),
+        );
+    }
+    folders
+}
+
+/// Encrypt `bundle` under `passphrase`, normally one from
+/// [`crypto::generate_one_time_passphrase`]. Slow: runs the KDF.
+pub fn seal_bundle(bundle: &Bundle, passphrase: &str) -> Result<String, VaultError> {
+    let key = CachedKey::derive(passphrase)?;
+    let json = Zeroizing::new(
+        serde_json::to_string(bundle).map_err(|e| VaultError::Invalid(e.to_string()))?,
+    );
+    Ok(crypto::encrypt_vault_fast(&json, &key)?)
+}
+
+// ---------------------------------------------------------------------------
+// Importing
+// ---------------------------------------------------------------------------
+
+/// Decrypt and check a sealed bundle. Slow: runs the KDF.
+pub fn open_bundle(raw: &str, passphrase: &str) -> Result<Bundle, VaultError> {
+    let passphrase = crypto::normalize_one_time_passphrase(passphrase);
+    let (json, _key) = crypto::decrypt_vault_returning_key(raw, &passphrase)?;
+    let json = Zeroizing::new(json);
+    let bundle: Bundle = serde_json::from_str(&json)
+        .map_err(|_| VaultError::Invalid("not a Pithos bundle".into()))?;
+    if bundle.kind != BUNDLE_KIND {
+        return Err(VaultError::Invalid("not a Pithos bundle".into()));
+    }
+    if bundle.format > FORMAT_VERSION {
+        return Err(VaultError::Invalid(
+            "the bundle was made by a newer version of Pithos".into(),
+        ));
+    }
+    // Every parent must come before what it contains
+    let mut seen = HashSet::new();
+    for folder in &bundle.folders {
+        if folder.parent_id.as_ref().is_some_and(|p| !seen.contains(p))
+            || !seen.insert(folder.id.clone())
+        {
+            return Err(VaultError::Invalid(format!(
+                "bundle folder {} is out of order",
+                folder.id
+            )));
+        }
+    }
+    if let Some(note) = bundle
+        .notes
+        .iter()
+        .find(|n| n.parent_id.as_ref().is_some_and(|p| !seen.contains(p)))
+    {
+        return Err(VaultError::Invalid(format!(
+            "bundle note {} is in a missing folder",
+            note.id
+        )));
+    }
+    Ok(bundle)
+}
+
+/// Add the contents of `bundle` to `state` below `parent`, with new IDs for
+/// every note, folder and asset. Assets are encrypted into the vault right
+/// away, so save `state` after.
+pub fn import_bundle(
+    state: &mut DocState,
+    vault_folder: &str,
+    key: &CachedKey,
+    mut bundle: Bundle,
+    parent: Option<String>,
+) -> Result<BundleReport, VaultError> {
+    if let Some(root) = state.locked_root(parent.as_deref()) {
+        return Err(VaultError::Locked(root.to_string()));
+    }
+    let mut report = BundleReport {
+        name: bundle.name.clone(),
+        ..Default::default()
+    };
+
+    let b64 = &base64::engine::general_purpose::STANDARD;
+    let mut asset_ids = HashMap::new();
+    for asset in &bundle.assets {
+        let data = Zeroizing::new(
+            b64.decode(&asset.data)
+                .map_err(|e| VaultError::Asset(asset.id.clone(), e.to_string()))?,
+        );
+        let id = vault::new_vault_id();
+        let binding = vault::binding_for(&state.vault_id, None, &id, 1);
+        let encrypted = crypto::encrypt_asset_bound(&data, key, &binding)?;
+        vault::write_asset(vault_folder, &id, encrypted.as_bytes())?;
+        state.assets.insert(
+            id.clone(),
+            AssetMeta {
+                id: id.clone(),
+                original_name: asset.original_name.clone(),
+                mime_type: asset.mime_type.clone(),
+                size: data.len() as u64,
+                created_at: unix_now() * 1000,
+                stored_in: None,
+                version: Some(1),
+            },
+        );
+        asset_ids.insert(asset.id.clone(), id);
+        report.assets += 1;
+    }
+
+    let mut folder_ids: HashMap<String, Option<String>> = HashMap::new();
+    for folder in &bundle.folders {
+        let parent_id = match &folder.parent_id {
+            Some(p) => folder_ids[p].clone(),
+            None => parent.clone(),
+        };
+        let mut name = folder.name.clone();
+        let mut n = 2;
+        while folder_name_exists(&state.folders, &name, &parent_id, None) {
+            name = format!("{} ({n})", folder.name);
+            n += 1;
+        }
+        if folder.parent_id.is_none() {
+            report.name = name.clone();
+        }
+        let id = format!("folder-{}", state.next_note_seq);
+        state.next_note_seq += 1;
+        state.folders.push(FolderItem {
+            id: id.clone(),
+            name,
+            expanded: true,
+            created_at: folder.created_at,
+            updated_at: folder.updated_at,
+            parent_id,
+            locked: false,
+        });
+        folder_ids.insert(folder.id.clone(), Some(id));
+        report.folders += 1;
+    }
+
+    for note in &mut bundle.notes {
+        let parent_id = match &note.parent_id {
+            Some(p) => folder_ids[p].clone(),
+            None => parent.clone(),
+        };
+        let name = deduplicate_note_name(&state.notes, &note.name, &parent_id);
+        if bundle.folders.is_empty() {
+            report.name = name.clone();
+        }
+        let (content, mut versions) = note.body.take();
+        for version in &mut versions {
+            relink_assets(&mut version.content, &asset_ids);
+        }
+        let id = format!("note-{}", state.next_note_seq);
+        state.next_note_seq += 1;
+        let mut item = NoteItem::new(id, name, content, std::mem::take(&mut note.tags));
+        relink_assets(&mut item.content, &asset_ids);
+        item.versions = versions;
+        item.parent_id = parent_id;
+        item.pinned = note.pinned;
+        item.created_at = note.created_at;
+        item.updated_at = note.updated_at;
+        state.notes.push(item);
+        report.notes += 1;
+    }
+    Ok(report)
+}
+
+/// Point `vault://<id>` links at the IDs the assets got on import.
+fn relink_assets(text: &mut String, ids: &HashMap<String, String>) {
+    if ids.is_empty() || !text.contains("vault://") {
+        return;
+    }
+    let out = vault::replace_asset_links(text, |id| Some(format!("vault://{}", ids.get(id)?)));
+    text.zeroize();
+    *text = out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_folder_round_trip() {
+        let dir = std::path::PathBuf::from(vault::tests::temp_vault("bundle"));
+        let from = dir.join("from");
+        let to = dir.join("to");
+        let (from, to) = (from.to_str().unwrap(), to.to_str().unwrap());
+        let key = CachedKey::derive_with("pass", crypto::KdfParams::legacy()).unwrap();
+
+        let mut state = DocState {
+            vault_id: vault::new_vault_id(),
+            ..Default::default()
+        };
+        let photo = vault::new_vault_id();
+        let binding = vault::binding_for(&state.vault_id, None, &photo, 1);
+        let encrypted = crypto::encrypt_asset_bound(b"PNG", &key, &binding).unwrap();
+        vault::write_asset(from, &photo, encrypted.as_bytes()).unwrap();
+        state.assets.insert(
+            photo.clone(),
+            AssetMeta {
+                id: photo.clone(),
+                original_name: "diagram.png".into(),
+                mime_type: "image/png".into(),
+                size: 3,
+                created_at: 0,
+                stored_in: None,
+                version: Some(1),
+            },
+        );
+        for (id, name, parent) in [
+            ("folder-7", "Threat Models", None),
+            ("folder-8", "Drafts", Some("folder-7")),
+            ("folder-9", "Elsewhere", None),
+        ] {
+            state.folders.push(FolderItem {
+                id: id.into(),
+                name: name.into(),
+                expanded: false,
+                created_at: 1,
+                updated_at: 2,
+                parent_id: parent.map(Into::into),
+                locked: false,
+            });
+        }
+        let mut model = NoteItem::new(
+            "note-10".into(),
+            "Payments".into(),
+            format!("![flow](vault://{photo})"),
+            vec!["security".into()],
+        );
+        model.parent_id = Some("folder-8".into());
+        model.versions.push(NoteVersion {
+            ts: 5,
+            content: "first draft".into(),
+        });
+        state.notes.push(model);
+        let mut other = NoteItem::new("note-11".into(), "Other".into(), "x".into(), vec![]);
+        other.parent_id = Some("folder-9".into());
+        state.notes.push(other);
+
+        let target = ShareTarget::Folder("folder-7".into());
+        let bundle = build_bundle(&state, from, &key, &target).unwrap();
+        assert_eq!(
+            (
+                bundle.folder_count(),
+                bundle.note_count(),
+                bundle.asset_count()
+            ),
+            (2, 1, 1)
+        );
+        let pass = crypto::generate_one_time_passphrase();
+        let sealed = seal_bundle(&bundle, &pass).unwrap();
+        assert!(!sealed.contains("first draft"));
+        assert!(open_bundle(&sealed, "wrong").is_err());
+        let bundle = open_bundle(&sealed, &pass.to_lowercase()).unwrap();
+
+        let mut theirs = DocState {
+            vault_id: vault::new_vault_id(),
+            ..Default::default()
+        };
+        theirs.folders.push(FolderItem {
+            id: "folder-2".into(),
+            name: "Threat Models".into(),
+            expanded: true,
+            created_at: 0,
+            updated_at: 0,
+            parent_id: None,
+            locked: false,
+        });
+        theirs.next_note_seq = 3;
+        let report = import_bundle(&mut theirs, to, &key, bundle, None).unwrap();
+        assert_eq!(report.name, "Threat Models (2)");
+        assert_eq!((report.folders, report.notes, report.assets), (2, 1, 1));
+
+        let note = theirs.notes.iter().find(|n| n.name == "Payments").unwrap();
+        assert_eq!(note.id, "note-5");
+        assert_eq!(note.tags, vec!["security".to_string()]);
+        assert_eq!(note.versions[0].content, "first draft");
+        let drafts = theirs.folders.iter().find(|f| f.name == "Drafts").unwrap();
+        assert_eq!(note.parent_id.as_deref(), Some(drafts.id.as_str()));
+        assert_eq!(drafts.parent_id.as_deref(), Some("folder-3"));
+
+        let (new_photo, meta) = theirs.assets.iter().next().unwrap();
+        assert_ne!(*new_photo, photo);
+        assert_eq!(note.content, format!("![flow](vault://{new_photo})"));
+        let binding = vault::asset_binding(&theirs.vault_id, meta);
+        let data = vault::read_asset(to, new_photo, &key, binding.as_ref()).unwrap();
+        assert_eq!(data, b"PNG");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
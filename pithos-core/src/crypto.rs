@@ -1,25 +1,63 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     Aes256Gcm, Nonce,
 };
 use base64::Engine as _;
 use rand::RngCore;
-use sha2::Sha256;
-use zeroize::Zeroize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use zeroize::{Zeroize, Zeroizing};
 
 /// PBKDF2 iteration count — 600k as recommended by OWASP (2024).
+/// Used by vaults written before the versioned envelope existed.
 pub const PBKDF2_ITERATIONS: u32 = 600_000;
+/// Argon2id memory cost (KiB) for new vaults — 64 MiB.
+pub const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+/// Argon2id pass count for new vaults.
+pub const ARGON2_ITERATIONS: u32 = 3;
+/// Argon2id lane count for new vaults.
+pub const ARGON2_PARALLELISM: u32 = 4;
+/// Envelope format version written by this build.
+pub const ENVELOPE_VERSION: u32 = 3;
+/// Last envelope version whose key is derived directly from the passphrase.
+const DIRECT_ENVELOPE_VERSION: u32 = 2;
+/// Maximum number of unlock slots in a vault header.
+pub const MAX_SLOTS: usize = 8;
+/// Cipher identifier recorded in the envelope header.
+pub const CIPHER_AES_256_GCM: &str = "aes-256-gcm";
 const SALT_LEN: usize = 16;
 const IV_LEN: usize = 12;
 const KEY_LEN: usize = 32;
+/// Size of a freshly generated key file.
+const KEYFILE_LEN: usize = 64;
+/// Recovery codes: 24 Crockford base32 characters (120 bits), shown in groups of four.
+const RECOVERY_CODE_LEN: usize = 24;
+const RECOVERY_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// Largest key file accepted — anything bigger is almost certainly the wrong file.
+const MAX_KEYFILE_LEN: u64 = 16 * 1024 * 1024;
 /// Maximum number of note version snapshots to keep per note.
 pub const MAX_VERSIONS: usize = 10;
 
+// Upper bounds on KDF parameters read from disk, so a tampered header
+// cannot make unlock allocate gigabytes or spin for hours.
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+const MAX_ARGON2_MEMORY_KIB: u32 = 1024 * 1024;
+const MAX_ARGON2_ITERATIONS: u32 = 64;
+const MAX_ARGON2_PARALLELISM: u32 = 16;
+
 #[derive(Debug)]
 pub enum CryptoError {
     EncryptionFailed(String),
     DecryptionFailed(String),
     InvalidData(String),
+    Unsupported(String),
+    KeyFileRequired,
+    /// An asset authenticated fine but belongs to another vault, asset or version.
+    BindingMismatch(String),
+    /// Plaintext found where ciphertext was expected (names what it was).
+    /// Only [`import_unencrypted_vault`] accepts it, and only on request.
+    Unencrypted(String),
 }
 
 impl std::fmt::Display for CryptoError {
@@ -28,26 +66,392 @@ impl std::fmt::Display for CryptoError {
             Self::EncryptionFailed(m) => write!(f, "Encryption failed: {m}"),
             Self::DecryptionFailed(m) => write!(f, "Decryption failed: {m}"),
             Self::InvalidData(m) => write!(f, "Invalid data: {m}"),
+            Self::Unsupported(m) => write!(f, "Unsupported format: {m}"),
+            Self::KeyFileRequired => write!(f, "This vault requires its key file"),
+            Self::BindingMismatch(m) => write!(f, "Encrypted data does not belong here: {m}"),
+            Self::Unencrypted(what) => write!(f, "The {what} is stored without encryption"),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Key derivation
+// ---------------------------------------------------------------------------
+
+/// Key derivation function and its cost parameters, as recorded in the
+/// envelope header (`"kdf": {"name": "argon2id", ...}`).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "name")]
+pub enum KdfParams {
+    #[serde(rename = "pbkdf2-sha256")]
+    Pbkdf2Sha256 { iterations: u32 },
+    #[serde(rename = "argon2id", rename_all = "camelCase")]
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+}
+
+impl Default for KdfParams {
+    /// The KDF used for newly created vaults.
+    fn default() -> Self {
+        KdfParams::Argon2id {
+            memory_kib: ARGON2_MEMORY_KIB,
+            iterations: ARGON2_ITERATIONS,
+            parallelism: ARGON2_PARALLELISM,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Parameters implied by a legacy (unversioned) envelope.
+    pub const fn legacy() -> Self {
+        KdfParams::Pbkdf2Sha256 {
+            iterations: PBKDF2_ITERATIONS,
+        }
+    }
+
+    /// Reject parameters that are unusable or unreasonably expensive.
+    pub fn validate(&self) -> Result<(), CryptoError> {
+        match *self {
+            KdfParams::Pbkdf2Sha256 { iterations } => {
+                if iterations == 0 || iterations > MAX_PBKDF2_ITERATIONS {
+                    return Err(CryptoError::Unsupported(format!(
+                        "PBKDF2 iteration count {iterations} out of range"
+                    )));
+                }
+            }
+            KdfParams::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                if memory_kib > MAX_ARGON2_MEMORY_KIB
+                    || iterations == 0
+                    || iterations > MAX_ARGON2_ITERATIONS
+                    || parallelism == 0
+                    || parallelism > MAX_ARGON2_PARALLELISM
+                {
+                    return Err(CryptoError::Unsupported(format!(
+                        "Argon2id parameters m={memory_kib} t={iterations} p={parallelism} out of range"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Derive the key from the passphrase, followed by the key file digest
+    /// when the vault requires one.
+    fn derive_key(
+        &self,
+        passphrase: &str,
+        keyfile: Option<&KeyFile>,
+        salt: &[u8],
+    ) -> Result<Zeroizing<[u8; KEY_LEN]>, CryptoError> {
+        self.validate()?;
+        let mut secret = Zeroizing::new(passphrase.as_bytes().to_vec());
+        if let Some(keyfile) = keyfile {
+            secret.extend_from_slice(&*keyfile.digest);
+        }
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        match *self {
+            KdfParams::Pbkdf2Sha256 { iterations } => {
+                pbkdf2::pbkdf2_hmac::<Sha256>(&secret, salt, iterations, &mut *key);
+            }
+            KdfParams::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params =
+                    argon2::Params::new(memory_kib, iterations, parallelism, Some(KEY_LEN))
+                        .map_err(|e| CryptoError::Unsupported(e.to_string()))?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(&secret, salt, &mut *key)
+                    .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+            }
+        }
+        Ok(key)
+    }
+}
+
+/// Second unlock factor: a local file (e.g. on a USB stick) whose SHA-256
+/// digest is mixed into key derivation after the passphrase. Only the digest
+/// is kept in memory; envelopes record that a key file is needed, never which.
+#[derive(Clone)]
+pub struct KeyFile {
+    digest: Zeroizing<[u8; 32]>,
+}
+
+impl std::fmt::Debug for KeyFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("KeyFile([REDACTED])")
+    }
+}
+
+impl KeyFile {
+    /// Use arbitrary file contents as a key file.
+    pub fn from_bytes(contents: &[u8]) -> Result<Self, CryptoError> {
+        if contents.is_empty() {
+            return Err(CryptoError::InvalidData("Key file is empty".into()));
         }
+        let mut digest = Zeroizing::new([0u8; 32]);
+        digest.copy_from_slice(&Sha256::digest(contents));
+        Ok(KeyFile { digest })
+    }
+
+    /// Read and hash a key file from disk.
+    pub fn read(path: &Path) -> Result<Self, CryptoError> {
+        let too_large = std::fs::metadata(path).is_ok_and(|m| m.len() > MAX_KEYFILE_LEN);
+        if too_large {
+            return Err(CryptoError::InvalidData("Key file is too large".into()));
+        }
+        let contents = Zeroizing::new(
+            std::fs::read(path)
+                .map_err(|e| CryptoError::InvalidData(format!("Cannot read key file: {e}")))?,
+        );
+        Self::from_bytes(&contents)
+    }
+
+    /// Random contents for a new key file; the caller writes them to disk.
+    pub fn generate() -> Zeroizing<Vec<u8>> {
+        let mut contents = Zeroizing::new(vec![0u8; KEYFILE_LEN]);
+        rand::thread_rng().fill_bytes(&mut contents);
+        contents
     }
 }
 
-fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; KEY_LEN] {
-    let mut key = [0u8; KEY_LEN];
-    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ITERATIONS, &mut key);
-    key
+/// What unlocks a key slot.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SlotKind {
+    /// The vault passphrase, optionally combined with a key file.
+    Passphrase,
+    /// A generated one-time-readable recovery code.
+    RecoveryCode,
+    /// A key file on its own.
+    KeyFile,
 }
 
-/// A cached encryption key — derives the expensive PBKDF2 key once, reuses for all saves.
-/// The salt is fixed per session; a fresh random IV is generated for each encryption.
-/// The passphrase is retained so assets encrypted with different salts can be decrypted
-/// by re-deriving the key from the asset's stored salt.
+/// Public description of a key slot (no key material).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotInfo {
+    pub kind: SlotKind,
+    pub kdf: KdfParams,
+    /// A passphrase slot that also needs the key file.
+    pub keyfile: bool,
+}
+
+/// One way of unlocking a v3 vault: the data key, wrapped (AES-256-GCM) under
+/// a key derived from the slot's secret. Stored as-is in the vault header.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct KeySlot {
+    #[serde(rename = "type")]
+    kind: SlotKind,
+    kdf: KdfParams,
+    salt: String,
+    iv: String,
+    wrapped_key: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    key_file: bool,
+}
+
+impl KeySlot {
+    /// Wrap `data_key` under `secret` (plus key file) with the default KDF (expensive).
+    fn wrap(
+        data_key: &[u8; KEY_LEN],
+        kind: SlotKind,
+        secret: &str,
+        keyfile: Option<&KeyFile>,
+    ) -> Result<Self, CryptoError> {
+        Self::wrap_with(data_key, kind, secret, keyfile, KdfParams::default())
+    }
+
+    fn wrap_with(
+        data_key: &[u8; KEY_LEN],
+        kind: SlotKind,
+        secret: &str,
+        keyfile: Option<&KeyFile>,
+        kdf: KdfParams,
+    ) -> Result<Self, CryptoError> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut iv = [0u8; IV_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut iv);
+        let wrapping_key = kdf.derive_key(secret, keyfile, &salt)?;
+        let wrapped = Aes256Gcm::new_from_slice(&*wrapping_key)
+            .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?
+            .encrypt(Nonce::from_slice(&iv), data_key.as_slice())
+            .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+        let b64 = &base64::engine::general_purpose::STANDARD;
+        Ok(KeySlot {
+            kind,
+            kdf,
+            salt: b64.encode(salt),
+            iv: b64.encode(iv),
+            wrapped_key: b64.encode(wrapped),
+            key_file: keyfile.is_some() && kind == SlotKind::Passphrase,
+        })
+    }
+
+    /// Recover the data key (expensive). Fails with `DecryptionFailed` on a wrong secret.
+    fn unwrap(
+        &self,
+        secret: &str,
+        keyfile: Option<&KeyFile>,
+    ) -> Result<Zeroizing<[u8; KEY_LEN]>, CryptoError> {
+        let salt: [u8; SALT_LEN] = fixed("salt", &b64_decode("salt", &self.salt)?)?;
+        let iv: [u8; IV_LEN] = fixed("iv", &b64_decode("iv", &self.iv)?)?;
+        let wrapped = b64_decode("wrappedKey", &self.wrapped_key)?;
+        let wrapping_key = self.kdf.derive_key(secret, keyfile, &salt)?;
+        let data_key = Zeroizing::new(
+            Aes256Gcm::new_from_slice(&*wrapping_key)
+                .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?
+                .decrypt(Nonce::from_slice(&iv), wrapped.as_slice())
+                .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?,
+        );
+        Ok(Zeroizing::new(fixed("wrappedKey", &data_key)?))
+    }
+
+    fn info(&self) -> SlotInfo {
+        SlotInfo {
+            kind: self.kind,
+            kdf: self.kdf,
+            keyfile: self.key_file,
+        }
+    }
+}
+
+/// Canonical form of a recovery code (`XXXX-XXXX-…`), or `None` if `input`
+/// can't be one. Case, spacing and dashes are ignored; O/I/L read as 0/1/1.
+fn normalize_recovery_code(input: &str) -> Option<Zeroizing<String>> {
+    let mut chars = Zeroizing::new(String::with_capacity(RECOVERY_CODE_LEN));
+    for c in input.chars() {
+        let c = match c.to_ascii_uppercase() {
+            '-' | ' ' => continue,
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        };
+        if !RECOVERY_ALPHABET.contains(&(c as u8)) || chars.len() == RECOVERY_CODE_LEN {
+            return None;
+        }
+        chars.push(c);
+    }
+    if chars.len() != RECOVERY_CODE_LEN {
+        return None;
+    }
+    let mut code = Zeroizing::new(String::with_capacity(RECOVERY_CODE_LEN + 5));
+    for (i, c) in chars.chars().enumerate() {
+        if i > 0 && i % 4 == 0 {
+            code.push('-');
+        }
+        code.push(c);
+    }
+    Some(code)
+}
+
+/// A random code in the recovery code format.
+fn random_code() -> Zeroizing<String> {
+    let mut raw = Zeroizing::new(String::with_capacity(RECOVERY_CODE_LEN));
+    let mut rng = rand::thread_rng();
+    for _ in 0..RECOVERY_CODE_LEN {
+        let i = (rng.next_u32() as usize) % RECOVERY_ALPHABET.len();
+        raw.push(RECOVERY_ALPHABET[i] as char);
+    }
+    normalize_recovery_code(&raw).expect("generated from the recovery alphabet")
+}
+
+/// A passphrase for a secret that is handed over once, such as a shared
+/// bundle's: 120 random bits written like a recovery code.
+pub fn generate_one_time_passphrase() -> Zeroizing<String> {
+    random_code()
+}
+
+/// `input` in the canonical form of [`generate_one_time_passphrase`] if it
+/// was typed as one, with other case or spacing; otherwise unchanged.
+pub fn normalize_one_time_passphrase(input: &str) -> Zeroizing<String> {
+    normalize_recovery_code(input).unwrap_or_else(|| Zeroizing::new(input.to_string()))
+}
+
+/// Try each slot that the supplied credentials could open. Returns the data
+/// key and the index of the slot that opened it.
+fn open_slots(
+    slots: &[KeySlot],
+    passphrase: &str,
+    keyfile: Option<&KeyFile>,
+) -> Result<(Zeroizing<[u8; KEY_LEN]>, usize), CryptoError> {
+    let recovery_code = normalize_recovery_code(passphrase);
+    let mut attempted = false;
+    let mut keyfile_slot_skipped = false;
+    for (index, slot) in slots.iter().enumerate() {
+        let (secret, slot_keyfile) = match slot.kind {
+            SlotKind::Passphrase if slot.key_file => match keyfile {
+                Some(k) => (passphrase, Some(k)),
+                None => {
+                    keyfile_slot_skipped = true;
+                    continue;
+                }
+            },
+            SlotKind::Passphrase if !passphrase.is_empty() => (passphrase, None),
+            SlotKind::Passphrase => continue,
+            SlotKind::RecoveryCode => match &recovery_code {
+                Some(code) => (code.as_str(), None),
+                None => continue,
+            },
+            SlotKind::KeyFile => match keyfile {
+                Some(k) => ("", Some(k)),
+                None => {
+                    keyfile_slot_skipped = true;
+                    continue;
+                }
+            },
+        };
+        attempted = true;
+        match slot.unwrap(secret, slot_keyfile) {
+            Ok(key) => return Ok((key, index)),
+            Err(CryptoError::DecryptionFailed(_)) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    if !attempted && keyfile_slot_skipped {
+        return Err(CryptoError::KeyFileRequired);
+    }
+    Err(CryptoError::DecryptionFailed(
+        "Wrong passphrase or corrupted data".into(),
+    ))
+}
+
+/// A cached encryption key — runs the expensive KDF once, reuses it for all saves.
+///
+/// New vaults use a random data key wrapped by one or more [`KeySlot`]s, so
+/// changing the passphrase or adding a recovery code only rewrites the vault
+/// header. Vaults that predate key wrapping keep a key derived directly from
+/// the passphrase until they are upgraded.
+/// The passphrase (and key file) are retained so assets written by older
+/// versions can still be decrypted by re-deriving their key.
 /// Key material is securely zeroed when dropped.
 #[derive(Clone)]
 pub struct CachedKey {
-    salt: [u8; SALT_LEN],
     key: [u8; KEY_LEN],
+    scheme: KeyScheme,
     passphrase: String,
+    keyfile: Option<KeyFile>,
+    unlocked_by: SlotKind,
+}
+
+#[derive(Clone)]
+enum KeyScheme {
+    /// v1/v2: `key` is derived from the passphrase with a fixed per-session salt.
+    Direct {
+        kdf: KdfParams,
+        salt: [u8; SALT_LEN],
+    },
+    /// v3: `key` is a random data key, wrapped by each slot.
+    Wrapped { slots: Vec<KeySlot> },
 }
 
 impl Drop for CachedKey {
@@ -59,221 +463,910 @@ impl Drop for CachedKey {
 
 impl std::fmt::Debug for CachedKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("CachedKey")
-            .field("key", &"[REDACTED]")
-            .field("salt", &self.salt)
-            .finish()
+        let mut s = f.debug_struct("CachedKey");
+        s.field("key", &"[REDACTED]");
+        match &self.scheme {
+            KeyScheme::Direct { kdf, salt } => s.field("salt", salt).field("kdf", kdf),
+            KeyScheme::Wrapped { slots } => s.field("slots", &slots.len()),
+        };
+        s.field("keyfile", &self.keyfile.is_some()).finish()
     }
 }
 
 impl CachedKey {
-    /// Derive and cache the key from a passphrase (expensive — call once at unlock/create time).
-    pub fn derive(passphrase: &str) -> Self {
+    /// Create a new random data key protected by `passphrase`
+    /// (expensive — call once at create time).
+    pub fn derive(passphrase: &str) -> Result<Self, CryptoError> {
+        Self::derive_with_keyfile(passphrase, None)
+    }
+
+    /// Derive a key directly from the passphrase with explicit KDF parameters
+    /// and a fresh salt — the scheme used before key wrapping. Vaults sealed
+    /// with such a key are written as version 2 envelopes.
+    pub fn derive_with(passphrase: &str, kdf: KdfParams) -> Result<Self, CryptoError> {
         let mut salt = [0u8; SALT_LEN];
         rand::thread_rng().fill_bytes(&mut salt);
-        let key = derive_key(passphrase, &salt);
-        CachedKey {
+        let key = kdf.derive_key(passphrase, None, &salt)?;
+        Ok(Self::from_raw(*key, salt, kdf, passphrase))
+    }
+
+    /// Create a new random data key protected by a passphrase slot that
+    /// optionally also requires a key file.
+    pub fn derive_with_keyfile(
+        passphrase: &str,
+        keyfile: Option<KeyFile>,
+    ) -> Result<Self, CryptoError> {
+        let mut key = [0u8; KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+        let slot = KeySlot::wrap(&key, SlotKind::Passphrase, passphrase, keyfile.as_ref());
+        let cached = CachedKey {
             key,
-            salt,
+            scheme: KeyScheme::Wrapped { slots: vec![slot?] },
             passphrase: passphrase.to_string(),
-        }
+            keyfile,
+            unlocked_by: SlotKind::Passphrase,
+        };
+        key.zeroize();
+        Ok(cached)
     }
 
-    /// Construct from already-derived key material (zero cost).
-    pub fn from_raw(key: [u8; KEY_LEN], salt: [u8; SALT_LEN], passphrase: &str) -> Self {
+    /// Construct from already-derived (direct, pre-key-wrapping) key material (zero cost).
+    pub fn from_raw(
+        key: [u8; KEY_LEN],
+        salt: [u8; SALT_LEN],
+        kdf: KdfParams,
+        passphrase: &str,
+    ) -> Self {
         CachedKey {
             key,
-            salt,
+            scheme: KeyScheme::Direct { kdf, salt },
             passphrase: passphrase.to_string(),
+            keyfile: None,
+            unlocked_by: SlotKind::Passphrase,
+        }
+    }
+
+    /// KDF parameters protecting the passphrase.
+    pub fn kdf(&self) -> KdfParams {
+        match &self.scheme {
+            KeyScheme::Direct { kdf, .. } => *kdf,
+            KeyScheme::Wrapped { slots } => slots
+                .iter()
+                .find(|s| s.kind == SlotKind::Passphrase)
+                .map_or_else(KdfParams::default, |s| s.kdf),
+        }
+    }
+
+    /// The key file used to unlock, if any.
+    pub fn keyfile(&self) -> Option<&KeyFile> {
+        self.keyfile.as_ref()
+    }
+
+    /// True when unlocking with the passphrase also needs the key file.
+    pub fn passphrase_uses_keyfile(&self) -> bool {
+        match &self.scheme {
+            KeyScheme::Direct { .. } => self.keyfile.is_some(),
+            KeyScheme::Wrapped { slots } => slots
+                .iter()
+                .any(|s| s.kind == SlotKind::Passphrase && s.key_file),
+        }
+    }
+
+    /// True for vaults using a wrapped data key (envelope v3 and later).
+    pub fn uses_key_slots(&self) -> bool {
+        matches!(self.scheme, KeyScheme::Wrapped { .. })
+    }
+
+    /// The vault's unlock slots, in header order. Empty for direct keys.
+    pub fn slots(&self) -> Vec<SlotInfo> {
+        match &self.scheme {
+            KeyScheme::Direct { .. } => Vec::new(),
+            KeyScheme::Wrapped { slots } => slots.iter().map(KeySlot::info).collect(),
+        }
+    }
+
+    /// True when both keys seal data with the same data key, so switching
+    /// between them only changes the vault header.
+    pub fn same_data_key(&self, other: &CachedKey) -> bool {
+        self.uses_key_slots() && other.uses_key_slots() && self.key == other.key
+    }
+
+    /// True when the vault predates key wrapping, or has a slot on other
+    /// than the current default KDF parameters that [`CachedKey::upgraded`]
+    /// can re-wrap: passphrase slots when unlocked with the passphrase, key
+    /// file slots when a key file is at hand, and the recovery code.
+    pub fn needs_upgrade(&self) -> bool {
+        match &self.scheme {
+            KeyScheme::Direct { .. } => true,
+            KeyScheme::Wrapped { slots } => slots
+                .iter()
+                .any(|s| s.kdf != KdfParams::default() && self.can_rewrap(s.kind)),
+        }
+    }
+
+    fn can_rewrap(&self, kind: SlotKind) -> bool {
+        match kind {
+            SlotKind::Passphrase => self.unlocked_by == SlotKind::Passphrase,
+            SlotKind::RecoveryCode => true,
+            SlotKind::KeyFile => self.keyfile.is_some(),
+        }
+    }
+
+    /// A replacement key in the current format for the same passphrase
+    /// (expensive). Direct keys get a new wrapped data key, so every asset
+    /// must be re-encrypted. Wrapped keys keep their data key and re-wrap
+    /// each outdated slot they can (see [`CachedKey::needs_upgrade`]); key
+    /// file slots the key file at hand doesn't open stay as they are. The
+    /// recovery code is never stored, so an outdated one is replaced by a new
+    /// code, returned to be shown once.
+    pub fn upgraded(&self) -> Result<(Self, Option<Zeroizing<String>>), CryptoError> {
+        let KeyScheme::Wrapped { slots } = &self.scheme else {
+            let upgraded = Self::derive_with_keyfile(&self.passphrase, self.keyfile.clone())?;
+            return Ok((upgraded, None));
+        };
+        let outdated = |kind| {
+            slots
+                .iter()
+                .any(|s| s.kind == kind && s.kdf != KdfParams::default())
+        };
+        let mut upgraded = self.clone();
+        if outdated(SlotKind::Passphrase) && self.can_rewrap(SlotKind::Passphrase) {
+            let keyfile = self
+                .passphrase_uses_keyfile()
+                .then(|| self.keyfile.clone())
+                .flatten();
+            upgraded.set_passphrase(&self.passphrase, keyfile)?;
+        }
+        if let Some(keyfile) = &self.keyfile {
+            for slot in upgraded.slots_mut()?.iter_mut() {
+                if slot.kind == SlotKind::KeyFile
+                    && slot.kdf != KdfParams::default()
+                    && slot.unwrap("", Some(keyfile)).is_ok()
+                {
+                    *slot = KeySlot::wrap(&self.key, SlotKind::KeyFile, "", Some(keyfile))?;
+                }
+            }
+        }
+        let code = if outdated(SlotKind::RecoveryCode) {
+            Some(upgraded.generate_recovery_code()?)
+        } else {
+            None
+        };
+        Ok((upgraded, code))
+    }
+
+    fn slots_mut(&mut self) -> Result<&mut Vec<KeySlot>, CryptoError> {
+        match &mut self.scheme {
+            KeyScheme::Wrapped { slots } => Ok(slots),
+            KeyScheme::Direct { .. } => Err(CryptoError::Unsupported(
+                "unlock methods need an upgraded vault".into(),
+            )),
+        }
+    }
+
+    fn push_slot(&mut self, slot: KeySlot) -> Result<(), CryptoError> {
+        let slots = self.slots_mut()?;
+        if slots.len() >= MAX_SLOTS {
+            return Err(CryptoError::Unsupported(format!(
+                "at most {MAX_SLOTS} unlock methods"
+            )));
+        }
+        slots.push(slot);
+        Ok(())
+    }
+
+    /// Replace the passphrase slot(s) with one for `passphrase` (plus an
+    /// optional key file). The data key is unchanged, so only the vault header
+    /// needs rewriting (expensive).
+    pub fn set_passphrase(
+        &mut self,
+        passphrase: &str,
+        keyfile: Option<KeyFile>,
+    ) -> Result<(), CryptoError> {
+        let slot = KeySlot::wrap(
+            &self.key,
+            SlotKind::Passphrase,
+            passphrase,
+            keyfile.as_ref(),
+        )?;
+        let slots = self.slots_mut()?;
+        slots.retain(|s| s.kind != SlotKind::Passphrase);
+        slots.insert(0, slot);
+        self.passphrase.zeroize();
+        self.passphrase = passphrase.to_string();
+        self.keyfile = keyfile;
+        self.unlocked_by = SlotKind::Passphrase;
+        Ok(())
+    }
+
+    /// Generate a recovery code and add a slot for it, replacing any previous
+    /// recovery code. The code is shown to the user once and never stored.
+    pub fn generate_recovery_code(&mut self) -> Result<Zeroizing<String>, CryptoError> {
+        let code = random_code();
+        let slot = KeySlot::wrap(&self.key, SlotKind::RecoveryCode, &code, None)?;
+        self.slots_mut()?
+            .retain(|s| s.kind != SlotKind::RecoveryCode);
+        self.push_slot(slot)?;
+        Ok(code)
+    }
+
+    /// Add a slot that unlocks the vault with `keyfile` alone.
+    pub fn add_keyfile_slot(&mut self, keyfile: &KeyFile) -> Result<(), CryptoError> {
+        let slot = KeySlot::wrap(&self.key, SlotKind::KeyFile, "", Some(keyfile))?;
+        self.push_slot(slot)
+    }
+
+    /// Remove the slot at `index` (as listed by [`CachedKey::slots`]).
+    /// The last passphrase slot can't be removed.
+    pub fn remove_slot(&mut self, index: usize) -> Result<(), CryptoError> {
+        let slots = self.slots_mut()?;
+        let Some(slot) = slots.get(index) else {
+            return Err(CryptoError::InvalidData(format!("no unlock slot {index}")));
+        };
+        let passphrase_slots = slots
+            .iter()
+            .filter(|s| s.kind == SlotKind::Passphrase)
+            .count();
+        if slot.kind == SlotKind::Passphrase && passphrase_slots == 1 {
+            return Err(CryptoError::Unsupported(
+                "the vault must keep a passphrase".into(),
+            ));
         }
+        slots.remove(index);
+        Ok(())
+    }
+
+    /// Key for a parsed envelope — the data key for v3 envelopes; for older
+    /// ones the cached key when kdf/salt match, otherwise re-derived from the
+    /// retained passphrase (and key file, if it was sealed with one).
+    fn key_for(&self, sealed: &Sealed) -> Result<Zeroizing<[u8; KEY_LEN]>, CryptoError> {
+        let (kdf, salt, needs_keyfile) = match &sealed.source {
+            KeySource::DataKey { .. } => {
+                if !self.uses_key_slots() {
+                    return Err(CryptoError::DecryptionFailed(
+                        "sealed with a wrapped data key".into(),
+                    ));
+                }
+                return Ok(Zeroizing::new(self.key));
+            }
+            KeySource::Direct { kdf, salt, keyfile } => (kdf, salt, *keyfile),
+        };
+        if let KeyScheme::Direct {
+            kdf: own_kdf,
+            salt: own_salt,
+        } = &self.scheme
+        {
+            if kdf == own_kdf && salt == own_salt && needs_keyfile == self.keyfile.is_some() {
+                return Ok(Zeroizing::new(self.key));
+            }
+        }
+        let keyfile = match (needs_keyfile, &self.keyfile) {
+            (false, _) => None,
+            (true, Some(keyfile)) => Some(keyfile),
+            (true, None) => return Err(CryptoError::KeyFileRequired),
+        };
+        kdf.derive_key(&self.passphrase, keyfile, salt)
     }
 }
 
-/// Fast encryption using a pre-derived cached key. No PBKDF2 on each call.
-pub fn encrypt_vault_fast(plaintext: &str, cached: &CachedKey) -> Result<String, CryptoError> {
-    encrypt_with_key(plaintext, &cached.key, &cached.salt)
+// ---------------------------------------------------------------------------
+// Envelope format
+// ---------------------------------------------------------------------------
+//
+// Legacy (v1):  {"encrypted":true,"data":"<b64 salt|iv|ciphertext>"}
+//               — implicitly PBKDF2-SHA256 with PBKDF2_ITERATIONS.
+// v2:           {"encrypted":true,"version":2,"cipher":"aes-256-gcm",
+//                "kdf":{"name":"argon2id",...},"salt":"<b64>","iv":"<b64>",
+//                "data":"<b64 ciphertext>"}
+//               — key derived directly from the passphrase, plus
+//               "keyFile":true when a key file is mixed into the KDF.
+// Current (v3): {"encrypted":true,"version":3,"cipher":"aes-256-gcm",
+//                "iv":"<b64>","data":"<b64 ciphertext>",
+//                "slots":[{"type":"passphrase","kdf":{...},"salt":"<b64>",
+//                          "iv":"<b64>","wrappedKey":"<b64>"}, ...]}
+//               — sealed with a random data key that each slot wraps.
+//               Asset envelopes omit "slots" and may carry a "binding"
+//               ({"vaultId","assetId","version"}) that is authenticated as
+//               AEAD associated data.
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Envelope {
+    encrypted: bool,
+    version: u32,
+    cipher: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kdf: Option<KdfParams>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    iv: String,
+    data: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    key_file: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    slots: Vec<KeySlot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    binding: Option<AssetBinding>,
 }
 
-fn encrypt_with_key(
-    plaintext: &str,
-    key: &[u8; KEY_LEN],
-    salt: &[u8; SALT_LEN],
-) -> Result<String, CryptoError> {
-    let mut iv = [0u8; IV_LEN];
-    rand::thread_rng().fill_bytes(&mut iv);
+/// What an asset ciphertext belongs to. Stored in the envelope header and
+/// authenticated with the ciphertext, so a file moved to another vault or
+/// asset ID, or replaced by an older version of itself, is detected.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetBinding {
+    pub vault_id: String,
+    pub asset_id: String,
+    pub version: u64,
+    /// What sort of blob this is; absent (an asset) in older envelopes.
+    #[serde(default, skip_serializing_if = "BlobKind::is_asset")]
+    pub kind: BlobKind,
+}
 
-    let cipher =
-        Aes256Gcm::new_from_slice(key).map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
-    let nonce = Nonce::from_slice(&iv);
-    let ciphertext = cipher
-        .encrypt(nonce, plaintext.as_bytes())
-        .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+/// The kinds of encrypted blob a vault folder holds besides vault.json.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BlobKind {
+    #[default]
+    Asset,
+    Note,
+    Journal,
+    SearchIndex,
+}
 
-    let mut combined = Vec::with_capacity(SALT_LEN + IV_LEN + ciphertext.len());
-    combined.extend_from_slice(salt);
-    combined.extend_from_slice(&iv);
-    combined.extend_from_slice(&ciphertext);
+impl BlobKind {
+    fn is_asset(&self) -> bool {
+        *self == Self::Asset
+    }
 
-    let b64 = base64::engine::general_purpose::STANDARD.encode(&combined);
-    Ok(format!(r#"{{"encrypted":true,"data":"{b64}"}}"#))
+    fn name(self) -> &'static str {
+        match self {
+            Self::Asset => "asset",
+            Self::Note => "note",
+            Self::Journal => "journal",
+            Self::SearchIndex => "search-index",
+        }
+    }
 }
 
-/// Decrypt vault and derive a fresh CachedKey for future saves.
-pub fn decrypt_vault_returning_key(
-    encrypted_json: &str,
-    passphrase: &str,
-) -> Result<(String, CachedKey), CryptoError> {
-    let envelope: serde_json::Value = serde_json::from_str(encrypted_json)
-        .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+impl AssetBinding {
+    fn associated_data(&self) -> Vec<u8> {
+        format!(
+            "pithos-{}\0{}\0{}\0{}",
+            self.kind.name(),
+            self.vault_id,
+            self.asset_id,
+            self.version
+        )
+        .into_bytes()
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// A parsed, still-encrypted payload from any envelope version.
+struct Sealed {
+    version: u32,
+    source: KeySource,
+    iv: [u8; IV_LEN],
+    ciphertext: Vec<u8>,
+    binding: Option<AssetBinding>,
+}
+
+/// Where the key for a sealed payload comes from.
+enum KeySource {
+    /// v1/v2: derived from the passphrase (and key file) with this kdf/salt.
+    Direct {
+        kdf: KdfParams,
+        salt: [u8; SALT_LEN],
+        keyfile: bool,
+    },
+    /// v3: the vault's data key; vault headers carry the slots that wrap it.
+    DataKey { slots: Vec<KeySlot> },
+}
+
+fn b64_decode(field: &str, value: &str) -> Result<Vec<u8>, CryptoError> {
+    base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .map_err(|e| CryptoError::InvalidData(format!("Bad base64 in '{field}': {e}")))
+}
 
-    let is_encrypted = envelope
+fn fixed<const N: usize>(field: &str, bytes: &[u8]) -> Result<[u8; N], CryptoError> {
+    bytes
+        .try_into()
+        .map_err(|_| CryptoError::InvalidData(format!("'{field}' must be {N} bytes")))
+}
+
+fn is_encrypted_envelope(envelope: &serde_json::Value) -> bool {
+    envelope
         .get("encrypted")
         .and_then(|v| v.as_bool())
-        .unwrap_or(false);
+        .unwrap_or(false)
+}
+
+/// Parse an encrypted envelope of any known version.
+fn parse_sealed(envelope: &serde_json::Value) -> Result<Sealed, CryptoError> {
+    let Some(version) = envelope.get("version") else {
+        return parse_legacy(envelope);
+    };
+    let version = version
+        .as_u64()
+        .ok_or_else(|| CryptoError::InvalidData("'version' is not a number".into()))?;
+    if version != u64::from(DIRECT_ENVELOPE_VERSION) && version != u64::from(ENVELOPE_VERSION) {
+        return Err(CryptoError::Unsupported(format!(
+            "envelope version {version} (this build reads up to {ENVELOPE_VERSION})"
+        )));
+    }
+
+    let env: Envelope = serde_json::from_value(envelope.clone())
+        .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    if env.cipher != CIPHER_AES_256_GCM {
+        return Err(CryptoError::Unsupported(format!("cipher '{}'", env.cipher)));
+    }
+
+    let source = if env.version == DIRECT_ENVELOPE_VERSION {
+        let kdf = env
+            .kdf
+            .ok_or_else(|| CryptoError::InvalidData("Missing 'kdf' field".into()))?;
+        kdf.validate()?;
+        let salt = env
+            .salt
+            .ok_or_else(|| CryptoError::InvalidData("Missing 'salt' field".into()))?;
+        KeySource::Direct {
+            kdf,
+            salt: fixed("salt", &b64_decode("salt", &salt)?)?,
+            keyfile: env.key_file,
+        }
+    } else {
+        if env.slots.len() > MAX_SLOTS {
+            return Err(CryptoError::Unsupported(format!(
+                "{} unlock slots (at most {MAX_SLOTS})",
+                env.slots.len()
+            )));
+        }
+        for slot in &env.slots {
+            slot.kdf.validate()?;
+        }
+        KeySource::DataKey { slots: env.slots }
+    };
 
-    if !is_encrypted {
-        let plaintext = envelope
-            .get("data")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string())
-            .or_else(|| serde_json::to_string(&envelope).ok())
-            .ok_or_else(|| CryptoError::InvalidData("No data field".into()))?;
-        let cached = CachedKey::derive(passphrase);
-        return Ok((plaintext, cached));
+    let ciphertext = b64_decode("data", &env.data)?;
+    if ciphertext.is_empty() {
+        return Err(CryptoError::InvalidData("Data too short".into()));
     }
+    Ok(Sealed {
+        version: env.version,
+        source,
+        iv: fixed("iv", &b64_decode("iv", &env.iv)?)?,
+        ciphertext,
+        binding: env.binding,
+    })
+}
 
+fn parse_legacy(envelope: &serde_json::Value) -> Result<Sealed, CryptoError> {
     let data_b64 = envelope
         .get("data")
         .and_then(|v| v.as_str())
         .ok_or_else(|| CryptoError::InvalidData("Missing 'data' field".into()))?;
-
-    let combined = base64::engine::general_purpose::STANDARD
-        .decode(data_b64)
-        .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    let combined = b64_decode("data", data_b64)?;
 
     if combined.len() < SALT_LEN + IV_LEN + 1 {
         return Err(CryptoError::InvalidData("Data too short".into()));
     }
 
-    let salt = &combined[..SALT_LEN];
-    let iv = &combined[SALT_LEN..SALT_LEN + IV_LEN];
-    let ciphertext = &combined[SALT_LEN + IV_LEN..];
-
-    let key = derive_key(passphrase, salt);
-    let cipher = Aes256Gcm::new_from_slice(&key)
-        .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
-    let nonce = Nonce::from_slice(iv);
-    let plaintext = cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|e| CryptoError::DecryptionFailed(format!("Wrong passphrase or corrupted data: {e}")))?;
-
-    let plaintext_str =
-        String::from_utf8(plaintext).map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
-
-    // Reuse the key+salt that were just successfully used for decryption,
-    // instead of calling CachedKey::derive() again (which would run another
-    // expensive 600k-iteration PBKDF2 derivation with a new random salt).
-    let mut salt_arr = [0u8; SALT_LEN];
-    salt_arr.copy_from_slice(salt);
-    let cached = CachedKey::from_raw(key, salt_arr, passphrase);
-
-    Ok((plaintext_str, cached))
+    Ok(Sealed {
+        version: 1,
+        source: KeySource::Direct {
+            kdf: KdfParams::legacy(),
+            salt: fixed("salt", &combined[..SALT_LEN])?,
+            keyfile: false,
+        },
+        iv: fixed("iv", &combined[SALT_LEN..SALT_LEN + IV_LEN])?,
+        ciphertext: combined[SALT_LEN + IV_LEN..].to_vec(),
+        binding: None,
+    })
 }
 
-/// Encrypt binary asset data using a pre-derived cached key.
-pub fn encrypt_asset(data: &[u8], cached: &CachedKey) -> Result<String, CryptoError> {
+/// Encrypt `plaintext` under the cached key and wrap it in an envelope —
+/// v3 (with the key slots if `with_slots`) for wrapped keys, v2 for direct ones.
+fn seal(
+    plaintext: &[u8],
+    cached: &CachedKey,
+    with_slots: bool,
+    binding: Option<&AssetBinding>,
+) -> Result<String, CryptoError> {
     let mut iv = [0u8; IV_LEN];
     rand::thread_rng().fill_bytes(&mut iv);
 
     let cipher = Aes256Gcm::new_from_slice(&cached.key)
         .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
     let nonce = Nonce::from_slice(&iv);
+    let aad = binding
+        .map(AssetBinding::associated_data)
+        .unwrap_or_default();
     let ciphertext = cipher
-        .encrypt(nonce, data)
+        .encrypt(
+            nonce,
+            Payload {
+                msg: plaintext,
+                aad: &aad,
+            },
+        )
         .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
 
-    let mut combined = Vec::with_capacity(SALT_LEN + IV_LEN + ciphertext.len());
-    combined.extend_from_slice(&cached.salt);
-    combined.extend_from_slice(&iv);
-    combined.extend_from_slice(&ciphertext);
+    let b64 = &base64::engine::general_purpose::STANDARD;
+    let mut envelope = Envelope {
+        encrypted: true,
+        version: ENVELOPE_VERSION,
+        cipher: CIPHER_AES_256_GCM.to_string(),
+        kdf: None,
+        salt: None,
+        iv: b64.encode(iv),
+        data: b64.encode(&ciphertext),
+        key_file: false,
+        slots: Vec::new(),
+        binding: binding.cloned(),
+    };
+    match &cached.scheme {
+        KeyScheme::Direct { kdf, salt } => {
+            envelope.version = DIRECT_ENVELOPE_VERSION;
+            envelope.kdf = Some(*kdf);
+            envelope.salt = Some(b64.encode(salt));
+            envelope.key_file = cached.keyfile.is_some();
+        }
+        KeyScheme::Wrapped { slots } => {
+            if with_slots {
+                envelope.slots = slots.clone();
+            }
+        }
+    }
+    serde_json::to_string(&envelope).map_err(|e| CryptoError::EncryptionFailed(e.to_string()))
+}
+
+fn open(sealed: &Sealed, key: &[u8; KEY_LEN]) -> Result<Vec<u8>, aes_gcm::Error> {
+    let cipher = Aes256Gcm::new_from_slice(key).map_err(|_| aes_gcm::Error)?;
+    let aad = sealed
+        .binding
+        .as_ref()
+        .map(AssetBinding::associated_data)
+        .unwrap_or_default();
+    cipher.decrypt(
+        Nonce::from_slice(&sealed.iv),
+        Payload {
+            msg: &sealed.ciphertext,
+            aad: &aad,
+        },
+    )
+}
 
-    let b64 = base64::engine::general_purpose::STANDARD.encode(&combined);
-    Ok(format!(r#"{{"encrypted":true,"data":"{b64}"}}"#))
+/// Header fields of an encrypted envelope — readable without the passphrase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvelopeInfo {
+    pub version: u32,
+    pub cipher: String,
+    /// KDF of a directly derived (v1/v2) key; `None` for wrapped data keys.
+    pub kdf: Option<KdfParams>,
+    /// Unlock slots of a v3 vault header (empty for assets and older versions).
+    pub slots: Vec<SlotInfo>,
+    /// Some way of unlocking this vault involves a key file.
+    pub keyfile: bool,
+    /// Claimed owner of an asset; only trustworthy once the asset decrypts.
+    pub binding: Option<AssetBinding>,
 }
 
-/// Decrypt binary asset bytes using a cached key.
-pub fn decrypt_asset(data: &[u8], cached: &CachedKey) -> Result<Vec<u8>, CryptoError> {
-    let Ok(as_text) = std::str::from_utf8(data) else {
-        return Ok(data.to_vec());
-    };
+impl EnvelopeInfo {
+    /// True when written in the current format with the current default KDF parameters.
+    pub fn is_current(&self) -> bool {
+        self.version == ENVELOPE_VERSION
+            && self.cipher == CIPHER_AES_256_GCM
+            && self.slots.iter().all(|s| s.kdf == KdfParams::default())
+    }
+}
 
-    let Ok(envelope) = serde_json::from_str::<serde_json::Value>(as_text) else {
-        return Ok(data.to_vec());
+/// Read the header of a vault or asset envelope without decrypting it.
+/// Returns `Ok(None)` for data that is not an encrypted envelope.
+pub fn envelope_info(data: &[u8]) -> Result<Option<EnvelopeInfo>, CryptoError> {
+    let Ok(envelope) = serde_json::from_slice::<serde_json::Value>(data) else {
+        return Ok(None);
     };
+    if !is_encrypted_envelope(&envelope) {
+        return Ok(None);
+    }
+    let sealed = parse_sealed(&envelope)?;
+    let (kdf, slots, keyfile) = match sealed.source {
+        KeySource::Direct { kdf, keyfile, .. } => (Some(kdf), Vec::new(), keyfile),
+        KeySource::DataKey { slots } => {
+            let slots: Vec<SlotInfo> = slots.iter().map(KeySlot::info).collect();
+            let keyfile = slots
+                .iter()
+                .any(|s| s.keyfile || s.kind == SlotKind::KeyFile);
+            (None, slots, keyfile)
+        }
+    };
+    Ok(Some(EnvelopeInfo {
+        version: sealed.version,
+        cipher: CIPHER_AES_256_GCM.to_string(),
+        kdf,
+        slots,
+        keyfile,
+        binding: sealed.binding,
+    }))
+}
 
-    let is_encrypted = envelope
-        .get("encrypted")
-        .and_then(|v| v.as_bool())
-        .unwrap_or(false);
-    if !is_encrypted {
-        return Ok(data.to_vec());
+// ---------------------------------------------------------------------------
+// Vault and asset encryption
+// ---------------------------------------------------------------------------
+
+/// Fast encryption using a pre-derived cached key. No key derivation on each call.
+pub fn encrypt_vault_fast(plaintext: &str, cached: &CachedKey) -> Result<String, CryptoError> {
+    seal(plaintext.as_bytes(), cached, true, None)
+}
+
+/// Decrypt a vault envelope with an already unlocked key, e.g. a backup of
+/// the open vault. Fails unless the envelope was sealed with the same data
+/// key (or, for older envelopes, the same passphrase).
+pub fn decrypt_vault_fast(encrypted_json: &str, cached: &CachedKey) -> Result<String, CryptoError> {
+    let envelope: serde_json::Value = serde_json::from_str(encrypted_json)
+        .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    if !is_encrypted_envelope(&envelope) {
+        return Err(CryptoError::Unencrypted("vault".into()));
     }
+    let sealed = parse_sealed(&envelope)?;
+    let key = cached.key_for(&sealed)?;
+    let plaintext = open(&sealed, &key)
+        .map_err(|e| CryptoError::DecryptionFailed(format!("Wrong key or corrupted data: {e}")))?;
+    String::from_utf8(plaintext).map_err(|e| CryptoError::DecryptionFailed(e.to_string()))
+}
 
-    let data_b64 = envelope
-        .get("data")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| CryptoError::InvalidData("Missing encrypted asset data".into()))?;
+/// Decrypt vault and derive a fresh CachedKey for future saves.
+/// Reads the legacy PBKDF2 envelope and every versioned envelope.
+pub fn decrypt_vault_returning_key(
+    encrypted_json: &str,
+    passphrase: &str,
+) -> Result<(String, CachedKey), CryptoError> {
+    decrypt_vault_with_keyfile(encrypted_json, passphrase, None)
+}
 
-    let combined = base64::engine::general_purpose::STANDARD
-        .decode(data_b64)
+/// Like [`decrypt_vault_returning_key`], for vaults that may need a key file.
+/// `passphrase` may also be a recovery code. Fails with
+/// [`CryptoError::KeyFileRequired`] if only a key file could open the vault
+/// and none was given, and with [`CryptoError::Unencrypted`] for a vault
+/// stored as plain text.
+pub fn decrypt_vault_with_keyfile(
+    encrypted_json: &str,
+    passphrase: &str,
+    keyfile: Option<KeyFile>,
+) -> Result<(String, CachedKey), CryptoError> {
+    let envelope: serde_json::Value = serde_json::from_str(encrypted_json)
         .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
 
-    if combined.len() < SALT_LEN + IV_LEN + 1 {
+    if !is_encrypted_envelope(&envelope) {
+        return Err(CryptoError::Unencrypted("vault".into()));
+    }
+
+    let sealed = parse_sealed(&envelope)?;
+    let (key, cached_without_key) = match &sealed.source {
+        KeySource::Direct {
+            kdf,
+            salt,
+            keyfile: needs_keyfile,
+        } => {
+            let keyfile = match (needs_keyfile, keyfile) {
+                (false, _) => None,
+                (true, Some(keyfile)) => Some(keyfile),
+                (true, None) => return Err(CryptoError::KeyFileRequired),
+            };
+            let key = kdf.derive_key(passphrase, keyfile.as_ref(), salt)?;
+            // Reuse the key+salt+KDF that were just used for decryption instead
+            // of running another expensive derivation with a new random salt.
+            let mut cached = CachedKey::from_raw(*key, *salt, *kdf, passphrase);
+            cached.keyfile = keyfile;
+            (key, cached)
+        }
+        KeySource::DataKey { slots } => {
+            let (key, index) = open_slots(slots, passphrase, keyfile.as_ref())?;
+            let slot = &slots[index];
+            let used_keyfile = slot.key_file || slot.kind == SlotKind::KeyFile;
+            let cached = CachedKey {
+                key: *key,
+                scheme: KeyScheme::Wrapped {
+                    slots: slots.clone(),
+                },
+                passphrase: passphrase.to_string(),
+                keyfile: keyfile.filter(|_| used_keyfile),
+                unlocked_by: slot.kind,
+            };
+            (key, cached)
+        }
+    };
+
+    let plaintext = open(&sealed, &key).map_err(|e| {
+        CryptoError::DecryptionFailed(format!("Wrong passphrase or corrupted data: {e}"))
+    })?;
+    let plaintext_str =
+        String::from_utf8(plaintext).map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
+
+    Ok((plaintext_str, cached_without_key))
+}
+
+/// Accept a vault stored as plain text — a bare vault JSON or an
+/// `{"encrypted":false,"data":…}` envelope — and derive a new key to encrypt
+/// it with. Only for when the user has explicitly chosen to import it.
+pub fn import_unencrypted_vault(
+    plaintext_json: &str,
+    passphrase: &str,
+    keyfile: Option<KeyFile>,
+) -> Result<(String, CachedKey), CryptoError> {
+    let envelope: serde_json::Value = serde_json::from_str(plaintext_json)
+        .map_err(|e| CryptoError::InvalidData(e.to_string()))?;
+    if is_encrypted_envelope(&envelope) {
         return Err(CryptoError::InvalidData(
-            "Encrypted asset data too short".into(),
+            "the vault is already encrypted".into(),
         ));
     }
+    let plaintext = envelope
+        .get("data")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+        .or_else(|| serde_json::to_string(&envelope).ok())
+        .ok_or_else(|| CryptoError::InvalidData("No data field".into()))?;
+    let cached = CachedKey::derive_with_keyfile(passphrase, keyfile)?;
+    Ok((plaintext, cached))
+}
+
+/// Encrypt binary asset data using a pre-derived cached key.
+pub fn encrypt_asset(data: &[u8], cached: &CachedKey) -> Result<String, CryptoError> {
+    seal(data, cached, false, None)
+}
+
+/// Encrypt an asset bound to its vault, ID and version (see [`AssetBinding`]).
+pub fn encrypt_asset_bound(
+    data: &[u8],
+    cached: &CachedKey,
+    binding: &AssetBinding,
+) -> Result<String, CryptoError> {
+    seal(data, cached, false, Some(binding))
+}
 
-    let asset_salt = &combined[..SALT_LEN];
-    let iv = &combined[SALT_LEN..SALT_LEN + IV_LEN];
-    let ciphertext = &combined[SALT_LEN + IV_LEN..];
+/// Parse an asset envelope; `None` for data stored without encryption.
+fn parse_asset(data: &[u8]) -> Result<Option<Sealed>, CryptoError> {
+    let Ok(envelope) = serde_json::from_slice::<serde_json::Value>(data) else {
+        return Ok(None);
+    };
+    if !is_encrypted_envelope(&envelope) {
+        return Ok(None);
+    }
+    parse_sealed(&envelope).map(Some)
+}
 
-    let key = derive_key(&cached.passphrase, asset_salt);
-    let cipher = Aes256Gcm::new_from_slice(&key)
-        .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))?;
-    let nonce = Nonce::from_slice(iv);
-    cipher
-        .decrypt(nonce, ciphertext)
+fn open_asset(sealed: &Sealed, cached: &CachedKey) -> Result<Vec<u8>, CryptoError> {
+    let key = cached.key_for(sealed)?;
+    open(sealed, &key)
         .map_err(|e| CryptoError::DecryptionFailed(format!("Wrong key or corrupted asset: {e}")))
 }
 
+/// Decrypt binary asset bytes (or a note body blob) using a cached key.
+/// Unencrypted data is refused with [`CryptoError::Unencrypted`]. Does not
+/// check what the asset is bound to — use [`decrypt_asset_bound`] when that
+/// is known.
+pub fn decrypt_asset(data: &[u8], cached: &CachedKey) -> Result<Vec<u8>, CryptoError> {
+    let sealed = parse_asset(data)?.ok_or_else(|| CryptoError::Unencrypted("blob".into()))?;
+    open_asset(&sealed, cached)
+}
+
+/// Decrypt an asset that must be bound to exactly `expected`. Unencrypted
+/// and unbound data are rejected as well as bindings that differ.
+pub fn decrypt_asset_bound(
+    data: &[u8],
+    cached: &CachedKey,
+    expected: &AssetBinding,
+) -> Result<Vec<u8>, CryptoError> {
+    let sealed = parse_asset(data)?.ok_or_else(|| CryptoError::Unencrypted("asset".into()))?;
+    let plaintext = open_asset(&sealed, cached)?;
+    match &sealed.binding {
+        Some(binding) if binding == expected => Ok(plaintext),
+        Some(binding) => Err(CryptoError::BindingMismatch(describe_mismatch(
+            binding, expected,
+        ))),
+        None => Err(CryptoError::BindingMismatch(
+            "not bound to this vault".into(),
+        )),
+    }
+}
+
+/// Decrypt a blob bound to `expected`, or one written before blobs of its
+/// kind were bound. Any other binding is rejected. For note bodies and the
+/// journal, whose owners record nothing that tells the two apart.
+pub fn decrypt_blob(
+    data: &[u8],
+    cached: &CachedKey,
+    expected: &AssetBinding,
+) -> Result<Vec<u8>, CryptoError> {
+    let sealed = parse_asset(data)?.ok_or_else(|| CryptoError::Unencrypted("blob".into()))?;
+    let plaintext = open_asset(&sealed, cached)?;
+    match &sealed.binding {
+        None => Ok(plaintext),
+        Some(binding) if binding == expected => Ok(plaintext),
+        Some(binding) => Err(CryptoError::BindingMismatch(describe_mismatch(
+            binding, expected,
+        ))),
+    }
+}
+
+fn describe_mismatch(found: &AssetBinding, expected: &AssetBinding) -> String {
+    if found.vault_id != expected.vault_id {
+        "belongs to another vault".into()
+    } else if found.kind != expected.kind {
+        format!("is a {} blob", found.kind.name())
+    } else if found.asset_id != expected.asset_id {
+        format!("is {} {}", found.kind.name(), found.asset_id)
+    } else if found.version < expected.version {
+        format!(
+            "is version {}, expected {} (rolled back)",
+            found.version, expected.version
+        )
+    } else {
+        format!(
+            "is version {}, expected {}",
+            found.version, expected.version
+        )
+    }
+}
+
+/// Decrypt an asset with `old` and encrypt it with `new`, keeping its binding.
+pub fn reencrypt_asset(
+    data: &[u8],
+    old: &CachedKey,
+    new: &CachedKey,
+) -> Result<String, CryptoError> {
+    let binding = parse_asset(data)?.and_then(|sealed| sealed.binding);
+    let mut plaintext = decrypt_asset(data, old)?;
+    let sealed = seal(&plaintext, new, false, binding.as_ref());
+    plaintext.zeroize();
+    sealed
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn unencrypted_fallback() {
+    fn unencrypted_vault_needs_import() {
         let envelope = r#"{"encrypted":false,"data":"{\"tree\":[]}"}"#;
-        let (result, _key) = decrypt_vault_returning_key(envelope, "any").expect("decrypt");
+        assert!(matches!(
+            decrypt_vault_returning_key(envelope, "any"),
+            Err(CryptoError::Unencrypted(_))
+        ));
+        let (result, key) = import_unencrypted_vault(envelope, "any", None).expect("import");
         assert_eq!(result, r#"{"tree":[]}"#);
+        let (bare, _) = import_unencrypted_vault(r#"{"tree":[]}"#, "any", None).expect("import");
+        assert_eq!(bare, r#"{"tree":[]}"#);
+
+        let encrypted = encrypt_vault_fast(&result, &key).expect("encrypt");
+        assert!(import_unencrypted_vault(&encrypted, "any", None).is_err());
     }
 
     #[test]
     fn cached_key_roundtrip() {
         let plaintext = r#"{"tree":[],"trash":[]}"#;
         let pass = "cached-key-test";
-        let cached = CachedKey::derive(pass);
+        let cached = CachedKey::derive(pass).expect("derive");
         let encrypted = encrypt_vault_fast(plaintext, &cached).expect("encrypt");
         assert!(encrypted.contains("\"encrypted\":true"));
         let (decrypted, _key) = decrypt_vault_returning_key(&encrypted, pass).expect("decrypt");
         assert_eq!(decrypted, plaintext);
+        assert_eq!(decrypt_vault_fast(&encrypted, &cached).unwrap(), plaintext);
+        let other = CachedKey::derive(pass).expect("derive");
+        assert!(decrypt_vault_fast(&encrypted, &other).is_err());
     }
 
     #[test]
     fn decrypt_returning_key_roundtrip() {
         let plaintext = r#"{"tree":[],"trash":[]}"#;
         let pass = "roundtrip-test-pass";
-        let cached = CachedKey::derive(pass);
+        let cached = CachedKey::derive(pass).expect("derive");
         let encrypted = encrypt_vault_fast(plaintext, &cached).expect("encrypt");
         let (decrypted, reused_key) =
             decrypt_vault_returning_key(&encrypted, pass).expect("decrypt");
@@ -287,7 +1380,7 @@ mod tests {
     #[test]
     fn asset_encrypt_decrypt_roundtrip() {
         let data = b"PNG binary image data here \x89\x50\x4e\x47";
-        let cached = CachedKey::derive("asset-test-pass");
+        let cached = CachedKey::derive("asset-test-pass").expect("derive");
         let encrypted = encrypt_asset(data, &cached).expect("encrypt asset");
         assert!(encrypted.contains("\"encrypted\":true"));
         let decrypted = decrypt_asset(encrypted.as_bytes(), &cached).expect("decrypt asset");
@@ -295,20 +1388,276 @@ mod tests {
     }
 
     #[test]
-    fn asset_decrypt_unencrypted_passthrough() {
-        let raw = b"plain unencrypted data";
-        let cached = CachedKey::derive("any-pass");
-        let result = decrypt_asset(raw, &cached).expect("passthrough");
-        assert_eq!(result, raw);
+    fn asset_decrypt_refuses_unencrypted() {
+        let cached = CachedKey::derive("any-pass").expect("derive");
+        for raw in [&b"plain unencrypted data"[..], br#"{"encrypted":false}"#] {
+            assert!(matches!(
+                decrypt_asset(raw, &cached),
+                Err(CryptoError::Unencrypted(_))
+            ));
+        }
     }
 
     #[test]
     fn asset_decrypt_wrong_key_fails() {
         let data = b"secret asset data";
-        let key1 = CachedKey::derive("correct-pass");
+        let key1 = CachedKey::derive("correct-pass").expect("derive");
         let encrypted = encrypt_asset(data, &key1).expect("encrypt");
-        let key2 = CachedKey::derive("wrong-pass");
+        let key2 = CachedKey::derive("wrong-pass").expect("derive");
         let result = decrypt_asset(encrypted.as_bytes(), &key2);
         assert!(result.is_err());
     }
+
+    /// Build a pre-versioning envelope exactly as older releases wrote it.
+    fn legacy_envelope(plaintext: &[u8], pass: &str) -> String {
+        let salt = [7u8; SALT_LEN];
+        let iv = [9u8; IV_LEN];
+        let key = KdfParams::legacy()
+            .derive_key(pass, None, &salt)
+            .expect("derive");
+        let ciphertext = Aes256Gcm::new_from_slice(&*key)
+            .unwrap()
+            .encrypt(Nonce::from_slice(&iv), plaintext)
+            .unwrap();
+        let mut combined = salt.to_vec();
+        combined.extend_from_slice(&iv);
+        combined.extend_from_slice(&ciphertext);
+        let b64 = base64::engine::general_purpose::STANDARD.encode(&combined);
+        format!(r#"{{"encrypted":true,"data":"{b64}"}}"#)
+    }
+
+    #[test]
+    fn envelope_records_kdf_and_version() {
+        let cached = CachedKey::derive("header-test-pass").expect("derive");
+        let encrypted = encrypt_vault_fast("{}", &cached).expect("encrypt");
+        let header: serde_json::Value = serde_json::from_str(&encrypted).expect("json");
+        assert_eq!(header["version"], ENVELOPE_VERSION);
+        assert_eq!(header["cipher"], CIPHER_AES_256_GCM);
+        assert_eq!(header["slots"][0]["type"], "passphrase");
+        assert_eq!(header["slots"][0]["kdf"]["name"], "argon2id");
+        assert_eq!(header["slots"][0]["kdf"]["memoryKib"], ARGON2_MEMORY_KIB);
+
+        // Assets are sealed with the data key and don't repeat the slots.
+        let asset = encrypt_asset(b"img", &cached).expect("encrypt");
+        let header: serde_json::Value = serde_json::from_str(&asset).expect("json");
+        assert!(header.get("slots").is_none());
+    }
+
+    #[test]
+    fn legacy_envelope_decrypts() {
+        let pass = "legacy-pass";
+        let envelope = legacy_envelope(br#"{"tree":[]}"#, pass);
+        let (plaintext, key) = decrypt_vault_returning_key(&envelope, pass).expect("decrypt");
+        assert_eq!(plaintext, r#"{"tree":[]}"#);
+        assert_eq!(key.kdf(), KdfParams::legacy());
+
+        // Re-saving writes the versioned envelope but keeps the legacy KDF.
+        let resaved = encrypt_vault_fast(&plaintext, &key).expect("encrypt");
+        assert!(resaved.contains("\"pbkdf2-sha256\""));
+        let (again, _) = decrypt_vault_returning_key(&resaved, pass).expect("decrypt");
+        assert_eq!(again, plaintext);
+    }
+
+    #[test]
+    fn legacy_asset_decrypts_with_argon2_session_key() {
+        let pass = "mixed-pass";
+        let envelope = legacy_envelope(b"old image bytes", pass);
+        let session = CachedKey::derive(pass).expect("derive");
+        let decrypted = decrypt_asset(envelope.as_bytes(), &session).expect("decrypt");
+        assert_eq!(decrypted, b"old image bytes");
+    }
+
+    #[test]
+    fn unknown_envelope_version_rejected() {
+        let cached = CachedKey::derive("future-pass").expect("derive");
+        let encrypted = encrypt_vault_fast("{}", &cached).expect("encrypt");
+        let future =
+            encrypted.replace(&format!("\"version\":{ENVELOPE_VERSION}"), "\"version\":99");
+        let result = decrypt_vault_returning_key(&future, "future-pass");
+        assert!(matches!(result, Err(CryptoError::Unsupported(_))));
+    }
+
+    #[test]
+    fn oversized_kdf_parameters_rejected() {
+        let kdf = KdfParams::Argon2id {
+            memory_kib: u32::MAX,
+            iterations: 3,
+            parallelism: 1,
+        };
+        assert!(matches!(kdf.validate(), Err(CryptoError::Unsupported(_))));
+    }
+
+    #[test]
+    fn legacy_key_offers_upgrade() {
+        let pass = "upgrade-pass";
+        let envelope = legacy_envelope(b"{}", pass);
+        let info = envelope_info(envelope.as_bytes())
+            .expect("info")
+            .expect("encrypted");
+        assert_eq!(info.version, 1);
+        assert!(!info.is_current());
+
+        let (_, legacy_key) = decrypt_vault_returning_key(&envelope, pass).expect("decrypt");
+        assert!(legacy_key.needs_upgrade());
+        let (upgraded, code) = legacy_key.upgraded().expect("upgrade");
+        assert!(!upgraded.needs_upgrade());
+        assert!(code.is_none());
+
+        let resealed = encrypt_vault_fast("{}", &upgraded).expect("encrypt");
+        let info = envelope_info(resealed.as_bytes())
+            .expect("info")
+            .expect("encrypted");
+        assert!(info.is_current());
+        assert_eq!(envelope_info(b"raw bytes").expect("info"), None);
+    }
+
+    #[test]
+    fn upgrade_rewraps_every_outdated_slot() {
+        let pass = "slot-upgrade-pass";
+        let contents = KeyFile::generate();
+        let keyfile = KeyFile::from_bytes(&contents).expect("keyfile");
+        let other = KeyFile::from_bytes(b"another key file").expect("keyfile");
+        let mut key = CachedKey::derive(pass).expect("derive");
+        let old = KdfParams::legacy();
+        let data_key = key.key;
+        let slots = key.slots_mut().expect("slots");
+        for (kind, secret, file) in [
+            (
+                SlotKind::RecoveryCode,
+                "AAAA-AAAA-AAAA-AAAA-AAAA-AAAA",
+                None,
+            ),
+            (SlotKind::KeyFile, "", Some(&keyfile)),
+            (SlotKind::KeyFile, "", Some(&other)),
+        ] {
+            slots.push(KeySlot::wrap_with(&data_key, kind, secret, file, old).expect("wrap"));
+        }
+        // The passphrase slot is current; the others are not.
+        let vault = encrypt_vault_fast("{}", &key).expect("encrypt");
+        let info = envelope_info(vault.as_bytes())
+            .expect("info")
+            .expect("encrypted");
+        assert!(!info.is_current());
+        assert!(
+            key.needs_upgrade(),
+            "the recovery code can always be replaced"
+        );
+
+        let (_, by_file) =
+            decrypt_vault_with_keyfile(&vault, "", Some(keyfile.clone())).expect("keyfile unlock");
+        let (upgraded, code) = by_file.upgraded().expect("upgrade");
+        assert!(upgraded.same_data_key(&key));
+        let code = code.expect("outdated recovery code replaced");
+        let kdfs: Vec<(SlotKind, bool)> = upgraded
+            .slots()
+            .iter()
+            .map(|s| (s.kind, s.kdf == KdfParams::default()))
+            .collect();
+        assert_eq!(
+            kdfs,
+            [
+                (SlotKind::Passphrase, true),
+                (SlotKind::KeyFile, true),
+                // Only a key file at hand can re-wrap its slot.
+                (SlotKind::KeyFile, false),
+                (SlotKind::RecoveryCode, true),
+            ]
+        );
+
+        let vault = encrypt_vault_fast("{}", &upgraded).expect("encrypt");
+        assert!(decrypt_vault_returning_key(&vault, pass).is_ok());
+        assert!(decrypt_vault_returning_key(&vault, &code).is_ok());
+        assert!(decrypt_vault_returning_key(&vault, "AAAA-AAAA-AAAA-AAAA-AAAA-AAAA").is_err());
+        assert!(decrypt_vault_with_keyfile(&vault, "", Some(keyfile)).is_ok());
+        assert!(decrypt_vault_with_keyfile(&vault, "", Some(other)).is_ok());
+    }
+
+    #[test]
+    fn keyfile_is_second_factor() {
+        let pass = "two-factor-pass";
+        let contents = KeyFile::generate();
+        let keyfile = KeyFile::from_bytes(&contents).expect("keyfile");
+        let cached = CachedKey::derive_with_keyfile(pass, Some(keyfile.clone())).expect("derive");
+        let encrypted = encrypt_vault_fast("{}", &cached).expect("encrypt");
+        let info = envelope_info(encrypted.as_bytes())
+            .expect("info")
+            .expect("encrypted");
+        assert!(info.keyfile);
+
+        let missing = decrypt_vault_returning_key(&encrypted, pass);
+        assert!(matches!(missing, Err(CryptoError::KeyFileRequired)));
+        let wrong = KeyFile::from_bytes(b"some other file").expect("keyfile");
+        let result = decrypt_vault_with_keyfile(&encrypted, pass, Some(wrong));
+        assert!(matches!(result, Err(CryptoError::DecryptionFailed(_))));
+
+        let (plaintext, key) =
+            decrypt_vault_with_keyfile(&encrypted, pass, Some(keyfile)).expect("decrypt");
+        assert_eq!(plaintext, "{}");
+        assert!(key.keyfile().is_some());
+
+        // Assets sealed by a pre-key-wrapping session still open.
+        let direct = CachedKey::derive_with(pass, KdfParams::default()).expect("derive");
+        let old_asset = encrypt_asset(b"img", &direct).expect("encrypt");
+        assert_eq!(
+            decrypt_asset(old_asset.as_bytes(), &key).expect("decrypt"),
+            b"img"
+        );
+        assert!(KeyFile::from_bytes(b"").is_err());
+    }
+
+    #[test]
+    fn passphrase_change_keeps_data_key() {
+        let mut key = CachedKey::derive("first-pass").expect("derive");
+        let asset = encrypt_asset(b"img", &key).expect("encrypt");
+        let before = key.clone();
+        key.set_passphrase("second-pass", None).expect("rewrap");
+        assert!(key.same_data_key(&before));
+
+        let vault = encrypt_vault_fast("{}", &key).expect("encrypt");
+        assert!(decrypt_vault_returning_key(&vault, "first-pass").is_err());
+        let (_, reopened) = decrypt_vault_returning_key(&vault, "second-pass").expect("decrypt");
+        assert_eq!(
+            decrypt_asset(asset.as_bytes(), &reopened).expect("decrypt"),
+            b"img"
+        );
+    }
+
+    #[test]
+    fn recovery_code_and_keyfile_slots_unlock() {
+        let mut key = CachedKey::derive("slot-pass").expect("derive");
+        let code = key.generate_recovery_code().expect("recovery");
+        let contents = KeyFile::generate();
+        key.add_keyfile_slot(&KeyFile::from_bytes(&contents).expect("keyfile"))
+            .expect("add slot");
+        let kinds: Vec<SlotKind> = key.slots().iter().map(|s| s.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                SlotKind::Passphrase,
+                SlotKind::RecoveryCode,
+                SlotKind::KeyFile
+            ]
+        );
+        let vault = encrypt_vault_fast("{}", &key).expect("encrypt");
+
+        // Recovery codes are accepted in any case and without dashes.
+        let sloppy = code.replace('-', " ").to_lowercase();
+        let (_, by_code) = decrypt_vault_returning_key(&vault, &sloppy).expect("recover");
+        assert!(by_code.same_data_key(&key));
+        assert!(!by_code.needs_upgrade());
+
+        let keyfile = KeyFile::from_bytes(&contents).expect("keyfile");
+        let (_, by_file) =
+            decrypt_vault_with_keyfile(&vault, "", Some(keyfile)).expect("keyfile unlock");
+        assert!(by_file.same_data_key(&key));
+
+        // Removing the recovery slot only changes the header.
+        key.remove_slot(1).expect("remove");
+        let vault = encrypt_vault_fast("{}", &key).expect("encrypt");
+        assert!(decrypt_vault_returning_key(&vault, &code).is_err());
+        assert!(
+            key.remove_slot(0).is_err(),
+            "last passphrase slot must stay"
+        );
+    }
 }
@@ -1,5 +1,8 @@
+pub mod archive;
+pub mod bundle;
 pub mod crypto;
 pub mod export;
+pub mod markdown_tree;
 pub mod notes;
 pub mod search;
 pub mod state;
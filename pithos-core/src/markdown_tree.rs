@@ -0,0 +1,882 @@
+//! A vault as a plain directory of Markdown files: one `.md` file per note,
+//! one directory per folder, assets decrypted into `attachments/`. Each note
+//! starts with YAML front matter carrying what the file name can't:
+//!
+//! ```text
+//! ---
+//! id: "note-12"
+//! title: "Standup: 3 May"
+//! tags: ["work", "meetings"]
+//! created: 2024-05-03T09:00:00Z
+//! updated: 2024-05-03T09:41:12Z
+//! pinned: false
+//! ---
+//! ```
+//!
+//! A folder whose name a directory can't hold, such as `Work/Clients`, gets a
+//! hidden `.pithos-folder` file in its directory with just a `title`.
+//!
+//! Importing such a tree recreates its folders and notes, and re-encrypts the
+//! attachments it links to. Locked folders, the trash and note history are
+//! not part of the tree.
+
+use crate::crypto::{self, CachedKey};
+use crate::state::*;
+use crate::vault::{self, AssetMeta, VaultError};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory at the top of the tree holding the decrypted assets.
+pub const ATTACHMENTS_DIR: &str = "attachments";
+/// Front matter holding a folder's name, when its directory's differs.
+const FOLDER_FILE: &str = ".pithos-folder";
+
+#[derive(Debug, Default)]
+pub struct TreeReport {
+    pub notes: usize,
+    pub folders: usize,
+    pub attachments: usize,
+    /// What was left out and why, one line each.
+    pub skipped: Vec<String>,
+}
+
+// ---------------------------------------------------------------------------
+// Export
+// ---------------------------------------------------------------------------
+
+/// Write every note outside locked folders under `out`, which must be empty
+/// or not exist yet. Note bodies are loaded into `state` as needed.
+pub fn export_tree(
+    state: &mut DocState,
+    vault_folder: &str,
+    key: &CachedKey,
+    out: &Path,
+) -> Result<TreeReport, VaultError> {
+    if out.exists() && fs::read_dir(out)?.next().is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} is not empty", out.display()),
+        )
+        .into());
+    }
+    let mut export = Exporter {
+        state,
+        vault_folder,
+        key,
+        out,
+        attachments: HashMap::new(),
+        attachment_names: HashSet::new(),
+        report: TreeReport::default(),
+    };
+    export.folder(None, Path::new(""), 0)?;
+    Ok(export.report)
+}
+
+struct Exporter<'a> {
+    state: &'a mut DocState,
+    vault_folder: &'a str,
+    key: &'a CachedKey,
+    out: &'a Path,
+    /// Asset ID -> file name in `attachments/`, once written.
+    attachments: HashMap<String, String>,
+    attachment_names: HashSet<String>,
+    report: TreeReport,
+}
+
+impl Exporter<'_> {
+    fn folder(&mut self, parent: Option<&str>, rel: &Path, depth: usize) -> Result<(), VaultError> {
+        fs::create_dir_all(self.out.join(rel))?;
+        let mut taken = HashSet::new();
+        if parent.is_none() {
+            taken.insert(ATTACHMENTS_DIR.to_string());
+        }
+
+        let subfolders: Vec<(String, String)> = self
+            .state
+            .folders
+            .iter()
+            .filter(|f| f.parent_id.as_deref() == parent)
+            .map(|f| (f.id.clone(), f.name.clone()))
+            .collect();
+        for (id, name) in subfolders {
+            let base = file_name_for(&name);
+            let dir_name = claim_name(&mut taken, &base, "");
+            let path = rel.join(&dir_name);
+            if !self.state.is_folder_accessible(Some(&id)) {
+                self.report
+                    .skipped
+                    .push(format!("{} (locked folder)", path.display()));
+                continue;
+            }
+            self.folder(Some(&id), &path, depth + 1)?;
+            // Keep the number a clash added, as an import without the file would
+            let title = format!("{name}{}", &dir_name[base.len()..]);
+            if title != dir_name {
+                let front = format!("---\ntitle: {}\n---\n", yaml_string(&title));
+                vault::atomic_write(&self.out.join(&path).join(FOLDER_FILE), front.as_bytes())?;
+            }
+            self.report.folders += 1;
+        }
+
+        let notes: Vec<usize> = (0..self.state.notes.len())
+            .filter(|&i| self.state.notes[i].parent_id.as_deref() == parent)
+            .collect();
+        for index in notes {
+            vault::load_note_body(
+                self.vault_folder,
+                &self.state.vault_id,
+                self.key,
+                &mut self.state.notes[index],
+            )?;
+            let note = &self.state.notes[index];
+            let file_name = claim_name(&mut taken, &file_name_for(&note.name), ".md");
+            let ids = vault::referenced_asset_ids(&note.content);
+            for id in ids {
+                self.attachment(&id);
+            }
+            let note = &self.state.notes[index];
+            let text = format!(
+                "{}{}",
+                front_matter(note),
+                rewrite_asset_links(&note.content, depth, &self.attachments)
+            );
+            vault::atomic_write(&self.out.join(rel).join(file_name), text.as_bytes())?;
+            self.report.notes += 1;
+        }
+        Ok(())
+    }
+
+    /// Decrypt an asset of the main vault into `attachments/`, once.
+    fn attachment(&mut self, id: &str) {
+        if self.attachments.contains_key(id) {
+            return;
+        }
+        let Some(meta) = self.state.assets.get(id).filter(|m| m.stored_in.is_none()) else {
+            return;
+        };
+        let binding = vault::asset_binding(&self.state.vault_id, meta);
+        let data = match vault::read_asset(self.vault_folder, id, self.key, binding.as_ref()) {
+            Ok(data) => data,
+            Err(e) => {
+                self.report.skipped.push(format!("attachment {id}: {e}"));
+                return;
+            }
+        };
+        // Keep links free of spaces, which Markdown doesn't allow unescaped
+        let base = format!(
+            "{}-{}",
+            &id[..id.len().min(8)],
+            file_name_for(&meta.original_name).replace(' ', "-")
+        );
+        let name = claim_name(&mut self.attachment_names, &base, "");
+        let dir = self.out.join(ATTACHMENTS_DIR);
+        let written = fs::create_dir_all(&dir).and_then(|_| fs::write(dir.join(&name), &data));
+        match written {
+            Ok(()) => {
+                self.attachments.insert(id.to_string(), name);
+                self.report.attachments += 1;
+            }
+            Err(e) => self.report.skipped.push(format!("attachment {id}: {e}")),
+        }
+    }
+}
+
+fn front_matter(note: &NoteItem) -> String {
+    let tags: Vec<String> = note.tags.iter().map(|t| yaml_string(t)).collect();
+    format!(
+        "---\nid: {}\ntitle: {}\ntags: [{}]\ncreated: {}\nupdated: {}\npinned: {}\n---\n",
+        yaml_string(&note.id),
+        yaml_string(&note.name),
+        tags.join(", "),
+        format_timestamp(note.created_at),
+        format_timestamp(note.updated_at),
+        note.pinned
+    )
+}
+
+/// A double-quoted YAML scalar; JSON's string syntax is a subset of it.
+fn yaml_string(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+/// Point `vault://<id>` links at the exported attachment, from a note
+/// `depth` folders down.
+fn rewrite_asset_links(content: &str, depth: usize, files: &HashMap<String, String>) -> String {
+    vault::replace_asset_links(content, |id| {
+        let file = files.get(id)?;
+        Some(format!("{}{ATTACHMENTS_DIR}/{file}", "../".repeat(depth)))
+    })
+}
+
+/// A note or folder name as a file name that works on any common filesystem.
+fn file_name_for(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = cleaned.trim().trim_start_matches('.').trim();
+    if trimmed.is_empty() {
+        "Untitled".to_string()
+    } else {
+        trimmed.chars().take(120).collect()
+    }
+}
+
+/// `base` + `ext`, numbered if the name is taken (case-insensitively).
+fn claim_name(taken: &mut HashSet<String>, base: &str, ext: &str) -> String {
+    let mut name = format!("{base}{ext}");
+    let mut n = 2;
+    while !taken.insert(name.to_lowercase()) {
+        name = format!("{base} ({n}){ext}");
+        n += 1;
+    }
+    name
+}
+
+// ---------------------------------------------------------------------------
+// Import
+// ---------------------------------------------------------------------------
+
+/// Add the notes and folders under `dir` to `state`, below `parent`.
+/// Folders that already exist there are merged into; note IDs from the
+/// front matter are kept unless the vault already uses them. Attachments
+/// the notes link to are encrypted into the vault, so save `state` after.
+pub fn import_tree(
+    state: &mut DocState,
+    vault_folder: &str,
+    key: &CachedKey,
+    dir: &Path,
+    parent: Option<String>,
+) -> Result<TreeReport, VaultError> {
+    if let Some(root) = state.locked_root(parent.as_deref()) {
+        return Err(VaultError::Locked(root.to_string()));
+    }
+    let attachments = fs::canonicalize(dir.join(ATTACHMENTS_DIR)).ok();
+    let mut import = Importer {
+        state,
+        vault_folder,
+        key,
+        attachments,
+        imported: HashMap::new(),
+        report: TreeReport::default(),
+    };
+    import.folder(dir, parent, true)?;
+    Ok(import.report)
+}
+
+struct Importer<'a> {
+    state: &'a mut DocState,
+    vault_folder: &'a str,
+    key: &'a CachedKey,
+    /// Canonical path of the tree's `attachments/`, if it has one.
+    attachments: Option<PathBuf>,
+    /// Attachment path -> asset ID, so a file linked twice is stored once.
+    imported: HashMap<PathBuf, String>,
+    report: TreeReport,
+}
+
+impl Importer<'_> {
+    fn folder(&mut self, dir: &Path, parent: Option<String>, top: bool) -> Result<(), VaultError> {
+        // File types as listed, so a symlink is never followed out of the tree
+        let mut entries: Vec<(PathBuf, fs::FileType)> = fs::read_dir(dir)?
+            .map(|entry| entry.and_then(|e| Ok((e.path(), e.file_type()?))))
+            .collect::<Result<_, _>>()?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, file_type) in entries {
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if name.starts_with('.') || (top && name == ATTACHMENTS_DIR) {
+                continue;
+            }
+            if file_type.is_symlink() {
+                self.report
+                    .skipped
+                    .push(format!("{} (symbolic link)", path.display()));
+            } else if file_type.is_dir() {
+                let name = folder_title(&path).unwrap_or_else(|| name.to_string());
+                let Some(folder_id) = self.folder_for(&name, &parent) else {
+                    self.report
+                        .skipped
+                        .push(format!("{} (locked folder)", path.display()));
+                    continue;
+                };
+                self.folder(&path, Some(folder_id), false)?;
+            } else if path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| matches!(e.to_lowercase().as_str(), "md" | "markdown"))
+            {
+                self.note(&path, &parent)?;
+            } else {
+                self.report
+                    .skipped
+                    .push(format!("{} (not Markdown)", path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The folder called `name` in `parent`, created if needed; `None` if
+    /// it is a locked folder.
+    fn folder_for(&mut self, name: &str, parent: &Option<String>) -> Option<String> {
+        let lower = name.to_lowercase();
+        if let Some(folder) = self
+            .state
+            .folders
+            .iter()
+            .find(|f| f.parent_id == *parent && f.name.to_lowercase() == lower)
+        {
+            return (!folder.locked).then(|| folder.id.clone());
+        }
+        let id = format!("folder-{}", self.state.next_note_seq);
+        self.state.next_note_seq += 1;
+        let now = unix_now();
+        self.state.folders.push(FolderItem {
+            id: id.clone(),
+            name: name.to_string(),
+            expanded: true,
+            created_at: now,
+            updated_at: now,
+            parent_id: parent.clone(),
+            locked: false,
+        });
+        self.report.folders += 1;
+        Some(id)
+    }
+
+    fn note(&mut self, path: &Path, parent: &Option<String>) -> Result<(), VaultError> {
+        let text = fs::read_to_string(path)?;
+        let (front, body) = split_front_matter(&text);
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let content = rewrite_links(body, |dest| self.link_to_asset(dir, dest));
+
+        let modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+        let name = front
+            .title
+            .filter(|t| !t.trim().is_empty())
+            .unwrap_or_else(|| {
+                path.file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("Imported Note")
+                    .to_string()
+            });
+        let state = &mut *self.state;
+        let id = match front.id {
+            Some(id) if vault::is_valid_asset_id(&id) && !id_in_use(state, &id) => {
+                // Keep generated IDs ahead of the ones brought in
+                let seq = id
+                    .strip_prefix("note-")
+                    .or_else(|| id.strip_prefix("folder-"));
+                if let Some(seq) = seq.and_then(|n| n.parse::<u64>().ok()) {
+                    state.next_note_seq = state.next_note_seq.max(seq + 1);
+                }
+                id
+            }
+            _ => {
+                let id = format!("note-{}", state.next_note_seq);
+                state.next_note_seq += 1;
+                id
+            }
+        };
+        let unique_name = deduplicate_note_name(&state.notes, &name, parent);
+        let mut note = NoteItem::new(id, unique_name, content, front.tags);
+        note.parent_id = parent.clone();
+        note.pinned = front.pinned;
+        note.updated_at = front.updated.or(modified).unwrap_or(note.updated_at);
+        note.created_at = front.created.unwrap_or(note.updated_at);
+        state.notes.push(note);
+        self.report.notes += 1;
+        Ok(())
+    }
+
+    /// Store the attachment a relative link in `dir` points to as an asset,
+    /// and return its `vault://` URL.
+    fn link_to_asset(&mut self, dir: &Path, dest: &str) -> Option<String> {
+        let attachments = self.attachments.as_ref()?;
+        if dest.contains("://") || dest.starts_with(['#', '/']) {
+            return None;
+        }
+        let path = fs::canonicalize(dir.join(percent_decode(dest))).ok()?;
+        if !path.starts_with(attachments) || !path.is_file() {
+            return None;
+        }
+        if let Some(id) = self.imported.get(&path) {
+            return Some(format!("vault://{id}"));
+        }
+        match self.store_asset(&path) {
+            Ok(id) => {
+                self.imported.insert(path, id.clone());
+                self.report.attachments += 1;
+                Some(format!("vault://{id}"))
+            }
+            Err(e) => {
+                self.report
+                    .skipped
+                    .push(format!("{} ({e})", path.display()));
+                None
+            }
+        }
+    }
+
+    fn store_asset(&mut self, path: &Path) -> Result<String, VaultError> {
+        let data = fs::read(path)?;
+        // Same random hex form as the app's asset IDs
+        let id = vault::new_vault_id();
+        let binding = vault::binding_for(&self.state.vault_id, None, &id, 1);
+        let encrypted = crypto::encrypt_asset_bound(&data, self.key, &binding)?;
+        vault::write_asset(self.vault_folder, &id, encrypted.as_bytes())?;
+
+        let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+        // Drop the ID prefix an export put in front of the original name
+        let original_name = match file_name.split_once('-') {
+            Some((prefix, rest))
+                if prefix.len() == 8 && prefix.chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                rest
+            }
+            _ => file_name,
+        };
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        self.state.assets.insert(
+            id.clone(),
+            AssetMeta {
+                id: id.clone(),
+                original_name: original_name.to_string(),
+                mime_type: mime_for_extension(ext).to_string(),
+                size: data.len() as u64,
+                // Asset times are in milliseconds, as the app writes them
+                created_at: unix_now() * 1000,
+                stored_in: None,
+                version: Some(1),
+            },
+        );
+        Ok(id)
+    }
+}
+
+/// The name an export recorded for the folder in `dir`, if the directory's
+/// name couldn't hold it.
+fn folder_title(dir: &Path) -> Option<String> {
+    let path = dir.join(FOLDER_FILE);
+    if !fs::symlink_metadata(&path).ok()?.is_file() {
+        return None;
+    }
+    let text = fs::read_to_string(path).ok()?;
+    split_front_matter(&text)
+        .0
+        .title
+        .filter(|t| !t.trim().is_empty())
+}
+
+fn id_in_use(state: &DocState, id: &str) -> bool {
+    find_note_index(&state.notes, id).is_some()
+        || state.folders.iter().any(|f| f.id == id)
+        || state.trash.iter().any(|t| t.id == id)
+}
+
+fn mime_for_extension(ext: &str) -> &'static str {
+    match ext.to_lowercase().as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Replace the destinations of `[text](dest)` links and images for which
+/// `replace` returns something.
+fn rewrite_links(content: &str, mut replace: impl FnMut(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(pos) = rest.find("](") {
+        out.push_str(&rest[..pos + 2]);
+        let after = &rest[pos + 2..];
+        let (dest, len) = match after.strip_prefix('<') {
+            Some(inner) => match inner.find('>') {
+                Some(end) => (&inner[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| c == ')' || c.is_whitespace())
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        match (len > 0).then(|| replace(dest)).flatten() {
+            Some(url) => out.push_str(&url),
+            None => out.push_str(&after[..len]),
+        }
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match hex {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// ---------------------------------------------------------------------------
+// Front matter
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Default)]
+struct FrontMatter {
+    id: Option<String>,
+    title: Option<String>,
+    tags: Vec<String>,
+    created: Option<i64>,
+    updated: Option<i64>,
+    pinned: bool,
+}
+
+/// The front matter of `text` and the Markdown after it. Reads the keys
+/// this module writes, plus the common hand-written forms: block lists for
+/// tags, plain or single-quoted scalars, and `date`/`modified` aliases.
+fn split_front_matter(text: &str) -> (FrontMatter, &str) {
+    let mut front = FrontMatter::default();
+    let Some(header) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return (front, text);
+    };
+    let mut end = None;
+    let mut offset = 0;
+    for line in header.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            end = Some((offset, offset + line.len()));
+            break;
+        }
+        offset += line.len();
+    }
+    let Some((header_end, body_start)) = end else {
+        return (front, text);
+    };
+
+    let mut list_key = "";
+    for line in header[..header_end].lines() {
+        if let Some(item) = line.trim_start().strip_prefix("- ") {
+            if list_key == "tags" {
+                front.tags.push(yaml_scalar(item));
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        list_key = key.trim();
+        match list_key {
+            "id" => front.id = Some(yaml_scalar(value)),
+            "title" => front.title = Some(yaml_scalar(value)),
+            "tags" => front.tags = yaml_flow_list(value),
+            "created" | "date" => front.created = parse_timestamp(&yaml_scalar(value)),
+            "updated" | "modified" => front.updated = parse_timestamp(&yaml_scalar(value)),
+            "pinned" => front.pinned = value == "true",
+            _ => {}
+        }
+    }
+    front.tags.retain(|t| !t.is_empty());
+    (front, &header[body_start..])
+}
+
+fn yaml_scalar(value: &str) -> String {
+    let value = value.trim();
+    if value.starts_with('"') {
+        if let Ok(s) = serde_json::from_str(value) {
+            return s;
+        }
+    }
+    if let Some(inner) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return inner.replace("''", "'");
+    }
+    value.trim_matches('"').to_string()
+}
+
+/// `[a, "b, c"]`, or a single scalar.
+fn yaml_flow_list(value: &str) -> Vec<String> {
+    let Some(inner) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) else {
+        return vec![yaml_scalar(value)];
+    };
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in inner.chars() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ',' => {
+                items.push(yaml_scalar(&current));
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    items.push(yaml_scalar(&current));
+    items
+}
+
+/// `2024-05-03T09:41:12Z` for a Unix time.
+fn format_timestamp(ts: i64) -> String {
+    let (days, secs) = (ts.div_euclid(86_400), ts.rem_euclid(86_400));
+    // Civil date from days since 1970-01-01 (H. Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// A UTC `YYYY-MM-DD[THH:MM[:SS]][Z]` date, or Unix seconds.
+pub(crate) fn parse_timestamp(value: &str) -> Option<i64> {
+    if let Ok(ts) = value.parse::<i64>() {
+        return Some(ts);
+    }
+    let value = value.trim_end_matches('Z');
+    let (date, time) = value.split_once(['T', ' ']).unwrap_or((value, "00:00"));
+    let mut date = date.split('-').map(|p| p.parse::<i64>().ok());
+    let (y, m, d) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time
+        .split(':')
+        .map(|p| p.split('.').next()?.parse::<i64>().ok());
+    let (h, min) = (time.next()??, time.next()??);
+    let s = time.next().unwrap_or(Some(0))?;
+    if !(1..=12).contains(&m) || !(1..=31).contains(&d) || h > 23 || min > 59 || s > 60 {
+        return None;
+    }
+    // Days since 1970-01-01 from a civil date (H. Hinnant's algorithm)
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(days * 86_400 + h * 3600 + min * 60 + s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_front_matter_round_trip() {
+        let mut note = NoteItem::new(
+            "note-7".into(),
+            "Standup: \"3 May\"".into(),
+            "# Notes\n\n---\nnot front matter\n".into(),
+            vec!["work".into(), "a, b".into()],
+        );
+        note.created_at = 1_714_726_800;
+        note.updated_at = 1_714_729_272;
+        note.pinned = true;
+        let text = format!("{}{}", front_matter(&note), note.content);
+        assert!(text.contains("created: 2024-05-03T09:00:00Z\n"));
+
+        let (front, body) = split_front_matter(&text);
+        assert_eq!(body, note.content);
+        assert_eq!(front.id.as_deref(), Some("note-7"));
+        assert_eq!(front.title.as_deref(), Some(note.name.as_str()));
+        assert_eq!(front.tags, note.tags);
+        assert_eq!(front.created, Some(note.created_at));
+        assert_eq!(front.updated, Some(note.updated_at));
+        assert!(front.pinned);
+
+        // Hand-written front matter
+        let (front, body) = split_front_matter(
+            "---\ntitle: Plain\ntags:\n  - x\n  - 'y z'\ndate: 2024-02-29\n---\nBody",
+        );
+        assert_eq!(front.title.as_deref(), Some("Plain"));
+        assert_eq!(front.tags, vec!["x".to_string(), "y z".to_string()]);
+        assert_eq!(
+            front.created.map(format_timestamp).as_deref(),
+            Some("2024-02-29T00:00:00Z")
+        );
+        assert_eq!(body, "Body");
+        assert_eq!(split_front_matter("No header").1, "No header");
+    }
+
+    #[test]
+    fn test_tree_round_trip() {
+        let root = PathBuf::from(vault::tests::temp_vault("tree"));
+        let (vault_a, vault_b) = (root.join("a"), root.join("b"));
+        let out = root.join("tree");
+        fs::create_dir_all(&vault_a).unwrap();
+        fs::create_dir_all(&vault_b).unwrap();
+        let (vault_a, vault_b) = (vault_a.to_str().unwrap(), vault_b.to_str().unwrap());
+        let key = CachedKey::derive_with("tree-pass", crypto::KdfParams::legacy()).unwrap();
+
+        let mut state = DocState {
+            notes: Vec::new(),
+            ..DocState::default()
+        };
+        state.folders.push(FolderItem {
+            id: "folder-1".into(),
+            name: "Work/Clients".into(),
+            expanded: true,
+            created_at: 0,
+            updated_at: 0,
+            parent_id: None,
+            locked: false,
+        });
+        let photo = "5ca11ed05ca11ed05ca11ed05ca11ed0";
+        let binding = vault::binding_for(&state.vault_id, None, photo, 1);
+        let asset = crypto::encrypt_asset_bound(b"png bytes", &key, &binding).unwrap();
+        vault::write_asset(vault_a, photo, asset.as_bytes()).unwrap();
+        state.assets.insert(
+            photo.into(),
+            AssetMeta {
+                id: photo.into(),
+                original_name: "site photo.png".into(),
+                mime_type: "image/png".into(),
+                size: 9,
+                created_at: 0,
+                stored_in: None,
+                version: Some(1),
+            },
+        );
+        let mut note = NoteItem::new(
+            "note-40".into(),
+            "Visit".into(),
+            format!("![photo](vault://{photo}) and ![gone](vault://missing)"),
+            vec!["site".into()],
+        );
+        note.parent_id = Some("folder-1".into());
+        state.notes.push(note);
+        state.notes.push(NoteItem::new(
+            "note-41".into(),
+            "Top".into(),
+            "Hi".into(),
+            vec![],
+        ));
+
+        let report = export_tree(&mut state, vault_a, &key, &out).expect("export");
+        assert_eq!(
+            (report.notes, report.folders, report.attachments),
+            (2, 1, 1)
+        );
+        let exported = fs::read_to_string(out.join("Work_Clients/Visit.md")).unwrap();
+        assert!(out.join("Work_Clients").join(FOLDER_FILE).is_file());
+        assert!(exported.ends_with(
+            "![photo](../attachments/5ca11ed0-site-photo.png) and ![gone](vault://missing)"
+        ));
+        assert_eq!(
+            fs::read(out.join("attachments/5ca11ed0-site-photo.png")).unwrap(),
+            b"png bytes"
+        );
+        assert!(export_tree(&mut state, vault_a, &key, &out).is_err());
+
+        let mut imported = DocState {
+            notes: Vec::new(),
+            next_note_seq: 1,
+            ..DocState::default()
+        };
+        let report = import_tree(&mut imported, vault_b, &key, &out, None).expect("import");
+        assert_eq!(
+            (report.notes, report.folders, report.attachments),
+            (2, 1, 1)
+        );
+        assert_eq!(imported.folders[0].name, "Work/Clients");
+        let visit = &imported.notes[find_note_index(&imported.notes, "note-40").unwrap()];
+        assert_eq!(
+            visit.parent_id.as_deref(),
+            Some(imported.folders[0].id.as_str())
+        );
+        assert_eq!(visit.tags, vec!["site".to_string()]);
+        // "Top.md" came first and moved the sequence past note-41
+        assert_eq!(imported.folders[0].id, "folder-42");
+        assert_eq!(imported.next_note_seq, 43);
+        let asset_id = visit.content["![photo](vault://".len()..]
+            .split(')')
+            .next()
+            .unwrap()
+            .to_string();
+        let meta = &imported.assets[&asset_id];
+        assert_eq!(meta.original_name, "site-photo.png");
+        let binding = vault::asset_binding(&imported.vault_id, meta);
+        let data = vault::read_asset(vault_b, &asset_id, &key, binding.as_ref()).unwrap();
+        assert_eq!(data, b"png bytes");
+        assert!(visit.content.ends_with("![gone](vault://missing)"));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_import_skips_symlinks() {
+        use std::os::unix::fs::symlink;
+        let root = PathBuf::from(vault::tests::temp_vault("tree-links"));
+        let (tree, outside, vault_dir) = (root.join("tree"), root.join("outside"), root.join("v"));
+        for dir in [&tree, &outside, &vault_dir] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(tree.join("Kept.md"), "inside").unwrap();
+        fs::write(outside.join("Secret.md"), "outside").unwrap();
+        symlink(&outside, tree.join("linked")).unwrap();
+        symlink(outside.join("Secret.md"), tree.join("Alias.md")).unwrap();
+        let key = CachedKey::derive_with("links-pass", crypto::KdfParams::legacy()).unwrap();
+
+        let mut state = DocState {
+            notes: Vec::new(),
+            ..DocState::default()
+        };
+        let report = import_tree(&mut state, vault_dir.to_str().unwrap(), &key, &tree, None)
+            .expect("import");
+        assert_eq!((report.notes, report.folders), (1, 0));
+        assert_eq!(state.notes[0].content, "inside");
+        assert_eq!(report.skipped.len(), 2);
+        assert!(report
+            .skipped
+            .iter()
+            .all(|s| s.ends_with("(symbolic link)")));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
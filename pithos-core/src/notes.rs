@@ -39,3 +39,75 @@ pub fn purge_old_trash(state: &mut DocState) -> usize {
     state.trash.retain(|item| item.deleted_at >= cutoff);
     before - state.trash.len()
 }
+
+// ---------------------------------------------------------------------------
+// Line diff
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Beyond this many lines × lines, the changed middle is shown as a whole
+/// block removed and added rather than aligned.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Line-by-line diff from `old` to `new` (longest common subsequence).
+pub fn line_diff<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut diff: Vec<DiffLine> = old[..prefix].iter().map(|l| DiffLine::Same(l)).collect();
+    if (old_mid.len() + 1) * (new_mid.len() + 1) > MAX_DIFF_CELLS {
+        diff.extend(old_mid.iter().map(|l| DiffLine::Removed(l)));
+        diff.extend(new_mid.iter().map(|l| DiffLine::Added(l)));
+    } else {
+        diff_middle(old_mid, new_mid, &mut diff);
+    }
+    diff.extend(old[old.len() - suffix..].iter().map(|l| DiffLine::Same(l)));
+    diff
+}
+
+fn diff_middle<'a>(old: &[&'a str], new: &[&'a str], diff: &mut Vec<DiffLine<'a>>) {
+    // common[i][j] = LCS length of old[i..] and new[j..]
+    let mut common = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            diff.push(DiffLine::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            diff.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
+    }
+    diff.extend(old[i..].iter().map(|l| DiffLine::Removed(l)));
+    diff.extend(new[j..].iter().map(|l| DiffLine::Added(l)));
+}
@@ -1,4 +1,9 @@
-use crate::state::NoteItem;
+use crate::state::{FolderItem, NoteItem, TrashItem};
+use crate::vault::NoteBody;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::{Bound, Range};
+use zeroize::{Zeroize, Zeroizing};
 
 /// Case-insensitive substring check without allocating lowercased copies.
 fn contains_case_insensitive(haystack: &str, needle: &str) -> bool {
@@ -8,15 +13,1648 @@ fn contains_case_insensitive(haystack: &str, needle: &str) -> bool {
     let needle_lower: Vec<char> = needle.chars().flat_map(|c| c.to_lowercase()).collect();
     let needle_len = needle_lower.len();
     let hay_chars: Vec<char> = haystack.chars().flat_map(|c| c.to_lowercase()).collect();
-    hay_chars.windows(needle_len).any(|w| w == needle_lower.as_slice())
+    hay_chars
+        .windows(needle_len)
+        .any(|w| w == needle_lower.as_slice())
 }
 
 /// Simple full-text search across note titles and content.
 /// Returns true if the note matches the query (case-insensitive substring).
 pub fn note_matches_query(note: &NoteItem, query: &str) -> bool {
+    text_matches_query(&note.name, &note.content, query)
+}
+
+/// Same as [`note_matches_query`] for a body that is not held in a `NoteItem`,
+/// e.g. one read from its blob just for the search.
+pub fn text_matches_query(name: &str, content: &str, query: &str) -> bool {
     if query.is_empty() {
         return true;
     }
-    contains_case_insensitive(&note.name, query)
-        || contains_case_insensitive(&note.content, query)
+    contains_case_insensitive(name, query) || contains_case_insensitive(content, query)
+}
+
+// ---------------------------------------------------------------------------
+// Ranked search index
+// ---------------------------------------------------------------------------
+
+/// BM25 term frequency saturation.
+const BM25_K1: f64 = 1.2;
+/// BM25 document length normalisation.
+const BM25_B: f64 = 0.75;
+/// A word in the title counts as this many in the body.
+const TITLE_WEIGHT: u32 = 3;
+/// Score factor for a query word that only starts an indexed word, so
+/// results show up while the word is still being typed.
+const PREFIX_WEIGHT: f64 = 0.8;
+/// Most indexed words a query word can stand for as a prefix or misspelling.
+const MAX_PREFIX_TERMS: usize = 256;
+/// Score factor per typo for a query word that matches no indexed word as
+/// typed, only one spelled slightly differently.
+const FUZZY_WEIGHT: f64 = 0.6;
+/// Added per query word found in the title, more than any body match
+/// scores, so title matches come first.
+const TITLE_MATCH_BOOST: f64 = 100.0;
+/// A note updated just now scores this much more than an old one...
+const RECENCY_BOOST: f64 = 0.25;
+/// ...halving with every this many days since.
+const RECENCY_HALF_LIFE_DAYS: f64 = 30.0;
+/// Longer words are cut, so base64 and hashes don't bloat the index.
+const MAX_TERM_CHARS: usize = 40;
+/// Version of the stored index; a different one is rebuilt.
+const INDEX_VERSION: u32 = 3;
+
+/// Inverted index over note titles and bodies, ranked with BM25. It is
+/// brought up to date with [`SearchIndex::sync`], which re-indexes only the
+/// notes whose title, `updated_at` or loaded body changed, so bodies that are
+/// not in memory are decrypted only when they are new to the index. It keeps
+/// only words and their counts, never the text: tests like phrases that look
+/// at the body decrypt the note they test, and wipe it afterwards.
+#[derive(Default)]
+pub struct SearchIndex {
+    notes: HashMap<String, IndexedNote>,
+    /// Term -> IDs of the notes containing it.
+    postings: BTreeMap<String, HashSet<String>>,
+    /// Sum of all note lengths in words.
+    total_len: u64,
+    /// Bumped on every change, to tell whether the stored copy is behind.
+    revision: u64,
+    /// Revision last written out, see [`SearchIndex::mark_stored`].
+    stored: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexedNote {
+    title: String,
+    updated_at: i64,
+    /// Hash of the body when indexed, to notice edits not yet in `updated_at`.
+    fingerprint: u64,
+    /// Term -> occurrences in the title and in the body.
+    terms: HashMap<String, (u32, u32)>,
+}
+
+// Only sizes: the index holds note words and titles
+impl std::fmt::Debug for SearchIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SearchIndex")
+            .field("notes", &self.notes.len())
+            .field("terms", &self.postings.len())
+            .finish()
+    }
+}
+
+impl IndexedNote {
+    fn len(&self) -> u64 {
+        self.terms.values().map(|(t, b)| (t + b) as u64).sum()
+    }
+}
+
+/// The words of a note body with their counts, all the index needs of it.
+/// Counted apart from the index, e.g. on a worker thread, so the body can be
+/// wiped as soon as it is read. The words are wiped when dropped.
+pub struct BodyTerms {
+    fingerprint: u64,
+    terms: HashMap<String, u32>,
+}
+
+impl BodyTerms {
+    pub fn new(content: &str) -> Self {
+        let mut terms: HashMap<String, u32> = HashMap::new();
+        for term in index_terms(content) {
+            *terms.entry(term).or_default() += 1;
+        }
+        BodyTerms {
+            fingerprint: fingerprint(content),
+            terms,
+        }
+    }
+}
+
+impl Drop for BodyTerms {
+    fn drop(&mut self) {
+        for (mut term, _) in self.terms.drain() {
+            term.zeroize();
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StoredIndex<'a> {
+    version: u32,
+    notes: BTreeMap<&'a str, &'a IndexedNote>,
+}
+
+#[derive(Deserialize)]
+struct LoadedIndex {
+    version: u32,
+    notes: HashMap<String, IndexedNote>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub id: String,
+    pub score: f64,
+}
+
+/// Finds where the words, phrases and regexes of a [`Query`] occur in a
+/// note's text, including the misspellings the search matched; made by
+/// [`SearchIndex::highlighter`].
+#[derive(Debug, Default)]
+pub struct Highlighter {
+    /// Query words, which also match words they start.
+    words: Vec<String>,
+    /// Indexed words the query words matched.
+    terms: HashSet<String>,
+    patterns: Vec<regex::Regex>,
+}
+
+impl Highlighter {
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.patterns.is_empty()
+    }
+
+    /// Byte ranges of the matches in `text`, in order and not overlapping.
+    pub fn ranges(&self, text: &str) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        if !self.words.is_empty() {
+            for (start, word) in word_spans(text) {
+                let term = index_term(word);
+                if self.terms.contains(&term) || self.words.iter().any(|w| term.starts_with(w)) {
+                    ranges.push(start..start + word.len());
+                }
+            }
+        }
+        for pattern in &self.patterns {
+            ranges.extend(
+                pattern
+                    .find_iter(text)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| m.range()),
+            );
+        }
+        ranges.sort_by_key(|r| r.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+}
+
+/// Lowercased words of `text`, as the index stores them.
+pub fn index_terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(index_term)
+}
+
+fn index_term(word: &str) -> String {
+    word.chars()
+        .take(MAX_TERM_CHARS)
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Words of `text` with their byte offsets, split as [`index_terms`] does.
+fn word_spans(text: &str) -> Vec<(usize, &str)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() {
+            start.get_or_insert(i);
+        } else if let Some(start) = start.take() {
+            spans.push((start, &text[start..i]));
+        }
+    }
+    if let Some(start) = start {
+        spans.push((start, &text[start..]));
+    }
+    spans
+}
+
+/// Typos allowed in a word of this many characters; none in short words,
+/// where one changed letter makes too many other words.
+fn typo_budget(len: usize) -> usize {
+    match len {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Edits (insertions, deletions, substitutions and swaps of neighbours)
+/// turning `a` into `b`, if no more than `max`.
+fn typo_distance(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    // Optimal string alignment, keeping the last two rows
+    let mut before: Vec<usize> = Vec::new();
+    let mut last: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (last[j] + 1).min(row[j - 1] + 1).min(last[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        if row.iter().min().is_some_and(|&min| min > max) {
+            return None;
+        }
+        before = std::mem::replace(&mut last, row);
+    }
+    Some(last[b.len()]).filter(|&d| d <= max)
+}
+
+/// Weight of `term` as a misspelling of `word`, or of a word starting with
+/// it, if it is within the typos `word` allows.
+fn fuzzy_weight(word: &[char], term: &str) -> Option<f64> {
+    let max = typo_budget(word.len());
+    if max == 0 {
+        return None;
+    }
+    let term: Vec<char> = term.chars().collect();
+    if let Some(typos) = typo_distance(word, &term, max) {
+        return Some(FUZZY_WEIGHT.powi(typos as i32));
+    }
+    let start = term.get(..word.len())?;
+    let typos = typo_distance(word, start, max)?;
+    Some(FUZZY_WEIGHT.powi(typos as i32) * PREFIX_WEIGHT)
+}
+
+/// The note a link to `name`, like `[[Threat model]]`, opens: the one with
+/// that title, ignoring case.
+pub fn title_match<'a>(notes: &'a [NoteItem], name: &str) -> Option<&'a NoteItem> {
+    let name = name.trim().to_lowercase();
+    notes.iter().find(|n| n.name.trim().to_lowercase() == name)
+}
+
+/// Notes a link to `name` may have meant when no title matches it: those
+/// whose title has the same words, allowing for the typos [`Query`] words
+/// do. Fewest typos first, then the latest update.
+pub fn similar_titles<'a>(notes: &'a [NoteItem], name: &str) -> Vec<&'a NoteItem> {
+    let words: Vec<Vec<char>> = index_terms(name).map(|w| w.chars().collect()).collect();
+    if words.is_empty() {
+        return Vec::new();
+    }
+    let mut similar: Vec<(usize, &NoteItem)> = notes
+        .iter()
+        .filter_map(|note| {
+            let title: Vec<Vec<char>> = index_terms(&note.name)
+                .map(|w| w.chars().collect())
+                .collect();
+            if title.len() != words.len() {
+                return None;
+            }
+            let typos = words
+                .iter()
+                .zip(&title)
+                .map(|(word, term)| typo_distance(word, term, typo_budget(word.len())))
+                .sum::<Option<usize>>()?;
+            Some((typos, note))
+        })
+        .collect();
+    similar.sort_by(|(a, x), (b, y)| a.cmp(b).then(y.updated_at.cmp(&x.updated_at)));
+    similar.into_iter().map(|(_, note)| note).collect()
+}
+
+/// FNV-1a: stable across builds, unlike `DefaultHasher`, as the value is stored.
+fn fingerprint(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+impl SearchIndex {
+    pub fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    /// True if `note` is missing from the index or was indexed before its
+    /// last change.
+    pub fn is_stale(&self, note: &NoteItem) -> bool {
+        self.notes.get(&note.id).is_none_or(|indexed| {
+            indexed.title != note.name
+                || indexed.updated_at != note.updated_at
+                || (note.loaded && indexed.fingerprint != fingerprint(&note.content))
+        })
+    }
+
+    /// Re-index the notes that changed and drop those no longer in `notes`.
+    /// `body` returns the body of a note that is not loaded, or `None` to
+    /// leave it as it is for now. Returns how many notes were indexed.
+    pub fn sync(
+        &mut self,
+        notes: &[NoteItem],
+        mut body: impl FnMut(&NoteItem) -> Option<NoteBody>,
+    ) -> usize {
+        let ids: HashSet<&str> = notes.iter().map(|n| n.id.as_str()).collect();
+        let gone: Vec<String> = self
+            .notes
+            .keys()
+            .filter(|id| !ids.contains(id.as_str()))
+            .cloned()
+            .collect();
+        for id in gone {
+            self.remove_note(&id);
+        }
+        let mut indexed = 0;
+        for note in notes {
+            if !self.is_stale(note) {
+                continue;
+            }
+            if note.loaded {
+                self.index_note(note, &note.content);
+            } else if let Some(body) = body(note) {
+                self.index_note(note, &body.content);
+            } else {
+                continue;
+            }
+            indexed += 1;
+        }
+        indexed
+    }
+
+    /// Index `note` with `content` as its body, replacing what was indexed
+    /// for it before.
+    pub fn index_note(&mut self, note: &NoteItem, content: &str) {
+        self.index_body_terms(note, BodyTerms::new(content));
+    }
+
+    /// Index `note` with the words of its body, counted by [`BodyTerms::new`]
+    /// while the body was at hand, replacing what was indexed for it before.
+    pub fn index_body_terms(&mut self, note: &NoteItem, mut body: BodyTerms) {
+        self.remove_note(&note.id);
+        let mut terms: HashMap<String, (u32, u32)> = HashMap::new();
+        for term in index_terms(&note.name) {
+            terms.entry(term).or_default().0 += 1;
+        }
+        for (term, count) in body.terms.drain() {
+            terms.entry(term).or_default().1 += count;
+        }
+        let entry = IndexedNote {
+            title: note.name.clone(),
+            updated_at: note.updated_at,
+            fingerprint: body.fingerprint,
+            terms,
+        };
+        self.insert(note.id.clone(), entry);
+    }
+
+    fn insert(&mut self, id: String, entry: IndexedNote) {
+        for term in entry.terms.keys() {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(id.clone());
+        }
+        self.total_len += entry.len();
+        self.notes.insert(id, entry);
+        self.revision += 1;
+    }
+
+    pub fn remove_note(&mut self, id: &str) {
+        let Some(mut entry) = self.notes.remove(id) else {
+            return;
+        };
+        self.total_len -= entry.len();
+        for term in entry.terms.keys() {
+            if let Some(ids) = self.postings.get_mut(term) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    if let Some((mut term, _)) = self.postings.remove_entry(term) {
+                        term.zeroize();
+                    }
+                }
+            }
+        }
+        wipe_entry(&mut entry);
+        self.revision += 1;
+    }
+
+    /// Notes containing every word of `query`, best match first. The last
+    /// word, and any other, also matches words it is the start of.
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        let words: Vec<String> = index_terms(query).collect();
+        if words.is_empty() || self.notes.is_empty() {
+            return Vec::new();
+        }
+        let mut scores: Option<HashMap<&str, f64>> = None;
+        for word in &words {
+            let word_scores = self.word_scores(word);
+            scores = Some(match scores {
+                None => word_scores,
+                Some(mut scores) => {
+                    scores.retain(|id, _| word_scores.contains_key(id));
+                    for (id, score) in &mut scores {
+                        *score += word_scores[id];
+                    }
+                    scores
+                }
+            });
+        }
+        let mut hits: Vec<SearchHit> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, score)| SearchHit {
+                id: id.to_string(),
+                score,
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.id.cmp(&b.id)));
+        hits
+    }
+
+    /// Indexed words `word` stands for, with the weight of a match on each:
+    /// the word itself and the words it starts, or if there are none, words
+    /// within a typo or two of it.
+    fn expand(&self, word: &str) -> Vec<(&str, f64)> {
+        let terms: Vec<(&str, f64)> = self
+            .postings
+            .range::<str, _>((Bound::Included(word), Bound::Unbounded))
+            .take_while(|(term, _)| term.starts_with(word))
+            .take(MAX_PREFIX_TERMS)
+            .map(|(term, _)| {
+                let weight = if term == word { 1.0 } else { PREFIX_WEIGHT };
+                (term.as_str(), weight)
+            })
+            .collect();
+        if !terms.is_empty() {
+            return terms;
+        }
+        let word: Vec<char> = word.chars().collect();
+        self.postings
+            .keys()
+            .filter_map(|term| Some((term.as_str(), fuzzy_weight(&word, term)?)))
+            .take(MAX_PREFIX_TERMS)
+            .collect()
+    }
+
+    /// BM25 score of `word`, or of the indexed words it stands for, per note
+    /// containing it; a note with it in the title gets [`TITLE_MATCH_BOOST`].
+    fn word_scores(&self, word: &str) -> HashMap<&str, f64> {
+        let mut word_scores: HashMap<&str, f64> = HashMap::new();
+        if self.notes.is_empty() {
+            return word_scores;
+        }
+        let count = self.notes.len() as f64;
+        let avg_len = (self.total_len as f64 / count).max(1.0);
+        for (term, weight) in self.expand(word) {
+            let ids = &self.postings[term];
+            let n = ids.len() as f64;
+            let idf = (1.0 + (count - n + 0.5) / (n + 0.5)).ln();
+            for id in ids {
+                let entry = &self.notes[id];
+                let (title, body) = entry.terms[term];
+                let tf = (title * TITLE_WEIGHT + body) as f64;
+                let norm = 1.0 - BM25_B + BM25_B * entry.len() as f64 / avg_len;
+                let mut score = idf * tf * (BM25_K1 + 1.0) / (tf + BM25_K1 * norm);
+                if title > 0 {
+                    score += TITLE_MATCH_BOOST;
+                }
+                let best = word_scores.entry(id.as_str()).or_default();
+                *best = best.max(weight * score);
+            }
+        }
+        word_scores
+    }
+
+    /// What to highlight in the notes `query` matches.
+    pub fn highlighter(&self, query: &Query) -> Highlighter {
+        let mut highlighter = Highlighter::default();
+        for atom in query.clauses.iter().flatten().filter(|a| !a.negated) {
+            match &atom.test {
+                Test::Words(words) => {
+                    for word in words {
+                        let terms = self.expand(word).into_iter();
+                        highlighter.terms.extend(terms.map(|(t, _)| t.to_string()));
+                        highlighter.words.push(word.clone());
+                    }
+                }
+                Test::Phrase(phrase) => {
+                    let pattern = regex::RegexBuilder::new(&regex::escape(phrase))
+                        .case_insensitive(true)
+                        .build();
+                    highlighter.patterns.extend(pattern);
+                }
+                Test::Regex(regex) => highlighter.patterns.push(regex.clone()),
+                _ => {}
+            }
+        }
+        highlighter
+    }
+
+    /// Serialized index of the notes `keep` accepts, with its revision, or
+    /// `None` if nothing changed since it was last stored. Plain text:
+    /// encrypt before storing, then pass the revision to
+    /// [`SearchIndex::mark_stored`].
+    pub fn to_bytes(&self, keep: impl Fn(&str) -> bool) -> Option<(u64, Zeroizing<Vec<u8>>)> {
+        if self.revision == self.stored {
+            return None;
+        }
+        let stored = StoredIndex {
+            version: INDEX_VERSION,
+            notes: self
+                .notes
+                .iter()
+                .filter(|(id, _)| keep(id))
+                .map(|(id, entry)| (id.as_str(), entry))
+                .collect(),
+        };
+        let bytes = serde_json::to_vec(&stored).ok()?;
+        Some((self.revision, Zeroizing::new(bytes)))
+    }
+
+    /// Record that the bytes [`SearchIndex::to_bytes`] gave for `revision`
+    /// were written, so they are not written again until the next change.
+    pub fn mark_stored(&mut self, revision: u64) {
+        self.stored = self.stored.max(revision);
+    }
+
+    /// An index read back from [`SearchIndex::to_bytes`]; `None` if it is
+    /// damaged or from another version, and has to be rebuilt.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let loaded: LoadedIndex = serde_json::from_slice(bytes).ok()?;
+        if loaded.version != INDEX_VERSION {
+            return None;
+        }
+        let mut index = SearchIndex::default();
+        for (id, entry) in loaded.notes {
+            index.insert(id, entry);
+        }
+        index.stored = index.revision;
+        Some(index)
+    }
+
+    /// Empty the index, wiping the words and titles it held.
+    pub fn clear(&mut self) {
+        for entry in self.notes.values_mut() {
+            wipe_entry(entry);
+        }
+        self.notes.clear();
+        for (mut term, _) in std::mem::take(&mut self.postings) {
+            term.zeroize();
+        }
+        self.total_len = 0;
+        self.stored = self.revision;
+    }
+}
+
+fn wipe_entry(entry: &mut IndexedNote) {
+    entry.title.zeroize();
+    for (mut term, _) in entry.terms.drain() {
+        term.zeroize();
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Query language
+// ---------------------------------------------------------------------------
+
+/// A search query that can't be run, e.g. one with a malformed `/regex/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError(pub String);
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Longest compiled `/regex/`, so a query can't make every note slow to test.
+const MAX_REGEX_SIZE: usize = 1 << 20;
+
+/// A parsed search query. Terms separated by spaces must all match; terms
+/// joined by `OR` match if either does.
+///
+/// | Term | Matches notes |
+/// |---|---|
+/// | `ingest job` | with words starting with `ingest` and `job`, or if there are none, words a typo or two away (one in words of 4 to 7 letters), ranked |
+/// | `"ingest job"` | containing that text |
+/// | `/in.*st/` | matching the regex, ignoring case |
+/// | `-term` | not matching `term` |
+/// | `tag:security` | with the tag |
+/// | `folder:"Client A"` | in that folder or one below it |
+/// | `updated:>2026-01-01`, `created:<=2025-06`, `updated:7d` | by date (`YYYY[-MM[-DD]]`, UTC) or age in days or weeks |
+/// | `pinned:true`, `is:pinned` | pinned, or not with `pinned:false` |
+/// | `has:image`, `has:task` | with an image or a task list item |
+/// | `is:todo` | with an unchecked task |
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// All must match; each matches if any of its atoms does.
+    clauses: Vec<Vec<Atom>>,
+    /// When the query was parsed, for relative dates and the recency boost.
+    now: i64,
+}
+
+#[derive(Debug, Clone)]
+struct Atom {
+    negated: bool,
+    test: Test,
+}
+
+#[derive(Debug, Clone)]
+enum Test {
+    /// Words, as [`index_terms`] splits them, that must all be indexed for
+    /// the note.
+    Words(Vec<String>),
+    /// Lowercased text that must occur in the title or body.
+    Phrase(String),
+    Regex(regex::Regex),
+    /// Lowercased tag.
+    Tag(String),
+    /// Lowercased folder name.
+    Folder(String),
+    Updated(DateTest),
+    Created(DateTest),
+    Pinned(bool),
+    HasImage,
+    HasTask,
+    IsTodo,
+}
+
+/// A time compared against a span: a calendar day, month or year, or an
+/// instant for relative ages like `7d`.
+#[derive(Debug, Clone, Copy)]
+struct DateTest {
+    op: DateOp,
+    start: i64,
+    end: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateOp {
+    Before,
+    AtOrBefore,
+    Within,
+    AtOrAfter,
+    After,
+}
+
+impl DateTest {
+    fn matches(&self, ts: i64) -> bool {
+        match self.op {
+            DateOp::Before => ts < self.start,
+            DateOp::AtOrBefore => ts < self.end,
+            DateOp::Within => self.start <= ts && ts < self.end,
+            DateOp::AtOrAfter => ts >= self.start,
+            DateOp::After => ts >= self.end,
+        }
+    }
+}
+
+impl Test {
+    /// Needs the note body, which may have to be decrypted.
+    fn needs_body(&self) -> bool {
+        matches!(
+            self,
+            Test::Phrase(_) | Test::Regex(_) | Test::HasImage | Test::HasTask | Test::IsTodo
+        )
+    }
+
+    /// Index words whose scores rank the notes this test matches.
+    fn words(&self) -> Vec<String> {
+        match self {
+            Test::Words(words) => words.clone(),
+            Test::Phrase(phrase) => index_terms(phrase).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, QueryError> {
+        Self::parse_at(input, crate::state::unix_now())
+    }
+
+    /// Parse with `now` as the time relative dates count back from.
+    fn parse_at(input: &str, now: i64) -> Result<Query, QueryError> {
+        let mut query = Query {
+            now,
+            ..Query::default()
+        };
+        let mut rest = input.trim_start();
+        let mut join = false;
+        while !rest.is_empty() {
+            let (token, tail) = split_token(rest);
+            rest = tail.trim_start();
+            if token == "OR" {
+                join = !query.clauses.is_empty();
+                continue;
+            }
+            // A term still being typed, like `tag:` or a lone `-`, is skipped
+            let Some(atom) = parse_atom(token, now)? else {
+                continue;
+            };
+            match query.clauses.last_mut() {
+                Some(clause) if join => clause.push(atom),
+                _ => query.clauses.push(vec![atom]),
+            }
+            join = false;
+        }
+        Ok(query)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clauses.is_empty()
+    }
+
+    /// Also require the notes to have `tags`, all of them if `all` is set
+    /// and any of them otherwise, as `tag:a tag:b` or `tag:a OR tag:b` would.
+    pub fn add_tag_filter(&mut self, tags: &[impl AsRef<str>], all: bool) {
+        let atoms = tags.iter().map(|tag| Atom {
+            negated: false,
+            test: Test::Tag(tag.as_ref().to_lowercase()),
+        });
+        if all {
+            self.clauses.extend(atoms.map(|atom| vec![atom]));
+        } else if !tags.is_empty() {
+            self.clauses.push(atoms.collect());
+        }
+    }
+}
+
+/// The next term of a query and the rest; spaces inside quotes, as in
+/// `folder:"Client A"`, and inside `/regex/` don't end a term.
+fn split_token(input: &str) -> (&str, &str) {
+    let body = input.strip_prefix('-').unwrap_or(input);
+    let offset = input.len() - body.len();
+    let end = if let Some(pattern) = body.strip_prefix('/') {
+        closing_slash(pattern).map_or(body.len(), |i| i + 2)
+    } else {
+        let mut quoted = false;
+        body.char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                c.is_whitespace() && !quoted
+            })
+            .map_or(body.len(), |(i, _)| i)
+    };
+    input.split_at(offset + end)
+}
+
+/// Byte offset of the first `/` in `pattern` not escaped as `\/`.
+fn closing_slash(pattern: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in pattern.char_indices() {
+        match c {
+            '/' if !escaped => return Some(i),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+fn parse_atom(token: &str, now: i64) -> Result<Option<Atom>, QueryError> {
+    let (negated, body) = match token.strip_prefix('-') {
+        Some(rest) if !rest.is_empty() => (true, rest),
+        _ => (false, token),
+    };
+    let test = if let Some(pattern) = body.strip_prefix('/') {
+        // An unclosed regex runs to the end, so it matches while typed
+        let pattern = pattern.strip_suffix('/').unwrap_or(pattern);
+        if pattern.is_empty() {
+            return Ok(None);
+        }
+        let regex = regex::RegexBuilder::new(&pattern.replace("\\/", "/"))
+            .case_insensitive(true)
+            .size_limit(MAX_REGEX_SIZE)
+            .build()
+            .map_err(|_| QueryError(format!("Invalid regular expression /{pattern}/")))?;
+        Some(Test::Regex(regex))
+    } else if let Some(phrase) = body.strip_prefix('"') {
+        let phrase = phrase.strip_suffix('"').unwrap_or(phrase);
+        (!phrase.trim().is_empty()).then(|| Test::Phrase(phrase.to_lowercase()))
+    } else {
+        match body.split_once(':') {
+            Some((field, value)) if is_field(field) => {
+                parse_field(&field.to_lowercase(), &value.replace('"', ""), now)?
+            }
+            _ => {
+                let words: Vec<String> = index_terms(body).collect();
+                (!words.is_empty()).then_some(Test::Words(words))
+            }
+        }
+    };
+    Ok(test.map(|test| Atom { negated, test }))
+}
+
+fn is_field(name: &str) -> bool {
+    ["tag", "folder", "updated", "created", "pinned", "has", "is"]
+        .iter()
+        .any(|field| name.eq_ignore_ascii_case(field))
+}
+
+fn parse_field(field: &str, value: &str, now: i64) -> Result<Option<Test>, QueryError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let lower = value.to_lowercase();
+    let test = match field {
+        "tag" => Test::Tag(lower.trim_start_matches('#').to_string()),
+        "folder" => Test::Folder(lower),
+        "updated" | "created" => {
+            let date = parse_date_test(&lower, now).ok_or_else(|| {
+                QueryError(format!(
+                    "{field}: needs a date like 2026-01-01, or an age like 7d or 2w"
+                ))
+            })?;
+            if field == "updated" {
+                Test::Updated(date)
+            } else {
+                Test::Created(date)
+            }
+        }
+        "pinned" => match lower.as_str() {
+            "true" | "yes" => Test::Pinned(true),
+            "false" | "no" => Test::Pinned(false),
+            _ => return Err(QueryError("pinned: needs true or false".into())),
+        },
+        "has" => match lower.as_str() {
+            "image" | "images" => Test::HasImage,
+            "task" | "tasks" => Test::HasTask,
+            _ => return Err(QueryError("has: needs image or task".into())),
+        },
+        _ => match lower.as_str() {
+            "todo" => Test::IsTodo,
+            "pinned" => Test::Pinned(true),
+            _ => return Err(QueryError("is: needs todo or pinned".into())),
+        },
+    };
+    Ok(Some(test))
+}
+
+/// `>2026-01-01`, `<=2025-06`, `2024`, `>7d`, ... A bare age means within
+/// that long, a bare date on that day, month or year.
+fn parse_date_test(value: &str, now: i64) -> Option<DateTest> {
+    let (op, value) = [
+        (">=", DateOp::AtOrAfter),
+        ("<=", DateOp::AtOrBefore),
+        (">", DateOp::After),
+        ("<", DateOp::Before),
+        ("=", DateOp::Within),
+    ]
+    .into_iter()
+    .find_map(|(prefix, op)| Some((op, value.strip_prefix(prefix)?)))
+    .unwrap_or((DateOp::Within, value));
+
+    let unit = match value.chars().last()? {
+        'd' => Some(86_400),
+        'w' => Some(7 * 86_400),
+        _ => None,
+    };
+    if let Some(unit) = unit {
+        let count: i64 = value[..value.len() - 1].parse().ok()?;
+        let at = now.checked_sub(count.checked_mul(unit)?)?;
+        // Only the instant matters: `7d` is after it, like `>7d`
+        let op = if op == DateOp::Within {
+            DateOp::AtOrAfter
+        } else {
+            op
+        };
+        return Some(DateTest {
+            op,
+            start: at,
+            end: at,
+        });
+    }
+
+    let mut parts = value.split('-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: Option<i64> = parts.next().map(str::parse).transpose().ok()?;
+    let day: Option<i64> = parts.next().map(str::parse).transpose().ok()?;
+    if parts.next().is_some() || !(1..=9999).contains(&year) {
+        return None;
+    }
+    let day_start = |y: i64, m: i64, d: i64| {
+        crate::markdown_tree::parse_timestamp(&format!("{y:04}-{m:02}-{d:02}"))
+    };
+    let (start, end) = match (month, day) {
+        (None, _) => (day_start(year, 1, 1)?, day_start(year + 1, 1, 1)?),
+        (Some(m), None) => {
+            let next = if m == 12 {
+                (year + 1, 1)
+            } else {
+                (year, m + 1)
+            };
+            (day_start(year, m, 1)?, day_start(next.0, next.1, 1)?)
+        }
+        (Some(m), Some(d)) => {
+            let start = day_start(year, m, d)?;
+            (start, start + 86_400)
+        }
+    };
+    Some(DateTest { op, start, end })
+}
+
+/// A Markdown image, `![alt](src)`, or an HTML `<img>`.
+fn has_image(text: &str) -> bool {
+    text.match_indices("![")
+        .any(|(i, _)| text[i..].lines().next().is_some_and(|l| l.contains("](")))
+        || contains_case_insensitive(text, "<img")
+}
+
+/// `Some(checked)` for a task list item like `- [ ] call back`.
+fn task_state(line: &str) -> Option<bool> {
+    let line = line.trim_start();
+    let rest = match line.strip_prefix(['-', '*', '+']) {
+        Some(rest) => rest,
+        None => {
+            let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            if digits == 0 {
+                return None;
+            }
+            line[digits..].strip_prefix(['.', ')'])?
+        }
+    };
+    let rest = rest.strip_prefix(' ')?.trim_start();
+    match rest.get(..3)? {
+        "[ ]" => Some(false),
+        "[x]" | "[X]" => Some(true),
+        _ => None,
+    }
+}
+
+/// A note being tested against a query, with its body read when a test first
+/// needs it and wiped when the subject is dropped.
+struct Subject<'a> {
+    note: &'a NoteItem,
+    body: Option<Option<NoteBody>>,
+}
+
+impl Subject<'_> {
+    fn content(&mut self, fetch: &mut impl FnMut(&NoteItem) -> Option<NoteBody>) -> Option<&str> {
+        if self.note.loaded {
+            return Some(&self.note.content);
+        }
+        let note = self.note;
+        self.body
+            .get_or_insert_with(|| fetch(note))
+            .as_ref()
+            .map(|body| body.content.as_str())
+    }
+}
+
+/// What the tests need besides the note.
+struct QueryScope<'a> {
+    scores: HashMap<String, HashMap<&'a str, f64>>,
+    /// Folder ID -> lowercased name and parent ID.
+    folders: HashMap<&'a str, (String, Option<&'a str>)>,
+}
+
+impl QueryScope<'_> {
+    fn score(&self, word: &str, id: &str) -> Option<f64> {
+        self.scores.get(word)?.get(id).copied()
+    }
+
+    fn in_folder(&self, folder_id: Option<&str>, name: &str) -> bool {
+        let mut folder = folder_id.and_then(|id| self.folders.get(id));
+        // Bounded, in case of a parent cycle
+        for _ in 0..=self.folders.len() {
+            let Some((folder_name, parent)) = folder else {
+                return false;
+            };
+            if folder_name == name {
+                return true;
+            }
+            folder = parent.and_then(|id| self.folders.get(id));
+        }
+        false
+    }
+
+    /// The score the test gives `subject`, if it matches.
+    fn test(
+        &self,
+        test: &Test,
+        subject: &mut Subject,
+        fetch: &mut impl FnMut(&NoteItem) -> Option<NoteBody>,
+    ) -> Option<f64> {
+        let note = subject.note;
+        let matched = match test {
+            Test::Words(words) => {
+                return words.iter().map(|word| self.score(word, &note.id)).sum();
+            }
+            Test::Phrase(phrase) => {
+                contains_case_insensitive(&note.name, phrase)
+                    || subject
+                        .content(fetch)
+                        .is_some_and(|text| contains_case_insensitive(text, phrase))
+            }
+            Test::Regex(regex) => {
+                regex.is_match(&note.name)
+                    || subject.content(fetch).is_some_and(|t| regex.is_match(t))
+            }
+            Test::Tag(tag) => note.tags.iter().any(|t| t.to_lowercase() == *tag),
+            Test::Folder(name) => self.in_folder(note.parent_id.as_deref(), name),
+            Test::Updated(date) => date.matches(note.updated_at),
+            Test::Created(date) => date.matches(note.created_at),
+            Test::Pinned(pinned) => note.pinned == *pinned,
+            Test::HasImage => subject.content(fetch).is_some_and(has_image),
+            Test::HasTask => subject
+                .content(fetch)
+                .is_some_and(|text| text.lines().any(|l| task_state(l).is_some())),
+            Test::IsTodo => subject
+                .content(fetch)
+                .is_some_and(|text| text.lines().any(|l| task_state(l) == Some(false))),
+        };
+        // Phrases rank by their words, other tests don't add to the score
+        matched.then(|| {
+            test.words()
+                .iter()
+                .filter_map(|word| self.score(word, &note.id))
+                .sum()
+        })
+    }
+
+    /// The best score among the atoms of `clause` that match.
+    fn clause(
+        &self,
+        clause: &[Atom],
+        subject: &mut Subject,
+        fetch: &mut impl FnMut(&NoteItem) -> Option<NoteBody>,
+    ) -> Option<f64> {
+        let mut best: Option<f64> = None;
+        for atom in clause {
+            let score = match self.test(&atom.test, subject, fetch) {
+                Some(_) if atom.negated => None,
+                None if atom.negated => Some(0.0),
+                score => score,
+            };
+            if let Some(score) = score {
+                best = Some(best.map_or(score, |best| best.max(score)));
+            }
+        }
+        best
+    }
+}
+
+/// Factor raising the score of recently updated notes; see [`RECENCY_BOOST`].
+fn recency_factor(updated_at: i64, now: i64) -> f64 {
+    let days = (now - updated_at).max(0) as f64 / 86_400.0;
+    1.0 + RECENCY_BOOST * 0.5f64.powf(days / RECENCY_HALF_LIFE_DAYS)
+}
+
+impl SearchIndex {
+    /// Notes matching `query`, best match first: notes with the words in
+    /// the title, then by BM25 score, raised for recently updated notes.
+    /// Notes that only filters match keep their order in `notes`. `body`
+    /// returns the body of a note that is not loaded, and is asked only when
+    /// a test needs the text and the other tests didn't already rule the
+    /// note out.
+    pub fn query<'n>(
+        &self,
+        query: &Query,
+        notes: impl IntoIterator<Item = &'n NoteItem>,
+        folders: &[FolderItem],
+        mut body: impl FnMut(&NoteItem) -> Option<NoteBody>,
+    ) -> Vec<SearchHit> {
+        let scope = QueryScope {
+            scores: query
+                .clauses
+                .iter()
+                .flatten()
+                .flat_map(|atom| atom.test.words())
+                .map(|word| {
+                    let scores = self.word_scores(&word);
+                    (word, scores)
+                })
+                .collect(),
+            folders: folders
+                .iter()
+                .map(|f| {
+                    (
+                        f.id.as_str(),
+                        (f.name.to_lowercase(), f.parent_id.as_deref()),
+                    )
+                })
+                .collect(),
+        };
+        // Tests that read the body last, so fewer bodies are decrypted
+        let (cheap, costly): (Vec<&Vec<Atom>>, Vec<&Vec<Atom>>) = query
+            .clauses
+            .iter()
+            .partition(|clause| !clause.iter().any(|atom| atom.test.needs_body()));
+
+        let mut hits = Vec::new();
+        for note in notes {
+            let mut subject = Subject { note, body: None };
+            let mut score = 0.0;
+            let matched = cheap.iter().chain(&costly).all(|clause| {
+                scope
+                    .clause(clause, &mut subject, &mut body)
+                    .map(|s| score += s)
+                    .is_some()
+            });
+            if matched {
+                if score > 0.0 {
+                    score *= recency_factor(note.updated_at, query.now);
+                }
+                hits.push(SearchHit {
+                    id: note.id.clone(),
+                    score,
+                });
+            }
+        }
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits
+    }
+}
+
+// ---------------------------------------------------------------------------
+// History and trash
+// ---------------------------------------------------------------------------
+
+/// Where a text found by [`search_archived`] is kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchivedId {
+    /// Snapshot `index` in the note's `versions`.
+    Version { note_id: String, index: usize },
+    /// A note in the trash.
+    Trash(String),
+}
+
+/// A note snapshot or a trashed note. The index holds neither, so these are
+/// searched with an [`ArchivedIndex`]. The text is wiped when dropped.
+pub struct ArchivedText {
+    pub source: ArchivedId,
+    /// The text as a note of its own, under an ID no other text shares.
+    note: NoteItem,
+    ts: i64,
+}
+
+impl ArchivedText {
+    /// Snapshot `index` of `note`, saved at `ts`. It is searched with the
+    /// note's current title, tags and folder.
+    pub fn version(note: &NoteItem, index: usize, ts: i64, content: &str) -> Self {
+        ArchivedText {
+            source: ArchivedId::Version {
+                note_id: note.id.clone(),
+                index,
+            },
+            note: NoteItem {
+                id: format!("{}@{index}", note.id),
+                name: note.name.clone(),
+                content: content.to_string(),
+                tags: note.tags.clone(),
+                created_at: note.created_at,
+                updated_at: ts,
+                versions: Vec::new(),
+                file_path: None,
+                parent_id: note.parent_id.clone(),
+                pinned: note.pinned,
+                loaded: true,
+                stored_in: note.stored_in.clone(),
+            },
+            ts,
+        }
+    }
+
+    /// The trashed note `item`, whose body is `content`.
+    pub fn trashed(item: &TrashItem, content: &str) -> Self {
+        ArchivedText {
+            source: ArchivedId::Trash(item.id.clone()),
+            note: NoteItem {
+                id: format!("trash:{}", item.id),
+                name: item.name.clone(),
+                content: content.to_string(),
+                tags: item.tags.clone(),
+                created_at: item.created_at,
+                updated_at: item.updated_at,
+                versions: Vec::new(),
+                file_path: None,
+                parent_id: item.parent_id.clone(),
+                pinned: item.pinned,
+                loaded: true,
+                stored_in: item.stored_in.clone(),
+            },
+            ts: item.deleted_at,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.note.name
+    }
+
+    pub fn content(&self) -> &str {
+        &self.note.content
+    }
+
+    /// When the snapshot was saved or the note was deleted.
+    pub fn ts(&self) -> i64 {
+        self.ts
+    }
+}
+
+impl Drop for ArchivedText {
+    fn drop(&mut self) {
+        self.note.name.zeroize();
+        self.note.content.zeroize();
+    }
+}
+
+/// Snapshots or trashed notes with an index of their own, built once and
+/// searched as often as needed, like while a history search is typed. The
+/// texts and the index are wiped when dropped.
+pub struct ArchivedIndex {
+    texts: Vec<ArchivedText>,
+    index: SearchIndex,
+}
+
+impl ArchivedIndex {
+    pub fn new(texts: Vec<ArchivedText>) -> Self {
+        let index = index_archived(&texts);
+        ArchivedIndex { texts, index }
+    }
+
+    pub fn texts(&self) -> &[ArchivedText] {
+        &self.texts
+    }
+
+    /// Search with `query` as notes are searched. Returns the positions in
+    /// [`ArchivedIndex::texts`] of those that match with their scores, best
+    /// match first, and a highlighter for the matches.
+    pub fn search(
+        &self,
+        query: &Query,
+        folders: &[FolderItem],
+    ) -> (Vec<(usize, f64)>, Highlighter) {
+        query_archived(&self.index, query, &self.texts, folders)
+    }
+}
+
+impl Drop for ArchivedIndex {
+    fn drop(&mut self) {
+        self.index.clear();
+    }
+}
+
+/// Search `texts` as [`ArchivedIndex::search`] does, indexing them just for
+/// this search.
+pub fn search_archived(
+    query: &Query,
+    texts: &[ArchivedText],
+    folders: &[FolderItem],
+) -> (Vec<(usize, f64)>, Highlighter) {
+    let mut index = index_archived(texts);
+    let found = query_archived(&index, query, texts, folders);
+    index.clear();
+    found
+}
+
+fn index_archived(texts: &[ArchivedText]) -> SearchIndex {
+    let mut index = SearchIndex::default();
+    for text in texts {
+        index.index_note(&text.note, &text.note.content);
+    }
+    index
+}
+
+fn query_archived(
+    index: &SearchIndex,
+    query: &Query,
+    texts: &[ArchivedText],
+    folders: &[FolderItem],
+) -> (Vec<(usize, f64)>, Highlighter) {
+    let positions: HashMap<&str, usize> = texts
+        .iter()
+        .enumerate()
+        .map(|(i, text)| (text.note.id.as_str(), i))
+        .collect();
+    let hits = index
+        .query(query, texts.iter().map(|text| &text.note), folders, |_| {
+            None
+        })
+        .into_iter()
+        .filter_map(|hit| Some((*positions.get(hit.id.as_str())?, hit.score)))
+        .collect();
+    (hits, index.highlighter(query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, name: &str, content: &str) -> NoteItem {
+        NoteItem::new(id.into(), name.into(), content.into(), Vec::new())
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter().map(|h| h.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_index_ranks_and_matches_all_words() {
+        let notes = vec![
+            note("note-1", "Ingest runbook", "Restart the ingest job."),
+            note(
+                "note-2",
+                "Standup",
+                "Talked about the ingest backlog and lunch.",
+            ),
+            note("note-3", "Threat model", "Payments flow, token storage."),
+        ];
+        let mut index = SearchIndex::default();
+        assert_eq!(index.sync(&notes, |_| None), 3);
+
+        // Title matches outrank body matches
+        assert_eq!(ids(&index.search("ingest")), ["note-1", "note-2"]);
+        assert_eq!(ids(&index.search("INGEST backlog")), ["note-2"]);
+        // The word being typed matches as a prefix
+        assert_eq!(ids(&index.search("paym")), ["note-3"]);
+        assert!(index.search("ingest payments").is_empty());
+        assert!(index.search("  ").is_empty());
+    }
+
+    #[test]
+    fn test_index_sync_is_incremental_and_round_trips() {
+        let mut notes = vec![
+            note("note-1", "Alpha", "first body"),
+            note("note-2", "Beta", "second body"),
+        ];
+        let mut index = SearchIndex::default();
+        index.sync(&notes, |_| None);
+        assert_eq!(index.sync(&notes, |_| None), 0);
+
+        notes[0].content = "rewritten text".into();
+        notes.remove(1);
+        assert_eq!(index.sync(&notes, |_| None), 1);
+        assert!(index.search("first").is_empty());
+        assert!(index.search("second").is_empty());
+        assert_eq!(ids(&index.search("rewritten")), ["note-1"]);
+
+        // Stored, then read back for a note whose body is not loaded
+        let (revision, bytes) = index.to_bytes(|_| true).unwrap();
+        // Still changed until the write is known to have worked
+        assert!(index.to_bytes(|_| true).is_some());
+        index.mark_stored(revision);
+        assert!(index.to_bytes(|_| true).is_none());
+        let mut loaded = SearchIndex::from_bytes(&bytes).unwrap();
+        notes[0].content.clear();
+        notes[0].loaded = false;
+        let mut decrypted = 0;
+        let reindexed = loaded.sync(&notes, |_| {
+            decrypted += 1;
+            None
+        });
+        assert_eq!((reindexed, decrypted), (0, 0));
+        assert_eq!(ids(&loaded.search("rewritten")), ["note-1"]);
+
+        // Renamed while not loaded: the body is asked for
+        notes[0].name = "Gamma".into();
+        loaded.sync(&notes, |_| {
+            Some(NoteBody {
+                content: "rewritten text".into(),
+                versions: Vec::new(),
+            })
+        });
+        assert_eq!(ids(&loaded.search("gamma text")), ["note-1"]);
+        assert!(loaded.search("alpha").is_empty());
+    }
+
+    #[test]
+    fn test_index_keeps_no_body_text() {
+        let body = "- [ ] open threat models";
+        let mut notes = vec![note("note-1", "Plan", body)];
+        let mut index = SearchIndex::default();
+        index.index_note(&notes[0], body);
+        let (_, bytes) = index.to_bytes(|_| true).unwrap();
+        let stored = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(!stored.contains(body) && !stored.contains("threat models"));
+        assert_eq!(ids(&index.search("threat")), ["note-1"]);
+
+        // Body tests decrypt the note they test, and only that one
+        let loaded = SearchIndex::from_bytes(&bytes).unwrap();
+        notes[0].content.clear();
+        notes[0].loaded = false;
+        notes.push(note("note-2", "Other", "nothing here"));
+        notes[1].loaded = false;
+        let mut decrypted = Vec::new();
+        let query = Query::parse("plan \"threat models\" is:todo").unwrap();
+        let hits = loaded.query(&query, &notes, &[], |note| {
+            decrypted.push(note.id.clone());
+            Some(NoteBody {
+                content: body.into(),
+                versions: Vec::new(),
+            })
+        });
+        assert_eq!(ids(&hits), ["note-1"]);
+        assert_eq!(decrypted, ["note-1"]);
+    }
+
+    #[test]
+    fn test_body_terms_index_like_the_body() {
+        let body = "Rotate the signing keys, then rotate the tokens.";
+        let keys = note("note-1", "Keys", body);
+        let mut direct = SearchIndex::default();
+        direct.index_note(&keys, body);
+
+        // Counted without the index, as a worker does before wiping the body
+        let mut counted = SearchIndex::default();
+        let mut content = body.to_string();
+        let terms = BodyTerms::new(&content);
+        content.zeroize();
+        counted.index_body_terms(&keys, terms);
+
+        assert!(!counted.is_stale(&keys));
+        assert_eq!(counted.search("rotate"), direct.search("rotate"));
+        let (_, bytes) = counted.to_bytes(|_| true).unwrap();
+        let stored = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(!stored.contains("signing keys"));
+    }
+
+    #[test]
+    fn test_query_language() {
+        let folder = |id: &str, name: &str, parent: Option<&str>| FolderItem {
+            id: id.into(),
+            name: name.into(),
+            expanded: true,
+            created_at: 0,
+            updated_at: 0,
+            parent_id: parent.map(str::to_string),
+            locked: false,
+        };
+        let folders = vec![
+            folder("folder-1", "Client A", None),
+            folder("folder-2", "Audits", Some("folder-1")),
+        ];
+        let mut notes = vec![
+            note(
+                "note-1",
+                "Threat model",
+                "Token storage.\n\n- [ ] rotate keys",
+            ),
+            note(
+                "note-2",
+                "Standup",
+                "- [x] ingest job\n![graph](vault://abc)",
+            ),
+            note("note-3", "Ingest runbook", "Restart the ingest job."),
+        ];
+        notes[0].tags = vec!["Security".into()];
+        notes[0].parent_id = Some("folder-2".into());
+        notes[0].updated_at = 1_767_312_000; // 2026-01-02
+        notes[1].pinned = true;
+        notes[1].updated_at = 1_735_689_600; // 2025-01-01
+        notes[2].tags = vec!["ops".into()];
+        notes[2].updated_at = 1_700_000_000; // 2023-11-14
+        let mut index = SearchIndex::default();
+        index.sync(&notes, |_| None);
+
+        let now = 1_767_571_200; // 2026-01-05
+        let run = |input: &str| -> Vec<String> {
+            let query = Query::parse_at(input, now).unwrap();
+            let hits = index.query(&query, &notes, &folders, |_| None);
+            hits.into_iter().map(|hit| hit.id).collect()
+        };
+        assert_eq!(run("tag:security"), ["note-1"]);
+        assert_eq!(run("folder:\"client a\""), ["note-1"]);
+        assert_eq!(run("updated:>2026-01-01"), ["note-1"]);
+        assert_eq!(run("updated:2025"), ["note-2"]);
+        assert_eq!(run("updated:<=2025-12 -pinned:true"), ["note-3"]);
+        assert_eq!(run("updated:7d"), ["note-1"]);
+        assert_eq!(run("is:pinned"), ["note-2"]);
+        assert_eq!(run("has:image"), ["note-2"]);
+        assert_eq!(run("has:task"), ["note-1", "note-2"]);
+        assert_eq!(run("is:todo"), ["note-1"]);
+        assert_eq!(run("\"the ingest\""), ["note-3"]);
+        assert_eq!(run("ingest -standup"), ["note-3"]);
+        assert_eq!(run("/tok.n|restart/"), ["note-1", "note-3"]);
+        assert_eq!(run("tag:ops OR tag:security token"), ["note-1"]);
+        // Ranked words order the results, filters keep the note order
+        assert_eq!(run("ingest"), ["note-3", "note-2"]);
+        assert_eq!(run(""), ["note-1", "note-2", "note-3"]);
+
+        // The tag filter is one more clause
+        let mut query = Query::parse_at("job", now).unwrap();
+        query.add_tag_filter(&["ops", "security"], false);
+        let hits = index.query(&query, &notes, &folders, |_| None);
+        assert_eq!(ids(&hits), ["note-3"]);
+
+        assert!(Query::parse("/(unclosed/").is_err());
+        assert!(Query::parse("has:video").is_err());
+        assert!(Query::parse("updated:>yesterday").is_err());
+        // Terms still being typed don't fail
+        assert!(Query::parse("tag: - \"half /abc").is_ok());
+    }
+
+    #[test]
+    fn test_fuzzy_search_ranks_titles_and_recent_notes_first() {
+        let now = 1_767_571_200;
+        let mut notes = vec![
+            note("note-1", "Login", "Authentication via SSO."),
+            note("note-2", "Authentication flow", "Tokens and sessions."),
+            note("note-3", "Old login", "Authentication via SSO."),
+        ];
+        notes[0].updated_at = now - 86_400;
+        notes[1].updated_at = now - 400 * 86_400;
+        notes[2].updated_at = now - 400 * 86_400;
+        let mut index = SearchIndex::default();
+        index.sync(&notes, |_| None);
+        let query = Query::parse_at("authentcation", now).unwrap();
+        let hits = index.query(&query, &notes, &[], |_| None);
+        assert_eq!(ids(&hits), ["note-2", "note-1", "note-3"]);
+        // No typos are allowed in short words
+        let query = Query::parse_at("lgn", now).unwrap();
+        assert!(index.query(&query, &notes, &[], |_| None).is_empty());
+
+        // Misspelled words and phrases are highlighted where they occur
+        let query = Query::parse_at("authentcation \"via sso\"", now).unwrap();
+        let text = "Authentication via SSO, authenticated.";
+        let ranges = index.highlighter(&query).ranges(text);
+        assert_eq!(ranges, [0..14, 15..22]);
+        let query = Query::parse_at("auth", now).unwrap();
+        assert_eq!(index.highlighter(&query).ranges(text), [0..14, 24..37]);
+
+        assert_eq!(
+            typo_distance(&['t', 'e', 'h'], &['t', 'h', 'e'], 1),
+            Some(1)
+        );
+        // Links open only the note with that title; the rest are offered
+        assert!(title_match(&notes, "authentcation flow").is_none());
+        let similar = similar_titles(&notes, "authentcation flow");
+        assert_eq!(
+            similar.iter().map(|n| n.id.as_str()).collect::<Vec<_>>(),
+            ["note-2"]
+        );
+        assert_eq!(
+            title_match(&notes, " LOGIN ").map(|n| n.id.as_str()),
+            Some("note-1")
+        );
+        assert!(similar_titles(&notes, "flow").is_empty());
+    }
+
+    #[test]
+    fn test_search_archived_snapshots_and_trash() {
+        let mut live = note("note-1", "Runbook", "Restart the service.");
+        live.tags = vec!["ops".into()];
+        let trashed = TrashItem {
+            id: "note-2".into(),
+            name: "Old runbook".into(),
+            content: String::new(),
+            tags: Vec::new(),
+            created_at: 0,
+            updated_at: 1_700_000_000,
+            deleted_at: 1_700_100_000,
+            parent_id: None,
+            versions: Vec::new(),
+            pinned: false,
+            loaded: false,
+            stored_in: None,
+        };
+        let texts = vec![
+            ArchivedText::version(&live, 1, 1_700_000_000, "Rotate the signing keys first."),
+            ArchivedText::version(&live, 0, 1_600_000_000, "Restart the service."),
+            ArchivedText::trashed(&trashed, "Drain the queue, then rotate keys."),
+        ];
+        // Indexed once for every search below
+        let archive = ArchivedIndex::new(texts);
+        let texts = archive.texts();
+        let search = |input: &str| {
+            let query = Query::parse(input).unwrap();
+            let (hits, highlighter) = archive.search(&query, &[]);
+            let found: Vec<&ArchivedId> = hits.iter().map(|(i, _)| &texts[*i].source).collect();
+            (found.into_iter().cloned().collect::<Vec<_>>(), highlighter)
+        };
+
+        // Text since deleted from the note is found in its snapshot
+        let (found, highlighter) = search("signing");
+        assert_eq!(
+            found,
+            [ArchivedId::Version {
+                note_id: "note-1".into(),
+                index: 1
+            }]
+        );
+        let ranges = highlighter.ranges(texts[0].content());
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(&texts[0].content()[ranges[0].clone()], "signing");
+
+        // Typos and filters work as in a normal search
+        let (found, _) = search("rotate tag:ops");
+        assert_eq!(found.len(), 1);
+        let (found, _) = search("drian");
+        assert_eq!(found, [ArchivedId::Trash("note-2".into())]);
+        assert_eq!(texts[2].ts(), 1_700_100_000);
+        assert_eq!(texts[2].name(), "Old runbook");
+
+        // One text searched on its own finds the same
+        let query = Query::parse("drian").unwrap();
+        let (hits, _) = search_archived(&query, &texts[2..], &[]);
+        assert_eq!(hits.len(), 1);
+    }
 }
@@ -2,7 +2,7 @@ use crate::vault::AssetMeta;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SortOrder {
     Manual,
     ModifiedDesc,
@@ -13,6 +13,17 @@ pub enum SortOrder {
     CreatedAsc,
 }
 
+/// What the sidebar search looks through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchScope {
+    /// The notes as they are now.
+    #[default]
+    Notes,
+    /// Every saved snapshot of the notes.
+    History,
+    Trash,
+}
+
 // ---------------------------------------------------------------------------
 // Data types
 // ---------------------------------------------------------------------------
@@ -35,6 +46,11 @@ pub struct NoteItem {
     pub file_path: Option<PathBuf>,
     pub parent_id: Option<String>,
     pub pinned: bool,
+    /// False while the body (`content` and `versions`) lives only in its
+    /// encrypted blob under `notes/`; see `vault::load_note_body`.
+    pub loaded: bool,
+    /// Locked folder whose sub-vault holds the body blob, `None` for the main vault.
+    pub stored_in: Option<String>,
 }
 
 impl NoteItem {
@@ -51,6 +67,8 @@ impl NoteItem {
             file_path: None,
             parent_id: None,
             pinned: false,
+            loaded: true,
+            stored_in: None,
         }
     }
 }
@@ -63,6 +81,8 @@ pub struct FolderItem {
     pub created_at: i64,
     pub updated_at: i64,
     pub parent_id: Option<String>,
+    /// Root of a sub-vault with its own passphrase; see `vault::lock_folder`.
+    pub locked: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -77,6 +97,8 @@ pub struct TrashItem {
     pub parent_id: Option<String>,
     pub versions: Vec<NoteVersion>,
     pub pinned: bool,
+    pub loaded: bool,
+    pub stored_in: Option<String>,
 }
 
 pub struct NoteSummary {
@@ -94,12 +116,51 @@ pub struct NoteSummary {
 pub enum SidebarRowKind {
     Folder(String),
     Note(String),
+    SavedSearch(String),
+    /// A snapshot or trashed note found by a history or trash search.
+    Archived(crate::search::ArchivedId),
+}
+
+/// A named search shown as a smart folder above the folder tree. Its notes
+/// are looked up again as the notes change.
+#[derive(Debug, Clone)]
+pub struct SavedSearch {
+    pub id: String,
+    pub name: String,
+    /// In the syntax of [`crate::search::Query`].
+    pub query: String,
+    pub filter_tags: Vec<String>,
+    pub tag_filter_and: bool,
+    pub sort_order: SortOrder,
+    pub expanded: bool,
+}
+
+impl SavedSearch {
+    /// An ID none of `searches` has, like `search-3`.
+    pub fn next_id(searches: &[SavedSearch]) -> String {
+        let last = searches
+            .iter()
+            .filter_map(|s| s.id.strip_prefix("search-")?.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0);
+        format!("search-{}", last + 1)
+    }
+
+    /// The query with the tag filter added.
+    pub fn to_query(&self) -> Result<crate::search::Query, crate::search::QueryError> {
+        let mut query = crate::search::Query::parse(&self.query)?;
+        query.add_tag_filter(&self.filter_tags, self.tag_filter_and);
+        Ok(query)
+    }
 }
 
 // ---------------------------------------------------------------------------
 // Welcome content
 // ---------------------------------------------------------------------------
 
+/// Minutes of inactivity before an unlocked vault locks itself.
+pub const DEFAULT_AUTO_LOCK_MINUTES: u32 = 15;
+
 pub const DEFAULT_DOC: &str = r#"# Untitled
 
 "#;
@@ -176,7 +237,7 @@ pub const PAGE_FEATURES: &str = r#"# Keyboard shortcuts
 - **Version history** — save named snapshots and restore earlier content from the menu
 - **Tabs** — open multiple notes in tabs, drag to reorder, close with Ctrl+W
 - **Export** — save any note as Markdown (.md) or PDF from the menu
-- **Encryption** — AES-256-GCM with PBKDF2 key derivation (600k iterations)
+- **Encryption** — AES-256-GCM with Argon2id key derivation (64 MiB, 3 passes)
 - **Multi-vault** — work with multiple vaults for different clients or projects
 "#;
 
@@ -262,6 +323,7 @@ pub struct DocState {
     pub active_note_id: String,
     pub open_tabs: Vec<String>,
     pub search_query: String,
+    pub search_scope: SearchScope,
     pub visible_row_items: Vec<SidebarRowKind>,
     pub next_note_seq: u64,
     pub sort_order: SortOrder,
@@ -276,12 +338,30 @@ pub struct DocState {
     pub disabled_templates: Vec<String>,                 // names of templates hidden from picker
     pub filter_tags: Vec<String>,
     pub tag_filter_and: bool,
+    pub saved_searches: Vec<SavedSearch>,
     pub sidebar_width: i32,
     pub spellcheck_enabled: bool,
 
     pub last_undo_push: std::time::Instant,
     pub assets: HashMap<String, AssetMeta>,
     pub cached_key: Option<crate::crypto::CachedKey>,
+    /// Store note bodies as separate encrypted blobs instead of inline in vault.json.
+    pub separate_bodies: bool,
+    /// Keys of the locked folders unlocked this session. The contents of a
+    /// locked folder are only in `notes`/`folders` while its key is here.
+    pub folder_keys: HashMap<String, crate::crypto::CachedKey>,
+    /// Lock the vault after this many idle minutes; 0 never locks.
+    pub auto_lock_minutes: u32,
+    pub lock_on_screen_lock: bool,
+    /// Identifies this vault in the associated data of its asset ciphertexts.
+    pub vault_id: String,
+    /// What the last save wrote, so autosave can journal just the changes.
+    pub journal: crate::vault::JournalMarks,
+    /// Opened without taking the vault's lock; nothing is written back.
+    pub read_only: bool,
+    /// Words of the notes, for ranked search. Stored encrypted as
+    /// `search-index`, without the notes of locked folders.
+    pub search_index: crate::search::SearchIndex,
 }
 
 impl Default for DocState {
@@ -316,6 +396,7 @@ impl Default for DocState {
             active_note_id: welcome.id.clone(),
             open_tabs: vec![welcome.id.clone()],
             search_query: String::new(),
+            search_scope: SearchScope::Notes,
             visible_row_items: vec![SidebarRowKind::Note(welcome.id)],
             next_note_seq: 4,
             sort_order: SortOrder::ModifiedDesc,
@@ -330,12 +411,21 @@ impl Default for DocState {
             disabled_templates: Vec::new(),
             filter_tags: Vec::new(),
             tag_filter_and: false,
+            saved_searches: Vec::new(),
             sidebar_width: 300,
             spellcheck_enabled: false,
 
             last_undo_push: std::time::Instant::now(),
             assets: HashMap::new(),
             cached_key: None,
+            separate_bodies: true,
+            folder_keys: HashMap::new(),
+            auto_lock_minutes: DEFAULT_AUTO_LOCK_MINUTES,
+            lock_on_screen_lock: true,
+            vault_id: crate::vault::new_vault_id(),
+            journal: crate::vault::JournalMarks::default(),
+            read_only: false,
+            search_index: crate::search::SearchIndex::default(),
         }
     }
 }
@@ -370,6 +460,8 @@ impl DocState {
             parent_id: note.parent_id,
             versions: note.versions,
             pinned: note.pinned,
+            loaded: note.loaded,
+            stored_in: note.stored_in,
         });
 
         self.open_tabs.retain(|id| id != note_id);
@@ -390,6 +482,53 @@ impl DocState {
             None
         }
     }
+
+    /// The locked folder that `folder_id` is in (or is), if any. A parent
+    /// cycle in a damaged vault ends the walk after every folder was seen.
+    pub fn locked_root(&self, folder_id: Option<&str>) -> Option<&str> {
+        let mut current = folder_id;
+        for _ in 0..=self.folders.len() {
+            let folder = self
+                .folders
+                .iter()
+                .find(|f| Some(f.id.as_str()) == current)?;
+            if folder.locked {
+                return Some(&folder.id);
+            }
+            current = folder.parent_id.as_deref();
+        }
+        None
+    }
+
+    /// False inside a locked folder that has not been unlocked — nothing can
+    /// be added there without its key.
+    pub fn is_folder_accessible(&self, folder_id: Option<&str>) -> bool {
+        self.locked_root(folder_id)
+            .is_none_or(|root| self.folder_keys.contains_key(root))
+    }
+
+    pub fn is_folder_unlocked(&self, folder_id: &str) -> bool {
+        self.folder_keys.contains_key(folder_id)
+    }
+
+    /// True if `folder_id` is `ancestor` or one of its subfolders.
+    pub fn folder_is_within(&self, folder_id: &str, ancestor: &str) -> bool {
+        let mut current = Some(folder_id);
+        for _ in 0..=self.folders.len() {
+            let Some(id) = current else {
+                return false;
+            };
+            if id == ancestor {
+                return true;
+            }
+            current = self
+                .folders
+                .iter()
+                .find(|f| f.id == id)
+                .and_then(|f| f.parent_id.as_deref());
+        }
+        false
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -478,4 +617,29 @@ mod tests {
         assert_eq!(state.notes.len(), 1);
         assert_eq!(state.trash.len(), 0);
     }
+
+    #[test]
+    fn test_folder_walks_end_on_a_parent_cycle() {
+        let folder = |id: &str, parent: &str| FolderItem {
+            id: id.to_string(),
+            name: id.to_string(),
+            expanded: false,
+            created_at: 0,
+            updated_at: 0,
+            parent_id: Some(parent.to_string()),
+            locked: false,
+        };
+        let mut state = DocState {
+            folders: vec![folder("a", "b"), folder("b", "a")],
+            ..DocState::default()
+        };
+
+        assert_eq!(state.locked_root(Some("a")), None);
+        assert!(state.is_folder_accessible(Some("b")));
+        assert!(state.folder_is_within("a", "b"));
+        assert!(!state.folder_is_within("a", "elsewhere"));
+
+        state.folders[1].locked = true;
+        assert_eq!(state.locked_root(Some("a")), Some("b"));
+    }
 }
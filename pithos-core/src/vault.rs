@@ -1,10 +1,69 @@
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::{fs, io};
 
+use crate::crypto::{self, AssetBinding, BlobKind, CachedKey, CryptoError, EnvelopeInfo};
+use crate::search::SearchIndex;
 use crate::state::*;
 
+// ---------------------------------------------------------------------------
+// Errors
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum VaultError {
+    Io(io::Error),
+    Crypto(CryptoError),
+    Asset(String, String),
+    Note(String, String),
+    Locked(String),
+    Invalid(String),
+    /// Written in a newer `schemaVersion` than this build reads.
+    NewerSchema(u32),
+    /// Open for writing in another running instance.
+    InUse(LockInfo),
+    /// Written by someone else since it was last read here.
+    Changed,
+}
+
+impl std::fmt::Display for VaultError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::Crypto(e) => write!(f, "{e}"),
+            Self::Asset(id, m) => write!(f, "Asset {id}: {m}"),
+            Self::Note(id, m) => write!(f, "Note {id}: {m}"),
+            Self::Locked(id) => write!(f, "Folder {id} is locked"),
+            Self::Invalid(m) => write!(f, "Invalid vault: {m}"),
+            Self::NewerSchema(v) => write!(
+                f,
+                "This vault was saved by a newer version of Pithos (format {v}, this \
+                 version reads up to {SCHEMA_VERSION}); update Pithos to open it"
+            ),
+            Self::InUse(lock) => write!(
+                f,
+                "The vault is open in another instance of Pithos (process {} on {})",
+                lock.pid, lock.hostname
+            ),
+            Self::Changed => write!(f, "The vault was changed by another writer"),
+        }
+    }
+}
+
+impl From<io::Error> for VaultError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<CryptoError> for VaultError {
+    fn from(e: CryptoError) -> Self {
+        Self::Crypto(e)
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Vault data model (camelCase JSON — web-app compatible)
 // ---------------------------------------------------------------------------
@@ -12,6 +71,9 @@ use crate::state::*;
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct VaultData {
+    /// Format version, see [`SCHEMA_VERSION`]. Always current once parsed.
+    #[serde(default)]
+    pub schema_version: u32,
     #[serde(default)]
     pub tree: Vec<TreeItem>,
     #[serde(default)]
@@ -38,6 +100,24 @@ pub struct VaultData {
     pub sidebar_width: i32,
     #[serde(default)]
     pub assets: HashMap<String, AssetMeta>,
+    /// Note bodies and their versions are stored in `notes/<id>`, not in the tree.
+    #[serde(default)]
+    pub separate_bodies: bool,
+    /// Idle minutes before auto-lock, 0 for never; `None` uses the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_lock_minutes: Option<u32>,
+    #[serde(default)]
+    pub keep_unlocked_on_screen_lock: bool,
+    /// Random ID that asset ciphertexts are bound to. Empty in sub-vaults,
+    /// whose assets are bound to the main vault's ID and their folder.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub vault_id: String,
+    /// Generation of this vault.json, bumped by every full save. Journal
+    /// entries written on top of another generation are stale.
+    #[serde(default)]
+    pub save_seq: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub saved_searches: Vec<SavedSearchEntry>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -48,6 +128,13 @@ pub struct AssetMeta {
     pub mime_type: String,
     pub size: u64,
     pub created_at: i64,
+    /// Locked folder whose sub-vault holds the asset, `None` for the main vault.
+    #[serde(skip)]
+    pub stored_in: Option<String>,
+    /// Version the ciphertext is bound to, bumped on every re-encryption into
+    /// another vault. `None` for assets written before bindings existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -75,6 +162,9 @@ pub struct TreeItem {
     pub tags: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pinned: Option<bool>,
+    /// Folder contents live in an encrypted sub-vault under `locked/<id>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -83,6 +173,51 @@ pub struct VersionEntry {
     pub content: String,
 }
 
+/// Body of a note stored on its own in `notes/<id>`, encrypted like an asset.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NoteBody {
+    pub content: String,
+    #[serde(default)]
+    pub versions: Vec<VersionEntry>,
+}
+
+impl NoteBody {
+    /// True if `note` still holds exactly this body.
+    pub fn matches(&self, note: &NoteItem) -> bool {
+        note.content == self.content
+            && note
+                .versions
+                .iter()
+                .map(|v| (v.ts, &v.content))
+                .eq(self.versions.iter().map(|v| (v.ts, &v.content)))
+    }
+}
+
+impl NoteBody {
+    /// Move the content and versions out, leaving nothing for `Drop` to wipe.
+    pub(crate) fn take(&mut self) -> (String, Vec<NoteVersion>) {
+        let versions = self
+            .versions
+            .iter_mut()
+            .map(|v| NoteVersion {
+                ts: v.ts,
+                content: std::mem::take(&mut v.content),
+            })
+            .collect();
+        (std::mem::take(&mut self.content), versions)
+    }
+}
+
+impl Drop for NoteBody {
+    fn drop(&mut self) {
+        use zeroize::Zeroize;
+        self.content.zeroize();
+        for version in &mut self.versions {
+            version.content.zeroize();
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CustomTemplate {
     pub name: String,
@@ -91,6 +226,111 @@ pub struct CustomTemplate {
     pub tags: String,
 }
 
+/// A smart folder in vault.json; see [`SavedSearch`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedSearchEntry {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub query: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub match_all_tags: bool,
+    #[serde(default)]
+    pub sort_by: String,
+    #[serde(default)]
+    pub sort_direction: String,
+    #[serde(default)]
+    pub expanded: bool,
+}
+
+// ---------------------------------------------------------------------------
+// Schema versions  (schemaVersion in vault.json and sub-vaults)
+// ---------------------------------------------------------------------------
+//
+// Parsing runs vault JSON through the migrations from its version up to
+// `SCHEMA_VERSION` before deserializing it. A vault from a newer version is
+// refused: this build would drop whatever it doesn't know on the next save.
+
+/// Version of the vault format this build reads and writes. Vaults written
+/// before the field existed are version 1.
+pub const SCHEMA_VERSION: u32 = 3;
+
+type Migration = fn(&mut serde_json::Value) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` turns version `n + 1` into version `n + 2`.
+const MIGRATIONS: &[Migration] = &[migrate_v1_to_v2, migrate_v2_to_v3];
+const _: () = assert!(MIGRATIONS.len() == SCHEMA_VERSION as usize - 1);
+
+/// Version 1 readers treated items of any unknown type as notes; version 2
+/// spells that out and only knows notes and folders.
+fn migrate_v1_to_v2(vault: &mut serde_json::Value) -> Result<(), String> {
+    fn retype(items: &mut [serde_json::Value]) {
+        for item in items.iter_mut().filter_map(|i| i.as_object_mut()) {
+            if !matches!(
+                item.get("type").and_then(|t| t.as_str()),
+                Some("note" | "folder")
+            ) {
+                item.insert("type".into(), "note".into());
+            }
+            if let Some(children) = item.get_mut("children").and_then(|c| c.as_array_mut()) {
+                retype(children);
+            }
+        }
+    }
+    for key in ["tree", "trash"] {
+        if let Some(items) = vault.get_mut(key).and_then(|i| i.as_array_mut()) {
+            retype(items);
+        }
+    }
+    Ok(())
+}
+
+/// Version 3 added saved searches. A version 2 vault has none, so nothing
+/// changes; the bump only stops version 2 builds from dropping them on save.
+fn migrate_v2_to_v3(_vault: &mut serde_json::Value) -> Result<(), String> {
+    Ok(())
+}
+
+/// Parse decrypted vault or sub-vault JSON of any version this build knows.
+pub fn parse_vault(json: &str) -> Result<VaultData, VaultError> {
+    let invalid = |e: serde_json::Error| VaultError::Invalid(e.to_string());
+    let mut value: serde_json::Value = serde_json::from_str(json).map_err(invalid)?;
+    let version = match value.get("schemaVersion") {
+        None => 1,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|&v| v >= 1)
+            .ok_or_else(|| VaultError::Invalid(format!("bad schemaVersion {v}")))?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(VaultError::NewerSchema(version));
+    }
+    for migrate in &MIGRATIONS[version as usize - 1..] {
+        migrate(&mut value).map_err(VaultError::Invalid)?;
+    }
+    let mut data: VaultData = serde_json::from_value(value).map_err(invalid)?;
+    fn check_types(items: &[TreeItem]) -> Result<(), VaultError> {
+        for item in items {
+            if item.item_type != "note" && item.item_type != "folder" {
+                return Err(VaultError::Invalid(format!(
+                    "unknown item type '{}' for '{}'",
+                    item.item_type, item.id
+                )));
+            }
+            check_types(item.children.as_deref().unwrap_or_default())?;
+        }
+        Ok(())
+    }
+    check_types(&data.tree)?;
+    check_types(&data.trash)?;
+    data.schema_version = SCHEMA_VERSION;
+    Ok(data)
+}
+
 // ---------------------------------------------------------------------------
 // App config  (~/.config/pithos-notebook/config.json)
 // ---------------------------------------------------------------------------
@@ -142,14 +382,6 @@ pub fn vault_file_path(vault_folder: &str) -> PathBuf {
     Path::new(vault_folder).join("vault.json")
 }
 
-/// Copy vault.json to vault.json.bak before each save (best-effort).
-pub fn backup_vault(vault_folder: &str) {
-    let src = vault_file_path(vault_folder);
-    if src.exists() {
-        let _ = fs::copy(&src, src.with_extension("json.bak"));
-    }
-}
-
 pub fn read_vault_raw(vault_folder: &str) -> io::Result<Option<String>> {
     let path = vault_file_path(vault_folder);
     if !path.exists() {
@@ -187,6 +419,75 @@ pub fn is_valid_asset_id(id: &str) -> bool {
         .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
 }
 
+/// New random vault ID.
+pub fn new_vault_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Who blobs stored in `stored_in` (a locked folder, or `None`) belong to:
+/// the vault, or the folder's sub-vault within it.
+pub fn blob_owner(vault_id: &str, stored_in: Option<&str>) -> String {
+    match stored_in {
+        Some(folder_id) => format!("{vault_id}/{folder_id}"),
+        None => vault_id.to_string(),
+    }
+}
+
+/// Binding for an asset stored in `stored_in` (a locked folder, or `None`).
+pub fn binding_for(
+    vault_id: &str,
+    stored_in: Option<&str>,
+    asset_id: &str,
+    version: u64,
+) -> AssetBinding {
+    AssetBinding {
+        vault_id: blob_owner(vault_id, stored_in),
+        asset_id: asset_id.to_string(),
+        version,
+        kind: BlobKind::Asset,
+    }
+}
+
+/// Binding for a note body, the journal or the search index of `owner`
+/// (see [`blob_owner`]). These are rewritten in place, so carry no version.
+fn blob_binding(owner: &str, kind: BlobKind, id: &str) -> AssetBinding {
+    AssetBinding {
+        vault_id: owner.to_string(),
+        asset_id: id.to_string(),
+        version: 0,
+        kind,
+    }
+}
+
+/// What an asset's ciphertext must be bound to; `None` for legacy assets.
+pub fn asset_binding(vault_id: &str, meta: &AssetMeta) -> Option<AssetBinding> {
+    meta.version
+        .map(|v| binding_for(vault_id, meta.stored_in.as_deref(), &meta.id, v))
+}
+
+/// Read and decrypt an asset from `vault_folder` (as returned by
+/// `blob_location`), checking its binding when it has one.
+pub fn read_asset(
+    vault_folder: &str,
+    asset_id: &str,
+    key: &CachedKey,
+    binding: Option<&AssetBinding>,
+) -> Result<Vec<u8>, VaultError> {
+    let fail = |m: String| VaultError::Asset(asset_id.to_string(), m);
+    if !is_valid_asset_id(asset_id) {
+        return Err(fail("invalid asset ID".into()));
+    }
+    let data = fs::read(assets_dir(vault_folder).join(asset_id))
+        .map_err(|e| fail(format!("read failed: {e}")))?;
+    let decrypted = match binding {
+        Some(binding) => crypto::decrypt_asset_bound(&data, key, binding),
+        None => crypto::decrypt_asset(&data, key),
+    };
+    decrypted.map_err(|e| fail(format!("decrypt failed: {e}")))
+}
+
 pub fn write_asset(vault_folder: &str, asset_id: &str, data: &[u8]) -> io::Result<()> {
     if !is_valid_asset_id(asset_id) {
         return Err(io::Error::new(
@@ -200,100 +501,2819 @@ pub fn write_asset(vault_folder: &str, asset_id: &str, data: &[u8]) -> io::Resul
 }
 
 // ---------------------------------------------------------------------------
-// Conversion helpers: internal flat model <-> tree vault format
+// Vault lock  (lock, the instance that has the vault open for writing)
 // ---------------------------------------------------------------------------
+//
+// The lock file is advisory: it names the process, host and time of the
+// instance that opened the vault, which refreshes it while running. Another
+// instance — a second session, or another machine on a synced folder — can
+// then only open the vault read-only. A lock whose process is gone from this
+// host, or that wasn't refreshed for `LOCK_STALE_SECS`, is taken over.
 
-pub fn sort_order_to_strings(order: SortOrder) -> (String, String) {
-    match order {
-        SortOrder::Manual => ("manual".into(), "manual".into()),
-        SortOrder::ModifiedDesc => ("modified".into(), "desc".into()),
-        SortOrder::ModifiedAsc => ("modified".into(), "asc".into()),
-        SortOrder::NameAsc => ("name".into(), "asc".into()),
-        SortOrder::NameDesc => ("name".into(), "desc".into()),
-        SortOrder::CreatedDesc => ("created".into(), "desc".into()),
-        SortOrder::CreatedAsc => ("created".into(), "asc".into()),
+/// An instance refreshes its lock about every 30 seconds; one that hasn't
+/// for this long has crashed, been killed or is asleep.
+pub const LOCK_STALE_SECS: i64 = 5 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LockInfo {
+    pub pid: u32,
+    pub hostname: String,
+    pub acquired_at: i64,
+    pub refreshed_at: i64,
+}
+
+impl LockInfo {
+    fn current(now: i64) -> Self {
+        Self {
+            pid: std::process::id(),
+            hostname: hostname(),
+            acquired_at: now,
+            refreshed_at: now,
+        }
+    }
+
+    pub fn is_ours(&self) -> bool {
+        self.pid == std::process::id() && self.hostname == hostname()
+    }
+
+    /// Whether the instance that wrote the lock is gone: it stopped
+    /// refreshing it, or its process no longer runs on this host.
+    pub fn is_stale(&self, now: i64) -> bool {
+        if now - self.refreshed_at > LOCK_STALE_SECS {
+            return true;
+        }
+        let proc = Path::new("/proc");
+        self.hostname == hostname()
+            && proc.join("self").exists()
+            && !proc.join(self.pid.to_string()).exists()
     }
 }
 
-pub fn parse_sort_order(sort_by: &str, direction: &str) -> SortOrder {
-    match (sort_by, direction) {
-        ("manual", _) => SortOrder::Manual,
-        ("modified", "desc") => SortOrder::ModifiedDesc,
-        ("modified", "asc") => SortOrder::ModifiedAsc,
-        ("name", "asc") => SortOrder::NameAsc,
-        ("name", "desc") => SortOrder::NameDesc,
-        ("created", "desc") => SortOrder::CreatedDesc,
-        ("created", "asc") => SortOrder::CreatedAsc,
-        _ => SortOrder::ModifiedDesc,
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+pub fn lock_path(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join("lock")
+}
+
+/// The vault's lock, if there is a readable one.
+pub fn read_lock(vault_folder: &str) -> io::Result<Option<LockInfo>> {
+    match fs::read(lock_path(vault_folder)) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes).ok()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
     }
 }
 
-/// Convert the flat in-memory DocState into the tree-based vault format.
-pub fn doc_state_to_vault(state: &DocState) -> VaultData {
-    let mut notes_by_parent: HashMap<Option<String>, Vec<&NoteItem>> = HashMap::new();
-    for note in &state.notes {
-        notes_by_parent
-            .entry(note.parent_id.clone())
-            .or_default()
-            .push(note);
+fn write_lock(vault_folder: &str, info: &LockInfo) -> io::Result<()> {
+    let json = serde_json::to_vec(info).map_err(io::Error::other)?;
+    atomic_write(&lock_path(vault_folder), &json)
+}
+
+/// Take the vault's lock. Fails with `VaultError::InUse` while another
+/// instance that is still running holds it.
+pub fn acquire_lock(vault_folder: &str) -> Result<(), VaultError> {
+    use std::io::Write;
+    let now = unix_now();
+    let ours = LockInfo::current(now);
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(lock_path(vault_folder))
+    {
+        Ok(mut file) => {
+            let json = serde_json::to_vec(&ours).map_err(io::Error::other)?;
+            file.write_all(&json)?;
+            return Ok(());
+        }
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e.into()),
+        Err(_) => {}
     }
-    let mut folders_by_parent: HashMap<Option<String>, Vec<&FolderItem>> = HashMap::new();
-    for folder in &state.folders {
-        folders_by_parent
-            .entry(folder.parent_id.clone())
-            .or_default()
-            .push(folder);
+    if let Some(held) = read_lock(vault_folder)? {
+        if !held.is_ours() && !held.is_stale(now) {
+            return Err(VaultError::InUse(held));
+        }
+    }
+    write_lock(vault_folder, &ours)?;
+    // Two instances taking over a stale lock at once: the last write wins
+    match read_lock(vault_folder)? {
+        Some(held) if !held.is_ours() => Err(VaultError::InUse(held)),
+        _ => Ok(()),
     }
-    let root_items = build_vault_tree_level(None, &notes_by_parent, &folders_by_parent);
+}
 
-    let trash: Vec<TreeItem> = state
-        .trash
-        .iter()
-        .map(|t| TreeItem {
-            id: t.id.clone(),
-            name: t.name.clone(),
-            item_type: "note".to_string(),
-            content: Some(t.content.clone()),
-            children: None,
-            expanded: None,
-            created_at: t.created_at,
-            updated_at: t.updated_at,
-            deleted: Some(true),
-            deleted_at: Some(t.deleted_at),
-            tags: if t.tags.is_empty() {
-                None
-            } else {
-                Some(t.tags.clone())
-            },
-            pinned: if t.pinned { Some(true) } else { None },
+/// The instance holding the vault's lock, unless that is us or it is gone.
+pub fn lock_holder(vault_folder: &str) -> Option<LockInfo> {
+    read_lock(vault_folder)
+        .ok()
+        .flatten()
+        .filter(|held| !held.is_ours() && !held.is_stale(unix_now()))
+}
+
+/// Keep our lock from going stale. Returns the lock of another instance
+/// that took it over in the meantime.
+pub fn refresh_lock(vault_folder: &str) -> io::Result<Option<LockInfo>> {
+    let now = unix_now();
+    match read_lock(vault_folder)? {
+        Some(held) if !held.is_ours() => Ok(Some(held)),
+        held => {
+            let info = LockInfo {
+                refreshed_at: now,
+                ..held.unwrap_or_else(|| LockInfo::current(now))
+            };
+            write_lock(vault_folder, &info)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Remove the vault's lock if it is ours.
+pub fn release_lock(vault_folder: &str) -> io::Result<()> {
+    if read_lock(vault_folder)?.is_some_and(|held| held.is_ours()) {
+        fs::remove_file(lock_path(vault_folder))?;
+    }
+    Ok(())
+}
+
+/// Whether this process can write to the vault folder. Permission bits say
+/// little on network shares and read-only mounts, so try creating a file.
+pub fn folder_writable(vault_folder: &str) -> bool {
+    let probe = Path::new(vault_folder).join(format!(".write-test-{}", std::process::id()));
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => fs::remove_file(&probe).is_ok(),
+        Err(_) => false,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Backups  (backups/<unix-time>/, rotated hourly, daily and weekly)
+// ---------------------------------------------------------------------------
+//
+// A backup is a copy of the vault folder's encrypted files — vault.json, note
+// bodies, assets and locked-folder sub-vaults — taken before a save. Every
+// write replaces files by renaming a new one over them, so backups hard-link
+// the current files instead of copying them where the filesystem allows.
+
+/// A new backup is taken before the first save after this long.
+pub const BACKUP_INTERVAL_SECS: i64 = 60 * 60;
+const HOUR_SECS: i64 = 60 * 60;
+const DAY_SECS: i64 = 24 * HOUR_SECS;
+const WEEK_SECS: i64 = 7 * DAY_SECS;
+/// Every backup is kept for a day, the newest of each day for a week and the
+/// newest of each week for this many weeks.
+const KEEP_DAILY_DAYS: i64 = 7;
+const KEEP_WEEKLY_WEEKS: i64 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: String,
+    pub created_at: i64,
+}
+
+pub fn backups_dir(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join("backups")
+}
+
+/// Vault folder of a backup, laid out like the vault itself.
+pub fn backup_path(vault_folder: &str, backup_id: &str) -> Result<String, VaultError> {
+    if backup_id.is_empty() || !backup_id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(VaultError::Invalid(format!("bad backup ID {backup_id}")));
+    }
+    Ok(backups_dir(vault_folder)
+        .join(backup_id)
+        .to_string_lossy()
+        .to_string())
+}
+
+/// Backups of the vault, newest first.
+pub fn list_backups(vault_folder: &str) -> io::Result<Vec<BackupInfo>> {
+    let mut backups: Vec<BackupInfo> = list_blob_ids(&backups_dir(vault_folder))?
+        .into_iter()
+        .filter_map(|id| {
+            let created_at = id.parse().ok()?;
+            Some(BackupInfo { id, created_at })
         })
         .collect();
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+    Ok(backups)
+}
+
+/// Back up the vault before a save, unless the newest backup is less than
+/// `BACKUP_INTERVAL_SECS` old, and drop backups past their retention.
+/// Returns the new backup, if one was taken.
+pub fn backup_vault(vault_folder: &str) -> io::Result<Option<BackupInfo>> {
+    let now = unix_now();
+    if !vault_file_path(vault_folder).exists() {
+        return Ok(None);
+    }
+    let newest = list_backups(vault_folder)?.first().map(|b| b.created_at);
+    if newest.is_some_and(|t| now - t < BACKUP_INTERVAL_SECS && t <= now) {
+        return Ok(None);
+    }
+    let backup = create_backup(vault_folder, now)?;
+    prune_backups(vault_folder, now)?;
+    Ok(Some(backup))
+}
+
+/// Back up the vault as it is on disk now, regardless of the interval. A
+/// backup taken in the same second as an earlier one gets the next free ID.
+pub fn create_backup(vault_folder: &str, now: i64) -> io::Result<BackupInfo> {
+    let mut created_at = now;
+    while backups_dir(vault_folder)
+        .join(created_at.to_string())
+        .exists()
+    {
+        created_at += 1;
+    }
+    let id = created_at.to_string();
+    let partial = backups_dir(vault_folder).join(format!("{id}.tmp"));
+    let _ = fs::remove_dir_all(&partial);
+    fs::create_dir_all(&partial)?;
+    let src = Path::new(vault_folder);
+    link_or_copy(&vault_file_path(vault_folder), &partial.join("vault.json"))?;
+    for dir in ["notes", "assets", "locked"] {
+        link_tree(&src.join(dir), &partial.join(dir))?;
+    }
+    fs::rename(&partial, backups_dir(vault_folder).join(&id))?;
+    Ok(BackupInfo { id, created_at })
+}
+
+pub(crate) fn link_or_copy(src: &Path, dest: &Path) -> io::Result<()> {
+    if fs::hard_link(src, dest).is_err() {
+        fs::copy(src, dest)?;
+    }
+    Ok(())
+}
+
+/// Link (or copy) every file under `src` into `dest`, skipping leftovers from
+/// interrupted writes.
+fn link_tree(src: &Path, dest: &Path) -> io::Result<()> {
+    if !src.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name.to_string_lossy().ends_with(".tmp") {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            link_tree(&entry.path(), &dest.join(&name))?;
+        } else {
+            link_or_copy(&entry.path(), &dest.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Backup times to keep: all from the last day, the newest of each day for a
+/// week, the newest of each week after that, and always the newest overall.
+pub fn backups_to_keep(times: &[i64], now: i64) -> HashSet<i64> {
+    let mut newest_in: HashMap<(i64, i64), i64> = HashMap::new();
+    let mut keep = HashSet::new();
+    for &t in times {
+        let age = now - t;
+        let bucket = if age < DAY_SECS {
+            keep.insert(t);
+            continue;
+        } else if age < KEEP_DAILY_DAYS * DAY_SECS {
+            (DAY_SECS, t.div_euclid(DAY_SECS))
+        } else if age < KEEP_WEEKLY_WEEKS * WEEK_SECS {
+            (WEEK_SECS, t.div_euclid(WEEK_SECS))
+        } else {
+            continue;
+        };
+        let newest = newest_in.entry(bucket).or_insert(t);
+        *newest = (*newest).max(t);
+    }
+    keep.extend(newest_in.into_values());
+    keep.extend(times.iter().max());
+    keep
+}
+
+fn prune_backups(vault_folder: &str, now: i64) -> io::Result<()> {
+    let backups = list_backups(vault_folder)?;
+    let times: Vec<i64> = backups.iter().map(|b| b.created_at).collect();
+    let keep = backups_to_keep(&times, now);
+    for backup in backups {
+        if !keep.contains(&backup.created_at) {
+            fs::remove_dir_all(backups_dir(vault_folder).join(&backup.id))?;
+        }
+    }
+    Ok(())
+}
+
+/// Decrypt a backup's vault.json. `key` is the open vault's key, or one
+/// unlocked from the backup itself if the passphrase has changed since.
+pub fn open_backup(
+    vault_folder: &str,
+    backup_id: &str,
+    key: &CachedKey,
+) -> Result<VaultData, VaultError> {
+    use zeroize::Zeroize;
+    let raw = read_vault_raw(&backup_path(vault_folder, backup_id)?)?
+        .ok_or_else(|| VaultError::Invalid(format!("backup {backup_id} has no vault.json")))?;
+    let mut json = crypto::decrypt_vault_fast(&raw, key)?;
+    let data = parse_vault(&json);
+    json.zeroize();
+    data
+}
+
+/// Copy one blob from a backup, re-encrypting it under `key` if the backup
+/// was made under another data key.
+fn restore_blob(
+    src: &Path,
+    dest: &Path,
+    backup_key: &CachedKey,
+    key: &CachedKey,
+) -> Result<(), VaultError> {
+    let data = fs::read(src)?;
+    if key.same_data_key(backup_key) {
+        atomic_write(dest, &data)?;
+    } else {
+        let encrypted = crypto::reencrypt_asset(&data, backup_key, key)?;
+        atomic_write(dest, encrypted.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Put the whole vault back the way it was in a backup. The current files
+/// are backed up first, so the restore itself can be undone. Note bodies and
+/// assets come back under `key`; locked folders keep their own passphrases.
+/// Returns the restored vault, which is also written as vault.json.
+pub fn restore_backup(
+    vault_folder: &str,
+    backup_id: &str,
+    backup_key: &CachedKey,
+    key: &CachedKey,
+) -> Result<VaultData, VaultError> {
+    use zeroize::Zeroize;
+    let src = backup_path(vault_folder, backup_id)?;
+    let data = open_backup(vault_folder, backup_id, backup_key)?;
+    create_backup(vault_folder, unix_now())?;
+
+    for (dir, dest) in [
+        (notes_dir(&src), notes_dir(vault_folder)),
+        (assets_dir(&src), assets_dir(vault_folder)),
+    ] {
+        fs::create_dir_all(&dest)?;
+        for id in list_blob_ids(&dir)? {
+            restore_blob(&dir.join(&id), &dest.join(&id), backup_key, key)?;
+        }
+    }
+    let locked = Path::new(vault_folder).join("locked");
+    for folder_id in list_blob_ids(&Path::new(&src).join("locked"))? {
+        let _ = fs::remove_dir_all(locked.join(&folder_id));
+        link_tree(
+            &Path::new(&src).join("locked").join(&folder_id),
+            &locked.join(&folder_id),
+        )?;
+    }
+
+    let mut json = serde_json::to_string(&data).map_err(|e| VaultError::Invalid(e.to_string()))?;
+    let encrypted = crypto::encrypt_vault_fast(&json, key);
+    json.zeroize();
+    write_vault_raw(vault_folder, &encrypted?)?;
+    clear_journal(vault_folder)?;
+    Ok(data)
+}
+
+/// Copy back the assets `markdown` links to that are gone from the vault but
+/// still in a backup (`backup_assets` as listed in its vault.json). Returns
+/// their metadata, to add to `DocState::assets`.
+pub fn restore_linked_assets(
+    vault_folder: &str,
+    backup_id: &str,
+    backup_assets: &HashMap<String, AssetMeta>,
+    backup_key: &CachedKey,
+    key: &CachedKey,
+    markdown: &str,
+    current: &HashMap<String, AssetMeta>,
+) -> Result<Vec<AssetMeta>, VaultError> {
+    let src = assets_dir(&backup_path(vault_folder, backup_id)?);
+    let mut restored = Vec::new();
+    for id in referenced_asset_ids(markdown) {
+        let Some(meta) = backup_assets.get(&id) else {
+            continue;
+        };
+        if current.contains_key(&id) || !src.join(&id).exists() {
+            continue;
+        }
+        fs::create_dir_all(assets_dir(vault_folder))?;
+        restore_blob(
+            &src.join(&id),
+            &assets_dir(vault_folder).join(&id),
+            backup_key,
+            key,
+        )?;
+        restored.push(meta.clone());
+    }
+    Ok(restored)
+}
+
+// ---------------------------------------------------------------------------
+// Note bodies  (notes/<id>, one encrypted blob per note)
+// ---------------------------------------------------------------------------
+
+pub fn notes_dir(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join("notes")
+}
+
+fn note_body_path(vault_folder: &str, note_id: &str) -> Result<PathBuf, VaultError> {
+    if !is_valid_asset_id(note_id) {
+        return Err(VaultError::Note(
+            note_id.to_string(),
+            "invalid note ID".into(),
+        ));
+    }
+    Ok(notes_dir(vault_folder).join(note_id))
+}
+
+/// Read and decrypt the body of `note_id` from `vault_folder` (as returned
+/// by `blob_location`), which `owner` holds (see [`blob_owner`]). A body
+/// bound to another note or vault is refused; one written before bodies
+/// were bound is accepted and bound on its next write.
+pub fn read_note_body(
+    vault_folder: &str,
+    owner: &str,
+    note_id: &str,
+    key: &CachedKey,
+) -> Result<NoteBody, VaultError> {
+    use zeroize::Zeroize;
+    let fail = |m: String| VaultError::Note(note_id.to_string(), m);
+    let data = fs::read(note_body_path(vault_folder, note_id)?)
+        .map_err(|e| fail(format!("read failed: {e}")))?;
+    let binding = blob_binding(owner, BlobKind::Note, note_id);
+    let mut plaintext = crypto::decrypt_blob(&data, key, &binding)
+        .map_err(|e| fail(format!("decrypt failed: {e}")))?;
+    let body = serde_json::from_slice(&plaintext).map_err(|e| fail(format!("parse failed: {e}")));
+    plaintext.zeroize();
+    body
+}
+
+pub fn write_note_body(
+    vault_folder: &str,
+    owner: &str,
+    note_id: &str,
+    body: &NoteBody,
+    key: &CachedKey,
+) -> Result<(), VaultError> {
+    let path = note_body_path(vault_folder, note_id)?;
+    let encrypted = seal_note_body(owner, note_id, body, key)?;
+    fs::create_dir_all(notes_dir(vault_folder))?;
+    atomic_write(&path, encrypted.as_bytes())
+        .map_err(|e| VaultError::Note(note_id.to_string(), format!("write failed: {e}")))
+}
+
+/// The blob `write_note_body` writes for `body`.
+fn seal_note_body(
+    owner: &str,
+    note_id: &str,
+    body: &NoteBody,
+    key: &CachedKey,
+) -> Result<String, VaultError> {
+    use zeroize::Zeroize;
+    let fail = |m: String| VaultError::Note(note_id.to_string(), m);
+    let mut json = serde_json::to_vec(body).map_err(|e| fail(format!("serialize failed: {e}")))?;
+    let binding = blob_binding(owner, BlobKind::Note, note_id);
+    let encrypted = crypto::encrypt_asset_bound(&json, key, &binding);
+    json.zeroize();
+    encrypted.map_err(|e| fail(format!("encrypt failed: {e}")))
+}
+
+/// Decrypt a note's body into `note` if it is not in memory yet.
+pub fn load_note_body(
+    vault_folder: &str,
+    owner: &str,
+    key: &CachedKey,
+    note: &mut NoteItem,
+) -> Result<(), VaultError> {
+    if note.loaded {
+        return Ok(());
+    }
+    (note.content, note.versions) = read_note_body(vault_folder, owner, &note.id, key)?.take();
+    note.loaded = true;
+    Ok(())
+}
+
+/// Wipe a note's body from memory. Only call once the body has been written
+/// with `write_note_bodies`, otherwise unsaved edits are lost.
+pub fn unload_note_body(note: &mut NoteItem) {
+    use zeroize::Zeroize;
+    note.content.zeroize();
+    for version in &mut note.versions {
+        version.content.zeroize();
+    }
+    note.versions = Vec::new();
+    note.loaded = false;
+}
+
+/// Zeroize the decrypted text held in `state` and drop its locked-folder
+/// keys, before the vault is locked. Only note IDs such as the open tabs
+/// are kept.
+pub fn wipe_doc_state(state: &mut DocState) {
+    use zeroize::Zeroize;
+    for note in &mut state.notes {
+        unload_note_body(note);
+        note.name.zeroize();
+        note.tags.iter_mut().for_each(Zeroize::zeroize);
+    }
+    for item in &mut state.trash {
+        item.name.zeroize();
+        item.content.zeroize();
+        for version in &mut item.versions {
+            version.content.zeroize();
+        }
+    }
+    for folder in &mut state.folders {
+        folder.name.zeroize();
+    }
+    for (name, content, tags) in &mut state.custom_templates {
+        name.zeroize();
+        content.zeroize();
+        tags.zeroize();
+    }
+    for search in &mut state.saved_searches {
+        search.name.zeroize();
+        search.query.zeroize();
+        search.filter_tags.iter_mut().for_each(Zeroize::zeroize);
+    }
+    state.undo_stack.iter_mut().for_each(Zeroize::zeroize);
+    state.redo_stack.iter_mut().for_each(Zeroize::zeroize);
+    state.saved_snapshot.zeroize();
+    state.last_snapshot.zeroize();
+    state.search_query.zeroize();
+    state.notes.clear();
+    state.trash.clear();
+    state.folders.clear();
+    state.custom_templates.clear();
+    state.saved_searches.clear();
+    state.undo_stack.clear();
+    state.redo_stack.clear();
+    state.assets.clear();
+    state.visible_row_items.clear();
+    state.folder_keys.clear();
+    state.search_index.clear();
+    state.cached_key = None;
+}
+
+/// Bodies currently held in memory of the notes in the main vault (`root` is
+/// `None`) or in the locked folder `root`, trashed notes kept there included.
+/// Empty when the state still uses inline bodies.
+pub fn loaded_note_bodies(state: &DocState, root: Option<&str>) -> Vec<(String, NoteBody)> {
+    if !state.separate_bodies {
+        return Vec::new();
+    }
+    let notes = state
+        .notes
+        .iter()
+        .filter(|n| n.loaded && state.locked_root(n.parent_id.as_deref()) == root)
+        .map(|n| (&n.id, &n.content, &n.versions));
+    let trash = state
+        .trash
+        .iter()
+        .filter(|t| t.loaded && t.stored_in.as_deref() == root)
+        .map(|t| (&t.id, &t.content, &t.versions));
+    notes
+        .chain(trash)
+        .map(|(id, content, versions)| {
+            let body = NoteBody {
+                content: content.clone(),
+                versions: versions_to_entries(versions),
+            };
+            (id.clone(), body)
+        })
+        .collect()
+}
+
+pub fn write_note_bodies(
+    vault_folder: &str,
+    owner: &str,
+    bodies: &[(String, NoteBody)],
+    key: &CachedKey,
+) -> Result<(), VaultError> {
+    for (note_id, body) in bodies {
+        write_note_body(vault_folder, owner, note_id, body, key)?;
+    }
+    Ok(())
+}
+
+/// Delete body blobs whose note is neither in the tree nor in the trash of `vault`.
+pub fn prune_note_bodies(vault_folder: &str, vault: &VaultData) -> io::Result<()> {
+    fn collect<'a>(items: &'a [TreeItem], ids: &mut HashSet<&'a str>) {
+        for item in items {
+            ids.insert(&item.id);
+            if let Some(children) = &item.children {
+                collect(children, ids);
+            }
+        }
+    }
+    let mut known = HashSet::new();
+    collect(&vault.tree, &mut known);
+    collect(&vault.trash, &mut known);
+
+    let dir = notes_dir(vault_folder);
+    for id in list_blob_ids(&dir)? {
+        if !known.contains(id.as_str()) {
+            fs::remove_file(dir.join(id))?;
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Search index  (search-index, the indexed words of the main vault's notes)
+// ---------------------------------------------------------------------------
+//
+// A cache: when it is missing, damaged or sealed with another key it is
+// rebuilt from the note bodies the next time notes are searched. Notes in
+// locked folders are indexed only in memory, so their words can't be read
+// with the main vault's key.
+
+pub fn search_index_path(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join("search-index")
+}
+
+/// The stored search index, or an empty one if it can't be used.
+pub fn read_search_index(vault_folder: &str, vault_id: &str, key: &CachedKey) -> SearchIndex {
+    let Ok(data) = fs::read(search_index_path(vault_folder)) else {
+        return SearchIndex::default();
+    };
+    let binding = blob_binding(vault_id, BlobKind::SearchIndex, "search-index");
+    let Ok(plaintext) = crypto::decrypt_asset_bound(&data, key, &binding) else {
+        return SearchIndex::default();
+    };
+    let plaintext = zeroize::Zeroizing::new(plaintext);
+    SearchIndex::from_bytes(&plaintext).unwrap_or_default()
+}
+
+/// Bring the index up to date with the loaded notes and serialize the part
+/// of it to store, with its revision for [`SearchIndex::mark_stored`] once
+/// written, or `None` if nothing changed since it was last stored.
+pub fn search_index_for_save(state: &mut DocState) -> Option<(u64, zeroize::Zeroizing<Vec<u8>>)> {
+    let main: HashSet<String> = state
+        .notes
+        .iter()
+        .filter(|n| state.locked_root(n.parent_id.as_deref()).is_none())
+        .map(|n| n.id.clone())
+        .collect();
+    state.search_index.sync(&state.notes, |_| None);
+    state.search_index.to_bytes(|id| main.contains(id))
+}
+
+pub fn write_search_index(
+    vault_folder: &str,
+    vault_id: &str,
+    index: &[u8],
+    key: &CachedKey,
+) -> Result<(), VaultError> {
+    let binding = blob_binding(vault_id, BlobKind::SearchIndex, "search-index");
+    let encrypted = crypto::encrypt_asset_bound(index, key, &binding)?;
+    atomic_write(&search_index_path(vault_folder), encrypted.as_bytes())?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Journal  (journal, note changes appended between full saves)
+// ---------------------------------------------------------------------------
+//
+// A full save rewrites vault.json and every note body in memory. Between full
+// saves, changes that only touch notes — text, history, name, tags, pin — and
+// the open tabs are appended to `journal`, one encrypted line per save. Opening
+// the vault replays the journal over vault.json; the next full save folds it
+// into vault.json and the note bodies and starts a new one. Entries name the
+// vault.json generation they follow, so a journal left behind by a crash
+// between writing vault.json and clearing the journal is ignored.
+
+/// A full save compacts the journal once it has this many entries, this many
+/// bytes of note text, or is this old.
+const JOURNAL_MAX_ENTRIES: usize = 200;
+const JOURNAL_MAX_BYTES: usize = 4 * 1024 * 1024;
+const JOURNAL_MAX_AGE_SECS: i64 = 10 * 60;
+
+/// What the last save wrote, to tell which notes changed since.
+#[derive(Debug, Clone, Default)]
+pub struct JournalMarks {
+    /// Generation of vault.json on disk (`VaultData::save_seq`).
+    pub save_seq: u64,
+    /// Fingerprint of everything the journal can't record; `None` until the
+    /// first full save after opening the vault.
+    layout: Option<u64>,
+    tabs: u64,
+    /// Note ID → fingerprints of its details and, if loaded, its body.
+    notes: HashMap<String, (u64, Option<u64>)>,
+    /// Smart folder ID → fingerprint, see `saved_search_fingerprint`.
+    saved_searches: HashMap<String, u64>,
+    entries: usize,
+    bytes: usize,
+    compacted_at: i64,
+    /// Fingerprint of vault.json and the journal as last read or written;
+    /// see `disk_changed`.
+    pub disk: Option<u64>,
+    /// Their sizes and modification times at the same point, so an unchanged
+    /// folder is told without reading the files.
+    disk_stat: Option<DiskStat>,
+}
+
+/// A note's details, and its body if that changed, as of one save.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalNote {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    pub updated_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<NoteBody>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    /// `saveSeq` of the vault.json this entry applies on top of.
+    pub base: u64,
+    pub notes: Vec<JournalNote>,
+    pub active_id: String,
+    pub open_tabs: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum SavePlan {
+    /// Write vault.json, note bodies and sub-vaults, then clear the journal.
+    Full,
+    /// Append this entry to the journal.
+    Journal(JournalEntry),
+    /// Nothing changed since the last save.
+    Unchanged,
+}
+
+pub fn journal_path(vault_folder: &str) -> PathBuf {
+    Path::new(vault_folder).join("journal")
+}
+
+fn fingerprint(value: impl std::hash::Hash) -> u64 {
+    use std::hash::{BuildHasher, BuildHasherDefault};
+    BuildHasherDefault::<std::collections::hash_map::DefaultHasher>::default().hash_one(value)
+}
+
+/// What a smart folder finds and how it lists it; being folded open or shut
+/// is not a change to merge.
+fn saved_search_fingerprint(search: &SavedSearch) -> u64 {
+    fingerprint((
+        &search.name,
+        &search.query,
+        &search.filter_tags,
+        search.tag_filter_and,
+        search.sort_order,
+    ))
+}
+
+fn saved_search_fingerprints(searches: &[SavedSearch]) -> HashMap<String, u64> {
+    searches
+        .iter()
+        .map(|s| (s.id.clone(), saved_search_fingerprint(s)))
+        .collect()
+}
+
+fn note_fingerprints(note: &NoteItem) -> (u64, Option<u64>) {
+    let details = fingerprint((
+        &note.name,
+        &note.tags,
+        note.pinned,
+        note.updated_at,
+        &note.parent_id,
+    ));
+    let body = note.loaded.then(|| {
+        let versions: Vec<_> = note.versions.iter().map(|v| (v.ts, &v.content)).collect();
+        fingerprint((&note.content, versions))
+    });
+    (details, body)
+}
+
+/// Fingerprint of `vault` without the fields journal entries carry.
+fn layout_fingerprint(vault: &VaultData) -> u64 {
+    fn strip(items: &mut [TreeItem]) {
+        for item in items {
+            if item.item_type == "note" {
+                item.name.clear();
+                item.tags = None;
+                item.pinned = None;
+                item.updated_at = 0;
+            }
+            if let Some(children) = &mut item.children {
+                strip(children);
+            }
+        }
+    }
+    let mut vault = vault.clone();
+    strip(&mut vault.tree);
+    vault.active_id.clear();
+    vault.open_tabs.clear();
+    vault.save_seq = 0;
+    // Through `Value` so map keys come out sorted
+    let json = serde_json::to_value(&vault)
+        .and_then(|v| serde_json::to_vec(&v))
+        .unwrap_or_default();
+    fingerprint(json)
+}
+
+/// Decide how to save `state`, whose `doc_state_to_vault` is `vault`;
+/// `full` rules out journaling. Returns the marks to store with
+/// `record_save` once the save succeeded.
+pub fn plan_save(state: &DocState, vault: &VaultData, full: bool) -> (SavePlan, JournalMarks) {
+    let old = &state.journal;
+    // A read-only vault may belong to another writer by now
+    if state.read_only {
+        return (SavePlan::Unchanged, old.clone());
+    }
+    let now = unix_now();
+    // The trash of locked folders is in their sub-vaults, not in `vault`
+    let locked_trash: Vec<(&str, &str)> = state
+        .trash
+        .iter()
+        .filter_map(|t| Some((t.id.as_str(), t.stored_in.as_deref()?)))
+        .collect();
+    let mut marks = JournalMarks {
+        layout: Some(fingerprint((layout_fingerprint(vault), locked_trash))),
+        tabs: fingerprint((&state.active_note_id, &state.open_tabs)),
+        notes: state
+            .notes
+            .iter()
+            .map(|n| (n.id.clone(), note_fingerprints(n)))
+            .collect(),
+        saved_searches: saved_search_fingerprints(&state.saved_searches),
+        ..old.clone()
+    };
+    let full_save = |mut marks: JournalMarks| {
+        marks.save_seq = vault.save_seq;
+        marks.entries = 0;
+        marks.bytes = 0;
+        marks.compacted_at = now;
+        (SavePlan::Full, marks)
+    };
+    if full
+        || !state.separate_bodies
+        || old.layout != marks.layout
+        || old.entries >= JOURNAL_MAX_ENTRIES
+        || old.bytes >= JOURNAL_MAX_BYTES
+        || now - old.compacted_at >= JOURNAL_MAX_AGE_SECS
+    {
+        return full_save(marks);
+    }
+
+    let mut notes = Vec::new();
+    for note in &state.notes {
+        let (details, body) = marks.notes[&note.id];
+        let (old_details, old_body) = old.notes.get(&note.id).copied().unwrap_or_default();
+        let body_changed = body.is_some() && body != old_body;
+        if details == old_details && !body_changed {
+            continue;
+        }
+        // Locked-folder notes are saved to their sub-vault
+        if state.locked_root(note.parent_id.as_deref()).is_some() {
+            return full_save(marks);
+        }
+        let body = body_changed.then(|| NoteBody {
+            content: note.content.clone(),
+            versions: versions_to_entries(&note.versions),
+        });
+        marks.bytes += body.as_ref().map_or(0, |b| {
+            b.content.len() + b.versions.iter().map(|v| v.content.len()).sum::<usize>()
+        });
+        notes.push(JournalNote {
+            id: note.id.clone(),
+            name: note.name.clone(),
+            tags: note.tags.clone(),
+            pinned: note.pinned,
+            updated_at: note.updated_at,
+            body,
+        });
+    }
+    if notes.is_empty() && marks.tabs == old.tabs {
+        return (SavePlan::Unchanged, old.clone());
+    }
+    marks.entries += 1;
+    let entry = JournalEntry {
+        base: old.save_seq,
+        notes,
+        active_id: state.active_note_id.clone(),
+        open_tabs: state.open_tabs.clone(),
+    };
+    (SavePlan::Journal(entry), marks)
+}
+
+/// Store the marks of a successful save, unless a newer full save already
+/// replaced the vault.json they refer to.
+pub fn record_save(state: &mut DocState, marks: JournalMarks) {
+    if marks.save_seq >= state.journal.save_seq {
+        state.journal = marks;
+    }
+}
+
+pub fn append_journal(
+    vault_folder: &str,
+    vault_id: &str,
+    entry: &JournalEntry,
+    key: &CachedKey,
+) -> Result<(), VaultError> {
+    use std::io::Write;
+    use zeroize::Zeroize;
+    let mut json = serde_json::to_vec(entry).map_err(|e| VaultError::Invalid(e.to_string()))?;
+    let binding = blob_binding(vault_id, BlobKind::Journal, "journal");
+    let encrypted = crypto::encrypt_asset_bound(&json, key, &binding);
+    json.zeroize();
+    let mut line = encrypted?;
+    line.push('\n');
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_path(vault_folder))?;
+    file.write_all(line.as_bytes())?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Entries in the journal, up to the first one that can't be read — the
+/// tail of an append cut short by a crash.
+pub fn read_journal(
+    vault_folder: &str,
+    vault_id: &str,
+    key: &CachedKey,
+) -> io::Result<Vec<JournalEntry>> {
+    use zeroize::Zeroize;
+    let raw = match fs::read_to_string(journal_path(vault_folder)) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let binding = blob_binding(vault_id, BlobKind::Journal, "journal");
+    let mut entries = Vec::new();
+    for line in raw.lines().filter(|l| !l.is_empty()) {
+        let Ok(mut json) = crypto::decrypt_blob(line.as_bytes(), key, &binding) else {
+            break;
+        };
+        let entry = serde_json::from_slice(&json);
+        json.zeroize();
+        match entry {
+            Ok(entry) => entries.push(entry),
+            Err(_) => break,
+        }
+    }
+    Ok(entries)
+}
+
+/// Apply the journal's entries for the vault.json `state` was loaded from.
+/// Returns how many were applied. The next save should be a full one, which
+/// `state.journal` already asks for.
+pub fn replay_journal(
+    vault_folder: &str,
+    key: &CachedKey,
+    state: &mut DocState,
+) -> io::Result<usize> {
+    let mut applied = 0;
+    for entry in read_journal(vault_folder, &state.vault_id, key)? {
+        if entry.base != state.journal.save_seq {
+            continue;
+        }
+        for change in entry.notes {
+            let Some(note) = state.notes.iter_mut().find(|n| n.id == change.id) else {
+                continue;
+            };
+            note.name = change.name;
+            note.tags = change.tags;
+            note.pinned = change.pinned;
+            note.updated_at = change.updated_at;
+            if let Some(mut body) = change.body {
+                (note.content, note.versions) = body.take();
+                note.loaded = true;
+            }
+        }
+        if find_note_index(&state.notes, &entry.active_id).is_some() {
+            state.active_note_id = entry.active_id;
+        }
+        state.open_tabs = entry
+            .open_tabs
+            .into_iter()
+            .filter(|id| find_note_index(&state.notes, id).is_some())
+            .collect();
+        applied += 1;
+    }
+    Ok(applied)
+}
+
+/// Remove the journal once a full save has folded it into vault.json.
+pub fn clear_journal(vault_folder: &str) -> io::Result<()> {
+    match fs::remove_file(journal_path(vault_folder)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Held saves  (a save that failed as the vault was locked)
+// ---------------------------------------------------------------------------
+//
+// Locking must not wait for the disk: the key and the decrypted notes go
+// either way. What the save would have written is sealed first, as the same
+// blobs a full save writes, and kept in memory until it can be written. That
+// needs no key, but is refused once someone else wrote the vault.
+
+/// Note ID and the blob `write_note_body` would write.
+type SealedBodies = Vec<(String, String)>;
+
+/// An encrypted full save waiting to be written, see `hold_save`.
+pub struct HeldSave {
+    vault: String,
+    bodies: SealedBodies,
+    /// Folder ID, sub-vault and its note bodies.
+    sub_vaults: Vec<(String, String, SealedBodies)>,
+    /// The vault on disk as the save found it.
+    marks: JournalMarks,
+}
+
+/// Seal a full save of `data`, `bodies` and `sub_vaults` under their keys.
+/// `marks` are those of the failed save, to tell whether the vault on disk
+/// changed before the held save is written.
+pub fn hold_save(
+    data: &VaultData,
+    bodies: &[(String, NoteBody)],
+    sub_vaults: &[SubVaultWrite],
+    key: &CachedKey,
+    marks: &JournalMarks,
+) -> Result<HeldSave, VaultError> {
+    use zeroize::Zeroize;
+    let seal_vault = |data: &VaultData, key: &CachedKey| -> Result<String, VaultError> {
+        let mut json =
+            serde_json::to_string_pretty(data).map_err(|e| VaultError::Invalid(e.to_string()))?;
+        let encrypted = crypto::encrypt_vault_fast(&json, key);
+        json.zeroize();
+        Ok(encrypted?)
+    };
+    let seal_bodies = |owner: &str, bodies: &[(String, NoteBody)], key: &CachedKey| {
+        bodies
+            .iter()
+            .map(|(id, body)| Ok((id.clone(), seal_note_body(owner, id, body, key)?)))
+            .collect::<Result<SealedBodies, VaultError>>()
+    };
+    let sub_vaults = sub_vaults
+        .iter()
+        .map(|sub| {
+            Ok((
+                sub.folder_id.clone(),
+                seal_vault(&sub.data, &sub.key)?,
+                seal_bodies(&sub.owner, &sub.bodies, &sub.key)?,
+            ))
+        })
+        .collect::<Result<_, VaultError>>()?;
+    Ok(HeldSave {
+        vault: seal_vault(data, key)?,
+        bodies: seal_bodies(&data.vault_id, bodies, key)?,
+        sub_vaults,
+        marks: marks.clone(),
+    })
+}
+
+/// Write `held` to `vault_folder` in the order of a full save, unless the
+/// vault changed on disk since it was held. Bodies of notes deleted before
+/// the lock are left for the next full save to prune.
+pub fn write_held_save(vault_folder: &str, held: &HeldSave) -> Result<(), VaultError> {
+    if held.marks.disk_changed(vault_folder) {
+        return Err(VaultError::Changed);
+    }
+    let write_bodies = |root: &str, bodies: &[(String, String)]| -> Result<(), VaultError> {
+        fs::create_dir_all(notes_dir(root))?;
+        for (id, sealed) in bodies {
+            atomic_write(&note_body_path(root, id)?, sealed.as_bytes())
+                .map_err(|e| VaultError::Note(id.clone(), format!("write failed: {e}")))?;
+        }
+        Ok(())
+    };
+    for (folder_id, vault, bodies) in &held.sub_vaults {
+        let root = sub_vault_path(vault_folder, folder_id);
+        write_bodies(&root, bodies)?;
+        write_vault_raw(&root, vault)?;
+    }
+    write_bodies(vault_folder, &held.bodies)?;
+    write_vault_raw(vault_folder, &held.vault)?;
+    clear_journal(vault_folder)?;
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// External changes  (vault.json or the journal saved by another writer)
+// ---------------------------------------------------------------------------
+//
+// The journal marks of the last load or save are the common base of a
+// three-way merge with the vault as it is on disk now. A note changed only
+// there is taken over, one changed only here is kept, and one changed on both
+// sides to different text is a conflict: their text goes into the note's
+// history until the user picks a side. Folders come along with the notes in
+// them, assets only they have are added, and settings and the tree order stay
+// ours. Notes in locked folders are left alone, as the main vault doesn't
+// list them.
+
+/// Size and modification time of vault.json and the journal, if they exist.
+type DiskStat = [Option<(u64, Option<std::time::SystemTime>)>; 2];
+
+fn disk_stat(vault_folder: &str) -> DiskStat {
+    [vault_file_path(vault_folder), journal_path(vault_folder)].map(|path| {
+        fs::metadata(path)
+            .ok()
+            .map(|m| (m.len(), m.modified().ok()))
+    })
+}
+
+/// Fingerprint of vault.json and the journal as they are on disk.
+fn disk_fingerprint(vault_folder: &str) -> io::Result<u64> {
+    let read = |path: PathBuf| match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    };
+    let vault = read(vault_file_path(vault_folder))?;
+    Ok(fingerprint((vault, read(journal_path(vault_folder))?)))
+}
+
+impl JournalMarks {
+    /// Remember vault.json and the journal as they are now, after reading or
+    /// writing them.
+    pub fn stamp_disk(&mut self, vault_folder: &str) {
+        // Before reading, so a write in between is noticed again later
+        self.disk_stat = Some(disk_stat(vault_folder));
+        self.disk = disk_fingerprint(vault_folder).ok();
+    }
+
+    /// Whether someone else wrote vault.json or the journal since
+    /// `stamp_disk`. Saving now would overwrite their changes. Reads the
+    /// files only if their size or modification time changed.
+    pub fn disk_changed(&self, vault_folder: &str) -> bool {
+        if self.disk.is_none() || self.disk_stat == Some(disk_stat(vault_folder)) {
+            return false;
+        }
+        disk_fingerprint(vault_folder).ok() != self.disk
+    }
+}
+
+/// Use freshly loaded `state` as the base for merging external changes.
+pub fn mark_loaded(state: &mut DocState, vault_folder: &str) {
+    state.journal.notes = state
+        .notes
+        .iter()
+        .map(|n| (n.id.clone(), note_fingerprints(n)))
+        .collect();
+    state.journal.saved_searches = saved_search_fingerprints(&state.saved_searches);
+    state.journal.stamp_disk(vault_folder);
+}
+
+/// A note changed here and by another writer to different text.
+#[derive(Debug, Clone)]
+pub struct NoteConflict {
+    pub note_id: String,
+    pub name: String,
+    /// Their text; ours is the note's content.
+    pub theirs: String,
+}
+
+/// Notes `merge_external` changed, by name.
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub updated: Vec<String>,
+    pub added: Vec<String>,
+    /// Moved to the trash because the other writer deleted them.
+    pub removed: Vec<String>,
+    pub conflicts: Vec<NoteConflict>,
+    /// Changed here and by the other writer, whose text could not be read
+    /// (not synced yet, or damaged). Ours is kept.
+    pub unreadable: Vec<String>,
+}
+
+impl MergeReport {
+    pub fn is_empty(&self) -> bool {
+        self.updated.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.conflicts.is_empty()
+            && self.unreadable.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Mine,
+    Theirs,
+    /// Keep ours and add theirs as a new note.
+    Both,
+}
+
+/// The vault as another writer left it: vault.json with the journal replayed,
+/// and bodies loaded for the notes that may have changed since `base`. Bodies
+/// the sync tool hasn't delivered yet stay unloaded.
+pub fn load_external(
+    vault_folder: &str,
+    key: &CachedKey,
+    base: &JournalMarks,
+) -> Result<DocState, VaultError> {
+    use zeroize::Zeroize;
+    // Read before the files so a write in between is noticed again later
+    let stat = disk_stat(vault_folder);
+    let disk = disk_fingerprint(vault_folder)?;
+    let raw = read_vault_raw(vault_folder)?
+        .ok_or_else(|| VaultError::Invalid("vault.json is missing".into()))?;
+    let mut json = crypto::decrypt_vault_fast(&raw, key)?;
+    let vault = parse_vault(&json);
+    json.zeroize();
+    let mut theirs = vault_to_doc_state(vault?);
+    replay_journal(vault_folder, key, &mut theirs)?;
+    for note in &mut theirs.notes {
+        // An edit within the second of the last save leaves the details alone
+        let details = note_fingerprints(note).0;
+        let compare = match base.notes.get(&note.id) {
+            Some(&(old, body)) => old != details || body.is_some(),
+            None => true,
+        };
+        if compare {
+            let _ = load_note_body(vault_folder, &theirs.vault_id, key, note);
+        }
+    }
+    theirs.journal.disk = Some(disk);
+    theirs.journal.disk_stat = Some(stat);
+    Ok(theirs)
+}
+
+/// Three-way merge of `theirs`, from `load_external`, into `state`. Afterwards
+/// `theirs` is the base for the next merge, and the next save is a full one.
+pub fn merge_external(state: &mut DocState, theirs: DocState) -> MergeReport {
+    let mut report = MergeReport::default();
+    // Nothing changes here in a read-only window, so theirs always wins
+    let read_only = state.read_only;
+    let base = std::mem::take(&mut state.journal.notes);
+    let changed = |id: &str, (details, body): (u64, Option<u64>)| match base.get(id) {
+        None => true,
+        Some(&(old_details, old_body)) => {
+            details != old_details || body.zip(old_body).is_some_and(|(a, b)| a != b)
+        }
+    };
+    let new_base: HashMap<String, (u64, Option<u64>)> = theirs
+        .notes
+        .iter()
+        .map(|n| (n.id.clone(), note_fingerprints(n)))
+        .collect();
+    state.next_note_seq = state.next_note_seq.max(theirs.next_note_seq);
+
+    // Deleted there: trash ours, unless it changed here since
+    for id in base.keys().filter(|id| !new_base.contains_key(*id)) {
+        let Some(index) = find_note_index(&state.notes, id) else {
+            continue;
+        };
+        let note = &state.notes[index];
+        if state.locked_root(note.parent_id.as_deref()).is_some()
+            || (!read_only && changed(id, note_fingerprints(note)))
+        {
+            continue;
+        }
+        let name = note.name.clone();
+        if state.move_note_to_trash(id).is_some() || find_note_index(&state.notes, id).is_none() {
+            report.removed.push(name);
+        }
+    }
+
+    for mut their in theirs.notes {
+        let their_changed = changed(&their.id, note_fingerprints(&their));
+        let in_base = base.contains_key(&their.id);
+        let index = find_note_index(&state.notes, &their.id);
+        let ours = index.map(|i| &state.notes[i]);
+        if ours.is_some_and(|n| state.locked_root(n.parent_id.as_deref()).is_some()) {
+            continue;
+        }
+        match (index, in_base) {
+            // Unchanged there
+            (_, true) if !their_changed => continue,
+            (Some(index), true) => {
+                let ours = &mut state.notes[index];
+                let ours_changed = !read_only && changed(&ours.id, note_fingerprints(ours));
+                let same_text = their.loaded && ours.loaded && their.content == ours.content;
+                if !ours_changed || same_text {
+                    ours.name = their.name;
+                    ours.tags = their.tags;
+                    ours.pinned = their.pinned;
+                    ours.updated_at = their.updated_at;
+                    if their.loaded {
+                        ours.content = their.content;
+                        ours.versions = their.versions;
+                        ours.loaded = true;
+                    } else {
+                        // Read from `notes/` when next opened
+                        unload_note_body(ours);
+                    }
+                    let name = ours.name.clone();
+                    if adopt_folder(state, &theirs.folders, their.parent_id.as_deref()) {
+                        state.notes[index].parent_id = their.parent_id;
+                    }
+                    report.updated.push(name);
+                } else if their.loaded && ours.loaded {
+                    crate::notes::push_snapshot(ours, their.content.clone());
+                    report.conflicts.push(NoteConflict {
+                        note_id: ours.id.clone(),
+                        name: ours.name.clone(),
+                        theirs: their.content,
+                    });
+                } else if their.loaded {
+                    // Only the details changed here
+                    ours.content = their.content;
+                    ours.versions = their.versions;
+                    ours.loaded = true;
+                    ours.updated_at = ours.updated_at.max(their.updated_at);
+                    report.updated.push(ours.name.clone());
+                } else {
+                    report.unreadable.push(ours.name.clone());
+                }
+            }
+            // Same text in a note both sides created under the same ID
+            (Some(index), false)
+                if their.loaded
+                    && state.notes[index].loaded
+                    && state.notes[index].content == their.content => {}
+            (_, in_base) => {
+                // New there, or deleted here but edited there since
+                if in_base {
+                    state.trash.retain(|t| t.id != their.id);
+                }
+                let taken = |state: &DocState, id: &str| {
+                    find_note_index(&state.notes, id).is_some()
+                        || state.trash.iter().any(|t| t.id == id)
+                        || state.folders.iter().any(|f| f.id == id)
+                };
+                if taken(state, &their.id) {
+                    let new_id = loop {
+                        let id = format!("note-{}", state.next_note_seq);
+                        state.next_note_seq += 1;
+                        if !taken(state, &id) {
+                            break id;
+                        }
+                    };
+                    their.id = new_id;
+                }
+                if !adopt_folder(state, &theirs.folders, their.parent_id.as_deref()) {
+                    their.parent_id = None;
+                }
+                their.name = deduplicate_note_name(&state.notes, &their.name, &their.parent_id);
+                report.added.push(their.name.clone());
+                state.notes.push(their);
+            }
+        }
+    }
+
+    for (id, meta) in theirs.assets {
+        state.assets.entry(id).or_insert(meta);
+    }
+    let searches_base = std::mem::take(&mut state.journal.saved_searches);
+    let new_searches_base = saved_search_fingerprints(&theirs.saved_searches);
+    merge_saved_searches(state, &searches_base, theirs.saved_searches, read_only);
+    state.journal = JournalMarks {
+        save_seq: theirs.journal.save_seq,
+        notes: new_base,
+        saved_searches: new_searches_base,
+        disk: theirs.journal.disk,
+        disk_stat: theirs.journal.disk_stat,
+        ..JournalMarks::default()
+    };
+    report
+}
+
+/// Merge their smart folders into ours by ID, like notes: a change or a
+/// deletion on one side since `base` is taken, and ours wins if both sides
+/// changed one.
+fn merge_saved_searches(
+    state: &mut DocState,
+    base: &HashMap<String, u64>,
+    theirs: Vec<SavedSearch>,
+    read_only: bool,
+) {
+    let changed =
+        |search: &SavedSearch| base.get(&search.id) != Some(&saved_search_fingerprint(search));
+    // Deleted there, unless it changed here since
+    let their_ids: HashSet<&str> = theirs.iter().map(|s| s.id.as_str()).collect();
+    state.saved_searches.retain(|ours| {
+        their_ids.contains(ours.id.as_str())
+            || !base.contains_key(&ours.id)
+            || (!read_only && changed(ours))
+    });
+    for mut their in theirs {
+        let Some(index) = state.saved_searches.iter().position(|s| s.id == their.id) else {
+            // Deleted here, unless it changed there since
+            if changed(&their) {
+                state.saved_searches.push(their);
+            }
+            continue;
+        };
+        let ours = &mut state.saved_searches[index];
+        if base.contains_key(&their.id) {
+            if changed(&their) && (read_only || !changed(ours)) {
+                their.expanded = ours.expanded;
+                *ours = their;
+            }
+        } else if saved_search_fingerprint(ours) != saved_search_fingerprint(&their) {
+            // Made on both sides under the same ID
+            their.id = SavedSearch::next_id(&state.saved_searches);
+            state.saved_searches.push(their);
+        }
+    }
+}
+
+/// Make sure `folder_id` exists here, copying it and its parents from
+/// `theirs` if needed. False if it can't hold notes from the main vault.
+fn adopt_folder(state: &mut DocState, theirs: &[FolderItem], folder_id: Option<&str>) -> bool {
+    let Some(folder_id) = folder_id else {
+        return true;
+    };
+    if state.folders.iter().any(|f| f.id == folder_id) {
+        return state.locked_root(Some(folder_id)).is_none();
+    }
+    let Some(folder) = theirs.iter().find(|f| f.id == folder_id && !f.locked) else {
+        return false;
+    };
+    let mut folder = folder.clone();
+    if !adopt_folder(state, theirs, folder.parent_id.as_deref()) {
+        folder.parent_id = None;
+    }
+    state.folders.push(folder);
+    true
+}
+
+/// Settle a conflict from `merge_external`. Returns the note that now holds
+/// their text: the conflicted note itself, or the copy made for `Both`.
+pub fn resolve_conflict(
+    state: &mut DocState,
+    conflict: &NoteConflict,
+    resolution: Resolution,
+) -> Option<String> {
+    let index = find_note_index(&state.notes, &conflict.note_id)?;
+    match resolution {
+        Resolution::Mine => None,
+        Resolution::Theirs => {
+            let note = &mut state.notes[index];
+            let ours = std::mem::replace(&mut note.content, conflict.theirs.clone());
+            crate::notes::push_snapshot(note, ours);
+            note.updated_at = unix_now();
+            Some(note.id.clone())
+        }
+        Resolution::Both => {
+            let note = &state.notes[index];
+            let (tags, parent_id) = (note.tags.clone(), note.parent_id.clone());
+            let name = deduplicate_note_name(
+                &state.notes,
+                &format!("{} (conflicted copy)", note.name),
+                &parent_id,
+            );
+            let id = loop {
+                let id = format!("note-{}", state.next_note_seq);
+                state.next_note_seq += 1;
+                if find_note_index(&state.notes, &id).is_none()
+                    && !state.trash.iter().any(|t| t.id == id)
+                {
+                    break id;
+                }
+            };
+            let mut copy = NoteItem::new(id.clone(), name, conflict.theirs.clone(), tags);
+            copy.parent_id = parent_id;
+            state.notes.push(copy);
+            Some(id)
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Locked folders  (locked/<folder-id>, a sub-vault with its own passphrase)
+// ---------------------------------------------------------------------------
+//
+// A locked folder keeps its subtree, note bodies and assets in a nested vault
+// folder laid out like the main one. The main vault.json records only the
+// folder itself, so its notes stay out of the sidebar and search until the
+// sub-vault is opened with its passphrase.
+
+pub fn sub_vault_path(vault_folder: &str, folder_id: &str) -> String {
+    Path::new(vault_folder)
+        .join("locked")
+        .join(folder_id)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Vault folder holding blobs stored in `stored_in`, and the key they are encrypted with.
+pub fn blob_location<'a>(
+    vault_folder: &str,
+    folder_keys: &'a HashMap<String, CachedKey>,
+    main_key: &'a CachedKey,
+    stored_in: Option<&str>,
+) -> Result<(String, &'a CachedKey), VaultError> {
+    match stored_in {
+        None => Ok((vault_folder.to_string(), main_key)),
+        Some(folder_id) => folder_keys
+            .get(folder_id)
+            .map(|key| (sub_vault_path(vault_folder, folder_id), key))
+            .ok_or_else(|| VaultError::Locked(folder_id.to_string())),
+    }
+}
+
+/// Decrypt a locked folder's sub-vault. Slow: runs the KDF.
+pub fn open_sub_vault(
+    vault_folder: &str,
+    folder_id: &str,
+    passphrase: &str,
+) -> Result<(VaultData, CachedKey), VaultError> {
+    use zeroize::Zeroize;
+    if !is_valid_asset_id(folder_id) {
+        return Err(VaultError::Invalid(format!("bad folder ID {folder_id}")));
+    }
+    let raw = read_vault_raw(&sub_vault_path(vault_folder, folder_id))?
+        .ok_or_else(|| VaultError::Invalid(format!("sub-vault of {folder_id} is missing")))?;
+    let (mut json, key) = crypto::decrypt_vault_returning_key(&raw, passphrase)?;
+    let data = parse_vault(&json);
+    json.zeroize();
+    Ok((data?, key))
+}
+
+/// The trash of `vault`, kept in the locked folder `stored_in` or in the main vault.
+fn trash_items(vault: &VaultData, loaded: bool, stored_in: Option<&str>) -> Vec<TrashItem> {
+    vault
+        .trash
+        .iter()
+        .map(|item| {
+            let versions = vault
+                .note_versions
+                .get(&item.id)
+                .map(|entries| {
+                    entries
+                        .iter()
+                        .map(|e| NoteVersion {
+                            ts: e.ts,
+                            content: e.content.clone(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            TrashItem {
+                id: item.id.clone(),
+                name: item.name.clone(),
+                content: item.content.clone().unwrap_or_default(),
+                tags: item.tags.clone().unwrap_or_default(),
+                created_at: item.created_at,
+                updated_at: item.updated_at,
+                deleted_at: item.deleted_at.unwrap_or(item.updated_at),
+                parent_id: None,
+                versions,
+                pinned: item.pinned.unwrap_or(false),
+                loaded,
+                stored_in: stored_in.map(str::to_string),
+            }
+        })
+        .collect()
+}
+
+/// Show an opened sub-vault's contents under its folder. Bodies stay on disk
+/// until each note is opened.
+pub fn merge_sub_vault(state: &mut DocState, folder_id: &str, data: VaultData, key: CachedKey) {
+    if state.is_folder_unlocked(folder_id) {
+        return;
+    }
+    let mut notes = Vec::new();
+    let mut folders = Vec::new();
+    flatten_tree(
+        &data.tree,
+        Some(folder_id.to_string()),
+        &mut notes,
+        &mut folders,
+        &data.note_versions,
+        false,
+        Some(folder_id),
+    );
+    state.notes.extend(notes);
+    state.folders.extend(folders);
+    state
+        .trash
+        .extend(trash_items(&data, false, Some(folder_id)));
+    for (id, meta) in &data.assets {
+        let meta = AssetMeta {
+            stored_in: Some(folder_id.to_string()),
+            ..meta.clone()
+        };
+        state.assets.insert(id.clone(), meta);
+    }
+    state.folder_keys.insert(folder_id.to_string(), key);
+}
+
+/// Hide an unlocked folder's contents again and forget its key. Only call
+/// right after a successful save. Returns the note to switch to if the active
+/// one was inside; `None` with no notes left means a new one is needed.
+pub fn close_sub_vault(state: &mut DocState, folder_id: &str) -> Option<String> {
+    state.folder_keys.remove(folder_id)?;
+    let inside = |state: &DocState, parent: &Option<String>| {
+        parent
+            .as_deref()
+            .is_some_and(|p| state.folder_is_within(p, folder_id))
+    };
+    let hidden_folders: HashSet<String> = state
+        .folders
+        .iter()
+        .filter(|f| inside(state, &f.parent_id))
+        .map(|f| f.id.clone())
+        .collect();
+    let (mut hidden, notes): (Vec<NoteItem>, Vec<NoteItem>) = std::mem::take(&mut state.notes)
+        .into_iter()
+        .partition(|n| inside(state, &n.parent_id));
+    for note in &mut hidden {
+        unload_note_body(note);
+        state.search_index.remove_note(&note.id);
+    }
+    state.notes = notes;
+    let (mut hidden_trash, trash): (Vec<TrashItem>, Vec<TrashItem>) =
+        std::mem::take(&mut state.trash)
+            .into_iter()
+            .partition(|t| t.stored_in.as_deref() == Some(folder_id));
+    for item in &mut hidden_trash {
+        use zeroize::Zeroize;
+        item.name.zeroize();
+        item.content.zeroize();
+        item.tags.iter_mut().for_each(Zeroize::zeroize);
+        item.versions.iter_mut().for_each(|v| v.content.zeroize());
+    }
+    state.trash = trash;
+    state.folders.retain(|f| !hidden_folders.contains(&f.id));
+    if state
+        .active_folder_id
+        .as_ref()
+        .is_some_and(|f| hidden_folders.contains(f))
+    {
+        state.active_folder_id = None;
+    }
+    state
+        .assets
+        .retain(|_, meta| meta.stored_in.as_deref() != Some(folder_id));
+
+    let note_ids: HashSet<&str> = state.notes.iter().map(|n| n.id.as_str()).collect();
+    state.open_tabs.retain(|id| note_ids.contains(id.as_str()));
+    if note_ids.contains(state.active_note_id.as_str()) {
+        return None;
+    }
+    let fallback = state
+        .open_tabs
+        .first()
+        .or_else(|| state.notes.first().map(|n| &n.id))
+        .cloned()?;
+    if state.open_tabs.is_empty() {
+        state.open_tabs.push(fallback.clone());
+    }
+    Some(fallback)
+}
+
+/// Turn `folder_id` into a locked folder protected by `key` (from
+/// `CachedKey::derive` with the folder's passphrase). Every note in it must be
+/// loaded; the next save writes them to the new sub-vault and drops them from
+/// the main vault. Assets the notes refer to are moved right away — returns
+/// their old files, to delete once that save has succeeded.
+pub fn lock_folder(
+    vault_folder: &str,
+    state: &mut DocState,
+    folder_id: &str,
+    key: CachedKey,
+    main_key: &CachedKey,
+) -> Result<Vec<PathBuf>, VaultError> {
+    let invalid = |m: &str| Err(VaultError::Invalid(m.to_string()));
+    if !is_valid_asset_id(folder_id) || !state.folders.iter().any(|f| f.id == folder_id) {
+        return invalid("unknown folder");
+    }
+    if state.locked_root(Some(folder_id)).is_some() {
+        return invalid("the folder is already inside a locked folder");
+    }
+    if state
+        .folders
+        .iter()
+        .any(|f| f.locked && state.folder_is_within(&f.id, folder_id))
+    {
+        return invalid("the folder contains a locked folder");
+    }
+    let notes: Vec<&NoteItem> = state
+        .notes
+        .iter()
+        .filter(|n| {
+            n.parent_id
+                .as_deref()
+                .is_some_and(|p| state.folder_is_within(p, folder_id))
+        })
+        .collect();
+    if notes.iter().any(|n| !n.loaded) {
+        return invalid("all notes in the folder must be loaded first");
+    }
+    let asset_ids: Vec<String> = notes
+        .iter()
+        .flat_map(|n| std::iter::once(&n.content).chain(n.versions.iter().map(|v| &v.content)))
+        .flat_map(|text| referenced_asset_ids(text))
+        .filter(|id| state.assets.contains_key(id))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    if let Some(folder) = state.folders.iter_mut().find(|f| f.id == folder_id) {
+        folder.locked = true;
+    }
+    state.folder_keys.insert(folder_id.to_string(), key);
+    move_assets(vault_folder, state, main_key, &asset_ids, Some(folder_id))
+}
+
+/// Make an unlocked locked folder an ordinary one again. The next save moves
+/// its notes and assets back into the main vault and deletes the sub-vault.
+pub fn remove_folder_lock(state: &mut DocState, folder_id: &str) -> Result<(), VaultError> {
+    if !state.is_folder_unlocked(folder_id) {
+        return Err(VaultError::Locked(folder_id.to_string()));
+    }
+    if let Some(folder) = state.folders.iter_mut().find(|f| f.id == folder_id) {
+        folder.locked = false;
+    }
+    Ok(())
+}
+
+/// Every `vault://<id>` link in `markdown`: the byte range of the whole link
+/// and the ID in it, which may be empty or not a valid asset ID.
+pub(crate) fn asset_links(markdown: &str) -> impl Iterator<Item = (Range<usize>, &str)> {
+    const SCHEME: &str = "vault://";
+    let mut from = 0;
+    std::iter::from_fn(move || {
+        let start = from + markdown[from..].find(SCHEME)?;
+        let id_start = start + SCHEME.len();
+        from = markdown[id_start..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+            .map_or(markdown.len(), |len| id_start + len);
+        Some((start..from, &markdown[id_start..from]))
+    })
+}
+
+/// `markdown` with each `vault://<id>` link replaced by what `replace`
+/// returns for its ID; links it returns `None` for stay as they are.
+pub(crate) fn replace_asset_links(
+    markdown: &str,
+    mut replace: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let mut copied = 0;
+    for (link, id) in asset_links(markdown) {
+        if let Some(replacement) = replace(id) {
+            out.push_str(&markdown[copied..link.start]);
+            out.push_str(&replacement);
+            copied = link.end;
+        }
+    }
+    out.push_str(&markdown[copied..]);
+    out
+}
+
+/// IDs in `vault://<id>` image links.
+pub(crate) fn referenced_asset_ids(markdown: &str) -> Vec<String> {
+    asset_links(markdown)
+        .map(|(_, id)| id)
+        .filter(|id| is_valid_asset_id(id))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Re-encrypt assets into the vault they now belong to (`to` is a locked
+/// folder, or `None` for the main vault), bound to it at the next version.
+/// Returns the old files, to delete once the vault has been saved with the
+/// updated asset list.
+pub fn move_assets(
+    vault_folder: &str,
+    state: &mut DocState,
+    main_key: &CachedKey,
+    ids: &[String],
+    to: Option<&str>,
+) -> Result<Vec<PathBuf>, VaultError> {
+    let mut stale = Vec::new();
+    for id in ids {
+        let Some(meta) = state.assets.get(id) else {
+            continue;
+        };
+        if meta.stored_in.as_deref() == to {
+            continue;
+        }
+        let from = meta.stored_in.clone();
+        let expected = asset_binding(&state.vault_id, meta);
+        let version = meta.version.unwrap_or(0) + 1;
+        let fail = |m: String| VaultError::Asset(id.clone(), m);
+        if !is_valid_asset_id(id) {
+            return Err(fail("invalid asset ID".into()));
+        }
+        let (from_dir, from_key) =
+            blob_location(vault_folder, &state.folder_keys, main_key, from.as_deref())?;
+        let (to_dir, to_key) = blob_location(vault_folder, &state.folder_keys, main_key, to)?;
+        let old_path = assets_dir(&from_dir).join(id);
+        let binding = binding_for(&state.vault_id, to, id, version);
+        match fs::read(&old_path) {
+            Ok(data) => {
+                let decrypted = match &expected {
+                    Some(expected) => crypto::decrypt_asset_bound(&data, from_key, expected),
+                    None => crypto::decrypt_asset(&data, from_key),
+                }
+                .map_err(|e| fail(format!("decrypt failed: {e}")))?;
+                let encrypted = crypto::encrypt_asset_bound(&decrypted, to_key, &binding)
+                    .map_err(|e| fail(format!("re-encrypt failed: {e}")))?;
+                write_asset(&to_dir, id, encrypted.as_bytes())
+                    .map_err(|e| fail(format!("write failed: {e}")))?;
+                stale.push(old_path);
+            }
+            // Nothing to move for an asset whose file is already gone.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(fail(format!("read failed: {e}"))),
+        }
+        if let Some(meta) = state.assets.get_mut(id) {
+            meta.stored_in = to.map(str::to_string);
+            meta.version = Some(version);
+        }
+    }
+    Ok(stale)
+}
+
+/// Bring blob locations in line with the folder tree before a save. Bodies
+/// and assets that moved into or out of a locked folder — or whose locked
+/// folder was deleted or had its lock removed — are re-encrypted for the vault
+/// they now belong to. Returns the files left behind, to delete after the save.
+pub fn settle_sub_vaults(
+    vault_folder: &str,
+    state: &mut DocState,
+    main_key: &CachedKey,
+) -> Result<Vec<PathBuf>, VaultError> {
+    if state.read_only {
+        return Ok(Vec::new());
+    }
+    let roots: Vec<Option<String>> = state
+        .notes
+        .iter()
+        .map(|n| {
+            state
+                .locked_root(n.parent_id.as_deref())
+                .map(str::to_string)
+        })
+        .collect();
+    // Trashed notes stay in the locked folder they were deleted from
+    let trash_roots: Vec<Option<String>> = state
+        .trash
+        .iter()
+        .map(|t| {
+            state
+                .locked_root(t.parent_id.as_deref())
+                .or(t
+                    .stored_in
+                    .as_deref()
+                    .filter(|id| state.folders.iter().any(|f| f.id == *id && f.locked)))
+                .map(str::to_string)
+        })
+        .collect();
+    let DocState {
+        notes,
+        trash,
+        folder_keys,
+        vault_id,
+        ..
+    } = &mut *state;
+    for (note, root) in notes.iter_mut().zip(roots) {
+        if !note.loaded && note.stored_in != root {
+            let (dir, key) = blob_location(
+                vault_folder,
+                folder_keys,
+                main_key,
+                note.stored_in.as_deref(),
+            )?;
+            let owner = blob_owner(vault_id, note.stored_in.as_deref());
+            load_note_body(&dir, &owner, key, note)?;
+        }
+        if note.loaded {
+            note.stored_in = root;
+        }
+    }
+    for (item, root) in trash.iter_mut().zip(trash_roots) {
+        if !item.loaded && item.stored_in != root {
+            let (dir, key) = blob_location(
+                vault_folder,
+                folder_keys,
+                main_key,
+                item.stored_in.as_deref(),
+            )?;
+            let owner = blob_owner(vault_id, item.stored_in.as_deref());
+            (item.content, item.versions) = read_note_body(&dir, &owner, &item.id, key)?.take();
+            item.loaded = true;
+        }
+        if item.loaded {
+            item.stored_in = root;
+        }
+    }
+
+    let is_locked =
+        |state: &DocState, id: &str| state.folders.iter().any(|f| f.id == id && f.locked);
+    let orphaned: Vec<String> = state
+        .assets
+        .iter()
+        .filter(|(_, m)| {
+            m.stored_in
+                .as_deref()
+                .is_some_and(|id| !is_locked(state, id))
+        })
+        .map(|(id, _)| id.clone())
+        .collect();
+    let stale = move_assets(vault_folder, state, main_key, &orphaned, None)?;
+    let unlocked: Vec<String> = state.folder_keys.keys().cloned().collect();
+    for id in unlocked {
+        if !is_locked(state, &id) {
+            state.folder_keys.remove(&id);
+        }
+    }
+    Ok(stale)
+}
+
+/// A locked folder's part of a save.
+pub struct SubVaultWrite {
+    pub folder_id: String,
+    /// See [`blob_owner`].
+    pub owner: String,
+    pub data: VaultData,
+    pub bodies: Vec<(String, NoteBody)>,
+    pub key: CachedKey,
+}
+
+/// Snapshot every unlocked locked folder for saving.
+pub fn sub_vault_writes(state: &DocState) -> Vec<SubVaultWrite> {
+    let (notes_by_parent, folders_by_parent) = group_by_parent(state);
+    state
+        .folders
+        .iter()
+        .filter(|f| f.locked)
+        .filter_map(|folder| {
+            let key = state.folder_keys.get(&folder.id)?;
+            let data = VaultData {
+                tree: build_vault_tree_level(
+                    Some(&folder.id),
+                    &notes_by_parent,
+                    &folders_by_parent,
+                    false,
+                ),
+                trash: trash_entries(state, Some(&folder.id), false),
+                assets: assets_stored_in(state, Some(&folder.id)),
+                separate_bodies: true,
+                schema_version: SCHEMA_VERSION,
+                ..VaultData::default()
+            };
+            Some(SubVaultWrite {
+                folder_id: folder.id.clone(),
+                owner: blob_owner(&state.vault_id, Some(&folder.id)),
+                data,
+                bodies: loaded_note_bodies(state, Some(&folder.id)),
+                key: key.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Write a sub-vault: its note bodies, then its vault.json, then drop bodies
+/// of notes that left it.
+pub fn write_sub_vault(vault_folder: &str, sub: &SubVaultWrite) -> Result<(), VaultError> {
+    use zeroize::Zeroize;
+    let root = sub_vault_path(vault_folder, &sub.folder_id);
+    write_note_bodies(&root, &sub.owner, &sub.bodies, &sub.key)?;
+    let mut json =
+        serde_json::to_string(&sub.data).map_err(|e| VaultError::Invalid(e.to_string()))?;
+    let encrypted = crypto::encrypt_vault_fast(&json, &sub.key);
+    json.zeroize();
+    write_vault_raw(&root, &encrypted?)?;
+    prune_note_bodies(&root, &sub.data)?;
+    Ok(())
+}
+
+/// Delete sub-vaults whose folder is no longer locked in `vault`. By then
+/// `settle_sub_vaults` has moved everything they held into the main vault.
+pub fn prune_sub_vaults(vault_folder: &str, vault: &VaultData) -> io::Result<()> {
+    fn collect<'a>(items: &'a [TreeItem], ids: &mut HashSet<&'a str>) {
+        for item in items {
+            if item.locked == Some(true) {
+                ids.insert(&item.id);
+            }
+            if let Some(children) = &item.children {
+                collect(children, ids);
+            }
+        }
+    }
+    let mut locked = HashSet::new();
+    collect(&vault.tree, &mut locked);
+
+    let dir = Path::new(vault_folder).join("locked");
+    for id in list_blob_ids(&dir)? {
+        if !locked.contains(id.as_str()) {
+            fs::remove_dir_all(dir.join(id))?;
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Re-encryption and encryption audit
+// ---------------------------------------------------------------------------
+
+/// Re-encrypt the vault (from `plaintext`), every listed asset and every note
+/// body under `notes/` with `new_key`.
+///
+/// When both keys share a data key (e.g. only the unlock slots changed) just the
+/// vault is rewritten. Otherwise all blobs are re-encrypted in memory before
+/// anything is written. The vault is written first — if that fails, nothing on
+/// disk has changed. If a later blob write fails, the vault is already on the
+/// new key and the caller can retry with it.
+pub fn rekey_vault(
+    vault_folder: &str,
+    plaintext: &str,
+    old_key: &CachedKey,
+    new_key: &CachedKey,
+    asset_ids: &[String],
+) -> Result<(), VaultError> {
+    let reencrypted = crypto::encrypt_vault_fast(plaintext, new_key)?;
+    if new_key.same_data_key(old_key) {
+        write_vault_raw(vault_folder, &reencrypted)?;
+        clear_journal(vault_folder)?;
+        return Ok(());
+    }
+
+    let reencrypted_assets = reencrypt_blobs(
+        &assets_dir(vault_folder),
+        asset_ids,
+        old_key,
+        new_key,
+        VaultError::Asset,
+    )?;
+    let note_ids = list_blob_ids(&notes_dir(vault_folder))?;
+    let reencrypted_notes = reencrypt_blobs(
+        &notes_dir(vault_folder),
+        &note_ids,
+        old_key,
+        new_key,
+        VaultError::Note,
+    )?;
+
+    write_vault_raw(vault_folder, &reencrypted)?;
+
+    for (asset_id, data) in &reencrypted_assets {
+        write_asset(vault_folder, asset_id, data)
+            .map_err(|e| VaultError::Asset(asset_id.to_string(), format!("write failed: {e}")))?;
+    }
+    for (note_id, data) in &reencrypted_notes {
+        atomic_write(&notes_dir(vault_folder).join(note_id), data)
+            .map_err(|e| VaultError::Note(note_id.to_string(), format!("write failed: {e}")))?;
+    }
+    // Its entries are under the old key; `plaintext` already has their changes.
+    clear_journal(vault_folder)?;
+    Ok(())
+}
+
+/// Move the backups that open with `old_key` to `new_key` after a rekey, so
+/// they stop opening with the old passphrase or unlock methods. Backups under
+/// an even older key and locked folders' sub-vaults are left as they are.
+/// Files are replaced rather than rewritten, so the vault's own files that a
+/// backup links to are untouched. Returns how many backups were moved.
+pub fn rekey_backups(
+    vault_folder: &str,
+    old_key: &CachedKey,
+    new_key: &CachedKey,
+) -> Result<usize, VaultError> {
+    use zeroize::Zeroize;
+    let mut moved = 0;
+    for backup in list_backups(vault_folder)? {
+        let folder = backup_path(vault_folder, &backup.id)?;
+        let Some(raw) = read_vault_raw(&folder)? else {
+            continue;
+        };
+        let Ok(mut json) = crypto::decrypt_vault_fast(&raw, old_key) else {
+            continue;
+        };
+        let reencrypted = crypto::encrypt_vault_fast(&json, new_key);
+        json.zeroize();
+        let reencrypted = reencrypted?;
+        let mut blobs = Vec::new();
+        if !new_key.same_data_key(old_key) {
+            let notes = notes_dir(&folder);
+            let ids = list_blob_ids(&notes)?;
+            for (id, data) in reencrypt_blobs(&notes, &ids, old_key, new_key, VaultError::Note)? {
+                blobs.push((notes.join(id), data));
+            }
+            let assets = assets_dir(&folder);
+            let ids = list_blob_ids(&assets)?;
+            for (id, data) in reencrypt_blobs(&assets, &ids, old_key, new_key, VaultError::Asset)? {
+                blobs.push((assets.join(id), data));
+            }
+        }
+        // Same order as `rekey_vault`: the vault first, then its blobs.
+        write_vault_raw(&folder, &reencrypted)?;
+        for (path, data) in &blobs {
+            atomic_write(path, data)?;
+        }
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+fn reencrypt_blobs<'a>(
+    dir: &Path,
+    ids: &'a [String],
+    old_key: &CachedKey,
+    new_key: &CachedKey,
+    error: fn(String, String) -> VaultError,
+) -> Result<Vec<(&'a str, Vec<u8>)>, VaultError> {
+    let mut reencrypted = Vec::with_capacity(ids.len());
+    for id in ids {
+        let fail = |m: String| error(id.clone(), m);
+        if !is_valid_asset_id(id) {
+            return Err(fail("invalid ID".into()));
+        }
+        let data = fs::read(dir.join(id)).map_err(|e| fail(format!("read failed: {e}")))?;
+        let encrypted = crypto::reencrypt_asset(&data, old_key, new_key)
+            .map_err(|e| fail(format!("re-encrypt failed: {e}")))?;
+        reencrypted.push((id.as_str(), encrypted.into_bytes()));
+    }
+    Ok(reencrypted)
+}
+
+/// IDs of the blobs stored in `dir`, skipping leftovers from interrupted writes.
+pub(crate) fn list_blob_ids(dir: &Path) -> io::Result<Vec<String>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if is_valid_asset_id(&name) && !name.ends_with(".tmp") {
+            ids.push(name);
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Encryption status of a vault folder, read from envelope headers only —
+/// no passphrase needed, so it can be checked across a fleet of machines.
+#[derive(Debug, Clone)]
+pub struct EncryptionAudit {
+    /// Header of vault.json; `None` if it is not encrypted.
+    pub vault: Option<EnvelopeInfo>,
+    /// Assets on an older envelope format or KDF, or with unreadable headers.
+    pub outdated_assets: Vec<String>,
+    /// Assets stored without encryption.
+    pub unencrypted_assets: Vec<String>,
+    /// Note bodies under `notes/` on an older envelope format or KDF.
+    pub outdated_notes: Vec<String>,
+    /// Note bodies stored without encryption.
+    pub unencrypted_notes: Vec<String>,
+}
+
+impl EncryptionAudit {
+    /// True when the vault and every blob use the current format and KDF parameters.
+    pub fn is_current(&self) -> bool {
+        self.vault.as_ref().is_some_and(|v| v.is_current())
+            && self.outdated_assets.is_empty()
+            && self.unencrypted_assets.is_empty()
+            && self.outdated_notes.is_empty()
+            && self.unencrypted_notes.is_empty()
+    }
+}
+
+/// Audit how `vault_folder` is encrypted from the vault header and each note
+/// and asset blob. Nothing is decrypted, so it also works on a locked vault.
+pub fn audit_encryption(vault_folder: &str) -> Result<EncryptionAudit, VaultError> {
+    let raw = fs::read(vault_file_path(vault_folder))?;
+    let (outdated_assets, unencrypted_assets) = audit_blobs(&assets_dir(vault_folder))?;
+    let (outdated_notes, unencrypted_notes) = audit_blobs(&notes_dir(vault_folder))?;
+    Ok(EncryptionAudit {
+        vault: crypto::envelope_info(&raw)?,
+        outdated_assets,
+        unencrypted_assets,
+        outdated_notes,
+        unencrypted_notes,
+    })
+}
+
+/// Returns `(outdated, unencrypted)` blob IDs in `dir`.
+fn audit_blobs(dir: &Path) -> io::Result<(Vec<String>, Vec<String>)> {
+    let mut outdated = Vec::new();
+    let mut unencrypted = Vec::new();
+    for id in list_blob_ids(dir)? {
+        match crypto::envelope_info(&fs::read(dir.join(&id))?) {
+            Ok(Some(info)) if info.is_current() => {}
+            Ok(None) => unencrypted.push(id),
+            Ok(Some(_)) | Err(_) => outdated.push(id),
+        }
+    }
+    Ok((outdated, unencrypted))
+}
+
+/// True when upgrading would change something: the key is on an older scheme
+/// or has slots it can re-wrap (see [`CachedKey::needs_upgrade`]), or note
+/// bodies or assets are sealed in an older format. Never in a read-only
+/// window, where another window may be writing the vault.
+pub fn should_offer_upgrade(state: &DocState, key: &CachedKey, audit: &EncryptionAudit) -> bool {
+    !state.read_only
+        && (key.needs_upgrade()
+            || !audit.outdated_notes.is_empty()
+            || !audit.outdated_assets.is_empty())
+}
+
+/// Re-encrypt the note bodies and assets of `vault_folder` that use an older
+/// format under `key`, which must open them. A rekey to a new data key
+/// already rewrites every blob; this is for upgrades that keep the data key.
+/// Every blob is re-encrypted in memory before any is written. Returns how
+/// many were rewritten.
+pub fn upgrade_outdated_blobs(vault_folder: &str, key: &CachedKey) -> Result<usize, VaultError> {
+    let (assets, _) = audit_blobs(&assets_dir(vault_folder))?;
+    let (notes, _) = audit_blobs(&notes_dir(vault_folder))?;
+    let reencrypted_assets = reencrypt_blobs(
+        &assets_dir(vault_folder),
+        &assets,
+        key,
+        key,
+        VaultError::Asset,
+    )?;
+    let reencrypted_notes =
+        reencrypt_blobs(&notes_dir(vault_folder), &notes, key, key, VaultError::Note)?;
+    for (asset_id, data) in &reencrypted_assets {
+        write_asset(vault_folder, asset_id, data)
+            .map_err(|e| VaultError::Asset(asset_id.to_string(), format!("write failed: {e}")))?;
+    }
+    for (note_id, data) in &reencrypted_notes {
+        atomic_write(&notes_dir(vault_folder).join(note_id), data)
+            .map_err(|e| VaultError::Note(note_id.to_string(), format!("write failed: {e}")))?;
+    }
+    Ok(reencrypted_assets.len() + reencrypted_notes.len())
+}
+
+/// Encrypt a vault folder found stored as plain text: vault.json plus any
+/// note bodies and listed assets without encryption, under a new key for
+/// `passphrase`. Loading normally refuses such a vault with
+/// `CryptoError::Unencrypted`; this is for when the user chooses to import
+/// it. Blobs are written first, so an interrupted import can be repeated.
+pub fn import_unencrypted_vault(
+    vault_folder: &str,
+    passphrase: &str,
+    keyfile: Option<crypto::KeyFile>,
+) -> Result<(String, CachedKey), VaultError> {
+    use zeroize::Zeroize;
+    let raw = read_vault_raw(vault_folder)?
+        .ok_or_else(|| VaultError::Invalid("vault.json is missing".into()))?;
+    let (mut json, key) = crypto::import_unencrypted_vault(&raw, passphrase, keyfile)?;
+    let data = parse_vault(&json);
+    json.zeroize();
+    let mut data = data?;
+    if data.vault_id.is_empty() {
+        data.vault_id = new_vault_id();
+    }
+
+    let plaintext_blob = |path: &Path| -> io::Result<Option<Vec<u8>>> {
+        let bytes = fs::read(path)?;
+        Ok(match crypto::envelope_info(&bytes) {
+            Ok(None) => Some(bytes),
+            _ => None,
+        })
+    };
+    for id in list_blob_ids(&notes_dir(vault_folder))? {
+        let fail = |m: String| VaultError::Note(id.clone(), m);
+        let path = notes_dir(vault_folder).join(&id);
+        if let Some(mut body) = plaintext_blob(&path)? {
+            let binding = blob_binding(&data.vault_id, BlobKind::Note, &id);
+            let encrypted = crypto::encrypt_asset_bound(&body, &key, &binding);
+            body.zeroize();
+            atomic_write(&path, encrypted?.as_bytes())
+                .map_err(|e| fail(format!("write failed: {e}")))?;
+        }
+    }
+    for (id, meta) in data.assets.iter_mut() {
+        let path = assets_dir(vault_folder).join(id);
+        if !is_valid_asset_id(id) || !path.exists() {
+            continue;
+        }
+        if let Some(bytes) = plaintext_blob(&path)? {
+            let binding = binding_for(&data.vault_id, None, id, 1);
+            let encrypted = crypto::encrypt_asset_bound(&bytes, &key, &binding)?;
+            write_asset(vault_folder, id, encrypted.as_bytes())
+                .map_err(|e| VaultError::Asset(id.clone(), format!("write failed: {e}")))?;
+            meta.version = Some(1);
+        }
+    }
+
+    let mut json =
+        serde_json::to_string_pretty(&data).map_err(|e| VaultError::Invalid(e.to_string()))?;
+    let written = crypto::encrypt_vault_fast(&json, &key)
+        .map_err(VaultError::from)
+        .and_then(|encrypted| Ok(write_vault_raw(vault_folder, &encrypted)?));
+    match written {
+        Ok(()) => Ok((json, key)),
+        Err(e) => {
+            json.zeroize();
+            Err(e)
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Asset verification
+// ---------------------------------------------------------------------------
+//
+// Every asset written since bindings were introduced carries its vault ID,
+// asset ID and version as AEAD associated data. Verifying decrypts each one
+// against what vault.json says it should be, catching files swapped between
+// assets, copied in from another vault or rolled back to an earlier version.
+
+#[derive(Debug, Clone, Default)]
+pub struct AssetReport {
+    /// Assets that decrypted and are bound to what the vault expects.
+    pub verified: usize,
+    /// Listed assets with no file.
+    pub missing: Vec<String>,
+    /// Assets written before bindings, which can't be checked until bound.
+    pub unbound: Vec<String>,
+    /// Assets that failed to decrypt or are bound elsewhere, with the reason.
+    pub mismatched: Vec<(String, String)>,
+}
+
+impl AssetReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.unbound.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+/// Check every listed asset whose vault is unlocked. Slow: reads and decrypts
+/// each file, so run it off the UI thread.
+pub fn verify_assets(
+    vault_folder: &str,
+    vault_id: &str,
+    assets: &HashMap<String, AssetMeta>,
+    main_key: &CachedKey,
+    folder_keys: &HashMap<String, CachedKey>,
+) -> AssetReport {
+    let mut report = AssetReport::default();
+    let mut ids: Vec<&String> = assets.keys().collect();
+    ids.sort();
+    for id in ids {
+        let meta = &assets[id];
+        let Ok((dir, key)) = blob_location(
+            vault_folder,
+            folder_keys,
+            main_key,
+            meta.stored_in.as_deref(),
+        ) else {
+            continue;
+        };
+        if !assets_dir(&dir).join(id).exists() {
+            report.missing.push(id.clone());
+            continue;
+        }
+        let binding = asset_binding(vault_id, meta);
+        match read_asset(&dir, id, key, binding.as_ref()) {
+            Ok(_) if binding.is_none() => report.unbound.push(id.clone()),
+            Ok(_) => report.verified += 1,
+            Err(VaultError::Asset(_, reason)) => report.mismatched.push((id.clone(), reason)),
+            Err(e) => report.mismatched.push((id.clone(), e.to_string())),
+        }
+    }
+    report
+}
+
+/// Re-encrypt the listed legacy assets bound at version 1. Returns the IDs
+/// rewritten; the caller sets their `version` to `Some(1)` and saves.
+pub fn bind_assets(
+    vault_folder: &str,
+    vault_id: &str,
+    assets: &HashMap<String, AssetMeta>,
+    ids: &[String],
+    main_key: &CachedKey,
+    folder_keys: &HashMap<String, CachedKey>,
+) -> Result<Vec<String>, VaultError> {
+    let mut bound = Vec::new();
+    for id in ids {
+        let Some(meta) = assets.get(id).filter(|m| m.version.is_none()) else {
+            continue;
+        };
+        let stored_in = meta.stored_in.as_deref();
+        let (dir, key) = blob_location(vault_folder, folder_keys, main_key, stored_in)?;
+        let data = read_asset(&dir, id, key, None)?;
+        let binding = binding_for(vault_id, stored_in, id, 1);
+        let encrypted = crypto::encrypt_asset_bound(&data, key, &binding)?;
+        write_asset(&dir, id, encrypted.as_bytes())
+            .map_err(|e| VaultError::Asset(id.clone(), format!("write failed: {e}")))?;
+        bound.push(id.clone());
+    }
+    Ok(bound)
+}
+
+// ---------------------------------------------------------------------------
+// Vault integrity check and repair
+// ---------------------------------------------------------------------------
+//
+// Checks the in-memory state against itself and against the files on disk:
+// problems here mean notes that would be dropped from the tree at the next
+// save, tabs pointing nowhere, or blobs that went missing after a crash.
+
+#[derive(Debug, Clone, Default)]
+pub struct VaultReport {
+    /// IDs used by more than one note, folder or trashed note.
+    pub duplicate_ids: Vec<String>,
+    /// Notes and folders whose parent folder is missing or part of a cycle.
+    pub dangling_parents: Vec<String>,
+    /// Open tabs (and the active note) that name no note.
+    pub broken_tabs: Vec<String>,
+    /// Unloaded notes and trashed notes whose body blob is missing.
+    pub missing_bodies: Vec<String>,
+    /// Body blobs no note refers to; the next save deletes them.
+    pub orphaned_bodies: Vec<String>,
+    /// Asset files not listed in the vault.
+    pub orphaned_assets: Vec<String>,
+    /// Decryption and binding check of every listed asset.
+    pub assets: AssetReport,
+}
+
+impl VaultReport {
+    pub fn is_healthy(&self) -> bool {
+        !self.is_repairable()
+            && self.missing_bodies.is_empty()
+            && self.orphaned_bodies.is_empty()
+            && self.orphaned_assets.is_empty()
+            && self.assets.missing.is_empty()
+            && self.assets.mismatched.is_empty()
+    }
+
+    /// True if [`repair_vault`] can fix some of the problems found.
+    pub fn is_repairable(&self) -> bool {
+        self.repairable_count() > 0
+    }
+
+    /// How many of the problems found [`repair_vault`] can fix.
+    pub fn repairable_count(&self) -> usize {
+        self.duplicate_ids.len() + self.dangling_parents.len() + self.broken_tabs.len()
+    }
+}
+
+/// What [`repair_vault`] did.
+#[derive(Debug, Clone, Default)]
+pub struct RepairOutcome {
+    /// A line per fix.
+    pub fixed: Vec<String>,
+    /// Duplicate notes left as they are: they share the first note's body
+    /// file, so their own text is lost unless a backup still has it.
+    pub unrecoverable: Vec<String>,
+}
+
+/// Check everything but the assets' contents — cheap enough for the UI thread.
+/// Fill in `assets` with [`verify_assets`], or use [`verify`] for both.
+pub fn check_vault_structure(
+    vault_folder: &str,
+    state: &DocState,
+) -> Result<VaultReport, VaultError> {
+    let mut report = VaultReport::default();
+
+    let mut seen = HashSet::new();
+    let ids = state
+        .notes
+        .iter()
+        .map(|n| &n.id)
+        .chain(state.folders.iter().map(|f| &f.id))
+        .chain(state.trash.iter().map(|t| &t.id));
+    for id in ids {
+        if !seen.insert(id.as_str()) && !report.duplicate_ids.contains(id) {
+            report.duplicate_ids.push(id.clone());
+        }
+    }
+
+    let parents = state
+        .notes
+        .iter()
+        .map(|n| (&n.id, &n.parent_id))
+        .chain(state.folders.iter().map(|f| (&f.id, &f.parent_id)));
+    for (id, parent) in parents {
+        if !reaches_root(&state.folders, parent.as_deref()) {
+            report.dangling_parents.push(id.clone());
+        }
+    }
+
+    let note_ids: HashSet<&str> = state.notes.iter().map(|n| n.id.as_str()).collect();
+    let mut tabs = HashSet::new();
+    for id in &state.open_tabs {
+        if !note_ids.contains(id.as_str()) || !tabs.insert(id.as_str()) {
+            report.broken_tabs.push(id.clone());
+        }
+    }
+    if !note_ids.contains(state.active_note_id.as_str())
+        && !report.broken_tabs.contains(&state.active_note_id)
+    {
+        report.broken_tabs.push(state.active_note_id.clone());
+    }
+
+    // Blobs live in the main vault and in each unlocked locked folder.
+    let mut locations = vec![None];
+    locations.extend(state.folder_keys.keys().map(|id| Some(id.as_str())));
+    for location in locations {
+        let root = match location {
+            Some(folder_id) => sub_vault_path(vault_folder, folder_id),
+            None => vault_folder.to_string(),
+        };
+        let bodies = list_blob_ids(&notes_dir(&root))?;
+        let stored_here = state
+            .notes
+            .iter()
+            .map(|n| (&n.id, n.loaded, &n.stored_in))
+            .chain(state.trash.iter().map(|t| (&t.id, t.loaded, &t.stored_in)))
+            .filter(|(_, _, stored_in)| stored_in.as_deref() == location);
+        let mut referenced = HashSet::new();
+        for (id, loaded, _) in stored_here {
+            referenced.insert(id.as_str());
+            if !loaded && !bodies.contains(id) {
+                report.missing_bodies.push(id.clone());
+            }
+        }
+        report.orphaned_bodies.extend(
+            bodies
+                .iter()
+                .filter(|id| !referenced.contains(id.as_str()))
+                .cloned(),
+        );
+        report
+            .orphaned_assets
+            .extend(list_blob_ids(&assets_dir(&root))?.into_iter().filter(|id| {
+                state
+                    .assets
+                    .get(id)
+                    .is_none_or(|meta| meta.stored_in.as_deref() != location)
+            }));
+    }
+    Ok(report)
+}
+
+/// False if following `parent` up the folder tree hits a missing folder or loops.
+fn reaches_root<'a>(folders: &'a [FolderItem], mut parent: Option<&'a str>) -> bool {
+    for _ in 0..=folders.len() {
+        let Some(id) = parent else {
+            return true;
+        };
+        let Some(folder) = folders.iter().find(|f| f.id == id) else {
+            return false;
+        };
+        parent = folder.parent_id.as_deref();
+    }
+    false
+}
+
+/// Full integrity check of the open vault. Slow: decrypts every asset.
+pub fn verify(
+    vault_folder: &str,
+    state: &DocState,
+    main_key: &CachedKey,
+) -> Result<VaultReport, VaultError> {
+    let mut report = check_vault_structure(vault_folder, state)?;
+    report.assets = verify_assets(
+        vault_folder,
+        &state.vault_id,
+        &state.assets,
+        main_key,
+        &state.folder_keys,
+    );
+    Ok(report)
+}
+
+/// Fix what can be fixed without losing anything: duplicate IDs get fresh
+/// ones, notes and folders with a dangling parent move to the top level, and
+/// broken tabs are closed. Missing and orphaned files are left alone — a
+/// backup may still have them. A duplicate note whose body is not in memory
+/// shares its body file with the first, so its own text is gone; it is left
+/// as it is and reported. Save afterwards.
+pub fn repair_vault(
+    vault_folder: &str,
+    state: &mut DocState,
+    main_key: &CachedKey,
+    report: &VaultReport,
+) -> RepairOutcome {
+    let mut fixed = Vec::new();
+    let mut unrecoverable = Vec::new();
+
+    let taken: HashSet<String> = state
+        .notes
+        .iter()
+        .map(|n| n.id.clone())
+        .chain(state.folders.iter().map(|f| f.id.clone()))
+        .chain(state.trash.iter().map(|t| t.id.clone()))
+        .collect();
+    let fresh_id = |state: &mut DocState, prefix: &str| loop {
+        let id = format!("{prefix}-{}", state.next_note_seq);
+        state.next_note_seq += 1;
+        if !taken.contains(&id) {
+            break id;
+        }
+    };
+    let mut first_seen = HashSet::new();
+    // Where the first note with an ID keeps its body; a duplicate stored in
+    // the same place would read that body.
+    let mut first_blob: HashMap<String, Option<String>> = HashMap::new();
+    let shares_blob =
+        |first_blob: &mut HashMap<String, Option<String>>, id: &str, stored_in: &Option<String>| {
+            match first_blob.get(id) {
+                Some(first) => first == stored_in,
+                None => {
+                    first_blob.insert(id.to_string(), stored_in.clone());
+                    false
+                }
+            }
+        };
+    for i in 0..state.folders.len() {
+        let id = state.folders[i].id.clone();
+        if report.duplicate_ids.contains(&id) && !first_seen.insert(id.clone()) {
+            let new_id = fresh_id(state, "folder");
+            fixed.push(format!("Gave a duplicate of folder {id} the ID {new_id}"));
+            state.folders[i].id = new_id;
+        }
+    }
+    for i in 0..state.notes.len() {
+        let id = state.notes[i].id.clone();
+        let shared = shares_blob(&mut first_blob, &id, &state.notes[i].stored_in);
+        if !report.duplicate_ids.contains(&id) || first_seen.insert(id.clone()) {
+            continue;
+        }
+        if shared && !state.notes[i].loaded {
+            unrecoverable.push(format!("\u{201c}{}\u{201d} ({id})", state.notes[i].name));
+            continue;
+        }
+        // The body must come along under the new ID.
+        let DocState {
+            notes,
+            folder_keys,
+            vault_id,
+            ..
+        } = &mut *state;
+        let note = &mut notes[i];
+        let owner = blob_owner(vault_id, note.stored_in.as_deref());
+        let loaded = blob_location(
+            vault_folder,
+            folder_keys,
+            main_key,
+            note.stored_in.as_deref(),
+        )
+        .and_then(|(dir, key)| load_note_body(&dir, &owner, key, note));
+        if loaded.is_err() {
+            continue;
+        }
+        let new_id = fresh_id(state, "note");
+        fixed.push(format!("Gave a duplicate of note {id} the ID {new_id}"));
+        state.notes[i].id = new_id;
+    }
+    for i in 0..state.trash.len() {
+        let id = state.trash[i].id.clone();
+        let shared = shares_blob(&mut first_blob, &id, &state.trash[i].stored_in);
+        if !report.duplicate_ids.contains(&id) || first_seen.insert(id.clone()) {
+            continue;
+        }
+        if shared && !state.trash[i].loaded {
+            unrecoverable.push(format!(
+                "\u{201c}{}\u{201d} ({id}, in the trash)",
+                state.trash[i].name
+            ));
+            continue;
+        }
+        let DocState {
+            trash,
+            folder_keys,
+            vault_id,
+            ..
+        } = &mut *state;
+        let item = &mut trash[i];
+        if !item.loaded {
+            let Ok((dir, key)) = blob_location(
+                vault_folder,
+                folder_keys,
+                main_key,
+                item.stored_in.as_deref(),
+            ) else {
+                continue;
+            };
+            let owner = blob_owner(vault_id, item.stored_in.as_deref());
+            let Ok(mut body) = read_note_body(&dir, &owner, &item.id, key) else {
+                continue;
+            };
+            (item.content, item.versions) = body.take();
+            item.loaded = true;
+        }
+        let new_id = fresh_id(state, "note");
+        fixed.push(format!(
+            "Gave a duplicate of trashed note {id} the ID {new_id}"
+        ));
+        state.trash[i].id = new_id;
+    }
+
+    for id in &report.dangling_parents {
+        if let Some(note) = state.notes.iter_mut().find(|n| &n.id == id) {
+            note.parent_id = None;
+            fixed.push(format!(
+                "Moved note \u{201c}{}\u{201d} to the top level",
+                note.name
+            ));
+        } else if let Some(folder) = state.folders.iter_mut().find(|f| &f.id == id) {
+            folder.parent_id = None;
+            fixed.push(format!(
+                "Moved folder \u{201c}{}\u{201d} to the top level",
+                folder.name
+            ));
+        }
+    }
+
+    if !report.broken_tabs.is_empty() {
+        let note_ids: HashSet<String> = state.notes.iter().map(|n| n.id.clone()).collect();
+        let mut seen = HashSet::new();
+        state
+            .open_tabs
+            .retain(|id| note_ids.contains(id) && seen.insert(id.clone()));
+        if !note_ids.contains(&state.active_note_id) {
+            if let Some(id) = state
+                .open_tabs
+                .first()
+                .or_else(|| state.notes.first().map(|n| &n.id))
+                .cloned()
+            {
+                if state.open_tabs.is_empty() {
+                    state.open_tabs.push(id.clone());
+                }
+                state.active_note_id = id;
+            }
+        }
+        fixed.push(format!("Closed {} broken tabs", report.broken_tabs.len()));
+    }
+    RepairOutcome {
+        fixed,
+        unrecoverable,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Conversion helpers: internal flat model <-> tree vault format
+// ---------------------------------------------------------------------------
+
+pub fn sort_order_to_strings(order: SortOrder) -> (String, String) {
+    match order {
+        SortOrder::Manual => ("manual".into(), "manual".into()),
+        SortOrder::ModifiedDesc => ("modified".into(), "desc".into()),
+        SortOrder::ModifiedAsc => ("modified".into(), "asc".into()),
+        SortOrder::NameAsc => ("name".into(), "asc".into()),
+        SortOrder::NameDesc => ("name".into(), "desc".into()),
+        SortOrder::CreatedDesc => ("created".into(), "desc".into()),
+        SortOrder::CreatedAsc => ("created".into(), "asc".into()),
+    }
+}
+
+pub fn parse_sort_order(sort_by: &str, direction: &str) -> SortOrder {
+    match (sort_by, direction) {
+        ("manual", _) => SortOrder::Manual,
+        ("modified", "desc") => SortOrder::ModifiedDesc,
+        ("modified", "asc") => SortOrder::ModifiedAsc,
+        ("name", "asc") => SortOrder::NameAsc,
+        ("name", "desc") => SortOrder::NameDesc,
+        ("created", "desc") => SortOrder::CreatedDesc,
+        ("created", "asc") => SortOrder::CreatedAsc,
+        _ => SortOrder::ModifiedDesc,
+    }
+}
+
+/// Convert the flat in-memory DocState into the tree-based vault format.
+pub fn doc_state_to_vault(state: &DocState) -> VaultData {
+    let (notes_by_parent, folders_by_parent) = group_by_parent(state);
+    let inline = !state.separate_bodies;
+    let root_items = build_vault_tree_level(None, &notes_by_parent, &folders_by_parent, inline);
+
+    let trash = trash_entries(state, None, inline);
 
+    // With separate bodies, versions travel in each note's blob instead.
     let mut note_versions: HashMap<String, Vec<VersionEntry>> = HashMap::new();
-    for note in &state.notes {
-        if !note.versions.is_empty() {
-            let entries = note
-                .versions
-                .iter()
-                .map(|v| VersionEntry {
-                    ts: v.ts,
-                    content: v.content.clone(),
-                })
-                .collect();
-            note_versions.insert(note.id.clone(), entries);
+    if inline {
+        for note in &state.notes {
+            if !note.versions.is_empty() {
+                note_versions.insert(note.id.clone(), versions_to_entries(&note.versions));
+            }
         }
-    }
-    for trash_item in &state.trash {
-        if !trash_item.versions.is_empty() {
-            let entries = trash_item
-                .versions
-                .iter()
-                .map(|v| VersionEntry {
-                    ts: v.ts,
-                    content: v.content.clone(),
-                })
-                .collect();
-            note_versions.insert(trash_item.id.clone(), entries);
+        for trash_item in state.trash.iter().filter(|t| t.stored_in.is_none()) {
+            if !trash_item.versions.is_empty() {
+                note_versions.insert(
+                    trash_item.id.clone(),
+                    versions_to_entries(&trash_item.versions),
+                );
+            }
         }
     }
 
@@ -310,6 +3330,7 @@ pub fn doc_state_to_vault(state: &DocState) -> VaultData {
         .collect();
 
     VaultData {
+        schema_version: SCHEMA_VERSION,
         tree: root_items,
         trash,
         active_id: state.active_note_id.clone(),
@@ -322,16 +3343,107 @@ pub fn doc_state_to_vault(state: &DocState) -> VaultData {
         custom_templates,
         disabled_templates: state.disabled_templates.clone(),
         sidebar_width: state.sidebar_width,
-        assets: state.assets.clone(),
+        assets: assets_stored_in(state, None),
+        separate_bodies: state.separate_bodies,
+        auto_lock_minutes: Some(state.auto_lock_minutes),
+        keep_unlocked_on_screen_lock: !state.lock_on_screen_lock,
+        vault_id: state.vault_id.clone(),
+        save_seq: state.journal.save_seq + 1,
+        saved_searches: state
+            .saved_searches
+            .iter()
+            .map(|search| {
+                let (sort_by, sort_direction) = sort_order_to_strings(search.sort_order);
+                SavedSearchEntry {
+                    id: search.id.clone(),
+                    name: search.name.clone(),
+                    query: search.query.clone(),
+                    tags: search.filter_tags.clone(),
+                    match_all_tags: search.tag_filter_and,
+                    sort_by,
+                    sort_direction,
+                    expanded: search.expanded,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Trash entries of the notes trashed in the main vault (`root` is `None`)
+/// or in the locked folder `root`.
+fn trash_entries(state: &DocState, root: Option<&str>, inline: bool) -> Vec<TreeItem> {
+    state
+        .trash
+        .iter()
+        .filter(|t| t.stored_in.as_deref() == root)
+        .map(|t| TreeItem {
+            id: t.id.clone(),
+            name: t.name.clone(),
+            item_type: "note".to_string(),
+            content: inline.then(|| t.content.clone()),
+            children: None,
+            expanded: None,
+            created_at: t.created_at,
+            updated_at: t.updated_at,
+            deleted: Some(true),
+            deleted_at: Some(t.deleted_at),
+            tags: if t.tags.is_empty() {
+                None
+            } else {
+                Some(t.tags.clone())
+            },
+            pinned: if t.pinned { Some(true) } else { None },
+            locked: None,
+        })
+        .collect()
+}
+
+type NotesByParent<'a> = HashMap<Option<String>, Vec<&'a NoteItem>>;
+type FoldersByParent<'a> = HashMap<Option<String>, Vec<&'a FolderItem>>;
+
+fn group_by_parent(state: &DocState) -> (NotesByParent<'_>, FoldersByParent<'_>) {
+    let mut notes_by_parent: NotesByParent = HashMap::new();
+    for note in &state.notes {
+        notes_by_parent
+            .entry(note.parent_id.clone())
+            .or_default()
+            .push(note);
+    }
+    let mut folders_by_parent: FoldersByParent = HashMap::new();
+    for folder in &state.folders {
+        folders_by_parent
+            .entry(folder.parent_id.clone())
+            .or_default()
+            .push(folder);
     }
+    (notes_by_parent, folders_by_parent)
 }
 
-fn note_to_tree_item(note: &NoteItem) -> TreeItem {
+fn assets_stored_in(state: &DocState, root: Option<&str>) -> HashMap<String, AssetMeta> {
+    state
+        .assets
+        .iter()
+        .filter(|(_, meta)| meta.stored_in.as_deref() == root)
+        .map(|(id, meta)| (id.clone(), meta.clone()))
+        .collect()
+}
+
+pub(crate) fn versions_to_entries(versions: &[NoteVersion]) -> Vec<VersionEntry> {
+    versions
+        .iter()
+        .map(|v| VersionEntry {
+            ts: v.ts,
+            content: v.content.clone(),
+        })
+        .collect()
+}
+
+fn note_to_tree_item(note: &NoteItem, inline: bool) -> TreeItem {
     TreeItem {
         id: note.id.clone(),
         name: note.name.clone(),
         item_type: "note".to_string(),
-        content: Some(note.content.clone()),
+        content: inline.then(|| note.content.clone()),
         children: None,
         expanded: None,
         created_at: note.created_at,
@@ -344,6 +3456,7 @@ fn note_to_tree_item(note: &NoteItem) -> TreeItem {
             Some(note.tags.clone())
         },
         pinned: if note.pinned { Some(true) } else { None },
+        locked: None,
     }
 }
 
@@ -351,20 +3464,23 @@ fn build_vault_tree_level(
     parent_id: Option<&str>,
     notes_by_parent: &HashMap<Option<String>, Vec<&NoteItem>>,
     folders_by_parent: &HashMap<Option<String>, Vec<&FolderItem>>,
+    inline: bool,
 ) -> Vec<TreeItem> {
     let mut items = Vec::new();
     let key = parent_id.map(|s| s.to_string());
 
     if let Some(child_folders) = folders_by_parent.get(&key) {
         for folder in child_folders {
-            let children =
-                build_vault_tree_level(Some(&folder.id), notes_by_parent, folders_by_parent);
+            // A locked folder's contents are written to its own sub-vault.
+            let children = (!folder.locked).then(|| {
+                build_vault_tree_level(Some(&folder.id), notes_by_parent, folders_by_parent, inline)
+            });
             items.push(TreeItem {
                 id: folder.id.clone(),
                 name: folder.name.clone(),
                 item_type: "folder".to_string(),
                 content: None,
-                children: Some(children),
+                children,
                 expanded: Some(folder.expanded),
                 created_at: folder.created_at,
                 updated_at: folder.updated_at,
@@ -372,13 +3488,14 @@ fn build_vault_tree_level(
                 deleted_at: None,
                 tags: None,
                 pinned: None,
+                locked: folder.locked.then_some(true),
             });
         }
     }
 
     if let Some(child_notes) = notes_by_parent.get(&key) {
         for note in child_notes {
-            items.push(note_to_tree_item(note));
+            items.push(note_to_tree_item(note, inline));
         }
     }
 
@@ -386,9 +3503,13 @@ fn build_vault_tree_level(
 }
 
 /// Convert tree-based vault format back into flat in-memory DocState.
+///
+/// With separate bodies every note starts unloaded. Vaults with inline bodies
+/// come back fully loaded and are moved to separate bodies on the next save.
 pub fn vault_to_doc_state(vault: VaultData) -> DocState {
     let mut notes: Vec<NoteItem> = Vec::new();
     let mut folders: Vec<FolderItem> = Vec::new();
+    let loaded = !vault.separate_bodies;
 
     flatten_tree(
         &vault.tree,
@@ -396,39 +3517,11 @@ pub fn vault_to_doc_state(vault: VaultData) -> DocState {
         &mut notes,
         &mut folders,
         &vault.note_versions,
+        loaded,
+        None,
     );
 
-    let trash: Vec<TrashItem> = vault
-        .trash
-        .iter()
-        .map(|item| {
-            let versions = vault
-                .note_versions
-                .get(&item.id)
-                .map(|entries| {
-                    entries
-                        .iter()
-                        .map(|e| NoteVersion {
-                            ts: e.ts,
-                            content: e.content.clone(),
-                        })
-                        .collect()
-                })
-                .unwrap_or_default();
-            TrashItem {
-                id: item.id.clone(),
-                name: item.name.clone(),
-                content: item.content.clone().unwrap_or_default(),
-                tags: item.tags.clone().unwrap_or_default(),
-                created_at: item.created_at,
-                updated_at: item.updated_at,
-                deleted_at: item.deleted_at.unwrap_or(item.updated_at),
-                parent_id: None,
-                versions,
-                pinned: item.pinned.unwrap_or(false),
-            }
-        })
-        .collect();
+    let trash = trash_items(&vault, loaded, None);
 
     let sort_order = parse_sort_order(&vault.sort_by, &vault.sort_direction);
 
@@ -462,7 +3555,7 @@ pub fn vault_to_doc_state(vault: VaultData) -> DocState {
         ("note-3", PAGE_FORMATTING),
     ];
     for (id, default_content) in guide_defaults {
-        if let Some(note) = notes.iter_mut().find(|n| n.id == *id) {
+        if let Some(note) = notes.iter_mut().find(|n| n.id == *id && n.loaded) {
             if note.content.trim().is_empty() {
                 note.content = default_content.to_string();
             }
@@ -504,6 +3597,7 @@ pub fn vault_to_doc_state(vault: VaultData) -> DocState {
         active_note_id: active_id,
         open_tabs,
         search_query: String::new(),
+        search_scope: SearchScope::Notes,
         visible_row_items: Vec::new(),
         next_note_seq,
         sort_order,
@@ -520,6 +3614,19 @@ pub fn vault_to_doc_state(vault: VaultData) -> DocState {
         disabled_templates: vault.disabled_templates,
         filter_tags: Vec::new(),
         tag_filter_and: false,
+        saved_searches: vault
+            .saved_searches
+            .into_iter()
+            .map(|entry| SavedSearch {
+                sort_order: parse_sort_order(&entry.sort_by, &entry.sort_direction),
+                id: entry.id,
+                name: entry.name,
+                query: entry.query,
+                filter_tags: entry.tags,
+                tag_filter_and: entry.match_all_tags,
+                expanded: entry.expanded,
+            })
+            .collect(),
         sidebar_width: if vault.sidebar_width > 0 {
             vault.sidebar_width
         } else {
@@ -530,6 +3637,21 @@ pub fn vault_to_doc_state(vault: VaultData) -> DocState {
         last_undo_push: std::time::Instant::now(),
         assets: vault.assets,
         cached_key: None,
+        separate_bodies: true,
+        folder_keys: HashMap::new(),
+        auto_lock_minutes: vault.auto_lock_minutes.unwrap_or(DEFAULT_AUTO_LOCK_MINUTES),
+        lock_on_screen_lock: !vault.keep_unlocked_on_screen_lock,
+        vault_id: if vault.vault_id.is_empty() {
+            new_vault_id()
+        } else {
+            vault.vault_id
+        },
+        journal: JournalMarks {
+            save_seq: vault.save_seq,
+            ..JournalMarks::default()
+        },
+        read_only: false,
+        search_index: crate::search::SearchIndex::default(),
     }
 }
 
@@ -539,11 +3661,10 @@ fn flatten_tree(
     notes: &mut Vec<NoteItem>,
     folders: &mut Vec<FolderItem>,
     versions_map: &HashMap<String, Vec<VersionEntry>>,
+    loaded: bool,
+    stored_in: Option<&str>,
 ) {
     for item in items {
-        if item.item_type != "folder" && item.item_type != "note" {
-            eprintln!("Warning: unknown item type '{}' for '{}', treating as note", item.item_type, item.id);
-        }
         if item.item_type == "folder" {
             folders.push(FolderItem {
                 id: item.id.clone(),
@@ -552,6 +3673,7 @@ fn flatten_tree(
                 created_at: item.created_at,
                 updated_at: item.updated_at,
                 parent_id: parent_id.clone(),
+                locked: item.locked.unwrap_or(false),
             });
             if let Some(children) = &item.children {
                 flatten_tree(
@@ -560,6 +3682,8 @@ fn flatten_tree(
                     notes,
                     folders,
                     versions_map,
+                    loaded,
+                    stored_in,
                 );
             }
         } else {
@@ -587,13 +3711,15 @@ fn flatten_tree(
                 file_path: None,
                 parent_id: parent_id.clone(),
                 pinned: item.pinned.unwrap_or(false),
+                loaded,
+                stored_in: stored_in.map(str::to_string),
             });
         }
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use std::collections::HashMap;
 
@@ -617,6 +3743,7 @@ mod tests {
                 deleted_at: None,
                 tags: Some(vec!["t1".to_string()]),
                 pinned: Some(true),
+                locked: None,
             }]),
             expanded: Some(true),
             created_at: 100,
@@ -625,9 +3752,11 @@ mod tests {
             deleted_at: None,
             tags: None,
             pinned: None,
+            locked: None,
         }];
 
         let vault = VaultData {
+            schema_version: SCHEMA_VERSION,
             tree,
             trash: vec![],
             active_id: "n1".to_string(),
@@ -641,6 +3770,12 @@ mod tests {
             disabled_templates: vec![],
             sidebar_width: 250,
             assets: HashMap::new(),
+            separate_bodies: false,
+            auto_lock_minutes: None,
+            keep_unlocked_on_screen_lock: false,
+            vault_id: String::new(),
+            save_seq: 0,
+            saved_searches: vec![],
         };
 
         let state = vault_to_doc_state(vault);
@@ -649,5 +3784,1107 @@ mod tests {
         assert_eq!(state.notes[0].id, "n1");
         assert_eq!(state.notes[0].parent_id, Some("f1".to_string()));
         assert_eq!(state.sort_order, SortOrder::NameAsc);
+        assert_eq!(state.auto_lock_minutes, DEFAULT_AUTO_LOCK_MINUTES);
+        assert!(state.lock_on_screen_lock);
+        assert_eq!(state.vault_id.len(), 32);
+    }
+
+    #[test]
+    fn test_wipe_doc_state() {
+        let mut state = DocState {
+            auto_lock_minutes: 0,
+            lock_on_screen_lock: false,
+            saved_searches: vec![SavedSearch {
+                id: "search-1".to_string(),
+                name: "Open threat models".to_string(),
+                query: "is:todo".to_string(),
+                filter_tags: vec!["threat-model".to_string()],
+                tag_filter_and: true,
+                sort_order: SortOrder::NameAsc,
+                expanded: true,
+            }],
+            ..DocState::default()
+        };
+        let restored = vault_to_doc_state(doc_state_to_vault(&state));
+        assert_eq!(restored.auto_lock_minutes, 0);
+        assert!(!restored.lock_on_screen_lock);
+        let search = &restored.saved_searches[0];
+        assert_eq!(
+            (search.name.as_str(), search.query.as_str()),
+            ("Open threat models", "is:todo")
+        );
+        assert_eq!(search.filter_tags, ["threat-model"]);
+        assert!(search.tag_filter_and && search.expanded);
+        assert_eq!(search.sort_order, SortOrder::NameAsc);
+        assert!(search.to_query().is_ok());
+
+        state.undo_stack.push("draft".to_string());
+        state.folder_keys.insert(
+            "folder-1".to_string(),
+            CachedKey::derive("wipe-pass").unwrap(),
+        );
+        let open_tabs = state.open_tabs.clone();
+        wipe_doc_state(&mut state);
+        assert!(state.notes.is_empty() && state.undo_stack.is_empty());
+        assert!(state.folder_keys.is_empty() && state.saved_searches.is_empty());
+        assert!(state.saved_snapshot.is_empty());
+        assert_eq!(state.open_tabs, open_tabs);
+    }
+
+    pub(crate) fn temp_vault(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("pithos-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("create temp vault");
+        dir.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_upgrade_offer_covers_outdated_blobs() {
+        let folder = temp_vault("outdated-blobs");
+        let pass = "outdated-pass";
+        let key = CachedKey::derive(pass).expect("derive");
+        write_vault_raw(
+            &folder,
+            &crypto::encrypt_vault_fast(r#"{"tree":[]}"#, &key).unwrap(),
+        )
+        .unwrap();
+        // One body left behind by a session before key wrapping, one current.
+        let old_key = CachedKey::derive_with(pass, crypto::KdfParams::legacy()).expect("derive");
+        fs::create_dir_all(notes_dir(&folder)).unwrap();
+        let old = crypto::encrypt_asset(b"old body", &old_key).unwrap();
+        fs::write(notes_dir(&folder).join("n1"), old).unwrap();
+        let current = crypto::encrypt_asset(b"new body", &key).unwrap();
+        fs::write(notes_dir(&folder).join("n2"), &current).unwrap();
+
+        let audit = audit_encryption(&folder).expect("audit");
+        assert_eq!(audit.outdated_notes, ["n1"]);
+        assert!(!key.needs_upgrade(), "the key itself is current");
+        let mut state = DocState::default();
+        assert!(should_offer_upgrade(&state, &key, &audit));
+        state.read_only = true;
+        assert!(!should_offer_upgrade(&state, &key, &audit));
+        state.read_only = false;
+
+        assert_eq!(upgrade_outdated_blobs(&folder, &key).expect("upgrade"), 1);
+        let audit = audit_encryption(&folder).expect("audit");
+        assert!(audit.is_current());
+        assert!(!should_offer_upgrade(&state, &key, &audit));
+        let n1 = fs::read(notes_dir(&folder).join("n1")).unwrap();
+        assert_eq!(crypto::decrypt_asset(&n1, &key).unwrap(), b"old body");
+        let n2 = fs::read_to_string(notes_dir(&folder).join("n2")).unwrap();
+        assert_eq!(n2, current, "current blobs are left alone");
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_rekey_upgrades_vault_and_assets() {
+        let folder = temp_vault("rekey");
+        let pass = "rekey-pass";
+        let old_key = CachedKey::derive_with(pass, crypto::KdfParams::legacy()).expect("derive");
+        let vault_json = r#"{"tree":[]}"#;
+        write_vault_raw(
+            &folder,
+            &crypto::encrypt_vault_fast(vault_json, &old_key).unwrap(),
+        )
+        .unwrap();
+        let asset = crypto::encrypt_asset(b"image", &old_key).unwrap();
+        write_asset(&folder, "img1", asset.as_bytes()).unwrap();
+
+        let before = audit_encryption(&folder).expect("audit");
+        assert!(!before.is_current());
+        assert_eq!(before.outdated_assets, vec!["img1".to_string()]);
+
+        let (new_key, _) = old_key.upgraded().expect("upgrade");
+        rekey_vault(
+            &folder,
+            vault_json,
+            &old_key,
+            &new_key,
+            &["img1".to_string()],
+        )
+        .expect("rekey");
+
+        let after = audit_encryption(&folder).expect("audit");
+        assert!(after.is_current());
+        let raw = read_vault_raw(&folder).unwrap().unwrap();
+        let (plaintext, _) = crypto::decrypt_vault_returning_key(&raw, pass).expect("decrypt");
+        assert_eq!(plaintext, vault_json);
+        let asset = fs::read(assets_dir(&folder).join("img1")).unwrap();
+        assert_eq!(crypto::decrypt_asset(&asset, &new_key).unwrap(), b"image");
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_separate_note_bodies() {
+        let folder = temp_vault("bodies");
+        let key = CachedKey::derive("bodies-pass").expect("derive");
+
+        // An inline vault loads fully and switches to separate bodies on save.
+        let mut state = DocState {
+            separate_bodies: false,
+            ..DocState::default()
+        };
+        state.notes[0].content = "secret body".to_string();
+        state.notes[0].versions = vec![NoteVersion {
+            ts: 1,
+            content: "old body".to_string(),
+        }];
+        let mut state = vault_to_doc_state(doc_state_to_vault(&state));
+        assert!(state.notes.iter().all(|n| n.loaded));
+        assert!(state.separate_bodies);
+
+        state.move_note_to_trash("note-3");
+        let vault = doc_state_to_vault(&state);
+        let bodies = loaded_note_bodies(&state, None);
+        assert_eq!(bodies.len(), 3);
+        write_note_bodies(&folder, &vault.vault_id, &bodies, &key).expect("write bodies");
+        let json = serde_json::to_string(&vault).unwrap();
+        assert!(!json.contains("secret body") && !json.contains("old body"));
+
+        let mut state = vault_to_doc_state(vault.clone());
+        let note = &mut state.notes[0];
+        assert!(!note.loaded && note.content.is_empty());
+        load_note_body(&folder, &vault.vault_id, &key, note).expect("load");
+        assert_eq!(note.content, "secret body");
+        assert_eq!(note.versions[0].content, "old body");
+        unload_note_body(note);
+        assert!(!note.loaded && note.content.is_empty() && note.versions.is_empty());
+        assert!(!state.trash[0].loaded);
+
+        // Bodies of permanently deleted notes are pruned; the rest survive a rekey.
+        let mut pruned = vault.clone();
+        pruned.trash.clear();
+        prune_note_bodies(&folder, &pruned).expect("prune");
+        assert!(!notes_dir(&folder).join("note-3").exists());
+
+        let new_key = CachedKey::derive("other-pass").expect("derive");
+        rekey_vault(&folder, "{}", &key, &new_key, &[]).expect("rekey");
+        let body = read_note_body(&folder, &vault.vault_id, "note-1", &new_key).expect("read");
+        assert_eq!(body.content, "secret body");
+        assert!(read_note_body(&folder, &vault.vault_id, "note-1", &key).is_err());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_blobs_are_bound_to_their_vault() {
+        let folder = temp_vault("bound-blobs");
+        let key = CachedKey::derive("bound-pass").expect("derive");
+        let mut state = DocState::default();
+        state.notes[0].content = "first".to_string();
+        state.notes[1].content = "second".to_string();
+        save_all(&folder, &mut state, &key);
+        let (first, second) = (&state.notes[0].id, &state.notes[1].id);
+
+        // A body swapped in from another note, or read for another vault, is refused.
+        let dir = notes_dir(&folder);
+        fs::copy(dir.join(second), dir.join(first)).unwrap();
+        let err = read_note_body(&folder, &state.vault_id, first, &key).unwrap_err();
+        assert!(
+            err.to_string().contains(&format!("is note {second}")),
+            "{err}"
+        );
+        assert!(read_note_body(&folder, "other-vault", second, &key).is_err());
+
+        // Bodies written before they were bound still load.
+        let legacy = crypto::encrypt_asset(br#"{"content":"legacy"}"#, &key).unwrap();
+        fs::write(dir.join(first), legacy).unwrap();
+        let body = read_note_body(&folder, &state.vault_id, first, &key).unwrap();
+        assert_eq!(body.content, "legacy");
+
+        // The journal and search index of another vault are ignored.
+        let mut theirs = DocState::default();
+        let (_, marks) = plan_save(&theirs, &doc_state_to_vault(&theirs), false);
+        record_save(&mut theirs, marks);
+        theirs.notes[0].content = "their edit".to_string();
+        let (plan, _) = plan_save(&theirs, &doc_state_to_vault(&theirs), false);
+        let SavePlan::Journal(entry) = plan else {
+            panic!("expected a journal entry, got {plan:?}");
+        };
+        append_journal(&folder, &theirs.vault_id, &entry, &key).unwrap();
+        assert_eq!(
+            read_journal(&folder, &theirs.vault_id, &key).unwrap().len(),
+            1
+        );
+        assert!(read_journal(&folder, &state.vault_id, &key)
+            .unwrap()
+            .is_empty());
+        theirs.search_index.sync(&theirs.notes, |_| None);
+        let (_, index) = theirs.search_index.to_bytes(|_| true).unwrap();
+        write_search_index(&folder, &theirs.vault_id, &index, &key).unwrap();
+        let read = read_search_index(&folder, &state.vault_id, &key);
+        assert!(read.is_stale(&theirs.notes[0]));
+        let read = read_search_index(&folder, &theirs.vault_id, &key);
+        assert!(!read.is_stale(&theirs.notes[0]));
+        assert_eq!(read.search("their edit").len(), 1);
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    /// The save sequence the app runs: settle, sub-vaults, bodies, vault.json, prune.
+    fn save_all(folder: &str, state: &mut DocState, key: &CachedKey) {
+        for path in settle_sub_vaults(folder, state, key).expect("settle") {
+            fs::remove_file(path).expect("remove stale");
+        }
+        for sub in sub_vault_writes(state) {
+            write_sub_vault(folder, &sub).expect("write sub-vault");
+        }
+        write_note_bodies(
+            folder,
+            &state.vault_id,
+            &loaded_note_bodies(state, None),
+            key,
+        )
+        .expect("bodies");
+        let vault = doc_state_to_vault(state);
+        let json = serde_json::to_string(&vault).unwrap();
+        write_vault_raw(folder, &crypto::encrypt_vault_fast(&json, key).unwrap()).unwrap();
+        prune_note_bodies(folder, &vault).expect("prune");
+        prune_sub_vaults(folder, &vault).expect("prune sub-vaults");
+    }
+
+    #[test]
+    fn test_locked_folder_sub_vault() {
+        let folder = temp_vault("locked");
+        let key = CachedKey::derive("main-pass").expect("derive");
+        let mut state = DocState::default();
+        state.folders.push(FolderItem {
+            id: "folder-9".to_string(),
+            name: "Clients".to_string(),
+            expanded: true,
+            created_at: 0,
+            updated_at: 0,
+            parent_id: None,
+            locked: false,
+        });
+        state.notes[1].name = "Client secrets".to_string();
+        state.notes[1].content = "![scan](vault://scan-1)".to_string();
+        state.notes[1].parent_id = Some("folder-9".to_string());
+        let asset = crypto::encrypt_asset(b"scan", &key).unwrap();
+        write_asset(&folder, "scan-1", asset.as_bytes()).unwrap();
+        state.assets.insert(
+            "scan-1".to_string(),
+            AssetMeta {
+                id: "scan-1".to_string(),
+                original_name: "scan.png".to_string(),
+                mime_type: "image/png".to_string(),
+                size: 4,
+                created_at: 0,
+                stored_in: None,
+                version: None,
+            },
+        );
+        save_all(&folder, &mut state, &key);
+
+        let folder_key = CachedKey::derive("folder-pass").expect("derive");
+        let stale = lock_folder(&folder, &mut state, "folder-9", folder_key, &key).expect("lock");
+        assert_eq!(stale, vec![assets_dir(&folder).join("scan-1")]);
+        for path in stale {
+            fs::remove_file(path).unwrap();
+        }
+        save_all(&folder, &mut state, &key);
+        assert_eq!(close_sub_vault(&mut state, "folder-9"), None);
+        assert!(!state.notes.iter().any(|n| n.id == "note-2"));
+        assert!(!state.assets.contains_key("scan-1"));
+
+        // Nothing about the folder's contents is readable with the main key.
+        let raw = read_vault_raw(&folder).unwrap().unwrap();
+        let (main_json, _) = crypto::decrypt_vault_returning_key(&raw, "main-pass").unwrap();
+        assert!(!main_json.contains("Client secrets") && !main_json.contains("scan-1"));
+        assert!(!notes_dir(&folder).join("note-2").exists());
+        let mut state = vault_to_doc_state(serde_json::from_str(&main_json).unwrap());
+        assert!(state.folders[0].locked);
+        assert!(!state.is_folder_accessible(Some("folder-9")));
+        assert!(open_sub_vault(&folder, "folder-9", "main-pass").is_err());
+
+        let (data, folder_key) = open_sub_vault(&folder, "folder-9", "folder-pass").expect("open");
+        merge_sub_vault(&mut state, "folder-9", data, folder_key);
+        let note = state.notes.iter_mut().find(|n| n.id == "note-2").unwrap();
+        assert_eq!(note.stored_in.as_deref(), Some("folder-9"));
+        let (dir, note_key) =
+            blob_location(&folder, &state.folder_keys, &key, Some("folder-9")).unwrap();
+        let body = read_note_body(
+            &dir,
+            &blob_owner(&state.vault_id, Some("folder-9")),
+            "note-2",
+            note_key,
+        )
+        .unwrap();
+        assert_eq!(body.content, "![scan](vault://scan-1)");
+
+        // Removing the lock moves everything back and deletes the sub-vault.
+        remove_folder_lock(&mut state, "folder-9").expect("remove lock");
+        save_all(&folder, &mut state, &key);
+        assert!(state.folder_keys.is_empty());
+        assert!(!Path::new(&sub_vault_path(&folder, "folder-9")).exists());
+        assert_eq!(
+            read_note_body(&folder, &state.vault_id, "note-2", &key)
+                .unwrap()
+                .content,
+            body.content
+        );
+        let asset = fs::read(assets_dir(&folder).join("scan-1")).unwrap();
+        assert_eq!(crypto::decrypt_asset(&asset, &key).unwrap(), b"scan");
+        // Each move re-bound the asset at the next version.
+        assert_eq!(state.assets["scan-1"].version, Some(2));
+        let report = verify_assets(
+            &folder,
+            &state.vault_id,
+            &state.assets,
+            &key,
+            &HashMap::new(),
+        );
+        assert!(report.is_clean() && report.verified == 1);
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_trash_stays_in_locked_folder() {
+        let folder = temp_vault("locked-trash");
+        let key = CachedKey::derive("main-pass").expect("derive");
+        let mut state = DocState::default();
+        state.folders.push(FolderItem {
+            id: "folder-9".to_string(),
+            name: "Clients".to_string(),
+            expanded: true,
+            created_at: 0,
+            updated_at: 0,
+            parent_id: None,
+            locked: false,
+        });
+        state.notes[1].name = "Client secrets".to_string();
+        state.notes[1].content = "acquisition target".to_string();
+        state.notes[1].tags = vec!["merger".to_string()];
+        state.notes[1].parent_id = Some("folder-9".to_string());
+        let note_id = state.notes[1].id.clone();
+        let folder_key = CachedKey::derive("folder-pass").expect("derive");
+        lock_folder(&folder, &mut state, "folder-9", folder_key, &key).expect("lock");
+        save_all(&folder, &mut state, &key);
+        state.journal = plan_save(&state, &doc_state_to_vault(&state), true).1;
+
+        state.move_note_to_trash(&note_id);
+        let (plan, _) = plan_save(&state, &doc_state_to_vault(&state), false);
+        assert!(matches!(plan, SavePlan::Full));
+        save_all(&folder, &mut state, &key);
+        assert_eq!(state.trash[0].stored_in.as_deref(), Some("folder-9"));
+
+        // Neither the main vault.json nor notes/ holds anything of it
+        let raw = read_vault_raw(&folder).unwrap().unwrap();
+        let (main_json, _) = crypto::decrypt_vault_returning_key(&raw, "main-pass").unwrap();
+        assert!(!main_json.contains("Client secrets") && !main_json.contains("merger"));
+        assert!(!main_json.contains(&note_id));
+        assert!(!notes_dir(&folder).join(&note_id).exists());
+
+        // It comes back with the folder, in its trash
+        assert_eq!(close_sub_vault(&mut state, "folder-9"), None);
+        assert!(state.trash.is_empty());
+        let (data, folder_key) = open_sub_vault(&folder, "folder-9", "folder-pass").expect("open");
+        merge_sub_vault(&mut state, "folder-9", data, folder_key);
+        let item = &state.trash[0];
+        assert_eq!((item.name.as_str(), item.loaded), ("Client secrets", false));
+        let (dir, folder_key) =
+            blob_location(&folder, &state.folder_keys, &key, item.stored_in.as_deref()).unwrap();
+        let body = read_note_body(
+            &dir,
+            &blob_owner(&state.vault_id, Some("folder-9")),
+            &note_id,
+            folder_key,
+        )
+        .unwrap();
+        assert_eq!(body.content, "acquisition target");
+
+        // Emptying the trash removes it from the sub-vault
+        state.trash.clear();
+        save_all(&folder, &mut state, &key);
+        let sub_notes = notes_dir(&sub_vault_path(&folder, "folder-9"));
+        assert!(!sub_notes.join(&note_id).exists());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_verify_assets() {
+        let folder = temp_vault("verify-assets");
+        let key = CachedKey::derive("verify-pass").expect("derive");
+        let no_folders = HashMap::new();
+        let mut state = DocState::default();
+        let add = |state: &mut DocState, id: &str, data: &[u8], version: Option<u64>| {
+            let encrypted = match version {
+                Some(v) => {
+                    let binding = binding_for(&state.vault_id, None, id, v);
+                    crypto::encrypt_asset_bound(data, &key, &binding).unwrap()
+                }
+                None => crypto::encrypt_asset(data, &key).unwrap(),
+            };
+            write_asset(&folder, id, encrypted.as_bytes()).unwrap();
+            let meta = AssetMeta {
+                id: id.to_string(),
+                original_name: format!("{id}.png"),
+                mime_type: "image/png".to_string(),
+                size: data.len() as u64,
+                created_at: 0,
+                stored_in: None,
+                version,
+            };
+            state.assets.insert(id.to_string(), meta);
+        };
+        add(&mut state, "a", b"alpha", Some(1));
+        add(&mut state, "b", b"beta", Some(1));
+        add(&mut state, "old", b"legacy", None);
+        let report = verify_assets(&folder, &state.vault_id, &state.assets, &key, &no_folders);
+        assert_eq!(report.verified, 2);
+        assert_eq!(report.unbound, vec!["old".to_string()]);
+
+        let binding = asset_binding(&state.vault_id, &state.assets["a"]).unwrap();
+        assert_eq!(
+            read_asset(&folder, "a", &key, Some(&binding)).unwrap(),
+            b"alpha"
+        );
+
+        // Swapping two files authenticates but is caught by the binding.
+        let a = fs::read(assets_dir(&folder).join("a")).unwrap();
+        let b = fs::read(assets_dir(&folder).join("b")).unwrap();
+        write_asset(&folder, "a", &b).unwrap();
+        write_asset(&folder, "b", &a).unwrap();
+        // Editing the plaintext header breaks authentication.
+        let forged = String::from_utf8(a)
+            .unwrap()
+            .replace("\"assetId\":\"a\"", "\"assetId\":\"old\"");
+        write_asset(&folder, "old", forged.as_bytes()).unwrap();
+        let report = verify_assets(&folder, &state.vault_id, &state.assets, &key, &no_folders);
+        let ids: Vec<&str> = report
+            .mismatched
+            .iter()
+            .map(|(id, _)| id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["a", "b", "old"]);
+        assert!(report.mismatched[0].1.contains("is asset b"));
+        assert!(read_asset(&folder, "old", &key, None).is_err());
+
+        // Putting back an older copy of an asset is reported as a rollback.
+        add(&mut state, "a", b"alpha", Some(1));
+        add(&mut state, "b", b"beta", Some(1));
+        add(&mut state, "old", b"legacy", None);
+        state.assets.get_mut("a").unwrap().version = Some(2);
+        let report = verify_assets(&folder, &state.vault_id, &state.assets, &key, &no_folders);
+        assert_eq!(report.mismatched.len(), 1);
+        assert!(report.mismatched[0].1.contains("rolled back"));
+
+        // Legacy assets can be bound in place.
+        add(&mut state, "a", b"alpha", Some(2));
+        let bound = bind_assets(
+            &folder,
+            &state.vault_id,
+            &state.assets,
+            &report.unbound,
+            &key,
+            &no_folders,
+        )
+        .unwrap();
+        assert_eq!(bound, vec!["old".to_string()]);
+        state.assets.get_mut("old").unwrap().version = Some(1);
+        fs::remove_file(assets_dir(&folder).join("b")).unwrap();
+        let report = verify_assets(&folder, &state.vault_id, &state.assets, &key, &no_folders);
+        assert_eq!(report.verified, 2);
+        assert_eq!(report.missing, vec!["b".to_string()]);
+
+        // The same files are rejected under another vault's ID.
+        let report = verify_assets(&folder, &new_vault_id(), &state.assets, &key, &no_folders);
+        assert_eq!(report.mismatched.len(), 2);
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_import_unencrypted_vault() {
+        let folder = temp_vault("import-plaintext");
+        let mut state = DocState::default();
+        state.assets.insert(
+            "img".to_string(),
+            AssetMeta {
+                id: "img".to_string(),
+                original_name: "img.png".to_string(),
+                mime_type: "image/png".to_string(),
+                size: 5,
+                created_at: 0,
+                stored_in: None,
+                version: None,
+            },
+        );
+        let mut data = doc_state_to_vault(&state);
+        data.vault_id.clear();
+        write_vault_raw(&folder, &serde_json::to_string(&data).unwrap()).unwrap();
+        write_asset(&folder, "img", b"image").unwrap();
+        fs::create_dir_all(notes_dir(&folder)).unwrap();
+        fs::write(notes_dir(&folder).join("note-1"), br#"{"content":"hi"}"#).unwrap();
+
+        // Plaintext is refused everywhere unless explicitly imported.
+        let raw = read_vault_raw(&folder).unwrap().unwrap();
+        assert!(matches!(
+            crypto::decrypt_vault_returning_key(&raw, "pass"),
+            Err(CryptoError::Unencrypted(_))
+        ));
+        let key = CachedKey::derive("pass").unwrap();
+        assert!(read_note_body(&folder, "", "note-1", &key).is_err());
+        assert!(read_asset(&folder, "img", &key, None).is_err());
+
+        let (json, key) = import_unencrypted_vault(&folder, "pass", None).expect("import");
+        let state = vault_to_doc_state(serde_json::from_str(&json).unwrap());
+        let raw = read_vault_raw(&folder).unwrap().unwrap();
+        let (reread, _) = crypto::decrypt_vault_returning_key(&raw, "pass").expect("decrypt");
+        assert_eq!(reread, json);
+        assert_eq!(
+            read_note_body(&folder, &state.vault_id, "note-1", &key)
+                .unwrap()
+                .content,
+            "hi"
+        );
+        let report = verify_assets(
+            &folder,
+            &state.vault_id,
+            &state.assets,
+            &key,
+            &HashMap::new(),
+        );
+        assert!(report.is_clean() && report.verified == 1);
+        assert!(audit_encryption(&folder)
+            .unwrap()
+            .unencrypted_assets
+            .is_empty());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_verify_and_repair_vault() {
+        let folder = temp_vault("integrity");
+        let key = CachedKey::derive("integrity-pass").expect("derive");
+        let mut state = DocState::default();
+        save_all(&folder, &mut state, &key);
+        assert!(verify(&folder, &state, &key).unwrap().is_healthy());
+
+        state.notes[2].id = "note-2".to_string();
+        state.notes[1].parent_id = Some("gone".to_string());
+        state.open_tabs = vec!["note-1".into(), "note-9".into(), "note-1".into()];
+        let mut unloaded = state.notes[0].clone();
+        unloaded.id = "note-7".to_string();
+        unload_note_body(&mut unloaded);
+        state.notes.push(unloaded);
+        write_asset(&folder, "stray", b"x").unwrap();
+
+        let report = verify(&folder, &state, &key).unwrap();
+        assert_eq!(report.duplicate_ids, vec!["note-2".to_string()]);
+        assert_eq!(report.dangling_parents, vec!["note-2".to_string()]);
+        assert_eq!(
+            report.broken_tabs,
+            vec!["note-9".to_string(), "note-1".to_string()]
+        );
+        assert_eq!(report.missing_bodies, vec!["note-7".to_string()]);
+        assert_eq!(report.orphaned_bodies, vec!["note-3".to_string()]);
+        assert_eq!(report.orphaned_assets, vec!["stray".to_string()]);
+        assert!(report.is_repairable() && !report.is_healthy());
+        assert_eq!(report.repairable_count(), 4);
+
+        let outcome = repair_vault(&folder, &mut state, &key, &report);
+        assert_eq!(outcome.fixed.len(), 3);
+        assert!(outcome.unrecoverable.is_empty());
+        assert_eq!(state.notes[2].id, "note-4");
+        assert_eq!(state.notes[2].name, "Formatting Examples");
+        assert_eq!(state.notes[1].parent_id, None);
+        assert_eq!(state.open_tabs, vec!["note-1".to_string()]);
+        let report = check_vault_structure(&folder, &state).unwrap();
+        assert_eq!(report.repairable_count(), 0);
+        assert_eq!(report.missing_bodies, vec!["note-7".to_string()]);
+
+        // Unloaded, a duplicate would read the first note's body file.
+        let mut twin = state.notes[0].clone();
+        unload_note_body(&mut twin);
+        state.notes.push(twin);
+        let report = check_vault_structure(&folder, &state).unwrap();
+        assert_eq!(report.duplicate_ids, vec!["note-1".to_string()]);
+        let outcome = repair_vault(&folder, &mut state, &key, &report);
+        assert!(outcome.fixed.is_empty());
+        assert_eq!(outcome.unrecoverable.len(), 1);
+        assert!(outcome.unrecoverable[0].ends_with("(note-1)"));
+        let twin = state.notes.last().unwrap();
+        assert_eq!(twin.id, "note-1");
+        assert!(!twin.loaded && twin.content.is_empty());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_rekey_moves_backups() {
+        let folder = temp_vault("rekey-backups");
+        let key = CachedKey::derive("old-pass").expect("derive");
+        let mut state = DocState::default();
+        state.notes[0].content = "backed up".to_string();
+        save_all(&folder, &mut state, &key);
+        let backup = create_backup(&folder, 1).unwrap();
+        let backup_folder = backup_path(&folder, &backup.id).unwrap();
+        let opens_with = |pass: &str| {
+            let raw = read_vault_raw(&backup_folder).unwrap().unwrap();
+            crypto::decrypt_vault_returning_key(&raw, pass).is_ok()
+        };
+
+        // A new passphrase on the same data key: the old one stops opening backups.
+        let mut rewrapped = key.clone();
+        rewrapped.set_passphrase("new-pass", None).unwrap();
+        let json = serde_json::to_string(&doc_state_to_vault(&state)).unwrap();
+        rekey_vault(&folder, &json, &key, &rewrapped, &[]).expect("rekey");
+        assert_eq!(rekey_backups(&folder, &key, &rewrapped).unwrap(), 1);
+        assert!(opens_with("new-pass") && !opens_with("old-pass"));
+
+        // A new data key: the backup's bodies move with it, the vault's stay readable.
+        let new_key = CachedKey::derive("newer-pass").expect("derive");
+        rekey_vault(&folder, &json, &rewrapped, &new_key, &[]).expect("rekey");
+        assert_eq!(rekey_backups(&folder, &rewrapped, &new_key).unwrap(), 1);
+        assert!(opens_with("newer-pass") && !opens_with("new-pass"));
+        let note_id = &state.notes[0].id;
+        for dir in [&backup_folder, &folder] {
+            let body = read_note_body(dir, &state.vault_id, note_id, &new_key).unwrap();
+            assert_eq!(body.content, "backed up");
+        }
+        // Backups under keys from before are left alone.
+        assert_eq!(rekey_backups(&folder, &key, &new_key).unwrap(), 0);
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_backup_retention() {
+        let now = 100 * WEEK_SECS;
+        let times = [
+            now - 10,                       // last day: kept
+            now - 2 * HOUR_SECS,            // last day: kept
+            now - 2 * DAY_SECS + HOUR_SECS, // newest of its day
+            now - 2 * DAY_SECS,             // same day, older: dropped
+            now - 3 * WEEK_SECS + DAY_SECS, // newest of its week
+            now - 3 * WEEK_SECS,            // same week, older: dropped
+            now - 20 * WEEK_SECS,           // too old: dropped
+        ];
+        let keep = backups_to_keep(&times, now);
+        let mut kept: Vec<i64> = keep.into_iter().collect();
+        kept.sort_by_key(|t| std::cmp::Reverse(*t));
+        assert_eq!(kept, vec![times[0], times[1], times[2], times[4]]);
+        // The newest backup survives however old it is.
+        assert!(backups_to_keep(&[times[6]], now).contains(&times[6]));
+    }
+
+    #[test]
+    fn test_backup_and_restore() {
+        let folder = temp_vault("backups");
+        let key = CachedKey::derive("backup-pass").expect("derive");
+        let mut state = DocState::default();
+        state.notes[0].content = "first draft".to_string();
+        save_all(&folder, &mut state, &key);
+
+        let backup = backup_vault(&folder).unwrap().expect("first backup");
+        assert!(
+            backup_vault(&folder).unwrap().is_none(),
+            "within the interval"
+        );
+        let data = open_backup(&folder, &backup.id, &key).expect("open");
+        assert_eq!(data.vault_id, state.vault_id);
+        assert!(backup_path(&folder, "../x").is_err());
+
+        // Change the note and the passphrase, then restore under the new key.
+        state.notes[0].content = "second draft".to_string();
+        save_all(&folder, &mut state, &key);
+        let new_key = CachedKey::derive("new-pass").expect("derive");
+        rekey_vault(&folder, "{}", &key, &new_key, &[]).expect("rekey");
+        assert!(open_backup(&folder, &backup.id, &new_key).is_err());
+
+        let restored = restore_backup(&folder, &backup.id, &key, &new_key).expect("restore");
+        assert_eq!(restored.vault_id, state.vault_id);
+        let body = read_note_body(&folder, &state.vault_id, "note-1", &new_key).expect("read");
+        assert_eq!(body.content, "first draft");
+        let raw = read_vault_raw(&folder).unwrap().unwrap();
+        assert!(crypto::decrypt_vault_fast(&raw, &new_key).is_ok());
+
+        // The restore left an undo point holding the state it replaced.
+        let backups = list_backups(&folder).unwrap();
+        assert_eq!(backups.len(), 2);
+        let undo = &backups[0];
+        assert!(undo.created_at > backup.created_at);
+        let undo_folder = backup_path(&folder, &undo.id).unwrap();
+        let body =
+            read_note_body(&undo_folder, &state.vault_id, "note-1", &new_key).expect("read undo");
+        assert_eq!(body.content, "second draft");
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        use std::io::Write;
+        let folder = temp_vault("journal");
+        let key = CachedKey::derive("journal-pass").expect("derive");
+        let load = || {
+            let raw = read_vault_raw(&folder).unwrap().unwrap();
+            let json = crypto::decrypt_vault_fast(&raw, &key).unwrap();
+            vault_to_doc_state(serde_json::from_str(&json).unwrap())
+        };
+        let mut state = DocState::default();
+        let (plan, marks) = plan_save(&state, &doc_state_to_vault(&state), false);
+        assert!(matches!(plan, SavePlan::Full));
+        save_all(&folder, &mut state, &key);
+        record_save(&mut state, marks);
+
+        // Editing notes only appends to the journal.
+        state.notes[0].content = "journaled edit".to_string();
+        state.notes[1].name = "Renamed".to_string();
+        let (plan, marks) = plan_save(&state, &doc_state_to_vault(&state), false);
+        let SavePlan::Journal(entry) = plan else {
+            panic!("expected a journal entry, got {plan:?}");
+        };
+        assert_eq!(entry.base, 1);
+        assert_eq!(entry.notes.len(), 2);
+        assert!(entry.notes[0].body.is_some() && entry.notes[1].body.is_none());
+        append_journal(&folder, &state.vault_id, &entry, &key).unwrap();
+        record_save(&mut state, marks);
+        let (plan, _) = plan_save(&state, &doc_state_to_vault(&state), false);
+        assert!(matches!(plan, SavePlan::Unchanged));
+
+        // After a crash, replay skips a torn append and keeps the rest.
+        fs::OpenOptions::new()
+            .append(true)
+            .open(journal_path(&folder))
+            .unwrap()
+            .write_all(b"{\"v\":3,\"ci")
+            .unwrap();
+        let mut reopened = load();
+        assert_eq!(replay_journal(&folder, &key, &mut reopened).unwrap(), 1);
+        let note = &reopened.notes[find_note_index(&reopened.notes, "note-1").unwrap()];
+        assert!(note.loaded);
+        assert_eq!(note.content, "journaled edit");
+        let note = &reopened.notes[find_note_index(&reopened.notes, "note-2").unwrap()];
+        assert_eq!(note.name, "Renamed");
+
+        // Anything else is a full save, after which the old journal is stale.
+        state.move_note_to_trash("note-3");
+        let (plan, marks) = plan_save(&state, &doc_state_to_vault(&state), false);
+        assert!(matches!(plan, SavePlan::Full));
+        save_all(&folder, &mut state, &key);
+        record_save(&mut state, marks);
+        let mut reopened = load();
+        assert_eq!(reopened.journal.save_seq, 2);
+        assert_eq!(replay_journal(&folder, &key, &mut reopened).unwrap(), 0);
+        clear_journal(&folder).unwrap();
+        assert!(read_journal(&folder, &state.vault_id, &key)
+            .unwrap()
+            .is_empty());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_read_only_is_never_saved() {
+        let folder = temp_vault("read-only");
+        let key = CachedKey::derive("read-only-pass").expect("derive");
+        let mut state = DocState::default();
+        let (_, marks) = plan_save(&state, &doc_state_to_vault(&state), false);
+        save_all(&folder, &mut state, &key);
+        record_save(&mut state, marks);
+
+        state.read_only = true;
+        state.notes[0].content = "edited while read-only".to_string();
+        state.move_note_to_trash("note-3");
+        for full in [false, true] {
+            let (plan, marks) = plan_save(&state, &doc_state_to_vault(&state), full);
+            assert!(matches!(plan, SavePlan::Unchanged));
+            assert_eq!(marks.save_seq, state.journal.save_seq);
+        }
+        assert!(settle_sub_vaults(&folder, &mut state, &key)
+            .unwrap()
+            .is_empty());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_schema_migrations() {
+        // Version 1: no schemaVersion, unknown item types read as notes.
+        let v1 = r#"{"tree":[{"id":"f","name":"F","type":"folder","children":[
+            {"id":"p","name":"Page","type":"page","content":"x"}]}]}"#;
+        let data = parse_vault(v1).expect("v1");
+        assert_eq!(data.schema_version, SCHEMA_VERSION);
+        let children = data.tree[0].children.as_ref().unwrap();
+        assert_eq!(children[0].item_type, "note");
+        let state = vault_to_doc_state(data);
+        assert_eq!(state.notes[0].parent_id.as_deref(), Some("f"));
+
+        let json = serde_json::to_string(&doc_state_to_vault(&state)).unwrap();
+        assert!(json.contains(&format!("\"schemaVersion\":{SCHEMA_VERSION}")));
+        assert!(parse_vault(&json).is_ok());
+
+        // Version 2: no saved searches yet; everything else survives a round trip.
+        let v2 = r#"{"schemaVersion":2,"tree":[{"id":"n","name":"N","type":"note",
+            "content":"body","tags":["t"]}],"trash":[]}"#;
+        let data = parse_vault(v2).expect("v2");
+        assert_eq!(data.schema_version, SCHEMA_VERSION);
+        assert!(data.saved_searches.is_empty());
+        let mut state = vault_to_doc_state(data);
+        state.saved_searches.push(SavedSearch {
+            id: "s".into(),
+            name: "S".into(),
+            query: "body".into(),
+            filter_tags: Vec::new(),
+            tag_filter_and: false,
+            sort_order: SortOrder::Manual,
+            expanded: false,
+        });
+        let json = serde_json::to_string(&doc_state_to_vault(&state)).unwrap();
+        let reread = vault_to_doc_state(parse_vault(&json).unwrap());
+        assert_eq!(reread.notes[0].id, "n");
+        assert_eq!(reread.notes[0].name, "N");
+        assert_eq!(reread.notes[0].tags, ["t"]);
+        assert_eq!(reread.saved_searches.len(), 1);
+
+        // From the current version on, an unknown type is an error...
+        let current = format!(
+            r#"{{"schemaVersion":{SCHEMA_VERSION},"tree":[{{"id":"p","name":"P","type":"page"}}]}}"#
+        );
+        assert!(matches!(parse_vault(&current), Err(VaultError::Invalid(_))));
+        // ...and a newer vault is refused rather than partly read.
+        let newer = format!(r#"{{"schemaVersion":{},"tree":[]}}"#, SCHEMA_VERSION + 1);
+        assert!(matches!(
+            parse_vault(&newer),
+            Err(VaultError::NewerSchema(v)) if v == SCHEMA_VERSION + 1
+        ));
+        let huge = format!(
+            r#"{{"schemaVersion":{},"tree":[]}}"#,
+            u64::from(u32::MAX) + 1
+        );
+        assert!(matches!(parse_vault(&huge), Err(VaultError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_held_save_outlives_the_key() {
+        let folder = temp_vault("held");
+        let pass = "held-pass";
+        let load = |key: &CachedKey| {
+            let raw = read_vault_raw(&folder).unwrap().unwrap();
+            let json = crypto::decrypt_vault_fast(&raw, key).unwrap();
+            let mut state = vault_to_doc_state(parse_vault(&json).unwrap());
+            for note in &mut state.notes {
+                load_note_body(&folder, &state.vault_id, key, note).unwrap();
+            }
+            mark_loaded(&mut state, &folder);
+            state
+        };
+        let hold = |state: &DocState, key: &CachedKey| {
+            let bodies = loaded_note_bodies(state, None);
+            let subs = sub_vault_writes(state);
+            let vault = doc_state_to_vault(state);
+            hold_save(&vault, &bodies, &subs, key, &state.journal).expect("hold")
+        };
+        let key = CachedKey::derive(pass).expect("derive");
+        save_all(&folder, &mut DocState::default(), &key);
+
+        // The save as the vault locked failed: only the sealed save is kept.
+        let mut state = load(&key);
+        state.notes[0].content = "edited before the lock".to_string();
+        let held = hold(&state, &key);
+        wipe_doc_state(&mut state);
+        drop(key);
+        assert!(held
+            .bodies
+            .iter()
+            .all(|(_, sealed)| !sealed.contains("edited before")));
+
+        write_held_save(&folder, &held).expect("write held save");
+        let raw = read_vault_raw(&folder).unwrap().unwrap();
+        let (_, key) = crypto::decrypt_vault_returning_key(&raw, pass).expect("unlock");
+        let state = load(&key);
+        assert_eq!(state.notes[0].content, "edited before the lock");
+
+        // Once someone else wrote the vault, a held save would overwrite them.
+        let held = hold(&state, &key);
+        let mut theirs = load(&key);
+        theirs.notes[1].content = "their edit".to_string();
+        save_all(&folder, &mut theirs, &key);
+        assert!(matches!(
+            write_held_save(&folder, &held),
+            Err(VaultError::Changed)
+        ));
+        assert_eq!(load(&key).notes[1].content, "their edit");
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_external_merge() {
+        let folder = temp_vault("external");
+        let key = CachedKey::derive("external-pass").expect("derive");
+        let load = || {
+            let raw = read_vault_raw(&folder).unwrap().unwrap();
+            let json = crypto::decrypt_vault_fast(&raw, &key).unwrap();
+            let mut state = vault_to_doc_state(parse_vault(&json).unwrap());
+            for note in &mut state.notes {
+                load_note_body(&folder, &state.vault_id, &key, note).unwrap();
+            }
+            mark_loaded(&mut state, &folder);
+            state
+        };
+        save_all(&folder, &mut DocState::default(), &key);
+        let mut ours = load();
+        let mut theirs = load();
+        assert!(!ours.journal.disk_changed(&folder));
+
+        // Another device edits two notes, deletes one and adds one.
+        theirs.notes[0].content = "their text".to_string();
+        theirs.notes[1].content = "only theirs".to_string();
+        theirs.move_note_to_trash("note-3");
+        theirs.notes.push(NoteItem::new(
+            "note-4".to_string(),
+            "From laptop".to_string(),
+            "laptop".to_string(),
+            Vec::new(),
+        ));
+        save_all(&folder, &mut theirs, &key);
+        // Meanwhile we edit the first note and add a note under the same ID.
+        ours.notes[0].content = "our text".to_string();
+        ours.notes.push(NoteItem::new(
+            "note-4".to_string(),
+            "Here".to_string(),
+            "desktop".to_string(),
+            Vec::new(),
+        ));
+        assert!(ours.journal.disk_changed(&folder));
+
+        let external = load_external(&folder, &key, &ours.journal).unwrap();
+        let report = merge_external(&mut ours, external);
+        assert_eq!(report.updated.len(), 1);
+        assert_eq!(ours.notes[1].content, "only theirs");
+        assert_eq!(report.removed.len(), 1);
+        assert!(find_note_index(&ours.notes, "note-3").is_none());
+        assert_eq!(report.added, vec!["From laptop".to_string()]);
+        let added = ours.notes.iter().find(|n| n.name == "From laptop").unwrap();
+        assert_ne!(added.id, "note-4");
+        assert_eq!(
+            ours.notes[find_note_index(&ours.notes, "note-4").unwrap()].content,
+            "desktop"
+        );
+
+        // A conflict keeps our text and files theirs in the history.
+        assert_eq!(report.conflicts.len(), 1);
+        let conflict = &report.conflicts[0];
+        assert_eq!(conflict.theirs, "their text");
+        let note = &ours.notes[find_note_index(&ours.notes, &conflict.note_id).unwrap()];
+        assert_eq!(note.content, "our text");
+        assert_eq!(note.versions.last().unwrap().content, "their text");
+        let copy = resolve_conflict(&mut ours, conflict, Resolution::Both).unwrap();
+        let copy = &ours.notes[find_note_index(&ours.notes, &copy).unwrap()];
+        assert_eq!(copy.content, "their text");
+        assert!(copy.name.ends_with("(conflicted copy)"));
+        resolve_conflict(&mut ours, conflict, Resolution::Theirs);
+        let note = &ours.notes[find_note_index(&ours.notes, &conflict.note_id).unwrap()];
+        assert_eq!(note.content, "their text");
+
+        // What was merged is the new base.
+        assert!(!ours.journal.disk_changed(&folder));
+        let external = load_external(&folder, &key, &ours.journal).unwrap();
+        let report = merge_external(&mut ours, external);
+        assert!(report.is_empty(), "{report:?}");
+
+        // Their text for a note we changed too hasn't arrived: ours stays, and it is reported.
+        let id = theirs.notes[1].id.clone();
+        theirs.notes[1].name = "Renamed there".to_string();
+        save_all(&folder, &mut theirs, &key);
+        fs::remove_file(notes_dir(&folder).join(&id)).unwrap();
+        let index = find_note_index(&ours.notes, &id).unwrap();
+        ours.notes[index].content = "changed here".to_string();
+        let external = load_external(&folder, &key, &ours.journal).unwrap();
+        let report = merge_external(&mut ours, external);
+        assert_eq!(report.unreadable, vec![ours.notes[index].name.clone()]);
+        assert_eq!(ours.notes[index].content, "changed here");
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_external_merge_saved_searches() {
+        let folder = temp_vault("external-searches");
+        let search = |id: &str, query: &str| SavedSearch {
+            id: id.to_string(),
+            name: format!("Search {id}"),
+            query: query.to_string(),
+            filter_tags: Vec::new(),
+            tag_filter_and: false,
+            sort_order: SortOrder::ModifiedDesc,
+            expanded: true,
+        };
+        let base = || {
+            let mut state = DocState {
+                saved_searches: vec![
+                    search("search-1", "todo"),
+                    search("search-2", "tag:ops"),
+                    search("search-3", "is:pinned"),
+                ],
+                ..DocState::default()
+            };
+            mark_loaded(&mut state, &folder);
+            state
+        };
+        let mut ours = base();
+        let mut theirs = base();
+
+        // They edit one, delete one and add one; we delete another, fold
+        // the edited one shut and add one under the same new ID.
+        theirs.saved_searches[0].query = "is:todo".to_string();
+        theirs.saved_searches.remove(1);
+        theirs.saved_searches.push(search("search-4", "has:image"));
+        ours.saved_searches[0].expanded = false;
+        ours.saved_searches.remove(2);
+        ours.saved_searches.push(search("search-4", "updated:7d"));
+
+        merge_external(&mut ours, theirs);
+        let found: Vec<(&str, &str, bool)> = ours
+            .saved_searches
+            .iter()
+            .map(|s| (s.id.as_str(), s.query.as_str(), s.expanded))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("search-1", "is:todo", false),
+                ("search-4", "updated:7d", true),
+                ("search-5", "has:image", true),
+            ]
+        );
+
+        // Deleted there: gone here too, unless it changed or is new here
+        merge_external(&mut ours, DocState::default());
+        let ids: Vec<&str> = ours.saved_searches.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["search-4", "search-5"]);
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn test_vault_lock() {
+        let folder = temp_vault("lock");
+        acquire_lock(&folder).expect("acquire");
+        acquire_lock(&folder).expect("acquire again");
+        assert!(refresh_lock(&folder).unwrap().is_none());
+
+        // A fresh lock from another machine keeps us out...
+        let now = unix_now();
+        let other = LockInfo {
+            pid: 1,
+            hostname: "elsewhere".to_string(),
+            acquired_at: now,
+            refreshed_at: now,
+        };
+        write_lock(&folder, &other).unwrap();
+        assert!(matches!(acquire_lock(&folder), Err(VaultError::InUse(held)) if held == other));
+        assert_eq!(refresh_lock(&folder).unwrap(), Some(other.clone()));
+        release_lock(&folder).unwrap();
+        assert!(lock_path(&folder).exists());
+
+        // ...until it goes stale.
+        let stale = LockInfo {
+            refreshed_at: now - LOCK_STALE_SECS - 1,
+            ..other
+        };
+        write_lock(&folder, &stale).unwrap();
+        acquire_lock(&folder).expect("take over stale lock");
+        assert!(read_lock(&folder).unwrap().unwrap().is_ours());
+        release_lock(&folder).unwrap();
+        assert!(!lock_path(&folder).exists());
+
+        // The write probe leaves nothing behind
+        assert!(folder_writable(&folder));
+        assert_eq!(fs::read_dir(&folder).unwrap().count(), 0);
+        assert!(!folder_writable(&format!("{folder}/missing")));
+        let _ = fs::remove_dir_all(&folder);
     }
 }
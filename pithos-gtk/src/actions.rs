@@ -16,6 +16,10 @@ pub fn build_content_menu() -> gtk::gio::Menu {
     section1.append(Some("Version History"), Some("win.version-history"));
     section1.append(Some("Move to Folder\u{2026}"), Some("win.move-to-folder"));
     section1.append(Some("Export\u{2026}"), Some("win.export"));
+    section1.append(
+        Some("Import Shared Bundle\u{2026}"),
+        Some("win.import-bundle"),
+    );
     menu.append_section(None, &section1);
 
     let section2 = gtk::gio::Menu::new();
@@ -29,6 +33,16 @@ pub fn build_content_menu() -> gtk::gio::Menu {
         Some("Change Passphrase\u{2026}"),
         Some("win.change-passphrase"),
     );
+    section3.append(Some("Unlock Methods\u{2026}"), Some("win.unlock-methods"));
+    section3.append(
+        Some("Encryption Audit\u{2026}"),
+        Some("win.audit-encryption"),
+    );
+    section3.append(Some("Check Vault Integrity"), Some("win.check-vault"));
+    section3.append(
+        Some("Export Vault Archive\u{2026}"),
+        Some("win.export-archive"),
+    );
     section3.append(Some("Lock Vault"), Some("win.lock-vault"));
     section3.append(Some("Change Vault\u{2026}"), Some("win.change-vault"));
     menu.append_section(None, &section3);
@@ -100,6 +114,32 @@ pub fn wire_menu_actions(ctx: &EditorCtx) {
     }
     window.add_action(&sort_action);
 
+    // Search scope (stateful string action)
+    let scope_action = SimpleAction::new_stateful(
+        "search-scope",
+        Some(&String::static_variant_type()),
+        &"notes".to_variant(),
+    );
+    {
+        let ctx = ctx.clone();
+        scope_action.connect_activate(move |action, param| {
+            let Some(val) = param.and_then(|p| p.get::<String>()) else {
+                return;
+            };
+            action.set_state(&val.to_variant());
+            let (scope, placeholder) = match val.as_str() {
+                "history" => (SearchScope::History, "Search snapshots\u{2026}"),
+                "trash" => (SearchScope::Trash, "Search the trash\u{2026}"),
+                _ => (SearchScope::Notes, "Search notes\u{2026}"),
+            };
+            ctx.state.borrow_mut().search_scope = scope;
+            ctx.archived_index.take();
+            ctx.search_entry.set_placeholder_text(Some(placeholder));
+            refresh_note_list(&ctx);
+        });
+    }
+    window.add_action(&scope_action);
+
     // New folder
     let action = SimpleAction::new("new-folder", None);
     {
@@ -108,6 +148,14 @@ pub fn wire_menu_actions(ctx: &EditorCtx) {
     }
     window.add_action(&action);
 
+    // New smart folder
+    let action = SimpleAction::new("new-smart-folder", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| show_saved_search_dialog(&ctx, None));
+    }
+    window.add_action(&action);
+
     // New from template
     let action = SimpleAction::new("new-from-template", None);
     {
@@ -254,6 +302,14 @@ pub fn wire_menu_actions(ctx: &EditorCtx) {
     }
     window.add_action(&action);
 
+    // Notes shared by someone else as an encrypted bundle
+    let action = SimpleAction::new("import-bundle", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| show_import_bundle_dialog(&ctx));
+    }
+    window.add_action(&action);
+
     // Delete note
     let action = SimpleAction::new("delete-note", None);
     {
@@ -596,23 +652,7 @@ pub fn wire_menu_actions(ctx: &EditorCtx) {
     {
         let ctx = ctx.clone();
         action.connect_activate(move |_, _| {
-            if !perform_vault_save_sync(&ctx) {
-                return;
-            }
-            let vault_folder = ctx.vault_folder.borrow().clone();
-
-            // Clear sensitive key material and stop background timers.
-            stop_background_tasks(&ctx);
-            *ctx.cached_key.borrow_mut() = None;
-            {
-                let mut state = ctx.state.borrow_mut();
-                state.suppress_sync = true;
-            }
-            // Clear editor buffer content from memory.
-            ctx.source_buffer.set_text("");
-
-            ctx.window.set_content(gtk::Widget::NONE);
-            show_unlock_vault_dialog(&ctx.window, vault_folder);
+            lock_vault(&ctx);
         });
     }
     window.add_action(&action);
@@ -635,6 +675,70 @@ pub fn wire_menu_actions(ctx: &EditorCtx) {
     }
     window.add_action(&action);
 
+    // Unlock methods (passphrase, recovery code, key files)
+    let action = SimpleAction::new("unlock-methods", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| show_unlock_methods_dialog(&ctx));
+    }
+    window.add_action(&action);
+
+    // Upgrade vault encryption to the current KDF/format
+    let action = SimpleAction::new("upgrade-encryption", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| {
+            with_encryption_audit(&ctx, |ctx, audit| {
+                if upgrade_applies(ctx, &audit) {
+                    upgrade_vault_encryption(ctx);
+                } else {
+                    send_toast(ctx, "Vault encryption is already up to date");
+                }
+            });
+        });
+    }
+    window.add_action(&action);
+
+    // Report the encryption format of the vault and every blob
+    let action = SimpleAction::new("audit-encryption", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| show_encryption_audit(&ctx));
+    }
+    window.add_action(&action);
+
+    // Check asset ciphertexts against their bindings
+    let action = SimpleAction::new("verify-assets", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| verify_vault_assets(&ctx));
+    }
+    window.add_action(&action);
+
+    // Integrity check and repair of the open vault
+    let action = SimpleAction::new("check-vault", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| check_vault_integrity(&ctx));
+    }
+    window.add_action(&action);
+
+    // Browse and restore backups
+    let action = SimpleAction::new("backups", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| show_backups_dialog(&ctx));
+    }
+    window.add_action(&action);
+
+    // Whole vault as one encrypted .pithos file
+    let action = SimpleAction::new("export-archive", None);
+    {
+        let ctx = ctx.clone();
+        action.connect_activate(move |_, _| show_export_archive_dialog(&ctx));
+    }
+    window.add_action(&action);
+
     // Open vault
     let action = SimpleAction::new("open-vault", None);
     {
@@ -1,6 +1,9 @@
 use crate::*;
 use adw::prelude::*;
+use pithos_core::archive;
+use pithos_core::bundle;
 use pithos_core::crypto;
+use pithos_core::search::{search_archived, ArchivedId, Query};
 use pithos_core::state::*;
 use pithos_core::vault;
 use std::cell::RefCell;
@@ -52,6 +55,128 @@ fn wire_passphrase_strength(entry: &gtk::PasswordEntry, label: &gtk::Label) {
     });
 }
 
+type KeyFilePath = Rc<RefCell<Option<std::path::PathBuf>>>;
+
+/// A "key file" picker row: shows the chosen file and offers to pick an
+/// existing one, create a new random one, or clear the selection.
+fn build_keyfile_row(dialog: &adw::Window, placeholder: &str) -> (gtk::Box, KeyFilePath) {
+    let path: KeyFilePath = Rc::new(RefCell::new(None));
+
+    let row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let label = gtk::Label::new(Some(placeholder));
+    label.set_hexpand(true);
+    label.set_xalign(0.0);
+    label.add_css_class("dim-label");
+    label.set_ellipsize(gtk::pango::EllipsizeMode::Middle);
+    let choose_btn = gtk::Button::with_label("Key File\u{2026}");
+    choose_btn.add_css_class("toolbar-pill");
+    choose_btn.set_tooltip_text(Some("Use an existing key file"));
+    let create_btn = gtk::Button::from_icon_name("document-new-symbolic");
+    create_btn.add_css_class("flat");
+    create_btn.set_tooltip_text(Some("Create a new key file"));
+    let clear_btn = gtk::Button::from_icon_name("edit-clear-symbolic");
+    clear_btn.add_css_class("flat");
+    clear_btn.set_tooltip_text(Some("Don't use a key file"));
+    row.append(&label);
+    row.append(&choose_btn);
+    row.append(&create_btn);
+    row.append(&clear_btn);
+
+    let set_path = {
+        let path = path.clone();
+        let label = label.clone();
+        let placeholder = placeholder.to_string();
+        Rc::new(move |p: Option<std::path::PathBuf>| {
+            match &p {
+                Some(p) => {
+                    label.set_label(&p.to_string_lossy());
+                    label.remove_css_class("dim-label");
+                }
+                None => {
+                    label.set_label(&placeholder);
+                    label.add_css_class("dim-label");
+                }
+            }
+            *path.borrow_mut() = p;
+        })
+    };
+
+    {
+        let dialog = dialog.clone();
+        let set_path = set_path.clone();
+        choose_btn.connect_clicked(move |_| {
+            let chooser = gtk::FileDialog::builder()
+                .title("Choose Key File")
+                .accept_label("Use")
+                .build();
+            let set_path = set_path.clone();
+            chooser.open(
+                Some(&dialog),
+                gtk::gio::Cancellable::NONE,
+                move |result: Result<gtk::gio::File, gtk::glib::Error>| match result {
+                    Ok(file) => set_path(file.path()),
+                    Err(e) => {
+                        if !e.matches(gtk::DialogError::Dismissed) {
+                            eprintln!("Key file selection failed: {e}");
+                        }
+                    }
+                },
+            );
+        });
+    }
+
+    {
+        let dialog = dialog.clone();
+        let set_path = set_path.clone();
+        create_btn.connect_clicked(move |_| {
+            let chooser = gtk::FileDialog::builder()
+                .title("Create Key File")
+                .accept_label("Create")
+                .initial_name("pithos.keyfile")
+                .build();
+            let set_path = set_path.clone();
+            let dlg = dialog.clone();
+            chooser.save(
+                Some(&dialog),
+                gtk::gio::Cancellable::NONE,
+                move |result: Result<gtk::gio::File, gtk::glib::Error>| match result {
+                    Ok(file) => {
+                        let Some(p) = file.path() else { return };
+                        let contents = crypto::KeyFile::generate();
+                        match vault::atomic_write(&p, &contents) {
+                            Ok(()) => set_path(Some(p)),
+                            Err(e) => {
+                                let alert = adw::AlertDialog::new(
+                                    Some("Key File Not Created"),
+                                    Some(&format!("Could not write key file: {e}")),
+                                );
+                                alert.add_response("ok", "OK");
+                                alert.set_close_response("ok");
+                                alert.present(Some(&dlg));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        if !e.matches(gtk::DialogError::Dismissed) {
+                            eprintln!("Key file creation failed: {e}");
+                        }
+                    }
+                },
+            );
+        });
+    }
+
+    clear_btn.connect_clicked(move |_| set_path(None));
+
+    (row, path)
+}
+
+/// Read the key file chosen in a [`build_keyfile_row`], if any (blocking).
+fn read_keyfile(path: Option<std::path::PathBuf>) -> Result<Option<crypto::KeyFile>, String> {
+    path.map(|p| crypto::KeyFile::read(&p).map_err(|e| e.to_string()))
+        .transpose()
+}
+
 // ---------------------------------------------------------------------------
 // Vault startup dialogs
 // ---------------------------------------------------------------------------
@@ -125,6 +250,10 @@ pub fn show_welcome_dialog(window: &adw::ApplicationWindow) {
     open_btn.add_css_class("pill");
     btn_box.append(&open_btn);
 
+    let archive_btn = gtk::Button::with_label("Open Vault Archive\u{2026}");
+    archive_btn.add_css_class("pill");
+    btn_box.append(&archive_btn);
+
     vbox.append(&btn_box);
 
     let toolbar = adw::HeaderBar::new();
@@ -196,6 +325,24 @@ pub fn show_welcome_dialog(window: &adw::ApplicationWindow) {
         });
     }
 
+    // Open Vault Archive — unpack it into a new folder, then unlock that
+    {
+        let window = window.clone();
+        let dialog = dialog.clone();
+        archive_btn.connect_clicked(move |_| {
+            let win = window.clone();
+            let dlg = dialog.clone();
+            extract_vault_archive(&dialog, move |folder, key| {
+                save_config_or_log(&vault::AppConfig {
+                    vault_path: Some(folder.clone()),
+                    ..Default::default()
+                });
+                transition_close(&dlg);
+                open_extracted_vault(&win, folder, key);
+            });
+        });
+    }
+
     dialog.present();
 }
 
@@ -246,6 +393,10 @@ pub fn show_vault_switcher_dialog(ctx: &EditorCtx) {
     open_btn.add_css_class("pill");
     btn_box.append(&open_btn);
 
+    let archive_btn = gtk::Button::with_label("Open Vault Archive\u{2026}");
+    archive_btn.add_css_class("pill");
+    btn_box.append(&archive_btn);
+
     vbox.append(&btn_box);
 
     let toolbar = adw::HeaderBar::new();
@@ -344,9 +495,175 @@ pub fn show_vault_switcher_dialog(ctx: &EditorCtx) {
         });
     }
 
+    // Open Vault Archive
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        archive_btn.connect_clicked(move |_| {
+            let ctx = ctx.clone();
+            let dlg = dialog.clone();
+            extract_vault_archive(&dialog, move |folder, key| {
+                if !teardown(&ctx) {
+                    return;
+                }
+                save_config_or_log(&vault::AppConfig {
+                    vault_path: Some(folder.clone()),
+                    ..Default::default()
+                });
+                transition_close(&dlg);
+                open_extracted_vault(&ctx.window, folder, key);
+            });
+        });
+    }
+
     dialog.present();
 }
 
+/// Ask for a `.pithos` archive, an empty folder and the archive's passphrase,
+/// and unpack the archive there. `done` gets the new vault folder and its key.
+fn extract_vault_archive(parent: &adw::Window, done: impl Fn(String, crypto::CachedKey) + 'static) {
+    let filter = gtk::FileFilter::new();
+    filter.set_name(Some("Pithos vault archives"));
+    filter.add_pattern(&format!("*.{}", archive::ARCHIVE_EXTENSION));
+    let filters = gtk::gio::ListStore::new::<gtk::FileFilter>();
+    filters.append(&filter);
+    let chooser = gtk::FileDialog::builder()
+        .title("Open Vault Archive")
+        .accept_label("Open")
+        .filters(&filters)
+        .build();
+
+    let parent_c = parent.clone();
+    chooser.open(Some(parent), gtk::gio::Cancellable::NONE, move |result| {
+        let Some(archive_path) = result.ok().and_then(|file| file.path()) else {
+            return;
+        };
+        let folder_chooser = gtk::FileDialog::builder()
+            .title("Choose an Empty Folder for the Vault")
+            .accept_label("Extract")
+            .build();
+        let parent = parent_c.clone();
+        folder_chooser.select_folder(
+            Some(&parent_c),
+            gtk::gio::Cancellable::NONE,
+            move |result| {
+                if let Some(folder) = result.ok().and_then(|file| file.path()) {
+                    let folder = folder.to_string_lossy().to_string();
+                    unpack_vault_archive(&parent, archive_path, folder, done);
+                }
+            },
+        );
+    });
+}
+
+fn unpack_vault_archive(
+    parent: &adw::Window,
+    archive_path: std::path::PathBuf,
+    folder: String,
+    done: impl Fn(String, crypto::CachedKey) + 'static,
+) {
+    let dialog = adw::AlertDialog::new(
+        Some("Open Vault Archive"),
+        Some("Enter the passphrase of the vault in the archive."),
+    );
+    let fields = gtk::Box::new(gtk::Orientation::Vertical, 8);
+    let entry = gtk::PasswordEntry::builder()
+        .placeholder_text("Passphrase or recovery code")
+        .show_peek_icon(true)
+        .activates_default(true)
+        .build();
+    fields.append(&entry);
+    let (keyfile_row, keyfile_path) = build_keyfile_row(parent, "Choose the vault's key file");
+    keyfile_row.set_visible(archive::archive_needs_keyfile(&archive_path).unwrap_or(false));
+    fields.append(&keyfile_row);
+    dialog.set_extra_child(Some(&fields));
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("open", "Open");
+    dialog.set_response_appearance("open", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("open"));
+    dialog.set_close_response("cancel");
+
+    let parent_c = parent.clone();
+    let done = Rc::new(done);
+    dialog.connect_response(None, move |dlg, response| {
+        let passphrase = entry.text().to_string();
+        entry.set_text("");
+        dlg.set_extra_child(gtk::Widget::NONE);
+        let keyfile_path = keyfile_path.borrow().clone();
+        if response != "open" || (passphrase.is_empty() && keyfile_path.is_none()) {
+            return;
+        }
+        let (tx, rx) = std::sync::mpsc::channel::<Result<crypto::CachedKey, String>>();
+        let archive_path = archive_path.clone();
+        let folder_t = folder.clone();
+        std::thread::spawn(move || {
+            use zeroize::Zeroize;
+            let mut passphrase = passphrase;
+            let result = read_keyfile(keyfile_path).and_then(|keyfile| {
+                archive::extract_archive(&archive_path, &folder_t, &passphrase, keyfile)
+                    .map(|(_, key)| key)
+                    .map_err(|e| e.to_string())
+            });
+            passphrase.zeroize();
+            let _ = tx.send(result);
+        });
+
+        let parent = parent_c.clone();
+        let folder = folder.clone();
+        let done = done.clone();
+        glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+            let result = match rx.try_recv() {
+                Ok(r) => r,
+                Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                Err(_) => return glib::ControlFlow::Break,
+            };
+            match result {
+                Ok(key) => (*done)(folder.clone(), key),
+                Err(e) => {
+                    let alert = adw::AlertDialog::new(Some("Could Not Open Archive"), Some(&e));
+                    alert.add_response("ok", "OK");
+                    alert.set_close_response("ok");
+                    alert.present(Some(&parent));
+                }
+            }
+            glib::ControlFlow::Break
+        });
+    });
+    dialog.present(Some(parent));
+}
+
+/// Open a vault just unpacked from an archive with the key that unpacked it,
+/// rather than asking for the passphrase again.
+fn open_extracted_vault(
+    window: &adw::ApplicationWindow,
+    vault_folder: String,
+    cached_key: crypto::CachedKey,
+) {
+    let data = vault::read_vault_raw(&vault_folder)
+        .map_err(|e| e.to_string())
+        .and_then(|raw| raw.ok_or_else(|| "Vault file not found.".to_string()))
+        .and_then(|raw| crypto::decrypt_vault_fast(&raw, &cached_key).map_err(|e| e.to_string()))
+        .and_then(|json| vault::parse_vault(&json).map_err(|e| e.to_string()));
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Could not open the extracted vault: {e}");
+            show_unlock_vault_dialog(window, vault_folder);
+            return;
+        }
+    };
+    let mut state = vault::vault_to_doc_state(data);
+    if let Err(e) = vault::replay_journal(&vault_folder, &cached_key, &mut state) {
+        eprintln!("Failed to replay the journal: {e}");
+    }
+    state.read_only = read_only_session() || !vault::folder_writable(&vault_folder);
+    // Let the dialogs close before the editor is built, as unlocking does
+    let window = window.clone();
+    glib::timeout_add_local_once(std::time::Duration::from_millis(50), move || {
+        build_editor(&window, state, vault_folder, cached_key);
+    });
+}
+
 pub fn show_create_vault_dialog(window: &adw::ApplicationWindow) {
     let dialog = adw::Window::builder()
         .transient_for(window)
@@ -406,6 +723,10 @@ pub fn show_create_vault_dialog(window: &adw::ApplicationWindow) {
         .build();
     vbox.append(&pass2);
 
+    let (keyfile_row, keyfile_path) =
+        build_keyfile_row(&dialog, "No key file (optional second factor)");
+    vbox.append(&keyfile_row);
+
     let error_label = gtk::Label::new(None);
     error_label.add_css_class("error");
     error_label.set_visible(false);
@@ -465,6 +786,7 @@ pub fn show_create_vault_dialog(window: &adw::ApplicationWindow) {
         let folder_path = folder_path.clone();
         let pass1 = pass1.clone();
         let pass2 = pass2.clone();
+        let keyfile_path = keyfile_path.clone();
         let error_label = error_label.clone();
         let create_btn_inner = create_btn.clone();
         create_btn.connect_clicked(move |_| {
@@ -531,7 +853,7 @@ pub fn show_create_vault_dialog(window: &adw::ApplicationWindow) {
                 }
             };
 
-            // Show spinner while deriving key (PBKDF2 is expensive)
+            // Show spinner while deriving key (Argon2id is expensive)
             create_btn.set_sensitive(false);
             error_label.set_label("Creating vault\u{2026}");
             error_label.remove_css_class("error");
@@ -539,8 +861,22 @@ pub fn show_create_vault_dialog(window: &adw::ApplicationWindow) {
 
             let (tx, rx) = std::sync::mpsc::channel::<Result<crypto::CachedKey, String>>();
             let vault_folder_for_thread = vault_folder.clone();
+            let keyfile_path = keyfile_path.borrow().clone();
             std::thread::spawn(move || {
-                let cached_key = crypto::CachedKey::derive(&p1);
+                let keyfile = match read_keyfile(keyfile_path) {
+                    Ok(k) => k,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                };
+                let cached_key = match crypto::CachedKey::derive_with_keyfile(&p1, keyfile) {
+                    Ok(k) => k,
+                    Err(e) => {
+                        let _ = tx.send(Err(format!("Key derivation failed: {e}")));
+                        return;
+                    }
+                };
                 match crypto::encrypt_vault_fast(&json, &cached_key) {
                     Ok(encrypted) => {
                         if let Err(e) = vault::write_vault_raw(&vault_folder_for_thread, &encrypted)
@@ -630,16 +966,48 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
     vbox.append(&subtitle);
 
     let pass_entry = gtk::PasswordEntry::builder()
-        .placeholder_text("Passphrase")
+        .placeholder_text("Passphrase or recovery code")
         .show_peek_icon(true)
         .build();
     vbox.append(&pass_entry);
 
+    // Only vaults whose header mentions a key file need the key file row.
+    let needs_keyfile = vault::read_vault_raw(&vault_folder)
+        .ok()
+        .flatten()
+        .and_then(|raw| crypto::envelope_info(raw.as_bytes()).ok().flatten())
+        .is_some_and(|info| info.keyfile);
+    let (keyfile_row, keyfile_path) = build_keyfile_row(&dialog, "Choose this vault's key file");
+    keyfile_row.set_visible(needs_keyfile);
+    if needs_keyfile {
+        subtitle.set_label("Enter your passphrase, choose your key file, or both");
+    }
+    vbox.append(&keyfile_row);
+
+    // Shown only for a vault found stored without encryption
+    let confirm_entry = gtk::PasswordEntry::builder()
+        .placeholder_text("Confirm passphrase")
+        .show_peek_icon(true)
+        .visible(false)
+        .build();
+    vbox.append(&confirm_entry);
+
     let error_label = gtk::Label::new(None);
     error_label.add_css_class("error");
+    error_label.set_wrap(true);
     error_label.set_visible(false);
     vbox.append(&error_label);
 
+    let import_btn = gtk::Button::with_label("Import and Encrypt");
+    import_btn.add_css_class("destructive-action");
+    import_btn.add_css_class("pill");
+    import_btn.set_visible(false);
+    vbox.append(&import_btn);
+
+    let read_only_check = gtk::CheckButton::with_label("Open read-only");
+    read_only_check.set_tooltip_text(Some("Browse, search and export without changing the vault"));
+    vbox.append(&read_only_check);
+
     let btn_row = gtk::Box::new(gtk::Orientation::Horizontal, 8);
     let unlock_btn = gtk::Button::with_label("Unlock");
     unlock_btn.add_css_class("suggested-action");
@@ -651,6 +1019,37 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
     btn_row.append(&change_btn);
     vbox.append(&btn_row);
 
+    // Another running instance has the vault open: it can only be read here
+    if let Some(held) = vault::lock_holder(&vault_folder) {
+        let lock_label = gtk::Label::new(Some(&format!(
+            "This vault is open in another instance of Pithos (process {} on {}) since {}. \
+             Unlock it read-only, or close the other instance first.",
+            held.pid,
+            held.hostname,
+            format_ts(held.acquired_at)
+        )));
+        lock_label.add_css_class("dim-label");
+        lock_label.set_wrap(true);
+        vbox.insert_child_after(&lock_label, Some(&subtitle));
+        read_only_check.set_active(true);
+        read_only_check.set_sensitive(false);
+    } else if read_only_session() || !vault::folder_writable(&vault_folder) {
+        read_only_check.set_active(true);
+        read_only_check.set_sensitive(false);
+    }
+    {
+        let unlock_btn = unlock_btn.clone();
+        let set_label = move |check: &gtk::CheckButton| {
+            unlock_btn.set_label(if check.is_active() {
+                "Unlock Read-Only"
+            } else {
+                "Unlock"
+            });
+        };
+        set_label(&read_only_check);
+        read_only_check.connect_toggled(set_label);
+    }
+
     let toolbar = adw::HeaderBar::new();
     let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
     content.append(&toolbar);
@@ -666,30 +1065,68 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
         let vault_folder = vault_folder.clone();
         let pass_entry_for_activate = pass_entry.clone();
         let pass_entry = pass_entry.clone();
+        let keyfile_path = keyfile_path.clone();
+        let subtitle = subtitle.clone();
+        let confirm_entry = confirm_entry.clone();
         let error_label = error_label.clone();
+        let import_btn_for_connect = import_btn.clone();
+        let confirm_entry_for_activate = confirm_entry.clone();
+        let import_btn = import_btn.clone();
         let unlock_btn = unlock_btn.clone();
         let unlock_btn_for_connect = unlock_btn.clone();
+        let read_only_check = read_only_check.clone();
         let do_unlock = move || {
             let passphrase = pass_entry.text().to_string();
+            let confirmation = confirm_entry.text().to_string();
             pass_entry.set_text(""); // clear passphrase from UI immediately
-            if passphrase.is_empty() {
+            confirm_entry.set_text("");
+            let keyfile_path = keyfile_path.borrow().clone();
+            if passphrase.is_empty() && keyfile_path.is_none() {
                 error_label.set_label("Please enter your passphrase");
                 error_label.set_visible(true);
                 return;
             }
+            let import = import_btn.is_visible();
+            let read_only = read_only_check.is_active();
+            if import && read_only {
+                error_label.add_css_class("error");
+                error_label
+                    .set_label("A vault stored without encryption can't be opened read-only");
+                error_label.set_visible(true);
+                return;
+            }
+            if import && passphrase != confirmation {
+                error_label.add_css_class("error");
+                error_label.set_label("Passphrases do not match");
+                error_label.set_visible(true);
+                return;
+            }
 
             let vault_folder_thread = vault_folder.clone();
 
-            // Show spinner while decrypting (PBKDF2 is expensive)
+            // Show spinner while decrypting (key derivation is expensive)
             unlock_btn.set_sensitive(false);
+            import_btn.set_sensitive(false);
             pass_entry.set_sensitive(false);
-            error_label.set_label("Unlocking\u{2026}");
+            error_label.set_label(if import {
+                "Encrypting\u{2026}"
+            } else {
+                "Unlocking\u{2026}"
+            });
             error_label.remove_css_class("error");
             error_label.set_visible(true);
 
             let (tx, rx) =
                 std::sync::mpsc::channel::<Result<(String, crypto::CachedKey), String>>();
             std::thread::spawn(move || {
+                if import {
+                    let result = read_keyfile(keyfile_path).and_then(|keyfile| {
+                        vault::import_unencrypted_vault(&vault_folder_thread, &passphrase, keyfile)
+                            .map_err(|e| format!("Import failed: {e}"))
+                    });
+                    let _ = tx.send(result);
+                    return;
+                }
                 let raw = match vault::read_vault_raw(&vault_folder_thread) {
                     Ok(Some(data)) => data,
                     Ok(None) => {
@@ -702,10 +1139,24 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
                     }
                 };
 
-                match crypto::decrypt_vault_returning_key(&raw, &passphrase) {
+                let keyfile = match read_keyfile(keyfile_path) {
+                    Ok(k) => k,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                match crypto::decrypt_vault_with_keyfile(&raw, &passphrase, keyfile) {
                     Ok((json, cached_key)) => {
                         let _ = tx.send(Ok((json, cached_key)));
                     }
+                    Err(e @ crypto::CryptoError::KeyFileRequired) => {
+                        let _ = tx.send(Err(e.to_string()));
+                    }
+                    Err(crypto::CryptoError::Unencrypted(_)) => {
+                        let _ = tx.send(Err("unencrypted".to_string()));
+                    }
                     Err(_) => {
                         let _ = tx.send(Err("wrong_passphrase".to_string()));
                     }
@@ -715,7 +1166,10 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
             let window = window.clone();
             let dialog = dialog.clone();
             let vault_folder = vault_folder.clone();
+            let subtitle = subtitle.clone();
+            let confirm_entry = confirm_entry.clone();
             let error_label = error_label.clone();
+            let import_btn = import_btn.clone();
             let pass_entry = pass_entry.clone();
             let unlock_btn = unlock_btn.clone();
             glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
@@ -728,17 +1182,35 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
                 };
                 match result {
                     Ok((json, cached_key)) => {
-                        let vault_data: vault::VaultData = match serde_json::from_str(&json) {
+                        let json = match write_held_save(&vault_folder, &cached_key) {
+                            Ok(written) => written.unwrap_or(json),
+                            Err(e) => {
+                                error_label.add_css_class("error");
+                                error_label.set_label(&e);
+                                unlock_btn.set_sensitive(true);
+                                import_btn.set_sensitive(true);
+                                pass_entry.set_sensitive(true);
+                                return glib::ControlFlow::Break;
+                            }
+                        };
+                        let vault_data = match vault::parse_vault(&json) {
                             Ok(v) => v,
                             Err(e) => {
                                 error_label.add_css_class("error");
-                                error_label.set_label(&format!("Corrupt vault: {e}"));
+                                error_label.set_label(&e.to_string());
                                 unlock_btn.set_sensitive(true);
+                                import_btn.set_sensitive(true);
                                 pass_entry.set_sensitive(true);
                                 return glib::ControlFlow::Break;
                             }
                         };
-                        let state = vault::vault_to_doc_state(vault_data);
+                        let mut state = vault::vault_to_doc_state(vault_data);
+                        if let Err(e) =
+                            vault::replay_journal(&vault_folder, &cached_key, &mut state)
+                        {
+                            eprintln!("Failed to replay the journal: {e}");
+                        }
+                        state.read_only = read_only || vault::lock_holder(&vault_folder).is_some();
                         gtk::prelude::GtkWindowExt::set_focus(&window, gtk::Widget::NONE);
                         transition_close(&dialog);
                         // Defer editor build so the dialog is fully destroyed and
@@ -756,13 +1228,31 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
                     Err(e) => {
                         error_label.add_css_class("error");
                         if e == "wrong_passphrase" {
-                            error_label.set_label("Wrong passphrase, try again");
+                            error_label.set_label(if needs_keyfile {
+                                "Wrong passphrase or key file, try again"
+                            } else {
+                                "Wrong passphrase, try again"
+                            });
                             pass_entry.set_text("");
                             pass_entry.grab_focus();
+                        } else if e == "unencrypted" {
+                            // Plaintext could have been planted by anyone with
+                            // access to the folder, so never load it silently.
+                            subtitle.set_label(
+                                "This vault is stored without encryption. Import it only if \
+                                 you trust where it came from; it will be encrypted with the \
+                                 passphrase you choose.",
+                            );
+                            error_label.set_label("Vault is not encrypted");
+                            confirm_entry.set_visible(true);
+                            import_btn.set_visible(true);
+                            unlock_btn.set_visible(false);
+                            pass_entry.grab_focus();
                         } else {
                             error_label.set_label(&e);
                         }
                         unlock_btn.set_sensitive(true);
+                        import_btn.set_sensitive(true);
                         pass_entry.set_sensitive(true);
                     }
                 }
@@ -773,6 +1263,12 @@ pub fn show_unlock_vault_dialog(window: &adw::ApplicationWindow, vault_folder: S
         let do_unlock_click = do_unlock.clone();
         unlock_btn_for_connect.connect_clicked(move |_| do_unlock_click());
 
+        let do_unlock_import = do_unlock.clone();
+        import_btn_for_connect.connect_clicked(move |_| do_unlock_import());
+
+        let do_unlock_confirm = do_unlock.clone();
+        confirm_entry_for_activate.connect_activate(move |_| do_unlock_confirm());
+
         let do_unlock_enter = do_unlock;
         pass_entry_for_activate.connect_activate(move |_| do_unlock_enter());
     }
@@ -827,6 +1323,11 @@ pub fn build_command_entries() -> Vec<CommandEntry> {
             accel: "Ctrl+O".into(),
             action_name: "win.import-file".into(),
         },
+        CommandEntry {
+            label: "Import Shared Bundle\u{2026}".into(),
+            accel: "".into(),
+            action_name: "win.import-bundle".into(),
+        },
         CommandEntry {
             label: "Rename Note".into(),
             accel: "F2".into(),
@@ -887,6 +1388,11 @@ pub fn build_command_entries() -> Vec<CommandEntry> {
             accel: "".into(),
             action_name: "win.new-folder".into(),
         },
+        CommandEntry {
+            label: "New Smart Folder\u{2026}".into(),
+            accel: "".into(),
+            action_name: "win.new-smart-folder".into(),
+        },
         CommandEntry {
             label: "New from Template\u{2026}".into(),
             accel: "".into(),
@@ -1007,6 +1513,36 @@ pub fn build_command_entries() -> Vec<CommandEntry> {
             accel: "".into(),
             action_name: "win.change-passphrase".into(),
         },
+        CommandEntry {
+            label: "Unlock Methods\u{2026}".into(),
+            accel: "".into(),
+            action_name: "win.unlock-methods".into(),
+        },
+        CommandEntry {
+            label: "Upgrade Vault Encryption".into(),
+            accel: "".into(),
+            action_name: "win.upgrade-encryption".into(),
+        },
+        CommandEntry {
+            label: "Encryption Audit\u{2026}".into(),
+            accel: "".into(),
+            action_name: "win.audit-encryption".into(),
+        },
+        CommandEntry {
+            label: "Verify Assets".into(),
+            accel: "".into(),
+            action_name: "win.verify-assets".into(),
+        },
+        CommandEntry {
+            label: "Check Vault Integrity".into(),
+            accel: "".into(),
+            action_name: "win.check-vault".into(),
+        },
+        CommandEntry {
+            label: "Backups\u{2026}".into(),
+            accel: "".into(),
+            action_name: "win.backups".into(),
+        },
         CommandEntry {
             label: "Find\u{2026}".into(),
             accel: "Ctrl+F".into(),
@@ -1047,6 +1583,11 @@ pub fn build_command_entries() -> Vec<CommandEntry> {
             accel: "".into(),
             action_name: "win.toggle-spellcheck".into(),
         },
+        CommandEntry {
+            label: "Export Vault Archive\u{2026}".into(),
+            accel: "".into(),
+            action_name: "win.export-archive".into(),
+        },
         CommandEntry {
             label: "Open Vault\u{2026}".into(),
             accel: "".into(),
@@ -1075,6 +1616,49 @@ pub fn build_command_entries() -> Vec<CommandEntry> {
     ]
 }
 
+/// Most notes the command palette lists below the commands.
+const PALETTE_NOTE_RESULTS: usize = 8;
+/// Widget name prefix of palette rows that open a note rather than run an action.
+const PALETTE_NOTE_PREFIX: &str = "note:";
+
+fn palette_row(label: &str, detail: &str, name: &str) -> gtk::ListBoxRow {
+    let row = gtk::ListBoxRow::new();
+    let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    row_box.set_margin_start(12);
+    row_box.set_margin_end(12);
+    row_box.set_margin_top(6);
+    row_box.set_margin_bottom(6);
+
+    let label = gtk::Label::new(Some(label));
+    label.set_xalign(0.0);
+    label.set_hexpand(true);
+    label.set_ellipsize(gtk::pango::EllipsizeMode::End);
+    row_box.append(&label);
+
+    if !detail.is_empty() {
+        let detail_label = gtk::Label::new(Some(detail));
+        detail_label.add_css_class("dim-label");
+        detail_label.add_css_class("caption");
+        row_box.append(&detail_label);
+    }
+
+    row.set_child(Some(&row_box));
+    row.set_widget_name(name);
+    row
+}
+
+/// Run the action of a palette row, or open its note.
+fn activate_palette_row(ctx: &EditorCtx, dialog: &adw::Window, name: &str) {
+    if name.is_empty() {
+        return;
+    }
+    dialog.close();
+    match name.strip_prefix(PALETTE_NOTE_PREFIX) {
+        Some(note_id) => switch_to_note(ctx, note_id),
+        None => activate_action_by_name(&ctx.window, name),
+    }
+}
+
 pub fn show_command_palette(ctx: &EditorCtx) {
     let entries = build_command_entries();
 
@@ -1093,7 +1677,7 @@ pub fn show_command_palette(ctx: &EditorCtx) {
     vbox.append(&header);
 
     let search_entry = gtk::SearchEntry::new();
-    search_entry.set_placeholder_text(Some("Type a command\u{2026}"));
+    search_entry.set_placeholder_text(Some("Type a command or search notes\u{2026}"));
     search_entry.set_margin_start(12);
     search_entry.set_margin_end(12);
     search_entry.set_margin_bottom(8);
@@ -1104,28 +1688,7 @@ pub fn show_command_palette(ctx: &EditorCtx) {
     list_box.add_css_class("navigation-sidebar");
 
     for entry in &entries {
-        let row = gtk::ListBoxRow::new();
-        let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
-        row_box.set_margin_start(12);
-        row_box.set_margin_end(12);
-        row_box.set_margin_top(6);
-        row_box.set_margin_bottom(6);
-
-        let label = gtk::Label::new(Some(&entry.label));
-        label.set_xalign(0.0);
-        label.set_hexpand(true);
-        row_box.append(&label);
-
-        if !entry.accel.is_empty() {
-            let accel_label = gtk::Label::new(Some(&entry.accel));
-            accel_label.add_css_class("dim-label");
-            accel_label.add_css_class("caption");
-            row_box.append(&accel_label);
-        }
-
-        row.set_child(Some(&row_box));
-        row.set_widget_name(&entry.action_name);
-        list_box.append(&row);
+        list_box.append(&palette_row(&entry.label, &entry.accel, &entry.action_name));
     }
 
     let scroll = gtk::ScrolledWindow::builder()
@@ -1137,12 +1700,31 @@ pub fn show_command_palette(ctx: &EditorCtx) {
 
     dialog.set_content(Some(&vbox));
 
-    // Filter rows on search
+    // Filter rows on search, and list the best matching notes below them
     let list_ref = list_box.clone();
+    let ctx_search = ctx.clone();
+    let command_rows = entries.len() as i32;
     search_entry.connect_search_changed(move |entry| {
         let query = entry.text().to_string().to_lowercase();
+        while let Some(row) = list_ref.row_at_index(command_rows) {
+            list_ref.remove(&row);
+        }
+        let note_query = Query::parse(&entry.text()).ok();
+        if let Some(note_query) = note_query.filter(|q| !q.is_empty()) {
+            let hits = search_notes(&ctx_search, &note_query);
+            let state = ctx_search.state.borrow();
+            for hit in hits.iter().take(PALETTE_NOTE_RESULTS) {
+                if let Some(index) = find_note_index(&state.notes, &hit.id) {
+                    let name = format!("{PALETTE_NOTE_PREFIX}{}", hit.id);
+                    list_ref.append(&palette_row(&state.notes[index].name, "Note", &name));
+                }
+            }
+        }
         let mut idx = 0;
         while let Some(row) = list_ref.row_at_index(idx) {
+            if row.widget_name().starts_with(PALETTE_NOTE_PREFIX) {
+                break;
+            }
             let matches = if query.is_empty() {
                 true
             } else if let Some(child) = row.child() {
@@ -1178,30 +1760,22 @@ pub fn show_command_palette(ctx: &EditorCtx) {
 
     // Activate on Enter
     {
+        let ctx = ctx.clone();
         let dialog_ref = dialog.clone();
-        let window_ref = ctx.window.clone();
         let list_ref = list_box.clone();
         search_entry.connect_activate(move |_| {
             if let Some(row) = list_ref.selected_row() {
-                let action_name = row.widget_name();
-                if !action_name.is_empty() {
-                    dialog_ref.close();
-                    activate_action_by_name(&window_ref, &action_name);
-                }
+                activate_palette_row(&ctx, &dialog_ref, &row.widget_name());
             }
         });
     }
 
     // Activate on row click
     {
+        let ctx = ctx.clone();
         let dialog_ref = dialog.clone();
-        let window_ref = ctx.window.clone();
         list_box.connect_row_activated(move |_, row| {
-            let action_name = row.widget_name();
-            if !action_name.is_empty() {
-                dialog_ref.close();
-                activate_action_by_name(&window_ref, &action_name);
-            }
+            activate_palette_row(&ctx, &dialog_ref, &row.widget_name());
         });
     }
 
@@ -1273,7 +1847,7 @@ pub fn activate_action_by_name(window: &adw::ApplicationWindow, action_name: &st
 // ---------------------------------------------------------------------------
 
 pub fn show_history_dialog(ctx: &EditorCtx) {
-    let (versions, note_name) = {
+    let (versions, note_name, read_only) = {
         let state = ctx.state.borrow();
         let name = find_note_index(&state.notes, &state.active_note_id)
             .map(|i| state.notes[i].name.clone())
@@ -1281,7 +1855,7 @@ pub fn show_history_dialog(ctx: &EditorCtx) {
         let versions = find_note_index(&state.notes, &state.active_note_id)
             .map(|index| state.notes[index].versions.clone())
             .unwrap_or_default();
-        (versions, name)
+        (versions, name, state.read_only)
     };
 
     if versions.is_empty() {
@@ -1317,6 +1891,7 @@ pub fn show_history_dialog(ctx: &EditorCtx) {
                 format!("Restore {ts}")
             };
             dialog.add_response(&response, &label);
+            dialog.set_response_enabled(&response, !read_only);
             if display_idx == 0 {
                 dialog.set_response_appearance(&response, adw::ResponseAppearance::Suggested);
             }
@@ -1336,15 +1911,134 @@ pub fn show_history_dialog(ctx: &EditorCtx) {
     }
 }
 
-pub fn rename_note_dialog(ctx: &EditorCtx) {
-    let current_name = {
+/// Show a snapshot or trashed note that a history or trash search found,
+/// scrolled to the first match of the search, with a button to restore it.
+pub fn show_archived_text(ctx: &EditorCtx, source: &ArchivedId) {
+    let Some(text) = archived_text(ctx, source) else {
+        send_toast(ctx, "It is no longer in the vault");
+        return;
+    };
+    let (query, folders, read_only) = {
         let state = ctx.state.borrow();
-        find_note_index(&state.notes, &state.active_note_id)
-            .map(|i| state.notes[i].name.clone())
-            .unwrap_or_else(|| "Untitled".to_string())
+        (
+            Query::parse(&state.search_query).ok(),
+            state.folders.clone(),
+            state.read_only,
+        )
+    };
+    let ranges = query
+        .map(|query| {
+            let (_, highlighter) = search_archived(&query, std::slice::from_ref(&text), &folders);
+            highlighter.ranges(text.content())
+        })
+        .unwrap_or_default();
+
+    let (title, restore_label) = match source {
+        ArchivedId::Version { .. } => (
+            format!("{} \u{2014} Snapshot {}", text.name(), format_ts(text.ts())),
+            "Restore This Version",
+        ),
+        ArchivedId::Trash(_) => (
+            format!("{} \u{2014} Deleted {}", text.name(), format_ts(text.ts())),
+            "Restore Note",
+        ),
     };
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title(title.as_str())
+        .default_width(640)
+        .default_height(560)
+        .build();
 
-    let dialog = adw::AlertDialog::new(Some("Rename Note"), Some("Enter a new name for this note"));
+    let text_view = gtk::TextView::new();
+    text_view.set_editable(false);
+    text_view.set_cursor_visible(false);
+    text_view.set_monospace(true);
+    text_view.set_wrap_mode(gtk::WrapMode::WordChar);
+    text_view.set_top_margin(8);
+    text_view.set_bottom_margin(8);
+    text_view.set_left_margin(8);
+    text_view.set_right_margin(8);
+    let buffer = text_view.buffer();
+    buffer.create_tag(
+        Some("match"),
+        &[("background", &"rgba(229, 165, 10, 0.35)")],
+    );
+    let content = text.content();
+    buffer.set_text(content);
+    // Byte ranges to the character offsets the buffer counts in
+    let offset = |byte: usize| content[..byte].chars().count() as i32;
+    for range in &ranges {
+        let start = buffer.iter_at_offset(offset(range.start));
+        let end = buffer.iter_at_offset(offset(range.end));
+        buffer.apply_tag_by_name("match", &start, &end);
+    }
+
+    let scroll = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .vexpand(true)
+        .child(&text_view)
+        .build();
+    let restore_btn = gtk::Button::with_label(restore_label);
+    restore_btn.add_css_class("suggested-action");
+    restore_btn.set_sensitive(!read_only);
+    let toolbar = adw::HeaderBar::new();
+    toolbar.pack_end(&restore_btn);
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    vbox.append(&toolbar);
+    vbox.append(&scroll);
+    dialog.set_content(Some(&vbox));
+
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        let source = source.clone();
+        let ts = text.ts();
+        restore_btn.connect_clicked(move |_| {
+            dialog.close();
+            match &source {
+                ArchivedId::Version { note_id, index } => {
+                    if !ctx.state.borrow().notes.iter().any(|n| n.id == *note_id) {
+                        send_toast(&ctx, "The note is no longer in the vault");
+                        return;
+                    }
+                    switch_to_note(&ctx, note_id);
+                    // Snapshots saved or dropped since the search move the index
+                    let unchanged = {
+                        let state = ctx.state.borrow();
+                        find_note_index(&state.notes, note_id)
+                            .and_then(|i| state.notes[i].versions.get(*index))
+                            .is_some_and(|version| version.ts == ts)
+                    };
+                    if unchanged {
+                        restore_snapshot(&ctx, *index);
+                        trigger_vault_save(&ctx);
+                    } else {
+                        send_toast(&ctx, "The snapshot has changed; search again");
+                    }
+                }
+                ArchivedId::Trash(trash_id) => restore_from_trash(&ctx, trash_id),
+            }
+        });
+    }
+
+    dialog.present();
+    if let Some(first) = ranges.first() {
+        let mark = buffer.create_mark(None, &buffer.iter_at_offset(offset(first.start)), true);
+        text_view.scroll_to_mark(&mark, 0.1, true, 0.0, 0.3);
+    }
+}
+
+pub fn rename_note_dialog(ctx: &EditorCtx) {
+    let current_name = {
+        let state = ctx.state.borrow();
+        find_note_index(&state.notes, &state.active_note_id)
+            .map(|i| state.notes[i].name.clone())
+            .unwrap_or_else(|| "Untitled".to_string())
+    };
+
+    let dialog = adw::AlertDialog::new(Some("Rename Note"), Some("Enter a new name for this note"));
 
     let entry = gtk::Entry::new();
     entry.set_text(&current_name);
@@ -1405,7 +2099,7 @@ pub fn show_change_passphrase_dialog(ctx: &EditorCtx) {
         .modal(true)
         .title("Change Passphrase")
         .default_width(420)
-        .default_height(360)
+        .default_height(420)
         .build();
 
     let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
@@ -1450,6 +2144,27 @@ pub fn show_change_passphrase_dialog(ctx: &EditorCtx) {
         .build();
     vbox.append(&new_pass2);
 
+    // Key file: keep the current one unless a different file is chosen.
+    let has_keyfile = ctx
+        .cached_key
+        .borrow()
+        .as_ref()
+        .is_some_and(|k| k.passphrase_uses_keyfile());
+    let keyfile_check = gtk::CheckButton::with_label("Require a key file to unlock");
+    keyfile_check.set_active(has_keyfile);
+    vbox.append(&keyfile_check);
+    let placeholder = if has_keyfile {
+        "Keep current key file"
+    } else {
+        "Choose or create a key file"
+    };
+    let (keyfile_row, keyfile_path) = build_keyfile_row(&dialog, placeholder);
+    keyfile_row.set_sensitive(has_keyfile);
+    keyfile_check
+        .bind_property("active", &keyfile_row, "sensitive")
+        .build();
+    vbox.append(&keyfile_row);
+
     let error_label = gtk::Label::new(None);
     error_label.add_css_class("error");
     error_label.set_visible(false);
@@ -1499,8 +2214,28 @@ pub fn show_change_passphrase_dialog(ctx: &EditorCtx) {
                 return;
             }
 
-            // Collect asset IDs to re-encrypt
-            let asset_ids: Vec<String> = ctx.state.borrow().assets.keys().cloned().collect();
+            let current_keyfile = ctx
+                .cached_key
+                .borrow()
+                .as_ref()
+                .and_then(|k| k.keyfile().cloned());
+            let new_keyfile_path = keyfile_path.borrow().clone();
+            let require_keyfile = keyfile_check.is_active();
+            if require_keyfile && new_keyfile_path.is_none() && current_keyfile.is_none() {
+                error_label.set_label("Choose or create a key file");
+                error_label.set_visible(true);
+                return;
+            }
+
+            // Collect asset IDs to re-encrypt; locked folders keep their own keys
+            let asset_ids: Vec<String> = ctx
+                .state
+                .borrow()
+                .assets
+                .iter()
+                .filter(|(_, meta)| meta.stored_in.is_none())
+                .map(|(id, _)| id.clone())
+                .collect();
 
             change_btn.set_sensitive(false);
             error_label.set_label("Changing passphrase\u{2026}");
@@ -1523,7 +2258,8 @@ pub fn show_change_passphrase_dialog(ctx: &EditorCtx) {
             }
             ctx.saving.set(true);
 
-            let (tx, rx) = std::sync::mpsc::channel::<Result<crypto::CachedKey, String>>();
+            let (tx, rx) =
+                std::sync::mpsc::channel::<Result<(crypto::CachedKey, Option<String>), String>>();
             let vault_folder_t = vault_folder.clone();
             std::thread::spawn(move || {
                 use zeroize::Zeroize;
@@ -1531,53 +2267,44 @@ pub fn show_change_passphrase_dialog(ctx: &EditorCtx) {
                 let mut p1 = p1;
 
                 // Inner closure does the real work; passphrases are zeroized after.
-                let result = (|| -> Result<crypto::CachedKey, String> {
+                let result = (|| -> Result<(crypto::CachedKey, Option<String>), String> {
                     // 1) Read and verify current passphrase (also gets plaintext + old key)
                     let raw = vault::read_vault_raw(&vault_folder_t)
                         .map_err(|e| format!("Read error: {e}"))?
                         .ok_or_else(|| "Vault file not found.".to_string())?;
-                    let (plaintext, old_key) = crypto::decrypt_vault_returning_key(&raw, &old_pass)
-                        .map_err(|_| "Wrong current passphrase.".to_string())?;
-
-                    // 2) Derive new key
-                    let new_key = crypto::CachedKey::derive(&p1);
-
-                    // 3) Re-encrypt vault with new key
-                    let reencrypted = crypto::encrypt_vault_fast(&plaintext, &new_key)
-                        .map_err(|e| format!("Encryption failed: {e}"))?;
-
-                    // 4) Re-encrypt ALL assets to memory first (transactional).
-                    //    Only write to disk if every asset succeeds.
-                    let assets_path = vault::assets_dir(&vault_folder_t);
-                    let mut reencrypted_assets: Vec<(String, Vec<u8>)> = Vec::new();
-                    for asset_id in &asset_ids {
-                        if !vault::is_valid_asset_id(asset_id) {
-                            return Err(format!("Invalid asset ID: {asset_id}"));
-                        }
-                        let path = assets_path.join(asset_id);
-                        let data = std::fs::read(&path)
-                            .map_err(|e| format!("Failed to read asset {asset_id}: {e}"))?;
-                        let decrypted = crypto::decrypt_asset(&data, &old_key)
-                            .map_err(|e| format!("Failed to decrypt asset {asset_id}: {e}"))?;
-                        let encrypted_str = crypto::encrypt_asset(&decrypted, &new_key)
-                            .map_err(|e| format!("Failed to re-encrypt asset {asset_id}: {e}"))?;
-                        reencrypted_assets.push((asset_id.clone(), encrypted_str.into_bytes()));
-                    }
+                    let (plaintext, old_key) = crypto::decrypt_vault_with_keyfile(
+                        &raw,
+                        &old_pass,
+                        current_keyfile.clone(),
+                    )
+                    .map_err(|_| "Wrong current passphrase.".to_string())?;
 
-                    // 5) Write new vault FIRST — if this fails, no assets
-                    //    have been touched and everything stays on the old key.
-                    vault::write_vault_raw(&vault_folder_t, &reencrypted)
-                        .map_err(|e| format!("Write failed: {e}"))?;
-
-                    // 6) Commit re-encrypted assets to disk.
-                    //    The vault is already on the new key, so if an asset
-                    //    write fails the user can retry with the new passphrase.
-                    for (asset_id, data) in &reencrypted_assets {
-                        vault::write_asset(&vault_folder_t, asset_id, data)
-                            .map_err(|e| format!("Failed to write asset {asset_id}: {e}"))?;
+                    // 2) Derive new key (with the new, current, or no key file)
+                    let new_keyfile = if !require_keyfile {
+                        None
+                    } else if new_keyfile_path.is_some() {
+                        read_keyfile(new_keyfile_path)?
+                    } else {
+                        current_keyfile
+                    };
+                    // Vaults with key slots keep their data key and only re-wrap
+                    // the passphrase slot; older vaults get a new data key.
+                    let new_key = if old_key.uses_key_slots() {
+                        let mut new_key = old_key.clone();
+                        new_key.set_passphrase(&p1, new_keyfile).map(|()| new_key)
+                    } else {
+                        crypto::CachedKey::derive_with_keyfile(&p1, new_keyfile)
                     }
+                    .map_err(|e| format!("Key derivation failed: {e}"))?;
+
+                    // 3) Re-encrypt the vault (and, for a new data key, all assets
+                    //    in memory), then write the vault first and the assets after it.
+                    vault::rekey_vault(&vault_folder_t, &plaintext, &old_key, &new_key, &asset_ids)
+                        .map_err(|e| e.to_string())?;
 
-                    Ok(new_key)
+                    // 4) Move the backups too, so the old passphrase stops opening them.
+                    let backups = vault::rekey_backups(&vault_folder_t, &old_key, &new_key).err();
+                    Ok((new_key, backups.map(|e| e.to_string())))
                 })();
 
                 // Zeroize passphrase strings regardless of success/failure.
@@ -1602,10 +2329,13 @@ pub fn show_change_passphrase_dialog(ctx: &EditorCtx) {
                 // Re-enable autosave now that re-encryption is done.
                 ctx.saving.set(false);
                 match result {
-                    Ok(new_key) => {
+                    Ok((new_key, backups_error)) => {
                         *ctx.cached_key.borrow_mut() = Some(new_key);
                         send_toast(&ctx, "Passphrase changed successfully");
                         dialog.close();
+                        if let Some(e) = backups_error {
+                            show_backups_not_rekeyed(&ctx, &e);
+                        }
                     }
                     Err(e) => {
                         error_label.add_css_class("error");
@@ -1622,91 +2352,2489 @@ pub fn show_change_passphrase_dialog(ctx: &EditorCtx) {
 }
 
 // ---------------------------------------------------------------------------
-// Vault switching dialogs
+// Encryption format upgrade
 // ---------------------------------------------------------------------------
 
-pub fn show_open_vault_dialog(ctx: &EditorCtx) {
-    let chooser = gtk::FileDialog::builder()
-        .title("Open Vault Folder")
-        .accept_label("Open")
-        .build();
+/// True when `audit` shows something the upgrade would change, see
+/// [`vault::should_offer_upgrade`].
+pub fn upgrade_applies(ctx: &EditorCtx, audit: &vault::EncryptionAudit) -> bool {
+    ctx.cached_key
+        .borrow()
+        .as_ref()
+        .is_some_and(|key| vault::should_offer_upgrade(&ctx.state.borrow(), key, audit))
+}
+
+/// After unlocking a vault sealed with an older KDF or envelope format, or
+/// with note bodies or assets in one, offer to re-encrypt it in place.
+/// Declining leaves the vault readable as-is.
+pub fn offer_encryption_upgrade(ctx: &EditorCtx) {
+    if ctx.state.borrow().read_only {
+        return;
+    }
+    with_encryption_audit(ctx, |ctx, audit| {
+        if upgrade_applies(ctx, &audit) {
+            present_upgrade_offer(ctx);
+        }
+    });
+}
+
+fn present_upgrade_offer(ctx: &EditorCtx) {
+    let dialog = adw::AlertDialog::new(
+        Some("Upgrade Vault Encryption?"),
+        Some(
+            "This vault uses an older encryption format. Upgrading re-encrypts \
+             the vault and its attachments with Argon2id and a wrapped data key, \
+             which also enables recovery codes. Older versions of Pithos will not \
+             be able to open it afterwards.",
+        ),
+    );
+    dialog.add_response("later", "Later");
+    dialog.add_response("upgrade", "Upgrade");
+    dialog.set_response_appearance("upgrade", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("upgrade"));
+    dialog.set_close_response("later");
+
+    let ctx_inner = ctx.clone();
+    dialog.connect_response(None, move |_, response| {
+        if response == "upgrade" {
+            upgrade_vault_encryption(&ctx_inner);
+        }
+    });
+    dialog.present(Some(&ctx.window));
+}
+
+/// Re-encrypt the open vault (and its assets, if the data key changes) under a
+/// key produced by `make_key` on a worker thread, then swap it into the session.
+/// With `upgrade_blobs`, note bodies and assets still in an older format are
+/// re-encrypted too. Uses the same save guard as the passphrase change so
+/// autosave can't race it.
+fn replace_vault_key<T, F, G>(
+    ctx: &EditorCtx,
+    busy: &str,
+    failure_title: &'static str,
+    upgrade_blobs: bool,
+    make_key: F,
+    on_done: G,
+) where
+    T: Send + 'static,
+    F: FnOnce(&crypto::CachedKey) -> Result<(crypto::CachedKey, T), String> + Send + 'static,
+    G: FnOnce(&EditorCtx, T) + 'static,
+{
+    // Another window may be writing this vault; a rekey would clobber it.
+    if ctx.state.borrow().read_only {
+        return;
+    }
+    if ctx.saving.get() {
+        send_toast(ctx, "A save is in progress — please try again in a moment");
+        return;
+    }
+    let snapshot = current_markdown(ctx);
+    let save = match prepare_vault_save(ctx, true) {
+        Ok(save) => save,
+        Err(e) => {
+            show_error(&ctx.window, failure_title, &e);
+            return;
+        }
+    };
+    let marks = save.marks.clone();
+    // Assets in locked folders are under their folder's key and stay as they are.
+    let asset_ids: Vec<String> = save.vault_data.assets.keys().cloned().collect();
+
+    if let Some(source_id) = ctx.save_timeout_id.take() {
+        source_id.remove();
+    }
+    ctx.saving.set(true);
+    send_toast(ctx, busy);
+
+    type Rekeyed<T> = (crypto::CachedKey, T, Option<String>);
+    let (tx, rx) = std::sync::mpsc::channel::<Result<Rekeyed<T>, String>>();
+    std::thread::spawn(move || {
+        use zeroize::Zeroize;
+        let result = (|| -> Result<Rekeyed<T>, String> {
+            let (new_key, extra) = make_key(&save.key)?;
+            // Always keep a restore point; it moves to the new key with the other backups.
+            vault::create_backup(&save.vault_folder, unix_now())
+                .map_err(|e| format!("Backup: {e}"))?;
+            let mut json = serde_json::to_string_pretty(&save.vault_data)
+                .map_err(|e| format!("Serialization: {e}"))?;
+            for sub in &save.sub_vaults {
+                vault::write_sub_vault(&save.vault_folder, sub).map_err(|e| e.to_string())?;
+            }
+            // Flush in-memory bodies under the old key so the rekey picks them up.
+            let result = vault::write_note_bodies(
+                &save.vault_folder,
+                &save.vault_data.vault_id,
+                &save.bodies,
+                &save.key,
+            )
+            .and_then(|()| {
+                vault::rekey_vault(&save.vault_folder, &json, &save.key, &new_key, &asset_ids)
+            });
+            json.zeroize();
+            result.map_err(|e| e.to_string())?;
+            if upgrade_blobs {
+                vault::upgrade_outdated_blobs(&save.vault_folder, &new_key)
+                    .map_err(|e| e.to_string())?;
+            }
+            for path in &save.stale_files {
+                let _ = std::fs::remove_file(path);
+            }
+            let backups = vault::rekey_backups(&save.vault_folder, &save.key, &new_key).err();
+            Ok((new_key, extra, backups.map(|e| e.to_string())))
+        })();
+        let _ = tx.send(result);
+    });
 
     let ctx = ctx.clone();
-    let window = ctx.window.clone();
-    chooser.select_folder(Some(&window), gtk::gio::Cancellable::NONE, move |result| {
+    let mut on_done = Some(on_done);
+    let mut marks = Some(marks);
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => {
+                ctx.saving.set(false);
+                return glib::ControlFlow::Break;
+            }
+        };
+        ctx.saving.set(false);
         match result {
-            Ok(file) => {
-                if let Some(path) = file.path() {
-                    let folder = path.to_string_lossy().to_string();
-                    if vault::vault_file_path(&folder).exists() {
-                        // Save current vault, lock, switch
-                        if !perform_vault_save_sync(&ctx) {
-                            return;
-                        }
-                        stop_background_tasks(&ctx);
-                        *ctx.cached_key.borrow_mut() = None;
-                        {
-                            let mut state = ctx.state.borrow_mut();
-                            state.suppress_sync = true;
-                        }
-                        ctx.source_buffer.set_text("");
-                        save_config_or_log(&vault::AppConfig {
-                            vault_path: Some(folder.clone()),
-                            ..Default::default()
-                        });
-                        ctx.window.set_content(gtk::Widget::NONE);
-                        show_unlock_vault_dialog(&ctx.window, folder);
-                    } else {
-                        show_error(
-                            &ctx.window,
-                            "No Vault Found",
-                            "The selected folder does not contain a vault file",
-                        );
+            Ok((new_key, extra, backups_error)) => {
+                *ctx.cached_key.borrow_mut() = Some(new_key);
+                let current = current_markdown(&ctx);
+                {
+                    let mut state = ctx.state.borrow_mut();
+                    state.saved_snapshot = snapshot.clone();
+                    if current == snapshot {
+                        state.dirty = false;
+                    }
+                    if let Some(mut marks) = marks.take() {
+                        marks.stamp_disk(&ctx.vault_folder.borrow());
+                        vault::record_save(&mut state, marks);
                     }
                 }
+                refresh_header(&ctx);
+                if let Some(on_done) = on_done.take() {
+                    on_done(&ctx, extra);
+                }
+                if let Some(e) = backups_error {
+                    show_backups_not_rekeyed(&ctx, &e);
+                }
             }
-            Err(e) => {
-                if !e.matches(gtk::DialogError::Dismissed) {
-                    send_toast(&ctx, &format!("Could not open folder picker: {e}"));
+            Err(e) => show_error(&ctx.window, failure_title, &e),
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+/// The vault moved to a new key but some backups did not; they still open
+/// with what unlocked the vault before.
+fn show_backups_not_rekeyed(ctx: &EditorCtx, error: &str) {
+    show_error(
+        &ctx.window,
+        "Backups Not Re-encrypted",
+        &format!(
+            "The vault uses the new key, but some backups could not be moved to it \
+             and still open with the old passphrase or unlock methods: {error}"
+        ),
+    );
+}
+
+/// Re-encrypt the open vault, its unlock slots and every outdated note body
+/// and asset in the current format. An outdated recovery code is replaced,
+/// and the new one shown.
+pub fn upgrade_vault_encryption(ctx: &EditorCtx) {
+    replace_vault_key(
+        ctx,
+        "Upgrading vault encryption\u{2026}",
+        "Upgrade Failed",
+        true,
+        |old_key| {
+            old_key
+                .upgraded()
+                .map_err(|e| format!("Key derivation failed: {e}"))
+        },
+        |ctx, code| {
+            send_toast(ctx, "Vault encryption upgraded");
+            if let Some(code) = code {
+                show_recovery_code(ctx, &code);
+            }
+        },
+    );
+}
+
+/// Report how the vault header, note bodies and assets are encrypted, read
+/// from their headers on a worker thread, and offer the upgrade if it applies.
+pub fn show_encryption_audit(ctx: &EditorCtx) {
+    with_encryption_audit(ctx, |ctx, audit| show_audit_report(ctx, &audit));
+}
+
+/// Audit the vault's encryption on a worker thread and pass the result to
+/// `f`. A failed audit is reported instead.
+pub fn with_encryption_audit<F>(ctx: &EditorCtx, f: F)
+where
+    F: FnOnce(&EditorCtx, vault::EncryptionAudit) + 'static,
+{
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(vault::audit_encryption(&vault_folder));
+    });
+
+    let ctx = ctx.clone();
+    let mut f = Some(f);
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        match rx.try_recv() {
+            Ok(Ok(audit)) => {
+                if let Some(f) = f.take() {
+                    f(&ctx, audit);
                 }
+                glib::ControlFlow::Break
             }
+            Ok(Err(e)) => {
+                show_error(&ctx.window, "Audit Failed", &e.to_string());
+                glib::ControlFlow::Break
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => glib::ControlFlow::Continue,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => glib::ControlFlow::Break,
         }
     });
 }
 
-pub fn show_new_vault_dialog(ctx: &EditorCtx) {
-    if !perform_vault_save_sync(ctx) {
+fn show_audit_report(ctx: &EditorCtx, audit: &vault::EncryptionAudit) {
+    let mut lines = Vec::new();
+    match &audit.vault {
+        Some(info) => {
+            lines.push(format!(
+                "Vault: envelope version {}, {}",
+                info.version,
+                info.cipher.to_uppercase()
+            ));
+            for slot in &info.slots {
+                let (title, kdf) = describe_slot(slot);
+                lines.push(format!("\u{2022} {title}: {kdf}"));
+            }
+        }
+        None => lines.push("Vault: not encrypted".into()),
+    }
+    let blobs = [
+        (
+            audit.outdated_notes.len(),
+            "note bodies use an older format",
+        ),
+        (
+            audit.unencrypted_notes.len(),
+            "note bodies are not encrypted",
+        ),
+        (audit.outdated_assets.len(), "assets use an older format"),
+        (audit.unencrypted_assets.len(), "assets are not encrypted"),
+    ];
+    for (count, problem) in blobs {
+        if count > 0 {
+            lines.push(format!("\u{2022} {count} {problem}"));
+        }
+    }
+    let heading = if audit.is_current() {
+        lines.push("Everything uses the current encryption format.".into());
+        "Encryption Is Current"
+    } else {
+        "Encryption Needs Attention"
+    };
+
+    let dialog = adw::AlertDialog::new(Some(heading), Some(&lines.join("\n")));
+    dialog.add_response("ok", "OK");
+    dialog.set_close_response("ok");
+    if upgrade_applies(ctx, audit) {
+        dialog.add_response("upgrade", "Upgrade");
+        dialog.set_response_appearance("upgrade", adw::ResponseAppearance::Suggested);
+        let ctx = ctx.clone();
+        dialog.connect_response(Some("upgrade"), move |_, _| upgrade_vault_encryption(&ctx));
+    }
+    dialog.present(Some(&ctx.window));
+}
+
+// ---------------------------------------------------------------------------
+// Unlock methods (key slots)
+// ---------------------------------------------------------------------------
+
+fn describe_slot(slot: &crypto::SlotInfo) -> (&'static str, String) {
+    let title = match slot.kind {
+        crypto::SlotKind::Passphrase if slot.keyfile => "Passphrase and key file",
+        crypto::SlotKind::Passphrase => "Passphrase",
+        crypto::SlotKind::RecoveryCode => "Recovery code",
+        crypto::SlotKind::KeyFile => "Key file",
+    };
+    let kdf = match slot.kdf {
+        crypto::KdfParams::Argon2id { memory_kib, .. } => {
+            format!("Argon2id, {} MiB", memory_kib / 1024)
+        }
+        crypto::KdfParams::Pbkdf2Sha256 { iterations } => {
+            format!("PBKDF2-SHA256, {iterations} iterations")
+        }
+    };
+    (title, kdf)
+}
+
+/// List the vault's unlock slots and let the user add a recovery code or key
+/// file, or remove one. Each change only rewrites the vault header.
+pub fn show_unlock_methods_dialog(ctx: &EditorCtx) {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Unlock Methods")
+        .default_width(460)
+        .default_height(420)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let subtitle = gtk::Label::new(Some(
+        "Any one of these unlocks the vault. Adding or removing one does not \
+         re-encrypt your notes.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    if !key.uses_key_slots() {
+        let note = gtk::Label::new(Some(
+            "This vault uses an older encryption format. Upgrade it to add \
+             recovery codes or key files.",
+        ));
+        note.set_wrap(true);
+        vbox.append(&note);
+        let upgrade_btn = gtk::Button::with_label("Upgrade Encryption");
+        upgrade_btn.add_css_class("suggested-action");
+        upgrade_btn.add_css_class("pill");
+        vbox.append(&upgrade_btn);
+        let ctx = ctx.clone();
+        let dlg = dialog.clone();
+        upgrade_btn.connect_clicked(move |_| {
+            dlg.close();
+            upgrade_vault_encryption(&ctx);
+        });
+        dialog.present();
         return;
     }
-    stop_background_tasks(ctx);
-    *ctx.cached_key.borrow_mut() = None;
+
+    let list = gtk::ListBox::new();
+    list.add_css_class("boxed-list");
+    list.set_selection_mode(gtk::SelectionMode::None);
+    let slots = key.slots();
+    let passphrase_slots = slots
+        .iter()
+        .filter(|s| s.kind == crypto::SlotKind::Passphrase)
+        .count();
+    for (index, slot) in slots.iter().enumerate() {
+        let (title, subtitle) = describe_slot(slot);
+        let row = adw::ActionRow::builder()
+            .title(title)
+            .subtitle(subtitle.as_str())
+            .build();
+        let remove_btn = gtk::Button::from_icon_name("user-trash-symbolic");
+        remove_btn.add_css_class("flat");
+        remove_btn.set_valign(gtk::Align::Center);
+        remove_btn.set_tooltip_text(Some("Remove"));
+        remove_btn
+            .set_sensitive(!(slot.kind == crypto::SlotKind::Passphrase && passphrase_slots == 1));
+        row.add_suffix(&remove_btn);
+        list.append(&row);
+
+        let ctx = ctx.clone();
+        let dlg = dialog.clone();
+        remove_btn.connect_clicked(move |_| {
+            let confirm = adw::AlertDialog::new(
+                Some("Remove Unlock Method?"),
+                Some(&format!(
+                    "\u{201c}{title}\u{201d} will no longer unlock this vault."
+                )),
+            );
+            confirm.add_response("cancel", "Cancel");
+            confirm.add_response("remove", "Remove");
+            confirm.set_response_appearance("remove", adw::ResponseAppearance::Destructive);
+            confirm.set_close_response("cancel");
+            let ctx = ctx.clone();
+            let dlg_inner = dlg.clone();
+            confirm.connect_response(None, move |_, response| {
+                if response != "remove" {
+                    return;
+                }
+                dlg_inner.close();
+                replace_vault_key(
+                    &ctx,
+                    "Removing unlock method\u{2026}",
+                    "Unlock Method Not Removed",
+                    false,
+                    move |old_key| {
+                        let mut new_key = old_key.clone();
+                        new_key.remove_slot(index).map_err(|e| e.to_string())?;
+                        Ok((new_key, ()))
+                    },
+                    |ctx, ()| send_toast(ctx, "Unlock method removed"),
+                );
+            });
+            confirm.present(Some(&dlg));
+        });
+    }
+    vbox.append(&list);
+
+    let recovery_btn = gtk::Button::with_label("Generate Recovery Code");
+    recovery_btn.add_css_class("pill");
+    vbox.append(&recovery_btn);
     {
-        let mut state = ctx.state.borrow_mut();
-        state.suppress_sync = true;
+        let ctx = ctx.clone();
+        let dlg = dialog.clone();
+        recovery_btn.connect_clicked(move |_| {
+            dlg.close();
+            replace_vault_key(
+                &ctx,
+                "Generating recovery code\u{2026}",
+                "Recovery Code Not Created",
+                false,
+                |old_key| {
+                    let mut new_key = old_key.clone();
+                    let code = new_key
+                        .generate_recovery_code()
+                        .map_err(|e| format!("Key derivation failed: {e}"))?;
+                    Ok((new_key, code))
+                },
+                |ctx, code| show_recovery_code(ctx, &code),
+            );
+        });
     }
-    ctx.source_buffer.set_text("");
-    ctx.window.set_content(gtk::Widget::NONE);
-    show_create_vault_dialog(&ctx.window);
+
+    let (keyfile_row, keyfile_path) = build_keyfile_row(&dialog, "Choose or create a key file");
+    let add_keyfile_btn = gtk::Button::with_label("Add Key File");
+    add_keyfile_btn.add_css_class("flat");
+    keyfile_row.append(&add_keyfile_btn);
+    vbox.append(&keyfile_row);
+    {
+        let ctx = ctx.clone();
+        let dlg = dialog.clone();
+        add_keyfile_btn.connect_clicked(move |_| {
+            let Some(path) = keyfile_path.borrow().clone() else {
+                send_toast(&ctx, "Choose or create a key file first");
+                return;
+            };
+            dlg.close();
+            replace_vault_key(
+                &ctx,
+                "Adding key file\u{2026}",
+                "Key File Not Added",
+                false,
+                move |old_key| {
+                    let keyfile = crypto::KeyFile::read(&path).map_err(|e| e.to_string())?;
+                    let mut new_key = old_key.clone();
+                    new_key
+                        .add_keyfile_slot(&keyfile)
+                        .map_err(|e| format!("Key derivation failed: {e}"))?;
+                    Ok((new_key, ()))
+                },
+                |ctx, ()| send_toast(ctx, "Key file added"),
+            );
+        });
+    }
+
+    dialog.present();
 }
 
-// ---------------------------------------------------------------------------
+/// Show a freshly generated recovery code once. It is not stored anywhere.
+fn show_recovery_code(ctx: &EditorCtx, code: &str) {
+    let dialog = adw::AlertDialog::new(
+        Some("Recovery Code"),
+        Some(
+            "Write this code down and keep it somewhere safe. Enter it instead of \
+             your passphrase if you ever forget it. Generating a new code replaces \
+             this one.",
+        ),
+    );
+    let label = gtk::Label::new(Some(code));
+    label.add_css_class("title-3");
+    label.add_css_class("monospace");
+    label.set_selectable(true);
+    dialog.set_extra_child(Some(&label));
+    dialog.add_response("copy", "Copy");
+    dialog.add_response("done", "Done");
+    dialog.set_response_appearance("done", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("done"));
+    dialog.set_close_response("done");
+    let code = code.to_string();
+    let ctx_inner = ctx.clone();
+    dialog.connect_response(Some("copy"), move |_, _| {
+        ctx_inner.window.clipboard().set_text(&code);
+        send_toast(&ctx_inner, "Recovery code copied");
+    });
+    dialog.present(Some(&ctx.window));
+}
 
 // ---------------------------------------------------------------------------
-// Settings dialog
+// Locked folders
 // ---------------------------------------------------------------------------
 
-pub fn show_settings_dialog(ctx: &EditorCtx) {
+fn folder_name(ctx: &EditorCtx, folder_id: &str) -> String {
+    ctx.state
+        .borrow()
+        .folders
+        .iter()
+        .find(|f| f.id == folder_id)
+        .map(|f| f.name.clone())
+        .unwrap_or_default()
+}
+
+pub fn show_lock_folder_dialog(ctx: &EditorCtx, folder_id: &str) {
+    if ctx.cached_key.borrow().is_none() {
+        show_info(
+            &ctx.window,
+            "Vault Not Encrypted",
+            "Folders can only be locked in an encrypted vault",
+        );
+        return;
+    }
+
     let dialog = adw::Window::builder()
         .transient_for(&ctx.window)
         .modal(true)
-        .title("Settings")
-        .default_width(520)
-        .default_height(560)
+        .title("Lock Folder")
+        .default_width(420)
+        .default_height(360)
         .build();
 
-    let outer = gtk::Box::new(gtk::Orientation::Vertical, 0);
-    let header = adw::HeaderBar::new();
-    outer.append(&header);
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let title = gtk::Label::new(Some(&format!(
+        "Lock \u{201c}{}\u{201d}",
+        folder_name(ctx, folder_id)
+    )));
+    title.add_css_class("title-2");
+    title.set_wrap(true);
+    vbox.append(&title);
+
+    let subtitle = gtk::Label::new(Some(
+        "Its notes stay hidden until this passphrase is entered, even while the vault is open",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let pass1 = gtk::PasswordEntry::builder()
+        .placeholder_text("Folder passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass1);
+
+    let strength_label = gtk::Label::new(None);
+    strength_label.set_xalign(0.0);
+    strength_label.add_css_class("caption");
+    strength_label.set_visible(false);
+    vbox.append(&strength_label);
+
+    wire_passphrase_strength(&pass1, &strength_label);
+
+    let pass2 = gtk::PasswordEntry::builder()
+        .placeholder_text("Confirm folder passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass2);
+
+    let error_label = gtk::Label::new(None);
+    error_label.add_css_class("error");
+    error_label.set_visible(false);
+    vbox.append(&error_label);
+
+    let lock_btn = gtk::Button::with_label("Lock Folder");
+    lock_btn.add_css_class("suggested-action");
+    lock_btn.add_css_class("pill");
+    vbox.append(&lock_btn);
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        let folder_id = folder_id.to_string();
+        let pass1 = pass1.clone();
+        lock_btn.connect_clicked(move |lock_btn| {
+            let p1 = pass1.text().to_string();
+            let p2 = pass2.text().to_string();
+            if p1.len() < 8 {
+                error_label.set_label("Passphrase must be at least 8 characters");
+                error_label.set_visible(true);
+                return;
+            }
+            if p1 != p2 {
+                error_label.set_label("Passphrases do not match");
+                error_label.set_visible(true);
+                return;
+            }
+
+            // The folder's notes are written to the sub-vault from memory.
+            let note_ids: Vec<String> = {
+                let state = ctx.state.borrow();
+                state
+                    .notes
+                    .iter()
+                    .filter(|n| {
+                        n.parent_id
+                            .as_deref()
+                            .is_some_and(|p| state.folder_is_within(p, &folder_id))
+                    })
+                    .map(|n| n.id.clone())
+                    .collect()
+            };
+            if !note_ids.iter().all(|id| ensure_note_loaded(&ctx, id)) {
+                return;
+            }
+
+            lock_btn.set_sensitive(false);
+            error_label.set_label("Locking\u{2026}");
+            error_label.remove_css_class("error");
+            error_label.set_visible(true);
+
+            let (tx, rx) = std::sync::mpsc::channel::<Result<crypto::CachedKey, String>>();
+            std::thread::spawn(move || {
+                use zeroize::Zeroize;
+                let mut p1 = p1;
+                let result = crypto::CachedKey::derive(&p1)
+                    .map_err(|e| format!("Key derivation failed: {e}"));
+                p1.zeroize();
+                let _ = tx.send(result);
+            });
+
+            let ctx = ctx.clone();
+            let dialog = dialog.clone();
+            let folder_id = folder_id.clone();
+            let error_label = error_label.clone();
+            let lock_btn = lock_btn.clone();
+            glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                let result = match rx.try_recv() {
+                    Ok(r) => r,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => {
+                        return glib::ControlFlow::Continue
+                    }
+                    Err(_) => return glib::ControlFlow::Break,
+                };
+                let locked = result.and_then(|key| {
+                    let main_key = ctx.cached_key.borrow().clone().ok_or("No vault key")?;
+                    let vault_folder = ctx.vault_folder.borrow().clone();
+                    let mut state = ctx.state.borrow_mut();
+                    vault::lock_folder(&vault_folder, &mut state, &folder_id, key, &main_key)
+                        .map_err(|e| e.to_string())
+                });
+                match locked {
+                    Ok(stale) => {
+                        dialog.close();
+                        if perform_vault_save_sync(&ctx) {
+                            for path in stale {
+                                let _ = std::fs::remove_file(path);
+                            }
+                            hide_locked_folder(&ctx, &folder_id);
+                            send_toast(&ctx, "Folder locked");
+                        } else {
+                            refresh_note_list(&ctx);
+                        }
+                    }
+                    Err(e) => {
+                        error_label.add_css_class("error");
+                        error_label.set_label(&e);
+                        lock_btn.set_sensitive(true);
+                    }
+                }
+                glib::ControlFlow::Break
+            });
+        });
+    }
+
+    dialog.present();
+    pass1.grab_focus();
+}
+
+pub fn show_unlock_folder_dialog(ctx: &EditorCtx, folder_id: &str) {
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Unlock Folder")
+        .default_width(400)
+        .default_height(240)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let title = gtk::Label::new(Some(&format!(
+        "Unlock \u{201c}{}\u{201d}",
+        folder_name(ctx, folder_id)
+    )));
+    title.add_css_class("title-2");
+    title.set_wrap(true);
+    vbox.append(&title);
+
+    let pass_entry = gtk::PasswordEntry::builder()
+        .placeholder_text("Folder passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass_entry);
+
+    let error_label = gtk::Label::new(None);
+    error_label.add_css_class("error");
+    error_label.set_visible(false);
+    vbox.append(&error_label);
+
+    let unlock_btn = gtk::Button::with_label("Unlock");
+    unlock_btn.add_css_class("suggested-action");
+    unlock_btn.add_css_class("pill");
+    vbox.append(&unlock_btn);
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    let do_unlock = {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        let folder_id = folder_id.to_string();
+        let pass_entry = pass_entry.clone();
+        let unlock_btn = unlock_btn.clone();
+        move || {
+            let passphrase = pass_entry.text().to_string();
+            pass_entry.set_text("");
+            if passphrase.is_empty() {
+                error_label.set_label("Please enter the folder passphrase");
+                error_label.set_visible(true);
+                return;
+            }
+
+            unlock_btn.set_sensitive(false);
+            pass_entry.set_sensitive(false);
+            error_label.set_label("Unlocking\u{2026}");
+            error_label.remove_css_class("error");
+            error_label.set_visible(true);
+
+            let (tx, rx) = std::sync::mpsc::channel::<
+                Result<(vault::VaultData, crypto::CachedKey), vault::VaultError>,
+            >();
+            let vault_folder = ctx.vault_folder.borrow().clone();
+            let folder_id_thread = folder_id.clone();
+            std::thread::spawn(move || {
+                use zeroize::Zeroize;
+                let mut passphrase = passphrase;
+                let result = vault::open_sub_vault(&vault_folder, &folder_id_thread, &passphrase);
+                passphrase.zeroize();
+                let _ = tx.send(result);
+            });
+
+            let ctx = ctx.clone();
+            let dialog = dialog.clone();
+            let folder_id = folder_id.clone();
+            let error_label = error_label.clone();
+            let pass_entry = pass_entry.clone();
+            let unlock_btn = unlock_btn.clone();
+            glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+                let result = match rx.try_recv() {
+                    Ok(r) => r,
+                    Err(std::sync::mpsc::TryRecvError::Empty) => {
+                        return glib::ControlFlow::Continue
+                    }
+                    Err(_) => return glib::ControlFlow::Break,
+                };
+                match result {
+                    Ok((data, key)) => {
+                        {
+                            let mut state = ctx.state.borrow_mut();
+                            vault::merge_sub_vault(&mut state, &folder_id, data, key);
+                            if let Some(f) = state.folders.iter_mut().find(|f| f.id == folder_id) {
+                                f.expanded = true;
+                            }
+                            state.active_folder_id = Some(folder_id.clone());
+                        }
+                        forget_saved_search_hits(&ctx);
+                        refresh_note_list(&ctx);
+                        index_unloaded_notes(&ctx);
+                        dialog.close();
+                    }
+                    Err(e) => {
+                        error_label.add_css_class("error");
+                        if matches!(e, vault::VaultError::Crypto(_)) {
+                            error_label.set_label("Wrong passphrase, try again");
+                            pass_entry.grab_focus();
+                        } else {
+                            error_label.set_label(&e.to_string());
+                        }
+                        unlock_btn.set_sensitive(true);
+                        pass_entry.set_sensitive(true);
+                    }
+                }
+                glib::ControlFlow::Break
+            });
+        }
+    };
+
+    let do_unlock_enter = do_unlock.clone();
+    pass_entry.connect_activate(move |_| do_unlock_enter());
+    unlock_btn.connect_clicked(move |_| do_unlock());
+
+    dialog.present();
+    pass_entry.grab_focus();
+}
+
+/// Save, then hide an unlocked folder's notes again and forget its key.
+pub fn lock_folder_now(ctx: &EditorCtx, folder_id: &str) {
+    if perform_vault_save_sync(ctx) {
+        hide_locked_folder(ctx, folder_id);
+    }
+}
+
+/// Only call right after a successful save.
+fn hide_locked_folder(ctx: &EditorCtx, folder_id: &str) {
+    let fallback = vault::close_sub_vault(&mut ctx.state.borrow_mut(), folder_id);
+    if let Some(note_id) = fallback {
+        switch_to_note(ctx, &note_id);
+    } else if ctx.state.borrow().notes.is_empty() {
+        create_note(
+            ctx,
+            "Untitled".to_string(),
+            "# Untitled\n\n".to_string(),
+            Vec::new(),
+        );
+    }
+    refresh_tabs(ctx);
+    refresh_note_list(ctx);
+    refresh_tags(ctx);
+}
+
+pub fn remove_folder_lock(ctx: &EditorCtx, folder_id: &str) {
+    let dialog = adw::AlertDialog::new(
+        Some("Remove Folder Lock?"),
+        Some("Notes in this folder will be stored in the main vault and shown without the folder passphrase"),
+    );
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("remove", "Remove Lock");
+    dialog.set_response_appearance("remove", adw::ResponseAppearance::Destructive);
+    dialog.set_default_response(Some("cancel"));
+    dialog.set_close_response("cancel");
+
+    let window = ctx.window.clone();
+    let ctx = ctx.clone();
+    let folder_id = folder_id.to_string();
+    dialog.connect_response(Some("remove"), move |_, _| {
+        let result = vault::remove_folder_lock(&mut ctx.state.borrow_mut(), &folder_id);
+        match result {
+            Ok(()) => {
+                refresh_note_list(&ctx);
+                trigger_vault_save(&ctx);
+            }
+            Err(e) => show_error(&ctx.window, "Remove Lock Failed", &e.to_string()),
+        }
+    });
+    dialog.present(Some(&window));
+}
+
+// ---------------------------------------------------------------------------
+// Asset verification
+// ---------------------------------------------------------------------------
+
+/// Check every asset in the unlocked vaults against the vault, asset ID and
+/// version it is bound to, then report anything swapped, replaced or missing.
+pub fn verify_vault_assets(ctx: &EditorCtx) {
+    let Some(main_key) = ctx.cached_key.borrow().clone() else {
+        show_info(
+            &ctx.window,
+            "Vault Not Encrypted",
+            "Only assets in an encrypted vault can be verified",
+        );
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let (vault_id, assets, folder_keys) = {
+        let state = ctx.state.borrow();
+        (
+            state.vault_id.clone(),
+            state.assets.clone(),
+            state.folder_keys.clone(),
+        )
+    };
+    send_toast(ctx, "Verifying assets\u{2026}");
+
+    let (tx, rx) = std::sync::mpsc::channel::<vault::AssetReport>();
+    std::thread::spawn(move || {
+        let report =
+            vault::verify_assets(&vault_folder, &vault_id, &assets, &main_key, &folder_keys);
+        let _ = tx.send(report);
+    });
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        match rx.try_recv() {
+            Ok(report) => {
+                show_asset_report(&ctx, report);
+                glib::ControlFlow::Break
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => glib::ControlFlow::Continue,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => glib::ControlFlow::Break,
+        }
+    });
+}
+
+fn show_asset_report(ctx: &EditorCtx, report: vault::AssetReport) {
+    if report.is_clean() {
+        send_toast(ctx, &format!("All {} assets verified", report.verified));
+        return;
+    }
+    let name = |id: &str| {
+        ctx.state
+            .borrow()
+            .assets
+            .get(id)
+            .map(|m| m.original_name.clone())
+            .unwrap_or_else(|| id.to_string())
+    };
+    let mut lines: Vec<String> = report
+        .mismatched
+        .iter()
+        .map(|(id, reason)| format!("\u{2022} {}: {reason}", name(id)))
+        .collect();
+    lines.extend(
+        report
+            .missing
+            .iter()
+            .map(|id| format!("\u{2022} {}: file is missing", name(id))),
+    );
+    if !report.unbound.is_empty() {
+        lines.push(format!(
+            "{} older assets are not bound to this vault yet and can't be checked.",
+            report.unbound.len()
+        ));
+    }
+    let heading = if report.mismatched.is_empty() && report.missing.is_empty() {
+        "Some Assets Can't Be Checked"
+    } else {
+        "Asset Problems Found"
+    };
+    let body = format!("{} verified.\n\n{}", report.verified, lines.join("\n"));
+
+    let dialog = adw::AlertDialog::new(Some(heading), Some(&body));
+    dialog.add_response("close", "Close");
+    if !report.unbound.is_empty() && !ctx.state.borrow().read_only {
+        dialog.add_response("bind", "Bind Older Assets");
+        dialog.set_response_appearance("bind", adw::ResponseAppearance::Suggested);
+    }
+    dialog.set_close_response("close");
+    let ctx_c = ctx.clone();
+    dialog.connect_response(None, move |_, response| {
+        if response == "bind" {
+            bind_legacy_assets(&ctx_c, report.unbound.clone());
+        }
+    });
+    dialog.present(Some(&ctx.window));
+}
+
+/// Re-encrypt assets written before bindings so later checks cover them. The
+/// files are rewritten first; until the save records their version they still
+/// open as unbound assets.
+fn bind_legacy_assets(ctx: &EditorCtx, ids: Vec<String>) {
+    let Some(main_key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let (vault_id, assets, folder_keys) = {
+        let state = ctx.state.borrow();
+        (
+            state.vault_id.clone(),
+            state.assets.clone(),
+            state.folder_keys.clone(),
+        )
+    };
+
+    let (tx, rx) = std::sync::mpsc::channel::<Result<Vec<String>, String>>();
+    std::thread::spawn(move || {
+        let result = vault::bind_assets(
+            &vault_folder,
+            &vault_id,
+            &assets,
+            &ids,
+            &main_key,
+            &folder_keys,
+        )
+        .map_err(|e| e.to_string());
+        let _ = tx.send(result);
+    });
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => return glib::ControlFlow::Break,
+        };
+        match result {
+            Ok(bound) => {
+                {
+                    let mut state = ctx.state.borrow_mut();
+                    for id in &bound {
+                        if let Some(meta) = state.assets.get_mut(id) {
+                            meta.version = Some(1);
+                        }
+                    }
+                }
+                if perform_vault_save_sync(&ctx) {
+                    send_toast(&ctx, &format!("Bound {} assets", bound.len()));
+                }
+            }
+            Err(e) => show_error(&ctx.window, "Binding Failed", &e),
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Vault integrity
+// ---------------------------------------------------------------------------
+
+/// Check the open vault for problems a crash or a bad sync could leave
+/// behind, then offer to repair what can be fixed safely.
+pub fn check_vault_integrity(ctx: &EditorCtx) {
+    let Some(main_key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let structure = vault::check_vault_structure(&vault_folder, &ctx.state.borrow());
+    let mut report = match structure {
+        Ok(report) => report,
+        Err(e) => {
+            show_error(&ctx.window, "Check Failed", &e.to_string());
+            return;
+        }
+    };
+    let (vault_id, assets, folder_keys) = {
+        let state = ctx.state.borrow();
+        (
+            state.vault_id.clone(),
+            state.assets.clone(),
+            state.folder_keys.clone(),
+        )
+    };
+    send_toast(ctx, "Checking vault\u{2026}");
+
+    // Decrypting every asset is the slow part
+    let (tx, rx) = std::sync::mpsc::channel::<vault::AssetReport>();
+    std::thread::spawn(move || {
+        let report =
+            vault::verify_assets(&vault_folder, &vault_id, &assets, &main_key, &folder_keys);
+        let _ = tx.send(report);
+    });
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        match rx.try_recv() {
+            Ok(assets) => {
+                report.assets = assets;
+                show_vault_report(&ctx, std::mem::take(&mut report));
+                glib::ControlFlow::Break
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => glib::ControlFlow::Continue,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => glib::ControlFlow::Break,
+        }
+    });
+}
+
+fn show_vault_report(ctx: &EditorCtx, report: vault::VaultReport) {
+    if report.is_healthy() {
+        send_toast(ctx, "No problems found");
+        return;
+    }
+    let mut lines = Vec::new();
+    let mut add = |ids: &[String], what: &str| {
+        if !ids.is_empty() {
+            lines.push(format!("\u{2022} {what}: {}", ids.join(", ")));
+        }
+    };
+    add(&report.duplicate_ids, "Duplicate IDs");
+    add(&report.dangling_parents, "In a missing folder");
+    add(&report.broken_tabs, "Tabs for missing notes");
+    add(&report.missing_bodies, "Notes whose text is missing");
+    add(&report.orphaned_bodies, "Unused note files");
+    add(&report.orphaned_assets, "Unlisted asset files");
+    add(&report.assets.missing, "Missing assets");
+    let mismatched: Vec<String> = report
+        .assets
+        .mismatched
+        .iter()
+        .map(|(id, _)| id.clone())
+        .collect();
+    add(&mismatched, "Damaged or replaced assets");
+    let mut body = lines.join("\n");
+    let repairable = report.is_repairable() && !ctx.state.borrow().read_only;
+    if repairable {
+        body.push_str(
+            "\n\nRepair gives duplicates new IDs, moves notes out of missing folders and \
+             closes broken tabs. Missing and extra files are left as they are.",
+        );
+    }
+
+    let dialog = adw::AlertDialog::new(Some("Vault Problems Found"), Some(&body));
+    dialog.add_response("close", "Close");
+    if repairable {
+        dialog.add_response("repair", "Repair");
+        dialog.set_response_appearance("repair", adw::ResponseAppearance::Suggested);
+    }
+    dialog.set_close_response("close");
+    let ctx_c = ctx.clone();
+    dialog.connect_response(None, move |_, response| {
+        if response == "repair" {
+            repair_open_vault(&ctx_c);
+        }
+    });
+    dialog.present(Some(&ctx.window));
+}
+
+/// Repair what a fresh check finds: the vault may have changed while the
+/// report was open.
+fn repair_open_vault(ctx: &EditorCtx) {
+    let Some(main_key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let active_before = ctx.state.borrow().active_note_id.clone();
+    let result = {
+        let mut state = ctx.state.borrow_mut();
+        vault::check_vault_structure(&vault_folder, &state).map(|report| {
+            let outcome = vault::repair_vault(&vault_folder, &mut state, &main_key, &report);
+            let left = vault::check_vault_structure(&vault_folder, &state)
+                .map_or(0, |after| after.repairable_count());
+            // Let switch_to_note move off the broken note the usual way
+            let active = std::mem::replace(&mut state.active_note_id, active_before.clone());
+            (
+                report.repairable_count().saturating_sub(left),
+                active,
+                outcome.unrecoverable,
+            )
+        })
+    };
+    let (repaired, active, unrecoverable) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            show_error(&ctx.window, "Repair Failed", &e.to_string());
+            return;
+        }
+    };
+    if !unrecoverable.is_empty() {
+        let notes: Vec<String> = unrecoverable
+            .iter()
+            .map(|note| format!("\u{2022} {note}"))
+            .collect();
+        show_error(
+            &ctx.window,
+            "Some Notes Can't Be Recovered",
+            &format!(
+                "These notes have the same ID as another note and share its stored \
+                 text, so their own text is no longer in the vault. They were left as \
+                 they are; restore them from a backup made before the IDs collided.\n\n{}",
+                notes.join("\n")
+            ),
+        );
+    }
+    if repaired == 0 {
+        if unrecoverable.is_empty() {
+            send_toast(ctx, "Nothing left to repair");
+        }
+        return;
+    }
+    if active != active_before {
+        switch_to_note(ctx, &active);
+    }
+    refresh_note_list(ctx);
+    refresh_tabs(ctx);
+    if perform_vault_save_sync(ctx) {
+        let noun = if repaired == 1 { "problem" } else { "problems" };
+        send_toast(ctx, &format!("Repaired {repaired} {noun}"));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Backups
+// ---------------------------------------------------------------------------
+
+/// A backup opened for browsing, with the key its files are encrypted under
+/// (the vault's current key unless the passphrase has changed since).
+#[derive(Clone)]
+struct OpenedBackup {
+    info: vault::BackupInfo,
+    folder: String,
+    key: crypto::CachedKey,
+    state: Rc<DocState>,
+}
+
+pub fn show_backups_dialog(ctx: &EditorCtx) {
+    if ctx.cached_key.borrow().is_none() {
+        return;
+    }
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let backups = match vault::list_backups(&vault_folder) {
+        Ok(backups) => backups,
+        Err(e) => {
+            show_error(&ctx.window, "Could Not List Backups", &e.to_string());
+            return;
+        }
+    };
+
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Backups")
+        .default_width(460)
+        .default_height(480)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let subtitle = gtk::Label::new(Some(
+        "A backup is taken before the first save each hour. All of the last day's \
+         backups are kept, then one a day for a week and one a week for eight weeks.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let list = gtk::ListBox::new();
+    list.add_css_class("boxed-list");
+    list.set_selection_mode(gtk::SelectionMode::None);
+    for backup in &backups {
+        let row = adw::ActionRow::builder()
+            .title(format_ts(backup.created_at))
+            .activatable(true)
+            .build();
+        row.add_suffix(&gtk::Image::from_icon_name("go-next-symbolic"));
+        let ctx = ctx.clone();
+        let dlg = dialog.clone();
+        let backup = backup.clone();
+        row.connect_activated(move |_| open_backup_for_browsing(&ctx, &dlg, backup.clone()));
+        list.append(&row);
+    }
+    if backups.is_empty() {
+        let empty = gtk::Label::new(Some("No backups yet"));
+        empty.add_css_class("dim-label");
+        vbox.append(&empty);
+    } else {
+        let scroll = gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .vexpand(true)
+            .child(&list)
+            .build();
+        vbox.append(&scroll);
+    }
+
+    let backup_btn = gtk::Button::with_label("Back Up Now");
+    backup_btn.add_css_class("pill");
+    backup_btn.set_halign(gtk::Align::Center);
+    backup_btn.set_sensitive(!ctx.state.borrow().read_only);
+    vbox.append(&backup_btn);
+    {
+        let ctx = ctx.clone();
+        let dlg = dialog.clone();
+        backup_btn.connect_clicked(move |_| {
+            if !perform_vault_save_sync(&ctx) {
+                return;
+            }
+            let vault_folder = ctx.vault_folder.borrow().clone();
+            match vault::create_backup(&vault_folder, unix_now()) {
+                Ok(_) => {
+                    dlg.close();
+                    send_toast(&ctx, "Backup created");
+                    show_backups_dialog(&ctx);
+                }
+                Err(e) => show_error(&ctx.window, "Backup Failed", &e.to_string()),
+            }
+        });
+    }
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+    dialog.present();
+}
+
+/// Decrypt a backup's note list with the vault's key, asking for the
+/// passphrase it was made under if that key no longer opens it.
+fn open_backup_for_browsing(ctx: &EditorCtx, parent: &adw::Window, info: vault::BackupInfo) {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let (tx, rx) = std::sync::mpsc::channel::<Result<OpenedBackup, vault::VaultError>>();
+    let info_thread = info.clone();
+    // Opening may derive a key if the backup predates a passphrase change
+    std::thread::spawn(move || {
+        let _ = tx.send(open_backup_blocking(&vault_folder, info_thread, key));
+    });
+
+    let ctx = ctx.clone();
+    let parent = parent.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => return glib::ControlFlow::Break,
+        };
+        match result {
+            Ok(backup) => show_backup_contents(&ctx, &parent, backup),
+            Err(vault::VaultError::Crypto(
+                crypto::CryptoError::DecryptionFailed(_) | crypto::CryptoError::KeyFileRequired,
+            )) => ask_backup_passphrase(&ctx, &parent, info.clone()),
+            Err(e) => show_error(&ctx.window, "Could Not Open Backup", &e.to_string()),
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+fn open_backup_blocking(
+    vault_folder: &str,
+    info: vault::BackupInfo,
+    key: crypto::CachedKey,
+) -> Result<OpenedBackup, vault::VaultError> {
+    let data = vault::open_backup(vault_folder, &info.id, &key)?;
+    Ok(OpenedBackup {
+        folder: vault::backup_path(vault_folder, &info.id)?,
+        info,
+        key,
+        state: Rc::new(vault::vault_to_doc_state(data)),
+    })
+}
+
+fn ask_backup_passphrase(ctx: &EditorCtx, parent: &adw::Window, info: vault::BackupInfo) {
+    let dialog = adw::AlertDialog::new(
+        Some("Backup Uses an Older Passphrase"),
+        Some("This backup was made before the vault's passphrase changed. Enter the passphrase it had then."),
+    );
+    let entry = gtk::PasswordEntry::builder()
+        .placeholder_text("Passphrase")
+        .show_peek_icon(true)
+        .activates_default(true)
+        .build();
+    dialog.set_extra_child(Some(&entry));
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("open", "Open");
+    dialog.set_response_appearance("open", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("open"));
+    dialog.set_close_response("cancel");
+
+    let ctx = ctx.clone();
+    let parent_c = parent.clone();
+    dialog.connect_response(None, move |dlg, response| {
+        let passphrase = entry.text().to_string();
+        entry.set_text("");
+        dlg.set_extra_child(gtk::Widget::NONE);
+        if response != "open" || passphrase.is_empty() {
+            return;
+        }
+        let vault_folder = ctx.vault_folder.borrow().clone();
+        let info_thread = info.clone();
+        let (tx, rx) = std::sync::mpsc::channel::<Result<OpenedBackup, String>>();
+        std::thread::spawn(move || {
+            use zeroize::Zeroize;
+            let mut passphrase = passphrase;
+            let result = (|| -> Result<OpenedBackup, String> {
+                let folder = vault::backup_path(&vault_folder, &info_thread.id)
+                    .map_err(|e| e.to_string())?;
+                let raw = vault::read_vault_raw(&folder)
+                    .map_err(|e| e.to_string())?
+                    .ok_or("The backup has no vault file")?;
+                let (mut json, key) = crypto::decrypt_vault_with_keyfile(&raw, &passphrase, None)
+                    .map_err(|e| match e {
+                    crypto::CryptoError::DecryptionFailed(_) => "Wrong passphrase".to_string(),
+                    e => e.to_string(),
+                })?;
+                json.zeroize();
+                open_backup_blocking(&vault_folder, info_thread, key).map_err(|e| e.to_string())
+            })();
+            passphrase.zeroize();
+            let _ = tx.send(result);
+        });
+
+        let ctx = ctx.clone();
+        let parent = parent_c.clone();
+        glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+            let result = match rx.try_recv() {
+                Ok(r) => r,
+                Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                Err(_) => return glib::ControlFlow::Break,
+            };
+            match result {
+                Ok(backup) => show_backup_contents(&ctx, &parent, backup),
+                Err(e) => show_error(&ctx.window, "Could Not Open Backup", &e),
+            }
+            glib::ControlFlow::Break
+        });
+    });
+    dialog.present(Some(parent));
+}
+
+/// The text of a note as it was in a backup.
+fn backup_note_content(backup: &OpenedBackup, note_id: &str) -> Result<String, String> {
+    let mut note = backup
+        .state
+        .notes
+        .iter()
+        .find(|n| n.id == note_id)
+        .cloned()
+        .ok_or("The note is not in the backup")?;
+    vault::load_note_body(
+        &backup.folder,
+        &backup.state.vault_id,
+        &backup.key,
+        &mut note,
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(std::mem::take(&mut note.content))
+}
+
+/// The current text of a note, `None` if it is no longer in the vault.
+fn current_note_content(ctx: &EditorCtx, note_id: &str) -> Option<String> {
+    if ctx.state.borrow().active_note_id == note_id {
+        return Some(current_markdown(ctx));
+    }
+    if !ensure_note_loaded(ctx, note_id) {
+        return None;
+    }
+    let state = ctx.state.borrow();
+    find_note_index(&state.notes, note_id).map(|i| state.notes[i].content.clone())
+}
+
+fn show_backup_contents(ctx: &EditorCtx, parent: &adw::Window, backup: OpenedBackup) {
+    // A read-only vault can still be browsed and compared, not restored into
+    let read_only = ctx.state.borrow().read_only;
+    let dialog = adw::Window::builder()
+        .transient_for(parent)
+        .modal(true)
+        .title(format!("Backup from {}", format_ts(backup.info.created_at)))
+        .default_width(520)
+        .default_height(560)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let subtitle = gtk::Label::new(Some(
+        "Compare a note with its current version or restore it. Restoring a note \
+         keeps its current text as a snapshot. Notes in locked folders are not shown.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let list = gtk::ListBox::new();
+    list.add_css_class("boxed-list");
+    list.set_selection_mode(gtk::SelectionMode::None);
+    let mut notes: Vec<&NoteItem> = backup
+        .state
+        .notes
+        .iter()
+        .filter(|n| n.stored_in.is_none())
+        .collect();
+    notes.sort_by_key(|n| std::cmp::Reverse(n.updated_at));
+    for note in notes {
+        let exists = find_note_index(&ctx.state.borrow().notes, &note.id).is_some();
+        let row_subtitle = if exists {
+            format!("Edited {}", format_ts(note.updated_at))
+        } else {
+            format!(
+                "Edited {} \u{2022} no longer in the vault",
+                format_ts(note.updated_at)
+            )
+        };
+        let row = adw::ActionRow::builder()
+            .title(glib::markup_escape_text(&note.name).as_str())
+            .subtitle(row_subtitle.as_str())
+            .build();
+
+        let compare_btn = gtk::Button::with_label("Compare");
+        compare_btn.add_css_class("flat");
+        compare_btn.set_valign(gtk::Align::Center);
+        compare_btn.set_sensitive(exists);
+        row.add_suffix(&compare_btn);
+        let restore_btn = gtk::Button::with_label("Restore");
+        restore_btn.add_css_class("flat");
+        restore_btn.set_valign(gtk::Align::Center);
+        restore_btn.set_sensitive(!read_only);
+        row.add_suffix(&restore_btn);
+        list.append(&row);
+
+        {
+            let ctx = ctx.clone();
+            let dlg = dialog.clone();
+            let backup = backup.clone();
+            let note_id = note.id.clone();
+            compare_btn
+                .connect_clicked(move |_| compare_backup_note(&ctx, &dlg, &backup, &note_id));
+        }
+        let ctx = ctx.clone();
+        let dlg = dialog.clone();
+        let backup = backup.clone();
+        let note_id = note.id.clone();
+        restore_btn.connect_clicked(move |_| {
+            if restore_backup_note(&ctx, &backup, &note_id) {
+                dlg.close();
+            }
+        });
+    }
+    let scroll = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .vexpand(true)
+        .child(&list)
+        .build();
+    vbox.append(&scroll);
+
+    let restore_all_btn = gtk::Button::with_label("Restore Entire Vault");
+    restore_all_btn.add_css_class("destructive-action");
+    restore_all_btn.add_css_class("pill");
+    restore_all_btn.set_halign(gtk::Align::Center);
+    restore_all_btn.set_sensitive(!read_only);
+    vbox.append(&restore_all_btn);
+    {
+        let ctx = ctx.clone();
+        let dlg = dialog.clone();
+        let parent = parent.clone();
+        restore_all_btn.connect_clicked(move |_| {
+            confirm_restore_vault(&ctx, &dlg, &parent, backup.clone());
+        });
+    }
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+    dialog.present();
+}
+
+fn compare_backup_note(
+    ctx: &EditorCtx,
+    parent: &adw::Window,
+    backup: &OpenedBackup,
+    note_id: &str,
+) {
+    use pithos_core::notes::{line_diff, DiffLine};
+    let old = match backup_note_content(backup, note_id) {
+        Ok(content) => content,
+        Err(e) => {
+            show_error(&ctx.window, "Could Not Read Backup", &e);
+            return;
+        }
+    };
+    let Some(new) = current_note_content(ctx, note_id) else {
+        return;
+    };
+
+    let dialog = adw::Window::builder()
+        .transient_for(parent)
+        .modal(true)
+        .title("Changes Since Backup")
+        .default_width(640)
+        .default_height(560)
+        .build();
+
+    let text_view = gtk::TextView::new();
+    text_view.set_editable(false);
+    text_view.set_cursor_visible(false);
+    text_view.set_monospace(true);
+    text_view.set_wrap_mode(gtk::WrapMode::WordChar);
+    text_view.set_top_margin(8);
+    text_view.set_bottom_margin(8);
+    text_view.set_left_margin(8);
+    text_view.set_right_margin(8);
+    let buffer = text_view.buffer();
+    let removed = buffer.create_tag(
+        Some("removed"),
+        &[
+            ("background", &"rgba(224, 27, 36, 0.2)"),
+            ("strikethrough", &true),
+        ],
+    );
+    let added = buffer.create_tag(Some("added"), &[("background", &"rgba(46, 194, 126, 0.2)")]);
+
+    let diff = line_diff(&old, &new);
+    let unchanged = diff.iter().all(|line| matches!(line, DiffLine::Same(_)));
+    if unchanged {
+        buffer.set_text("No changes since this backup.");
+    }
+    for line in diff.into_iter().filter(|_| !unchanged) {
+        let (prefix, text, tag) = match line {
+            DiffLine::Same(text) => ("  ", text, None),
+            DiffLine::Removed(text) => ("- ", text, removed.as_ref()),
+            DiffLine::Added(text) => ("+ ", text, added.as_ref()),
+        };
+        let mut end = buffer.end_iter();
+        let line = format!("{prefix}{text}\n");
+        match tag {
+            Some(tag) => buffer.insert_with_tags(&mut end, &line, &[tag]),
+            None => buffer.insert(&mut end, &line),
+        }
+    }
+
+    let scroll = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .vexpand(true)
+        .child(&text_view)
+        .build();
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&scroll);
+    dialog.set_content(Some(&content));
+    dialog.present();
+}
+
+/// Put one note back the way it was in a backup. A note still in the vault
+/// keeps its current text as a snapshot; a deleted one is re-created at the
+/// root if its folder is gone, with any images it links to that were removed.
+fn restore_backup_note(ctx: &EditorCtx, backup: &OpenedBackup, note_id: &str) -> bool {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return false;
+    };
+    let content = match backup_note_content(backup, note_id) {
+        Ok(content) => content,
+        Err(e) => {
+            show_error(&ctx.window, "Could Not Read Backup", &e);
+            return false;
+        }
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let current_assets = ctx.state.borrow().assets.clone();
+    let restored_assets = match vault::restore_linked_assets(
+        &vault_folder,
+        &backup.info.id,
+        &backup.state.assets,
+        &backup.key,
+        &key,
+        &content,
+        &current_assets,
+    ) {
+        Ok(assets) => assets,
+        Err(e) => {
+            show_error(&ctx.window, "Could Not Restore Images", &e.to_string());
+            return false;
+        }
+    };
+
+    let exists = find_note_index(&ctx.state.borrow().notes, note_id).is_some();
+    let current = current_note_content(ctx, note_id);
+    if exists && current.is_none() {
+        return false;
+    }
+    {
+        let mut state = ctx.state.borrow_mut();
+        for meta in restored_assets {
+            state.assets.insert(meta.id.clone(), meta);
+        }
+        match find_note_index(&state.notes, note_id) {
+            Some(index) => {
+                let note = &mut state.notes[index];
+                if let Some(current) = current {
+                    pithos_core::notes::push_snapshot(note, current);
+                }
+                note.content = content.clone();
+                note.updated_at = unix_now();
+            }
+            None => {
+                let Some(mut note) = backup.state.notes.iter().find(|n| n.id == note_id).cloned()
+                else {
+                    return false;
+                };
+                if note
+                    .parent_id
+                    .as_ref()
+                    .is_some_and(|p| !state.folders.iter().any(|f| &f.id == p && !f.locked))
+                {
+                    note.parent_id = None;
+                }
+                note.content = content.clone();
+                note.versions = Vec::new();
+                note.loaded = true;
+                note.updated_at = unix_now();
+                state.trash.retain(|item| item.id != note_id);
+                state.notes.push(note);
+            }
+        }
+    }
+
+    let is_active = ctx.state.borrow().active_note_id == note_id;
+    if is_active {
+        load_document(ctx, &content, None);
+        refresh_tabs(ctx);
+        refresh_tags(ctx);
+    } else {
+        switch_to_note(ctx, note_id);
+    }
+    refresh_note_list(ctx);
+    trigger_vault_save(ctx);
+    send_toast(ctx, "Note restored from backup");
+    true
+}
+
+fn confirm_restore_vault(
+    ctx: &EditorCtx,
+    dialog: &adw::Window,
+    browser: &adw::Window,
+    backup: OpenedBackup,
+) {
+    let confirm = adw::AlertDialog::new(
+        Some("Restore Entire Vault?"),
+        Some(&format!(
+            "Every note, folder and image goes back to how it was on {}. The vault \
+             as it is now is backed up first, so this can be undone from Backups.",
+            format_ts(backup.info.created_at)
+        )),
+    );
+    confirm.add_response("cancel", "Cancel");
+    confirm.add_response("restore", "Restore");
+    confirm.set_response_appearance("restore", adw::ResponseAppearance::Destructive);
+    confirm.set_close_response("cancel");
+    let ctx = ctx.clone();
+    let dialog = dialog.clone();
+    let browser = browser.clone();
+    confirm.connect_response(None, move |_, response| {
+        if response != "restore" {
+            return;
+        }
+        dialog.close();
+        browser.close();
+        restore_vault_from_backup(&ctx, backup.clone());
+    });
+    confirm.present(Some(&dialog));
+}
+
+fn restore_vault_from_backup(ctx: &EditorCtx, backup: OpenedBackup) {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    if !perform_vault_save_sync(ctx) {
+        return;
+    }
+    ctx.saving.set(true);
+    send_toast(ctx, "Restoring backup\u{2026}");
+
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let backup_id = backup.info.id.clone();
+    let backup_key = backup.key.clone();
+    let (tx, rx) = std::sync::mpsc::channel::<Result<vault::VaultData, String>>();
+    std::thread::spawn(move || {
+        let result = vault::restore_backup(&vault_folder, &backup_id, &backup_key, &key)
+            .map_err(|e| e.to_string());
+        let _ = tx.send(result);
+    });
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => {
+                ctx.saving.set(false);
+                return glib::ControlFlow::Break;
+            }
+        };
+        ctx.saving.set(false);
+        match result {
+            Ok(data) => reopen_vault(&ctx, vault::vault_to_doc_state(data)),
+            Err(e) => show_error(&ctx.window, "Restore Failed", &e),
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Vault archive export
+// ---------------------------------------------------------------------------
+
+pub fn show_export_archive_dialog(ctx: &EditorCtx) {
+    if ctx.cached_key.borrow().is_none() {
+        return;
+    }
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Export Vault Archive")
+        .default_width(420)
+        .default_height(420)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let title = gtk::Label::new(Some("Export Vault Archive"));
+    title.add_css_class("title-2");
+    vbox.append(&title);
+
+    let subtitle = gtk::Label::new(Some(
+        "Saves the whole vault, still encrypted, as one .pithos file that Change Vault \
+         opens on another machine. Locked folders keep their own passphrases.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let backups_check = gtk::CheckButton::with_label("Include backups");
+    backups_check.set_active(true);
+    vbox.append(&backups_check);
+
+    let other_check = gtk::CheckButton::with_label("Encrypt with a different passphrase");
+    vbox.append(&other_check);
+
+    let pass1 = gtk::PasswordEntry::builder()
+        .placeholder_text("Archive passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass1);
+
+    let strength_label = gtk::Label::new(None);
+    strength_label.set_xalign(0.0);
+    strength_label.add_css_class("caption");
+    strength_label.set_visible(false);
+    vbox.append(&strength_label);
+    wire_passphrase_strength(&pass1, &strength_label);
+
+    let pass2 = gtk::PasswordEntry::builder()
+        .placeholder_text("Confirm archive passphrase")
+        .show_peek_icon(true)
+        .build();
+    vbox.append(&pass2);
+    for entry in [&pass1, &pass2] {
+        entry.set_sensitive(false);
+        other_check
+            .bind_property("active", entry, "sensitive")
+            .build();
+    }
+
+    let error_label = gtk::Label::new(None);
+    error_label.add_css_class("error");
+    error_label.set_visible(false);
+    vbox.append(&error_label);
+
+    let export_btn = gtk::Button::with_label("Export\u{2026}");
+    export_btn.add_css_class("suggested-action");
+    export_btn.add_css_class("pill");
+    vbox.append(&export_btn);
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+
+    {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        export_btn.connect_clicked(move |_| {
+            let new_passphrase = if other_check.is_active() {
+                let p1 = pass1.text().to_string();
+                if p1.len() < 8 {
+                    error_label.set_label("The passphrase must be at least 8 characters");
+                    error_label.set_visible(true);
+                    return;
+                }
+                if p1 != pass2.text() {
+                    error_label.set_label("Passphrases do not match");
+                    error_label.set_visible(true);
+                    return;
+                }
+                Some(p1)
+            } else {
+                None
+            };
+            error_label.set_visible(false);
+            // The archive is made from the files on disk
+            if !perform_vault_save_sync(&ctx) {
+                return;
+            }
+
+            let vault_name = std::path::Path::new(ctx.vault_folder.borrow().as_str())
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "vault".to_string());
+            let chooser = gtk::FileDialog::builder()
+                .title("Save Vault Archive")
+                .accept_label("Save")
+                .initial_name(format!("{vault_name}.{}", archive::ARCHIVE_EXTENSION))
+                .build();
+            let ctx = ctx.clone();
+            let dlg = dialog.clone();
+            let include_backups = backups_check.is_active();
+            chooser.save(Some(&dialog), gtk::gio::Cancellable::NONE, move |result| {
+                let Some(out) = result.ok().and_then(|file| file.path()) else {
+                    return;
+                };
+                dlg.close();
+                export_vault_archive(&ctx, out, new_passphrase, include_backups);
+            });
+        });
+    }
+
+    dialog.present();
+}
+
+fn export_vault_archive(
+    ctx: &EditorCtx,
+    out: std::path::PathBuf,
+    new_passphrase: Option<String>,
+    include_backups: bool,
+) {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    send_toast(ctx, "Exporting vault archive\u{2026}");
+
+    let (tx, rx) = std::sync::mpsc::channel::<Result<archive::ArchiveReport, String>>();
+    std::thread::spawn(move || {
+        use zeroize::Zeroize;
+        // A new data key for the archive; deriving it takes a moment
+        let new_key = new_passphrase
+            .map(|mut passphrase| {
+                let key = crypto::CachedKey::derive(&passphrase);
+                passphrase.zeroize();
+                key
+            })
+            .transpose();
+        let result = new_key.map_err(|e| e.to_string()).and_then(|new_key| {
+            let options = archive::ArchiveOptions {
+                new_key: new_key.as_ref(),
+                include_backups,
+            };
+            archive::write_archive(&vault_folder, &key, &options, &out).map_err(|e| e.to_string())
+        });
+        let _ = tx.send(result);
+    });
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => return glib::ControlFlow::Break,
+        };
+        match result {
+            Ok(report) if report.skipped_backups.is_empty() => {
+                send_toast(&ctx, "Vault archive saved");
+            }
+            Ok(report) => send_toast(
+                &ctx,
+                &format!(
+                    "Vault archive saved without {} backups made under an older passphrase",
+                    report.skipped_backups.len()
+                ),
+            ),
+            Err(e) => show_error(&ctx.window, "Archive Export Failed", &e),
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Encrypted bundles
+// ---------------------------------------------------------------------------
+
+/// Seal a note or folder into a bundle file under a new one-time passphrase,
+/// which is shown once the file is written.
+pub fn share_as_bundle(ctx: &EditorCtx, target: bundle::ShareTarget) {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let built = bundle::build_bundle(&ctx.state.borrow(), &vault_folder, &key, &target);
+    let shared = match built {
+        Ok(shared) => shared,
+        Err(e) => {
+            show_error(&ctx.window, "Could Not Share", &e.to_string());
+            return;
+        }
+    };
+
+    let file_name = shared.name.replace(['/', '\\'], "-");
+    let chooser = gtk::FileDialog::builder()
+        .title("Share as Encrypted Bundle")
+        .accept_label("Save")
+        .initial_name(format!("{file_name}.{}", bundle::BUNDLE_EXTENSION))
+        .build();
+    let ctx = ctx.clone();
+    let window = ctx.window.clone();
+    chooser.save(Some(&window), gtk::gio::Cancellable::NONE, move |result| {
+        if let Some(out) = result.ok().and_then(|file| file.path()) {
+            write_bundle(&ctx, shared, out);
+        }
+    });
+}
+
+fn write_bundle(ctx: &EditorCtx, shared: bundle::Bundle, out: std::path::PathBuf) {
+    let passphrase = crypto::generate_one_time_passphrase();
+    let passphrase_t = passphrase.clone();
+    send_toast(ctx, "Encrypting bundle\u{2026}");
+
+    let (tx, rx) = std::sync::mpsc::channel::<Result<(), String>>();
+    std::thread::spawn(move || {
+        let result = bundle::seal_bundle(&shared, &passphrase_t)
+            .and_then(|sealed| vault::atomic_write(&out, sealed.as_bytes()).map_err(Into::into))
+            .map_err(|e| e.to_string());
+        let _ = tx.send(result);
+    });
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => return glib::ControlFlow::Break,
+        };
+        match result {
+            Ok(()) => show_bundle_passphrase(&ctx, &passphrase),
+            Err(e) => show_error(&ctx.window, "Could Not Share", &e),
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+fn show_bundle_passphrase(ctx: &EditorCtx, passphrase: &str) {
+    let dialog = adw::AlertDialog::new(
+        Some("Bundle Passphrase"),
+        Some(
+            "Send this passphrase to the recipient separately from the file, for \
+             example by phone or in another messenger. It opens only this bundle \
+             and is not shown again.",
+        ),
+    );
+    let label = gtk::Label::new(Some(passphrase));
+    label.add_css_class("title-3");
+    label.add_css_class("monospace");
+    label.set_selectable(true);
+    dialog.set_extra_child(Some(&label));
+    dialog.add_response("copy", "Copy");
+    dialog.add_response("done", "Done");
+    dialog.set_response_appearance("done", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("done"));
+    dialog.set_close_response("done");
+    let passphrase = passphrase.to_string();
+    let ctx_inner = ctx.clone();
+    dialog.connect_response(Some("copy"), move |_, _| {
+        ctx_inner.window.clipboard().set_text(&passphrase);
+        send_toast(&ctx_inner, "Passphrase copied");
+    });
+    dialog.present(Some(&ctx.window));
+}
+
+/// Ask for a bundle file and its passphrase, and add what it holds to the
+/// top of the vault.
+pub fn show_import_bundle_dialog(ctx: &EditorCtx) {
+    if ctx.cached_key.borrow().is_none() {
+        return;
+    }
+    let filter = gtk::FileFilter::new();
+    filter.set_name(Some("Pithos bundles"));
+    filter.add_pattern(&format!("*.{}", bundle::BUNDLE_EXTENSION));
+    let filters = gtk::gio::ListStore::new::<gtk::FileFilter>();
+    filters.append(&filter);
+    let chooser = gtk::FileDialog::builder()
+        .title("Import Shared Bundle")
+        .accept_label("Open")
+        .filters(&filters)
+        .build();
+
+    let ctx = ctx.clone();
+    let window = ctx.window.clone();
+    chooser.open(Some(&window), gtk::gio::Cancellable::NONE, move |result| {
+        let Some(path) = result.ok().and_then(|file| file.path()) else {
+            return;
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(raw) => ask_bundle_passphrase(&ctx, raw),
+            Err(e) => show_error(&ctx.window, "Could Not Open Bundle", &e.to_string()),
+        }
+    });
+}
+
+fn ask_bundle_passphrase(ctx: &EditorCtx, raw: String) {
+    let dialog = adw::AlertDialog::new(
+        Some("Open Shared Bundle"),
+        Some("Enter the passphrase you were given with the bundle."),
+    );
+    let entry = gtk::PasswordEntry::builder()
+        .placeholder_text("Passphrase")
+        .show_peek_icon(true)
+        .activates_default(true)
+        .build();
+    dialog.set_extra_child(Some(&entry));
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("import", "Import");
+    dialog.set_response_appearance("import", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("import"));
+    dialog.set_close_response("cancel");
+
+    let ctx = ctx.clone();
+    dialog.connect_response(None, move |dlg, response| {
+        let passphrase = entry.text().to_string();
+        entry.set_text("");
+        dlg.set_extra_child(gtk::Widget::NONE);
+        if response != "import" || passphrase.is_empty() {
+            return;
+        }
+        let raw = raw.clone();
+        let (tx, rx) = std::sync::mpsc::channel::<Result<bundle::Bundle, String>>();
+        std::thread::spawn(move || {
+            use zeroize::Zeroize;
+            let mut passphrase = passphrase;
+            let result = bundle::open_bundle(&raw, &passphrase).map_err(|e| match e {
+                vault::VaultError::Crypto(crypto::CryptoError::DecryptionFailed(_)) => {
+                    "Wrong passphrase".to_string()
+                }
+                e => e.to_string(),
+            });
+            passphrase.zeroize();
+            let _ = tx.send(result);
+        });
+
+        let ctx = ctx.clone();
+        glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+            let result = match rx.try_recv() {
+                Ok(r) => r,
+                Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                Err(_) => return glib::ControlFlow::Break,
+            };
+            match result {
+                Ok(shared) => import_bundle(&ctx, shared),
+                Err(e) => show_error(&ctx.window, "Could Not Open Bundle", &e),
+            }
+            glib::ControlFlow::Break
+        });
+    });
+    dialog.present(Some(&ctx.window));
+}
+
+fn import_bundle(ctx: &EditorCtx, shared: bundle::Bundle) {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let result = {
+        let mut state = ctx.state.borrow_mut();
+        bundle::import_bundle(&mut state, &vault_folder, &key, shared, None)
+    };
+    match result {
+        Ok(report) => {
+            refresh_note_list(ctx);
+            refresh_tags(ctx);
+            trigger_vault_save(ctx);
+            let notes = match report.notes {
+                1 => "1 note".to_string(),
+                n => format!("{n} notes"),
+            };
+            send_toast(
+                ctx,
+                &format!("Imported \u{201c}{}\u{201d}, {notes}", report.name),
+            );
+        }
+        Err(e) => show_error(&ctx.window, "Import Failed", &e.to_string()),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Conflict resolver
+// ---------------------------------------------------------------------------
+
+/// One side of the resolver: a read-only text view with changed lines marked.
+fn conflict_pane(title: &str) -> (gtk::Box, gtk::TextBuffer) {
+    let text_view = gtk::TextView::new();
+    text_view.set_editable(false);
+    text_view.set_cursor_visible(false);
+    text_view.set_monospace(true);
+    text_view.set_wrap_mode(gtk::WrapMode::WordChar);
+    text_view.set_top_margin(8);
+    text_view.set_bottom_margin(8);
+    text_view.set_left_margin(8);
+    text_view.set_right_margin(8);
+    let buffer = text_view.buffer();
+    buffer.create_tag(
+        Some("changed"),
+        &[("background", &"rgba(229, 165, 10, 0.25)")],
+    );
+
+    let label = gtk::Label::new(Some(title));
+    label.add_css_class("heading");
+    label.set_xalign(0.0);
+    let scroll = gtk::ScrolledWindow::builder()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .vexpand(true)
+        .child(&text_view)
+        .build();
+    scroll.add_css_class("card");
+    let pane = gtk::Box::new(gtk::Orientation::Vertical, 6);
+    pane.append(&label);
+    pane.append(&scroll);
+    (pane, buffer)
+}
+
+/// Fill both panes, marking the lines only one side has.
+fn fill_conflict_panes(mine: &gtk::TextBuffer, theirs: &gtk::TextBuffer, ours: &str, other: &str) {
+    use pithos_core::notes::{line_diff, DiffLine};
+    mine.set_text("");
+    theirs.set_text("");
+    let append = |buffer: &gtk::TextBuffer, text: &str, changed: bool| {
+        let mut end = buffer.end_iter();
+        let line = format!("{text}\n");
+        if changed {
+            buffer.insert_with_tags_by_name(&mut end, &line, &["changed"]);
+        } else {
+            buffer.insert(&mut end, &line);
+        }
+    };
+    for line in line_diff(ours, other) {
+        match line {
+            DiffLine::Same(text) => {
+                append(mine, text, false);
+                append(theirs, text, false);
+            }
+            DiffLine::Removed(text) => append(mine, text, true),
+            DiffLine::Added(text) => append(theirs, text, true),
+        }
+    }
+}
+
+/// Walk through the notes changed both here and by another writer, with
+/// both versions side by side. Closing early keeps ours; their text stays in
+/// each note's history either way.
+pub fn show_conflict_resolver(ctx: &EditorCtx, conflicts: Vec<vault::NoteConflict>) {
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Resolve Conflicts")
+        .default_width(960)
+        .default_height(640)
+        .build();
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 12);
+    vbox.set_margin_start(24);
+    vbox.set_margin_end(24);
+    vbox.set_margin_top(24);
+    vbox.set_margin_bottom(24);
+
+    let heading = gtk::Label::new(None);
+    heading.add_css_class("title-3");
+    heading.set_xalign(0.0);
+    heading.set_wrap(true);
+    vbox.append(&heading);
+    let subtitle = gtk::Label::new(Some(
+        "This note was changed here and on another device since it was last saved. \
+         Their version is also kept in the note's history.",
+    ));
+    subtitle.add_css_class("dim-label");
+    subtitle.set_xalign(0.0);
+    subtitle.set_wrap(true);
+    vbox.append(&subtitle);
+
+    let panes = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+    panes.set_homogeneous(true);
+    panes.set_vexpand(true);
+    let (mine_pane, mine) = conflict_pane("This Device");
+    let (theirs_pane, theirs) = conflict_pane("Other Device");
+    panes.append(&mine_pane);
+    panes.append(&theirs_pane);
+    vbox.append(&panes);
+
+    let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 12);
+    buttons.set_halign(gtk::Align::End);
+    let keep_mine_btn = gtk::Button::with_label("Keep Mine");
+    let keep_both_btn = gtk::Button::with_label("Keep Both");
+    let use_theirs_btn = gtk::Button::with_label("Use Theirs");
+    use_theirs_btn.add_css_class("suggested-action");
+    buttons.append(&keep_mine_btn);
+    buttons.append(&keep_both_btn);
+    buttons.append(&use_theirs_btn);
+    vbox.append(&buttons);
+
+    let conflicts = Rc::new(conflicts);
+    let current = Rc::new(std::cell::Cell::new(0));
+    let show = {
+        let ctx = ctx.clone();
+        let conflicts = conflicts.clone();
+        Rc::new(move |index: usize| {
+            let conflict = &conflicts[index];
+            let label = if conflicts.len() > 1 {
+                format!("{} ({} of {})", conflict.name, index + 1, conflicts.len())
+            } else {
+                conflict.name.clone()
+            };
+            heading.set_label(&label);
+            let ours = current_note_content(&ctx, &conflict.note_id).unwrap_or_default();
+            fill_conflict_panes(&mine, &theirs, &ours, &conflict.theirs);
+        })
+    };
+    show(0);
+
+    let resolve = {
+        let ctx = ctx.clone();
+        let dialog = dialog.clone();
+        Rc::new(move |resolution: vault::Resolution| {
+            let index = current.get();
+            let conflict = &conflicts[index];
+            // Keep what is on screen if the note is open
+            let markdown = current_markdown(&ctx);
+            update_active_note_content(&ctx, &markdown);
+            let resolved =
+                vault::resolve_conflict(&mut ctx.state.borrow_mut(), conflict, resolution);
+            let is_active = ctx.state.borrow().active_note_id == conflict.note_id;
+            if resolution == vault::Resolution::Theirs && resolved.is_some() && is_active {
+                load_document(&ctx, &conflict.theirs, None);
+            }
+            refresh_tabs(&ctx);
+            refresh_note_list(&ctx);
+            if resolved.is_some() {
+                trigger_vault_save(&ctx);
+            }
+            if index + 1 < conflicts.len() {
+                current.set(index + 1);
+                show(index + 1);
+            } else {
+                dialog.close();
+            }
+        })
+    };
+    for (button, resolution) in [
+        (&keep_mine_btn, vault::Resolution::Mine),
+        (&keep_both_btn, vault::Resolution::Both),
+        (&use_theirs_btn, vault::Resolution::Theirs),
+    ] {
+        let resolve = resolve.clone();
+        button.connect_clicked(move |_| resolve(resolution));
+    }
+
+    let toolbar = adw::HeaderBar::new();
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    content.append(&toolbar);
+    content.append(&vbox);
+    dialog.set_content(Some(&content));
+    dialog.present();
+}
+
+// ---------------------------------------------------------------------------
+// Vault switching dialogs
+// ---------------------------------------------------------------------------
+
+pub fn show_open_vault_dialog(ctx: &EditorCtx) {
+    let chooser = gtk::FileDialog::builder()
+        .title("Open Vault Folder")
+        .accept_label("Open")
+        .build();
+
+    let ctx = ctx.clone();
+    let window = ctx.window.clone();
+    chooser.select_folder(Some(&window), gtk::gio::Cancellable::NONE, move |result| {
+        match result {
+            Ok(file) => {
+                if let Some(path) = file.path() {
+                    let folder = path.to_string_lossy().to_string();
+                    if vault::vault_file_path(&folder).exists() {
+                        // Save current vault, lock, switch
+                        if !perform_vault_save_sync(&ctx) {
+                            return;
+                        }
+                        stop_background_tasks(&ctx);
+                        *ctx.cached_key.borrow_mut() = None;
+                        {
+                            let mut state = ctx.state.borrow_mut();
+                            state.suppress_sync = true;
+                        }
+                        ctx.source_buffer.set_text("");
+                        save_config_or_log(&vault::AppConfig {
+                            vault_path: Some(folder.clone()),
+                            ..Default::default()
+                        });
+                        ctx.window.set_content(gtk::Widget::NONE);
+                        show_unlock_vault_dialog(&ctx.window, folder);
+                    } else {
+                        show_error(
+                            &ctx.window,
+                            "No Vault Found",
+                            "The selected folder does not contain a vault file",
+                        );
+                    }
+                }
+            }
+            Err(e) => {
+                if !e.matches(gtk::DialogError::Dismissed) {
+                    send_toast(&ctx, &format!("Could not open folder picker: {e}"));
+                }
+            }
+        }
+    });
+}
+
+pub fn show_new_vault_dialog(ctx: &EditorCtx) {
+    if !perform_vault_save_sync(ctx) {
+        return;
+    }
+    stop_background_tasks(ctx);
+    *ctx.cached_key.borrow_mut() = None;
+    {
+        let mut state = ctx.state.borrow_mut();
+        state.suppress_sync = true;
+    }
+    ctx.source_buffer.set_text("");
+    ctx.window.set_content(gtk::Widget::NONE);
+    show_create_vault_dialog(&ctx.window);
+}
+
+// ---------------------------------------------------------------------------
+
+// ---------------------------------------------------------------------------
+// Settings dialog
+// ---------------------------------------------------------------------------
+
+pub fn show_settings_dialog(ctx: &EditorCtx) {
+    let dialog = adw::Window::builder()
+        .transient_for(&ctx.window)
+        .modal(true)
+        .title("Settings")
+        .default_width(520)
+        .default_height(560)
+        .build();
+
+    let outer = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    let header = adw::HeaderBar::new();
+    outer.append(&header);
 
     let content = gtk::Box::new(gtk::Orientation::Vertical, 12);
     content.set_margin_start(24);
@@ -1714,8 +4842,72 @@ pub fn show_settings_dialog(ctx: &EditorCtx) {
     content.set_margin_top(24);
     content.set_margin_bottom(24);
 
+    // --- Security ---
+    let security_title = gtk::Label::new(Some("Security"));
+    security_title.add_css_class("title-3");
+    security_title.set_xalign(0.0);
+    content.append(&security_title);
+
+    let security_list = gtk::ListBox::new();
+    security_list.set_selection_mode(gtk::SelectionMode::None);
+    security_list.add_css_class("boxed-list");
+
+    const AUTO_LOCK_CHOICES: [(u32, &str); 7] = [
+        (0, "Never"),
+        (1, "After 1 minute"),
+        (5, "After 5 minutes"),
+        (15, "After 15 minutes"),
+        (30, "After 30 minutes"),
+        (60, "After 1 hour"),
+        (240, "After 4 hours"),
+    ];
+    let (auto_lock_minutes, lock_on_screen_lock) = {
+        let state = ctx.state.borrow();
+        (state.auto_lock_minutes, state.lock_on_screen_lock)
+    };
+    let labels: Vec<&str> = AUTO_LOCK_CHOICES.iter().map(|(_, l)| *l).collect();
+    let auto_lock_row = adw::ComboRow::builder()
+        .title("Lock When Idle")
+        .subtitle("Save and lock the vault after no keyboard or mouse input")
+        .model(&gtk::StringList::new(&labels))
+        .build();
+    // Other values from the vault file show as the next longer choice.
+    let selected = AUTO_LOCK_CHOICES
+        .iter()
+        .position(|(m, _)| *m >= auto_lock_minutes)
+        .unwrap_or(AUTO_LOCK_CHOICES.len() - 1);
+    auto_lock_row.set_selected(selected as u32);
+    {
+        let ctx = ctx.clone();
+        auto_lock_row.connect_selected_notify(move |row| {
+            let Some((minutes, _)) = AUTO_LOCK_CHOICES.get(row.selected() as usize) else {
+                return;
+            };
+            ctx.state.borrow_mut().auto_lock_minutes = *minutes;
+            ctx.last_activity.set(std::time::Instant::now());
+            trigger_vault_save(&ctx);
+        });
+    }
+    security_list.append(&auto_lock_row);
+
+    let screen_lock_row = adw::SwitchRow::builder()
+        .title("Lock With Screen")
+        .subtitle("Lock the vault when the desktop session locks")
+        .active(lock_on_screen_lock)
+        .build();
+    {
+        let ctx = ctx.clone();
+        screen_lock_row.connect_active_notify(move |row| {
+            ctx.state.borrow_mut().lock_on_screen_lock = row.is_active();
+            trigger_vault_save(&ctx);
+        });
+    }
+    security_list.append(&screen_lock_row);
+    content.append(&security_list);
+
     let section_title = gtk::Label::new(Some("Templates"));
     section_title.add_css_class("title-3");
+    section_title.set_margin_top(12);
     section_title.set_xalign(0.0);
     content.append(&section_title);
 
@@ -1,5 +1,6 @@
 use crate::*;
 use adw::prelude::*;
+use pithos_core::search::{similar_titles, title_match};
 use pithos_core::state::*;
 use sourceview5 as sourceview;
 use std::{fs, path::PathBuf};
@@ -105,6 +106,9 @@ pub fn wire_editor_signals(ctx: &EditorCtx, tag_entry: &gtk::Entry) {
         let ctx = ctx.clone();
         let drop = gtk::DropTarget::new(gtk::gio::File::static_type(), gdk::DragAction::COPY);
         drop.connect_drop(move |_, value, _, _| {
+            if ctx.state.borrow().read_only {
+                return false;
+            }
             if let Ok(file) = value.get::<gtk::gio::File>() {
                 if let Some(path) = file.path() {
                     let ext = path
@@ -301,7 +305,13 @@ pub fn do_sync_and_undo_inner(ctx: &EditorCtx, force_checkpoint: bool) {
     if should_refresh {
         refresh_header(ctx);
     }
-    if !ctx.state.borrow().search_query.trim().is_empty() {
+    // Edits don't change what a history or trash search finds, and smart
+    // folders are looked up again when the save starts
+    let searching = {
+        let state = ctx.state.borrow();
+        !state.search_query.trim().is_empty() && state.search_scope == SearchScope::Notes
+    };
+    if searching {
         refresh_note_list(ctx);
     }
     if content_changed {
@@ -874,22 +884,74 @@ pub fn show_link_dialog(ctx: &EditorCtx, initial_label: &str, initial_url: &str)
 }
 
 pub fn navigate_to_wiki_link(ctx: &EditorCtx, name: &str) {
-    let found_id = {
+    // The note titled `name`; titles a typo away are only offered
+    let (found_id, similar) = {
         let state = ctx.state.borrow();
-        state
-            .notes
-            .iter()
-            .find(|n| n.name.eq_ignore_ascii_case(name))
-            .map(|n| n.id.clone())
+        match title_match(&state.notes, name) {
+            Some(note) => (Some(note.id.clone()), Vec::new()),
+            None => {
+                let similar: Vec<(String, String)> = similar_titles(&state.notes, name)
+                    .into_iter()
+                    .map(|n| (n.id.clone(), n.name.clone()))
+                    .collect();
+                (None, similar)
+            }
+        }
     };
 
     if let Some(id) = found_id {
         switch_to_note(ctx, &id);
-    } else {
+    } else if similar.is_empty() {
         create_note(ctx, name.to_string(), format!("# {name}\n\n"), Vec::new());
+    } else {
+        show_similar_notes_dialog(ctx, name, similar);
     }
 }
 
+/// Ask whether a link to `name`, which no note has as its title, meant one
+/// of the `similar` notes (ID and title), or a new note.
+fn show_similar_notes_dialog(ctx: &EditorCtx, name: &str, similar: Vec<(String, String)>) {
+    let dialog = adw::AlertDialog::new(
+        Some("No Note With That Title"),
+        Some(&format!(
+            "No note is titled \"{name}\". Open one with a similar title, or create it."
+        )),
+    );
+
+    let list = gtk::ListBox::new();
+    list.set_selection_mode(gtk::SelectionMode::Single);
+    list.add_css_class("boxed-list");
+    for (_, title) in &similar {
+        let row = adw::ActionRow::builder().title(title).build();
+        list.append(&row);
+    }
+    list.select_row(list.row_at_index(0).as_ref());
+    dialog.set_extra_child(Some(&list));
+
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("create", "Create Note");
+    dialog.add_response("open", "Open");
+    dialog.set_response_appearance("open", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("open"));
+    dialog.set_close_response("cancel");
+    dialog.set_response_enabled("create", !ctx.state.borrow().read_only);
+
+    let window = ctx.window.clone();
+    let ctx = ctx.clone();
+    let name = name.to_string();
+    dialog.connect_response(None, move |_, response| match response {
+        "open" => {
+            let selected = list.selected_row().map(|r| r.index()).unwrap_or(-1);
+            if let Some((id, _)) = usize::try_from(selected).ok().and_then(|i| similar.get(i)) {
+                switch_to_note(&ctx, id);
+            }
+        }
+        "create" => create_note(&ctx, name.clone(), format!("# {name}\n\n"), Vec::new()),
+        _ => {}
+    });
+    dialog.present(Some(&window));
+}
+
 pub fn insert_table_snippet(buffer: &sourceview::Buffer) {
     let snippet = "| Column 1 | Column 2 |\n| --- | --- |\n| | |\n";
     let mut iter = buffer.iter_at_offset(buffer.cursor_position());
@@ -1176,6 +1238,9 @@ pub fn insert_image_snippet(ctx: &EditorCtx) {
 }
 
 pub fn try_paste_image(ctx: &EditorCtx) -> bool {
+    if ctx.state.borrow().read_only {
+        return false;
+    }
     let Some(display) = gdk::Display::default() else {
         return false;
     };
@@ -1230,8 +1295,29 @@ pub fn mime_from_ext(ext: &str) -> String {
 
 pub fn store_image_as_asset(ctx: &EditorCtx, data: &[u8], filename: &str, mime: &str) {
     let asset_id = generate_asset_id();
-    let vault_folder = ctx.vault_folder.borrow().clone();
-    let cached_key = ctx.cached_key.borrow().clone();
+    let mut vault_folder = ctx.vault_folder.borrow().clone();
+    let mut cached_key = ctx.cached_key.borrow().clone();
+    // Images pasted into a locked folder go straight into its sub-vault
+    let stored_in = {
+        let state = ctx.state.borrow();
+        let parent = find_note_index(&state.notes, &state.active_note_id)
+            .and_then(|i| state.notes[i].parent_id.as_deref());
+        state.locked_root(parent).map(str::to_string)
+    };
+    if let Some(root) = &stored_in {
+        let state = ctx.state.borrow();
+        let Some(key) = state.folder_keys.get(root) else {
+            return;
+        };
+        vault_folder = pithos_core::vault::sub_vault_path(&vault_folder, root);
+        cached_key = Some(key.clone());
+    }
+    // New assets are bound to their vault at version 1
+    let binding = cached_key.as_ref().map(|_| {
+        let vault_id = ctx.state.borrow().vault_id.clone();
+        pithos_core::vault::binding_for(&vault_id, stored_in.as_deref(), &asset_id, 1)
+    });
+    let version = binding.as_ref().map(|b| b.version);
     let data_size = data.len() as u64;
     let filename_owned = filename.to_string();
     let mime_owned = mime.to_string();
@@ -1243,8 +1329,8 @@ pub fn store_image_as_asset(ctx: &EditorCtx, data: &[u8], filename: &str, mime:
     let asset_id_thread = asset_id.clone();
 
     std::thread::spawn(move || {
-        let write_data = if let Some(ref key) = cached_key {
-            match pithos_core::crypto::encrypt_asset(&data_owned, key) {
+        let write_data = if let (Some(key), Some(binding)) = (&cached_key, &binding) {
+            match pithos_core::crypto::encrypt_asset_bound(&data_owned, key, binding) {
                 Ok(encrypted) => encrypted.into_bytes(),
                 Err(e) => {
                     let _ = tx.send(Err(format!("Asset encryption failed: {e}")));
@@ -1279,6 +1365,8 @@ pub fn store_image_as_asset(ctx: &EditorCtx, data: &[u8], filename: &str, mime:
                     mime_type: mime_owned.clone(),
                     size: data_size,
                     created_at: now,
+                    stored_in: stored_in.clone(),
+                    version,
                 };
                 ctx.state.borrow_mut().assets.insert(asset_id.clone(), meta);
 
@@ -31,7 +31,20 @@ fn main() {
     }
     install_css();
 
-    let app = adw::Application::builder().application_id(APP_ID).build();
+    // `--read-only` is ours; GApplication would reject it as unknown.
+    let mut args: Vec<String> = std::env::args().collect();
+    let arg_count = args.len();
+    args.retain(|arg| arg != "--read-only");
+    if args.len() != arg_count {
+        set_read_only_session();
+    }
+
+    // Registering with the session manager makes screen-lock changes visible
+    // to the auto-lock.
+    let app = adw::Application::builder()
+        .application_id(APP_ID)
+        .register_session(true)
+        .build();
     app.connect_activate(|app| {
         // If a window already exists, just present it (single-instance).
         if let Some(window) = app.active_window() {
@@ -40,5 +53,6 @@ fn main() {
         }
         build_ui(app);
     });
-    app.run();
+    app.connect_shutdown(|_| release_vault_lock());
+    app.run_with_args(&args);
 }
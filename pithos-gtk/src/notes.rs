@@ -1,6 +1,7 @@
 use crate::*;
 use adw::prelude::*;
 use pithos_core::state::*;
+use pithos_core::vault;
 
 // ---------------------------------------------------------------------------
 // Note management
@@ -9,7 +10,10 @@ use pithos_core::state::*;
 pub fn create_note(ctx: &EditorCtx, name: String, content: String, tags: Vec<String>) {
     let note_id = {
         let mut state = ctx.state.borrow_mut();
-        let folder = state.active_folder_id.clone();
+        let folder = state
+            .active_folder_id
+            .clone()
+            .filter(|f| state.is_folder_accessible(Some(f)));
         let unique_name = deduplicate_note_name(&state.notes, &name, &folder);
         let note_id = format!("note-{}", state.next_note_seq);
         state.next_note_seq += 1;
@@ -68,6 +72,10 @@ pub fn switch_to_note(ctx: &EditorCtx, note_id: &str) {
         id.remove();
     }
 
+    if !ensure_note_loaded(ctx, note_id) {
+        return;
+    }
+
     let current_id = ctx.state.borrow().active_note_id.clone();
     if current_id == note_id {
         {
@@ -83,6 +91,9 @@ pub fn switch_to_note(ctx: &EditorCtx, note_id: &str) {
     }
 
     let outgoing_markdown = current_markdown(ctx);
+    ctx.tab_cursors
+        .borrow_mut()
+        .insert(current_id, ctx.source_buffer.cursor_position());
     let incoming_markdown = {
         let mut state = ctx.state.borrow_mut();
 
@@ -106,11 +117,74 @@ pub fn switch_to_note(ctx: &EditorCtx, note_id: &str) {
     };
 
     load_document(ctx, &incoming_markdown, None);
+    let cursor = ctx.tab_cursors.borrow_mut().remove(note_id);
+    if let Some(cursor) = cursor {
+        ctx.source_buffer
+            .place_cursor(&ctx.source_buffer.iter_at_offset(cursor));
+    }
     refresh_tabs(ctx);
     refresh_note_list(ctx);
     refresh_tags(ctx);
 }
 
+/// Decrypt a note's body from `notes/` if it is not in memory yet.
+/// Shows an error and returns false if the body cannot be read.
+pub fn ensure_note_loaded(ctx: &EditorCtx, note_id: &str) -> bool {
+    let result = {
+        let mut state = ctx.state.borrow_mut();
+        let Some(index) = find_note_index(&state.notes, note_id) else {
+            return true;
+        };
+        if state.notes[index].loaded {
+            return true;
+        }
+        let main_key = ctx.cached_key.borrow();
+        let Some(main_key) = main_key.as_ref() else {
+            return false;
+        };
+        let vault_folder = ctx.vault_folder.borrow().clone();
+        let state = &mut *state;
+        let stored_in = state.notes[index].stored_in.clone();
+        vault::blob_location(
+            &vault_folder,
+            &state.folder_keys,
+            main_key,
+            stored_in.as_deref(),
+        )
+        .and_then(|(dir, key)| {
+            let owner = vault::blob_owner(&state.vault_id, stored_in.as_deref());
+            vault::load_note_body(&dir, &owner, key, &mut state.notes[index])
+        })
+    };
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            show_error(&ctx.window, "Could Not Open Note", &e.to_string());
+            false
+        }
+    }
+}
+
+/// Wipe the bodies of notes that are not open in a tab once `saved` has been
+/// written. A body edited since the save started stays loaded until the next one.
+pub fn unload_closed_notes<'a>(
+    ctx: &EditorCtx,
+    saved: impl Iterator<Item = &'a (String, vault::NoteBody)>,
+) {
+    let mut state = ctx.state.borrow_mut();
+    let state = &mut *state;
+    for (id, body) in saved {
+        if *id == state.active_note_id || state.open_tabs.contains(id) {
+            continue;
+        }
+        if let Some(note) = state.notes.iter_mut().find(|n| n.id == *id) {
+            if note.loaded && body.matches(note) {
+                vault::unload_note_body(note);
+            }
+        }
+    }
+}
+
 pub fn open_or_create_daily_note(ctx: &EditorCtx) {
     let date = if let Ok(now) = glib::DateTime::now_local() {
         now.format("%Y-%m-%d")
@@ -446,16 +520,16 @@ pub fn restore_from_trash(ctx: &EditorCtx, trash_id: &str) {
             return;
         };
         let item = state.trash.remove(idx);
-        // Check if original parent folder still exists
-        let parent_id = if item
-            .parent_id
-            .as_ref()
-            .is_some_and(|pid| state.folders.iter().any(|f| &f.id == pid))
-        {
-            item.parent_id
-        } else {
-            None
+        // Check if original parent folder still exists and is not locked away;
+        // a note trashed in a locked folder goes back into that folder
+        let usable = |pid: &String| {
+            state.folders.iter().any(|f| &f.id == pid) && state.is_folder_accessible(Some(pid))
         };
+        let parent_id = item
+            .parent_id
+            .clone()
+            .filter(usable)
+            .or_else(|| item.stored_in.clone().filter(usable));
         let note = NoteItem {
             id: item.id.clone(),
             name: item.name,
@@ -467,6 +541,8 @@ pub fn restore_from_trash(ctx: &EditorCtx, trash_id: &str) {
             file_path: None,
             parent_id,
             pinned: item.pinned,
+            loaded: item.loaded,
+            stored_in: item.stored_in,
         };
         state.notes.push(note);
         state.viewing_trash = false;
@@ -539,6 +615,7 @@ pub fn refresh_trash_view(ctx: &EditorCtx) {
         return;
     }
 
+    let read_only = ctx.state.borrow().read_only;
     for item in &trash {
         let row = gtk::ListBoxRow::new();
         let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
@@ -565,6 +642,8 @@ pub fn refresh_trash_view(ctx: &EditorCtx) {
         let del_btn = gtk::Button::with_label("Delete");
         del_btn.add_css_class("flat");
         del_btn.add_css_class("destructive-action");
+        restore_btn.set_sensitive(!read_only);
+        del_btn.set_sensitive(!read_only);
 
         {
             let ctx = ctx.clone();
@@ -612,6 +691,11 @@ pub fn create_folder(ctx: &EditorCtx, parent_id: Option<String>) {
         dlg.set_extra_child(gtk::Widget::NONE);
         if response == "create" && !name.is_empty() {
             let mut state = ctx.state.borrow_mut();
+            if !state.is_folder_accessible(parent_id.as_deref()) {
+                drop(state);
+                send_toast(&ctx, "Unlock the folder first");
+                return;
+            }
             if folder_name_exists(&state.folders, &name, &parent_id, None) {
                 drop(state);
                 send_toast(&ctx, "A folder with that name already exists here");
@@ -626,6 +710,7 @@ pub fn create_folder(ctx: &EditorCtx, parent_id: Option<String>) {
                 created_at: unix_now(),
                 updated_at: unix_now(),
                 parent_id: parent_id.clone(),
+                locked: false,
             });
             drop(state);
             refresh_note_list(&ctx);
@@ -641,6 +726,7 @@ pub fn move_note_to_folder(ctx: &EditorCtx) {
         let folders: Vec<(String, String)> = state
             .folders
             .iter()
+            .filter(|f| state.is_folder_accessible(Some(&f.id)))
             .map(|f| (f.id.clone(), f.name.clone()))
             .collect();
         let parent = find_note_index(&state.notes, &state.active_note_id)
@@ -3,7 +3,13 @@ use adw::prelude::*;
 use pithos_core::crypto;
 use pithos_core::state::*;
 use pithos_core::vault;
-use std::{fs, io::Write, path::PathBuf};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::PathBuf,
+};
 
 const TYPST_TEMPLATE: &str = include_str!("../../data/typst_template.typ");
 
@@ -447,10 +453,38 @@ pub fn trigger_vault_save(ctx: &EditorCtx) {
     ctx.save_timeout_id.set(Some(source_id));
 }
 
+/// Everything a vault save needs, collected on the main thread.
+pub struct PendingSave {
+    pub vault_data: vault::VaultData,
+    /// Bodies of the notes currently in memory, written to `notes/`.
+    pub bodies: Vec<(String, vault::NoteBody)>,
+    /// Unlocked locked folders, each written to its own sub-vault.
+    pub sub_vaults: Vec<vault::SubVaultWrite>,
+    /// Blobs that moved between vaults; deleted once the save succeeded.
+    pub stale_files: Vec<PathBuf>,
+    pub key: crypto::CachedKey,
+    pub vault_folder: String,
+    /// Full save, or just a journal entry for the notes that changed.
+    pub plan: vault::SavePlan,
+    /// Stored with `vault::record_save` once the save succeeded.
+    pub marks: vault::JournalMarks,
+    /// Serialized search index and its revision, on full saves that changed
+    /// it; dropped if it could not be written.
+    pub search_index: Option<(u64, zeroize::Zeroizing<Vec<u8>>)>,
+}
+
+impl PendingSave {
+    /// Every note body written by this save, main vault and sub-vaults alike.
+    pub fn saved_bodies(&self) -> impl Iterator<Item = &(String, vault::NoteBody)> {
+        self.bodies
+            .iter()
+            .chain(self.sub_vaults.iter().flat_map(|sub| &sub.bodies))
+    }
+}
+
 /// Collect vault data on the main thread (cheap), return everything needed for I/O.
-pub fn prepare_vault_save(
-    ctx: &EditorCtx,
-) -> Option<(vault::VaultData, crypto::CachedKey, String)> {
+/// Unless `full` is set, a save that only changes notes becomes a journal entry.
+pub fn prepare_vault_save(ctx: &EditorCtx, full: bool) -> Result<PendingSave, String> {
     let markdown = current_markdown(ctx);
     update_active_note_content(ctx, &markdown);
 
@@ -462,29 +496,95 @@ pub fn prepare_vault_save(
         state.sidebar_width = (fraction * total).round() as i32;
     }
 
-    let vault_data = vault::doc_state_to_vault(&ctx.state.borrow());
-    let key = ctx.cached_key.borrow().clone()?;
+    let key = ctx.cached_key.borrow().clone();
     let vault_folder = ctx.vault_folder.borrow().clone();
-    if vault_folder.is_empty() {
-        return None;
-    }
-    Some((vault_data, key, vault_folder))
+    let Some(key) = key.filter(|_| !vault_folder.is_empty()) else {
+        return Err("Vault is not unlocked or no vault folder is configured.".into());
+    };
+    let stale_files = vault::settle_sub_vaults(&vault_folder, &mut ctx.state.borrow_mut(), &key)
+        .map_err(|e| format!("Could not move notes between locked folders: {e}"))?;
+    let state = ctx.state.borrow();
+    let vault_data = vault::doc_state_to_vault(&state);
+    let (plan, marks) = vault::plan_save(&state, &vault_data, full || !stale_files.is_empty());
+    let (bodies, sub_vaults) = match plan {
+        vault::SavePlan::Full => (
+            vault::loaded_note_bodies(&state, None),
+            vault::sub_vault_writes(&state),
+        ),
+        _ => (Vec::new(), Vec::new()),
+    };
+    drop(state);
+    let search_index = match plan {
+        vault::SavePlan::Full => vault::search_index_for_save(&mut ctx.state.borrow_mut()),
+        _ => None,
+    };
+    Ok(PendingSave {
+        vault_data,
+        bodies,
+        sub_vaults,
+        stale_files,
+        key,
+        vault_folder,
+        plan,
+        marks,
+        search_index,
+    })
 }
 
 /// Perform serialization + encryption + write (expensive, blocking).
-pub fn vault_save_blocking(
-    vault_data: vault::VaultData,
-    key: &crypto::CachedKey,
-    vault_folder: &str,
-) -> Result<(), String> {
-    vault::backup_vault(vault_folder);
+///
+/// Sub-vaults and note bodies go first so vault.json never lists a note or a
+/// locked folder whose blobs are missing. The journal is cleared last, once
+/// vault.json holds everything in it. Afterwards `save.marks` notes the files
+/// as written, to tell them from another writer's.
+pub fn vault_save_blocking(save: &mut PendingSave) -> Result<(), String> {
+    let vault_folder = save.vault_folder.as_str();
+    let vault_id = save.vault_data.vault_id.as_str();
+    match &save.plan {
+        vault::SavePlan::Full => {}
+        vault::SavePlan::Journal(entry) => {
+            vault::append_journal(vault_folder, vault_id, entry, &save.key)
+                .map_err(|e| format!("Journal: {e}"))?;
+            save.marks.stamp_disk(vault_folder);
+            return Ok(());
+        }
+        vault::SavePlan::Unchanged => return Ok(()),
+    }
+    if let Err(e) = vault::backup_vault(vault_folder) {
+        eprintln!("Vault backup failed: {e}");
+    }
+    for sub in &save.sub_vaults {
+        vault::write_sub_vault(vault_folder, sub).map_err(|e| format!("Write: {e}"))?;
+    }
+    vault::write_note_bodies(vault_folder, vault_id, &save.bodies, &save.key)
+        .map_err(|e| format!("Write: {e}"))?;
     use zeroize::Zeroize;
-    let mut json =
-        serde_json::to_string_pretty(&vault_data).map_err(|e| format!("Serialization: {e}"))?;
-    let result = crypto::encrypt_vault_fast(&json, key).map_err(|e| format!("Encryption: {e}"));
+    let mut json = serde_json::to_string_pretty(&save.vault_data)
+        .map_err(|e| format!("Serialization: {e}"))?;
+    let result =
+        crypto::encrypt_vault_fast(&json, &save.key).map_err(|e| format!("Encryption: {e}"));
     json.zeroize(); // Wipe plaintext vault contents from memory
     let encrypted = result?;
     vault::write_vault_raw(vault_folder, &encrypted).map_err(|e| format!("Write: {e}"))?;
+    if let Err(e) = vault::clear_journal(vault_folder) {
+        eprintln!("Failed to clear the journal: {e}");
+    }
+    if let Some((_, index)) = &save.search_index {
+        if let Err(e) = vault::write_search_index(vault_folder, vault_id, index, &save.key) {
+            eprintln!("Failed to store the search index: {e}");
+            save.search_index = None;
+        }
+    }
+    if let Err(e) = vault::prune_note_bodies(vault_folder, &save.vault_data) {
+        eprintln!("Failed to prune deleted note bodies: {e}");
+    }
+    if let Err(e) = vault::prune_sub_vaults(vault_folder, &save.vault_data) {
+        eprintln!("Failed to remove unlocked sub-vaults: {e}");
+    }
+    for path in &save.stale_files {
+        let _ = fs::remove_file(path);
+    }
+    save.marks.stamp_disk(vault_folder);
     Ok(())
 }
 
@@ -492,20 +592,13 @@ pub fn vault_save_blocking(
 /// If an async save is in flight, bump the generation so the async callback
 /// won't overwrite our (newer) sync write, then proceed with the sync save.
 pub fn perform_vault_save_sync(ctx: &EditorCtx) -> bool {
-    // Invalidate any in-flight async save so its completion callback won't
-    // re-save or mark the document clean with stale data.
-    if ctx.saving.get() {
-        ctx.save_generation.set(ctx.save_generation.get().wrapping_add(2));
-    }
-    let Some((vault_data, key, vault_folder)) = prepare_vault_save(ctx) else {
-        return false;
-    };
-    match vault_save_blocking(vault_data, &key, &vault_folder) {
-        Ok(()) => {
-            send_toast(ctx, "Saved");
-            true
+    match vault_save_sync(ctx) {
+        Ok(()) => true,
+        Err((e, None)) => {
+            eprintln!("Vault save skipped: {e}");
+            false
         }
-        Err(e) => {
+        Err((e, Some(_))) => {
             show_error(
                 &ctx.window,
                 "Save Failed",
@@ -516,43 +609,82 @@ pub fn perform_vault_save_sync(ctx: &EditorCtx) -> bool {
     }
 }
 
+/// The save behind `perform_vault_save_sync`. On failure, returns the error
+/// and the save that could not be written, if it got that far.
+fn vault_save_sync(ctx: &EditorCtx) -> Result<(), (String, Option<PendingSave>)> {
+    if ctx.state.borrow().read_only {
+        return Ok(());
+    }
+    // Invalidate any in-flight async save so its completion callback won't
+    // re-save or mark the document clean with stale data.
+    if ctx.saving.get() {
+        ctx.save_generation.set(ctx.save_generation.get().wrapping_add(2));
+    }
+    forget_saved_search_hits(ctx);
+    merge_external_blocking(ctx);
+    let mut save = prepare_vault_save(ctx, true).map_err(|e| (e, None))?;
+    if let Err(e) = vault_save_blocking(&mut save) {
+        return Err((e, Some(save)));
+    }
+    let mut state = ctx.state.borrow_mut();
+    if let Some((revision, _)) = &save.search_index {
+        state.search_index.mark_stored(*revision);
+    }
+    vault::record_save(&mut state, save.marks);
+    drop(state);
+    send_toast(ctx, "Saved");
+    Ok(())
+}
+
 /// Async vault save — runs serialization + encryption + write on a background thread.
 /// Uses generation IDs to prevent stale saves from overwriting newer data.
 /// Only marks document clean after the write succeeds.
 pub fn perform_vault_save_async(ctx: &EditorCtx, toast: bool) {
+    if ctx.state.borrow().read_only {
+        return;
+    }
     // If a save is already in flight, bump generation so it re-saves when done
     if ctx.saving.get() {
         ctx.save_generation.set(ctx.save_generation.get() + 1);
         return;
     }
+    // Someone else saved since our last load or save: merge that in first
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    if ctx.state.borrow().journal.disk_changed(&vault_folder) {
+        merge_external_changes(ctx);
+        return;
+    }
 
     let snapshot = current_markdown(ctx);
-    let Some((vault_data, key, vault_folder)) = prepare_vault_save(ctx) else {
-        show_error(
-            &ctx.window,
-            "Save Failed",
-            "Vault is not unlocked or no vault folder is configured.",
-        );
-        return;
+    // Closing compacts the journal
+    let mut save = match prepare_vault_save(ctx, ctx.close_requested.get()) {
+        Ok(save) => save,
+        Err(e) => {
+            show_error(&ctx.window, "Save Failed", &e);
+            return;
+        }
     };
 
     let gen = ctx.save_generation.get() + 1;
     ctx.save_generation.set(gen);
     ctx.saving.set(true);
+    // Smart folders are looked up again once per save, not per keystroke
+    if forget_saved_search_hits(ctx) {
+        refresh_note_list(ctx);
+    }
 
-    let (tx, rx) = std::sync::mpsc::channel::<Result<(), String>>();
+    let (tx, rx) = std::sync::mpsc::channel::<Result<PendingSave, String>>();
 
     std::thread::spawn(move || {
-        let result = vault_save_blocking(vault_data, &key, &vault_folder);
+        let result = vault_save_blocking(&mut save).map(|()| save);
         let _ = tx.send(result);
     });
 
     let ctx = ctx.clone();
     glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
         match rx.try_recv() {
-            Ok(Ok(())) => {
+            Ok(Ok(mut saved)) => {
                 ctx.saving.set(false);
-                ctx.last_save_completed.set(std::time::Instant::now());
                 // Only mark clean if no new edits happened during save
                 let current = current_markdown(&ctx);
                 {
@@ -561,8 +693,13 @@ pub fn perform_vault_save_async(ctx: &EditorCtx, toast: bool) {
                     if current == snapshot {
                         state.dirty = false;
                     }
+                    if let Some((revision, _)) = &saved.search_index {
+                        state.search_index.mark_stored(*revision);
+                    }
+                    vault::record_save(&mut state, std::mem::take(&mut saved.marks));
                 }
                 refresh_header(&ctx);
+                unload_closed_notes(&ctx, saved.saved_bodies());
                 if toast && !ctx.close_requested.get() {
                     send_toast(&ctx, "Saved");
                 }
@@ -620,6 +757,18 @@ pub fn stop_background_tasks(ctx: &EditorCtx) {
     if let Some(monitor) = ctx.vault_file_monitor.borrow_mut().take() {
         monitor.cancel();
     }
+    if let Some(source_id) = ctx.external_merge_id.take() {
+        source_id.remove();
+    }
+    release_vault_lock();
+    if let Some(source_id) = ctx.auto_lock_timeout_id.take() {
+        source_id.remove();
+    }
+    if let Some(handler) = ctx.screensaver_handler.borrow_mut().take() {
+        if let Some(app) = ctx.window.application() {
+            app.disconnect(handler);
+        }
+    }
 }
 
 pub fn setup_auto_save(ctx: &EditorCtx) {
@@ -629,6 +778,7 @@ pub fn setup_auto_save(ctx: &EditorCtx) {
 
     let ctx_clone = ctx.clone();
     let source_id = glib::timeout_add_seconds_local(AUTO_SAVE_INTERVAL_SECS, move || {
+        keep_vault_lock(&ctx_clone);
         auto_save_tick(&ctx_clone);
         glib::ControlFlow::Continue
     });
@@ -636,63 +786,690 @@ pub fn setup_auto_save(ctx: &EditorCtx) {
     ctx.auto_save_timeout_id.set(Some(source_id));
 }
 
-/// Watch vault.json for external changes and notify the user.
+pub fn auto_save_tick(ctx: &EditorCtx) {
+    if ctx.state.borrow().read_only {
+        return;
+    }
+    // Flush the source buffer into the note before saving,
+    // in case the debounced buffer-change hasn't fired yet.
+    let markdown = source_buffer_text(&ctx.source_buffer);
+    update_active_note_content(ctx, &markdown);
+
+    // Push a snapshot to version history
+    {
+        let mut state = ctx.state.borrow_mut();
+        if let Some(index) = find_note_index(&state.notes, &state.active_note_id) {
+            let content = state.notes[index].content.clone();
+            pithos_core::notes::push_snapshot(&mut state.notes[index], content);
+        }
+    }
+    perform_vault_save_async(ctx, false);
+}
+
+// ---------------------------------------------------------------------------
+// External changes
+// ---------------------------------------------------------------------------
+
+/// Watch vault.json and the journal for saves by another device or program,
+/// e.g. arriving through a sync tool, and merge them in once they settle.
 pub fn watch_vault_file(ctx: &EditorCtx) {
     if let Some(monitor) = ctx.vault_file_monitor.borrow_mut().take() {
         monitor.cancel();
     }
 
     let vault_folder = ctx.vault_folder.borrow().clone();
-    let vault_path = format!("{vault_folder}/vault.json");
-    let file = gtk::gio::File::for_path(&vault_path);
-    let monitor = match file.monitor_file(
-        gtk::gio::FileMonitorFlags::NONE,
+    let folder = gtk::gio::File::for_path(&vault_folder);
+    let monitor = match folder.monitor_directory(
+        gtk::gio::FileMonitorFlags::WATCH_MOVES,
         None::<&gtk::gio::Cancellable>,
     ) {
         Ok(m) => m,
         Err(e) => {
-            eprintln!("Failed to watch vault file: {e}");
+            eprintln!("Failed to watch vault folder: {e}");
             return;
         }
     };
 
-    let saving = ctx.saving.clone();
-    let last_save_completed = ctx.last_save_completed.clone();
-    let toast_overlay = ctx.toast_overlay.clone();
-    monitor.connect_changed(move |_, _, _, event| {
-        if event == gtk::gio::FileMonitorEvent::ChangesDoneHint {
-            // Ignore changes within 3 seconds of our own save completing,
-            // or while a save is still in flight
-            if saving.get() {
-                return;
+    let ctx_clone = ctx.clone();
+    monitor.connect_changed(move |_, file, other_file, _| {
+        // Both are atomically replaced, so renames count too
+        let touches_vault = std::iter::once(file).chain(other_file).any(|f| {
+            f.basename().is_some_and(|name| {
+                name.as_os_str() == "vault.json" || name.as_os_str() == "journal"
+            })
+        });
+        if touches_vault {
+            schedule_external_merge(&ctx_clone);
+        }
+    });
+
+    *ctx.vault_file_monitor.borrow_mut() = Some(monitor);
+}
+
+/// Merge external changes a second after the files last changed, so a sync
+/// tool delivering several files is picked up at once. Our own saves trigger
+/// this too; they are recognized by the files' fingerprint.
+fn schedule_external_merge(ctx: &EditorCtx) {
+    if let Some(source_id) = ctx.external_merge_id.take() {
+        source_id.remove();
+    }
+    let ctx_clone = ctx.clone();
+    let source_id = glib::timeout_add_local_once(std::time::Duration::from_secs(1), move || {
+        ctx_clone.external_merge_id.set(None);
+        merge_external_changes(&ctx_clone);
+    });
+    ctx.external_merge_id.set(Some(source_id));
+}
+
+/// Load and merge what another writer saved since the vault was loaded or
+/// last saved. Saves wait until the merge is done, so none of it is
+/// overwritten; conflicting notes open in the resolver.
+pub fn merge_external_changes(ctx: &EditorCtx) {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    if ctx.saving.get() {
+        schedule_external_merge(ctx);
+        return;
+    }
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let base = ctx.state.borrow().journal.clone();
+    if !base.disk_changed(&vault_folder) {
+        return;
+    }
+    if let Some(source_id) = ctx.save_timeout_id.take() {
+        source_id.remove();
+    }
+    ctx.saving.set(true);
+
+    let loaded_disk = base.disk;
+    let (tx, rx) = std::sync::mpsc::channel::<Result<DocState, String>>();
+    std::thread::spawn(move || {
+        let result = vault::load_external(&vault_folder, &key, &base).map_err(|e| e.to_string());
+        let _ = tx.send(result);
+    });
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        let result = match rx.try_recv() {
+            Ok(r) => r,
+            Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+            Err(_) => {
+                ctx.saving.set(false);
+                eprintln!("Merge thread disconnected");
+                return glib::ControlFlow::Break;
             }
-            if last_save_completed.get().elapsed() < std::time::Duration::from_secs(3) {
-                return;
+        };
+        ctx.saving.set(false);
+        // Locked, or a blocking save merged and wrote in the meantime
+        if ctx.cached_key.borrow().is_none() {
+            return glib::ControlFlow::Break;
+        }
+        if ctx.state.borrow().journal.disk != loaded_disk {
+            schedule_external_merge(&ctx);
+            return glib::ControlFlow::Break;
+        }
+        match result {
+            Ok(theirs) => {
+                let report = apply_external_changes(&ctx, theirs);
+                if !report.conflicts.is_empty() {
+                    show_conflict_resolver(&ctx, report.conflicts.clone());
+                }
+                if let Some(summary) = merge_summary(&report) {
+                    send_toast(&ctx, &summary);
+                }
+                // Our save, or one waiting for the merge, writes the result
+                if !report.is_empty() || ctx.state.borrow().dirty || ctx.close_requested.get() {
+                    trigger_vault_save(&ctx);
+                }
+            }
+            Err(e) => {
+                keep_unreadable_external(&ctx, &e);
+                trigger_vault_save(&ctx);
             }
-            let toast = adw::Toast::new("Vault changed externally");
-            toast.set_timeout(2);
-            toast_overlay.add_toast(toast);
         }
+        glib::ControlFlow::Break
     });
+}
 
-    *ctx.vault_file_monitor.borrow_mut() = Some(monitor);
+/// Merge external changes right away before a save that can't wait for the
+/// resolver, e.g. when locking. Conflicts keep their text in the notes' history.
+fn merge_external_blocking(ctx: &EditorCtx) {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let base = ctx.state.borrow().journal.clone();
+    if !base.disk_changed(&vault_folder) {
+        return;
+    }
+    match vault::load_external(&vault_folder, &key, &base) {
+        Ok(theirs) => {
+            let report = apply_external_changes(ctx, theirs);
+            if let Some(summary) = merge_summary(&report) {
+                send_toast(ctx, &summary);
+            }
+        }
+        Err(e) => keep_unreadable_external(ctx, &e.to_string()),
+    }
 }
 
-pub fn auto_save_tick(ctx: &EditorCtx) {
-    // Flush the source buffer into the note before saving,
-    // in case the debounced buffer-change hasn't fired yet.
-    let markdown = source_buffer_text(&ctx.source_buffer);
+/// Merge `theirs` into the open vault and bring the editor up to date.
+fn apply_external_changes(ctx: &EditorCtx, theirs: DocState) -> vault::MergeReport {
+    // Typing not yet synced into the note counts as ours
+    let markdown = current_markdown(ctx);
     update_active_note_content(ctx, &markdown);
+    let report = vault::merge_external(&mut ctx.state.borrow_mut(), theirs);
+    if !report.unreadable.is_empty() {
+        keep_unreadable_notes(ctx, &report.unreadable);
+    }
+    if report.is_empty() {
+        return report;
+    }
 
-    // Push a snapshot to version history
+    let (active_id, fallback) = {
+        let state = ctx.state.borrow();
+        let fallback = state
+            .open_tabs
+            .first()
+            .or(state.notes.first().map(|n| &n.id))
+            .cloned();
+        (state.active_note_id.clone(), fallback)
+    };
+    if find_note_index(&ctx.state.borrow().notes, &active_id).is_none() {
+        if let Some(fallback) = fallback {
+            switch_to_note(ctx, &fallback);
+        }
+    } else if ensure_note_loaded(ctx, &active_id) {
+        let (content, updated_at) = {
+            let state = ctx.state.borrow();
+            let note = &state.notes[find_note_index(&state.notes, &active_id).unwrap()];
+            (note.content.clone(), note.updated_at)
+        };
+        if content != markdown {
+            load_document(ctx, &content, None);
+            // Showing their text is not an edit of ours
+            let mut state = ctx.state.borrow_mut();
+            if let Some(index) = find_note_index(&state.notes, &active_id) {
+                state.notes[index].updated_at = updated_at;
+            }
+        }
+    }
+    forget_saved_search_hits(ctx);
+    refresh_tabs(ctx);
+    refresh_note_list(ctx);
+    refresh_tags(ctx);
+    // Their edits to notes we haven't loaded
+    index_unloaded_notes(ctx);
+    report
+}
+
+/// Another writer's files could not be read, e.g. because the vault was given
+/// a new passphrase there. Back them up, then let our next save replace them.
+fn keep_unreadable_external(ctx: &EditorCtx, error: &str) {
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    eprintln!("Could not merge external vault changes: {error}");
+    ctx.state.borrow_mut().journal.stamp_disk(&vault_folder);
+    if ctx.state.borrow().read_only {
+        return;
+    }
+    show_error(
+        &ctx.window,
+        "Could Not Merge Changes",
+        &format!(
+            "The vault was changed by another device or program, but its changes \
+             could not be read: {error}\n\n{}",
+            back_up_theirs(&vault_folder)
+        ),
+    );
+}
+
+/// Notes changed here and by another writer whose text for them could not be
+/// read, e.g. because it hasn't finished syncing. Ours are kept.
+fn keep_unreadable_notes(ctx: &EditorCtx, names: &[String]) {
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    show_error(
+        &ctx.window,
+        "Could Not Merge Notes",
+        &format!(
+            "{} changed here and on another device or program, but the text it \
+             saved could not be read, perhaps because it has not finished syncing.\n\n{}",
+            names.join(", "),
+            back_up_theirs(&vault_folder)
+        ),
+    );
+}
+
+/// Back up the vault as the other writer left it, before our next save
+/// replaces it. Says where their version went.
+fn back_up_theirs(vault_folder: &str) -> &'static str {
+    match vault::create_backup(vault_folder, unix_now()) {
+        Ok(_) => "Their version was kept as a backup, and your changes will be saved over it.",
+        Err(e) => {
+            eprintln!("Backup failed: {e}");
+            "Your changes will be saved over it."
+        }
+    }
+}
+
+fn merge_summary(report: &vault::MergeReport) -> Option<String> {
+    let changed = report.updated.len() + report.added.len() + report.removed.len();
+    Some(match report.conflicts.len() {
+        0 if changed == 0 => return None,
+        0 if changed == 1 => "Merged 1 note changed on another device".to_string(),
+        0 => format!("Merged {changed} notes changed on another device"),
+        1 => "1 note was changed here and on another device".to_string(),
+        n => format!("{n} notes were changed here and on another device"),
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Auto-lock
+// ---------------------------------------------------------------------------
+
+const AUTO_LOCK_CHECK_SECS: u32 = 15;
+
+thread_local! {
+    // Vault folder, active note and the cursor offset of every open tab when
+    // the vault was locked, so unlocking again puts the cursors back.
+    static LOCK_RESUME: RefCell<Option<(String, String, HashMap<String, i32>)>> =
+        const { RefCell::new(None) };
+    // Vault folder and the save that failed as it was locked, written once
+    // the vault is unlocked again.
+    static HELD_SAVE: RefCell<Option<(String, vault::HeldSave)>> = const { RefCell::new(None) };
+}
+
+/// Track input on `root` and lock the vault after the configured idle time or
+/// when the desktop session locks the screen.
+pub fn setup_auto_lock(ctx: &EditorCtx, root: &impl IsA<gtk::Widget>) {
+    let touch = {
+        let last_activity = ctx.last_activity.clone();
+        move || last_activity.set(std::time::Instant::now())
+    };
+    let keys = gtk::EventControllerKey::new();
+    keys.set_propagation_phase(gtk::PropagationPhase::Capture);
+    {
+        let touch = touch.clone();
+        keys.connect_key_pressed(move |_, _, _, _| {
+            touch();
+            glib::Propagation::Proceed
+        });
+    }
+    root.add_controller(keys);
+    let motion = gtk::EventControllerMotion::new();
+    {
+        let touch = touch.clone();
+        motion.connect_motion(move |_, _, _| touch());
+    }
+    root.add_controller(motion);
+    let scroll = gtk::EventControllerScroll::new(gtk::EventControllerScrollFlags::BOTH_AXES);
+    scroll.set_propagation_phase(gtk::PropagationPhase::Capture);
+    scroll.connect_scroll(move |_, _, _| {
+        touch();
+        glib::Propagation::Proceed
+    });
+    root.add_controller(scroll);
+
+    let ctx_tick = ctx.clone();
+    let source_id =
+        glib::timeout_add_seconds_local(AUTO_LOCK_CHECK_SECS, move || auto_lock_tick(&ctx_tick));
+    ctx.auto_lock_timeout_id.set(Some(source_id));
+
+    if let Some(app) = ctx.window.application() {
+        let ctx = ctx.clone();
+        let handler = app.connect_screensaver_active_notify(move |app| {
+            if app.is_screensaver_active() && ctx.state.borrow().lock_on_screen_lock {
+                lock_vault_when_idle(&ctx);
+            }
+        });
+        *ctx.screensaver_handler.borrow_mut() = Some(handler);
+    }
+}
+
+fn auto_lock_tick(ctx: &EditorCtx) -> glib::ControlFlow {
+    let minutes = ctx.state.borrow().auto_lock_minutes;
+    let idle_for = std::time::Duration::from_secs(u64::from(minutes) * 60);
+    if minutes == 0 || ctx.saving.get() || ctx.last_activity.get().elapsed() < idle_for {
+        return glib::ControlFlow::Continue;
+    }
+    // This source ends here; don't let lock_vault remove it a second time.
+    ctx.auto_lock_timeout_id.take();
+    lock_vault(ctx);
+    glib::ControlFlow::Break
+}
+
+/// Lock as soon as no save or re-encryption is running.
+fn lock_vault_when_idle(ctx: &EditorCtx) {
+    if ctx.cached_key.borrow().is_none() {
+        return;
+    }
+    if !ctx.saving.get() {
+        lock_vault(ctx);
+        return;
+    }
+    let ctx = ctx.clone();
+    glib::timeout_add_local_once(std::time::Duration::from_millis(500), move || {
+        lock_vault_when_idle(&ctx)
+    });
+}
+
+/// Save, wipe the key and decrypted notes from memory and return to the
+/// unlock screen. Open tabs are kept in the vault; the cursor is restored by
+/// `restore_lock_resume` after unlocking. A failed save doesn't keep the
+/// vault open: it is held encrypted until the next unlock writes it, and
+/// reported once the vault is locked.
+pub fn lock_vault(ctx: &EditorCtx) {
+    if ctx.cached_key.borrow().is_none() {
+        return;
+    }
+    let save_error = vault_save_sync(ctx).err().map(|(e, save)| {
+        let held = save.map(|save| {
+            vault::hold_save(
+                &save.vault_data,
+                &save.bodies,
+                &save.sub_vaults,
+                &save.key,
+                &save.marks,
+            )
+        });
+        match held {
+            Some(Ok(held)) => {
+                HELD_SAVE.set(Some((ctx.vault_folder.borrow().clone(), held)));
+                format!(
+                    "The vault could not be saved ({e}). Your changes are kept encrypted \
+                     and written when you unlock it again; don't quit Pithos until then."
+                )
+            }
+            Some(Err(held_error)) => format!(
+                "The vault could not be saved ({e}), nor its changes kept until it is \
+                 unlocked ({held_error}). Changes since the last save are lost."
+            ),
+            None => format!(
+                "The vault could not be saved ({e}). Changes since the last save are \
+                 lost."
+            ),
+        }
+    });
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let active_id = ctx.state.borrow().active_note_id.clone();
+    let mut cursors = ctx.tab_cursors.take();
+    cursors.insert(active_id.clone(), ctx.source_buffer.cursor_position());
+    LOCK_RESUME.set(Some((vault_folder.clone(), active_id, cursors)));
+
+    // Clear sensitive key material and stop background timers.
+    stop_background_tasks(ctx);
+    *ctx.cached_key.borrow_mut() = None;
     {
         let mut state = ctx.state.borrow_mut();
-        if let Some(index) = find_note_index(&state.notes, &state.active_note_id) {
-            let content = state.notes[index].content.clone();
-            pithos_core::notes::push_snapshot(&mut state.notes[index], content);
+        vault::wipe_doc_state(&mut state);
+        state.suppress_sync = true;
+    }
+    ctx.archived_index.take();
+    // Clear editor buffer and preview content from memory.
+    ctx.source_buffer.set_text("");
+    ctx.preview_webview.load_html("", None);
+
+    ctx.window.set_content(gtk::Widget::NONE);
+    show_unlock_vault_dialog(&ctx.window, vault_folder);
+    if let Some(e) = save_error {
+        show_error(&ctx.window, "Vault Locked Without Saving", &e);
+    }
+}
+
+/// Write the save held when `vault_folder` was locked, if any, now that it
+/// was unlocked with `key`. Returns the vault JSON as written, to open
+/// instead of what was read before. A save that still can't be written is
+/// kept for the next attempt.
+pub fn write_held_save(
+    vault_folder: &str,
+    key: &crypto::CachedKey,
+) -> Result<Option<String>, String> {
+    let Some((folder, held)) = HELD_SAVE.take() else {
+        return Ok(None);
+    };
+    if folder != vault_folder {
+        HELD_SAVE.set(Some((folder, held)));
+        return Ok(None);
+    }
+    if let Err(e) = vault::write_held_save(vault_folder, &held) {
+        HELD_SAVE.set(Some((folder, held)));
+        return Err(format!(
+            "Changes made before the vault locked still can't be saved: {e}"
+        ));
+    }
+    let raw = vault::read_vault_raw(vault_folder)
+        .map_err(|e| format!("Read error: {e}"))?
+        .ok_or("Vault file not found.")?;
+    crypto::decrypt_vault_fast(&raw, key)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Rebuild the editor around `state` with the same key, after the vault
+/// files were replaced underneath it (e.g. by restoring a backup). Nothing
+/// is saved first: the old in-memory state is wiped, not written back.
+pub fn reopen_vault(ctx: &EditorCtx, state: DocState) {
+    let Some(cached_key) = ctx.cached_key.borrow_mut().take() else {
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    stop_background_tasks(ctx);
+    {
+        let mut old = ctx.state.borrow_mut();
+        vault::wipe_doc_state(&mut old);
+        old.suppress_sync = true;
+    }
+    ctx.archived_index.take();
+    ctx.source_buffer.set_text("");
+    ctx.preview_webview.load_html("", None);
+    ctx.window.set_content(gtk::Widget::NONE);
+
+    let window = ctx.window.clone();
+    glib::timeout_add_local_once(std::time::Duration::from_millis(50), move || {
+        build_editor(&window, state, vault_folder, cached_key);
+    });
+}
+
+/// Put the cursor of every open tab back where it was when this vault was
+/// locked; the other tabs' cursors are placed when they are switched to.
+pub fn restore_lock_resume(ctx: &EditorCtx) {
+    let Some((vault_folder, note_id, mut cursors)) = LOCK_RESUME.take() else {
+        return;
+    };
+    if *ctx.vault_folder.borrow() != vault_folder {
+        return;
+    }
+    let active_id = ctx.state.borrow().active_note_id.clone();
+    let cursor = cursors.remove(&active_id);
+    {
+        let state = ctx.state.borrow();
+        cursors.retain(|id, _| state.open_tabs.contains(id));
+    }
+    *ctx.tab_cursors.borrow_mut() = cursors;
+    let Some(cursor) = cursor.filter(|_| active_id == note_id) else {
+        return;
+    };
+    let iter = ctx.source_buffer.iter_at_offset(cursor);
+    ctx.source_buffer.place_cursor(&iter);
+    let ctx = ctx.clone();
+    glib::idle_add_local_once(move || {
+        let mark = ctx.source_buffer.get_insert();
+        ctx.source_view.scroll_to_mark(&mark, 0.1, false, 0.0, 0.0);
+        ctx.source_view.grab_focus();
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Vault lock
+// ---------------------------------------------------------------------------
+
+thread_local! {
+    // Vault folder whose lock file this instance holds.
+    static HELD_LOCK: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Take the lock of `vault_folder`, releasing the lock of any other vault
+/// this instance held.
+pub fn take_vault_lock(vault_folder: &str) -> Result<(), vault::VaultError> {
+    if HELD_LOCK.with_borrow(|held| held.as_deref() != Some(vault_folder)) {
+        release_vault_lock();
+    }
+    vault::acquire_lock(vault_folder)?;
+    HELD_LOCK.set(Some(vault_folder.to_string()));
+    Ok(())
+}
+
+/// Remove this instance's vault lock once the vault is locked, switched or closed.
+pub fn release_vault_lock() {
+    if let Some(vault_folder) = HELD_LOCK.take() {
+        if let Err(e) = vault::release_lock(&vault_folder) {
+            eprintln!("Failed to remove the vault lock: {e}");
         }
     }
-    perform_vault_save_async(ctx, false);
+}
+
+/// Refresh the vault lock so other instances can tell this one is running.
+/// If one took the vault over anyway, e.g. while this machine was asleep,
+/// save what we have and carry on read-only.
+fn keep_vault_lock(ctx: &EditorCtx) {
+    let Some(vault_folder) = HELD_LOCK.with_borrow(|held| held.clone()) else {
+        return;
+    };
+    match vault::refresh_lock(&vault_folder) {
+        Ok(None) => {}
+        Ok(Some(held)) => {
+            // The vault is theirs now: writing to it would overwrite their work
+            HELD_LOCK.set(None);
+            ctx.state.borrow_mut().read_only = true;
+            apply_read_only(ctx);
+            let message = format!(
+                "Process {} on {} took over this vault while this window was inactive. \
+                 It is read-only here now; open the vault again to edit it.",
+                held.pid, held.hostname
+            );
+            if ctx.state.borrow().dirty {
+                offer_copy_elsewhere(ctx, &message);
+            } else {
+                show_error(&ctx.window, "Vault Opened Elsewhere", &message);
+            }
+        }
+        Err(e) => eprintln!("Failed to refresh the vault lock: {e}"),
+    }
+}
+
+/// The vault went read-only with the active note unsaved: offer to write it
+/// to a file outside the vault instead.
+fn offer_copy_elsewhere(ctx: &EditorCtx, message: &str) {
+    let dialog = adw::AlertDialog::new(
+        Some("Vault Opened Elsewhere"),
+        Some(&format!(
+            "{message}\n\nYour unsaved changes to this note can no longer be saved \
+             to the vault. Save a copy of it elsewhere?"
+        )),
+    );
+    dialog.add_response("cancel", "Don't Save");
+    dialog.add_response("copy", "Save a Copy…");
+    dialog.set_response_appearance("copy", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("copy"));
+    dialog.set_close_response("cancel");
+
+    let ctx_for_response = ctx.clone();
+    dialog.connect_response(None, move |_, response| {
+        if response == "copy" {
+            save_document_as(&ctx_for_response);
+        }
+    });
+    dialog.present(Some(&ctx.window));
+}
+
+// ---------------------------------------------------------------------------
+// Read-only mode
+// ---------------------------------------------------------------------------
+
+thread_local! {
+    // Set by `--read-only`: every vault in this session opens read-only.
+    static READ_ONLY_SESSION: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn set_read_only_session() {
+    READ_ONLY_SESSION.set(true);
+}
+
+pub fn read_only_session() -> bool {
+    READ_ONLY_SESSION.get()
+}
+
+/// Window actions that change the vault, disabled while it is read-only.
+/// Browsing, search, export, checks and view toggles stay available; the
+/// dialogs behind the checks, backups and history hide what would write.
+const VAULT_EDIT_ACTIONS: &[&str] = &[
+    "new-note",
+    "new-folder",
+    "new-smart-folder",
+    "new-from-template",
+    "daily-note",
+    "import-file",
+    "import-bundle",
+    "rename-note",
+    "move-to-folder",
+    "delete-note",
+    "empty-trash",
+    "save-snapshot",
+    "save-vault",
+    "undo",
+    "redo",
+    "fmt-bold",
+    "fmt-italic",
+    "fmt-underline",
+    "fmt-strike",
+    "fmt-code",
+    "fmt-link",
+    "fmt-h1",
+    "fmt-h2",
+    "fmt-h3",
+    "fmt-h4",
+    "fmt-h5",
+    "fmt-h6",
+    "fmt-quote",
+    "fmt-bullet-list",
+    "fmt-ordered-list",
+    "fmt-task-list",
+    "toggle-checkbox",
+    "replace-one",
+    "replace-all",
+    "table-add-row",
+    "table-add-column",
+    "table-align",
+    "change-passphrase",
+    "unlock-methods",
+    "upgrade-encryption",
+];
+
+/// Put the editor into read-only mode: the text can't be edited and every
+/// action that would change the vault is disabled. The save paths and
+/// auto-save already skip a read-only state; this covers the UI.
+pub fn apply_read_only(ctx: &EditorCtx) {
+    ctx.source_view.set_editable(false);
+    ctx.toolbar.set_sensitive(false);
+    ctx.tag_entry.set_sensitive(false);
+    ctx.tags_box.set_sensitive(false);
+    ctx.replace_row.set_visible(false);
+    for name in VAULT_EDIT_ACTIONS {
+        if let Some(action) = ctx
+            .window
+            .lookup_action(name)
+            .and_downcast::<gtk::gio::SimpleAction>()
+        {
+            action.set_enabled(false);
+        }
+    }
+    let vault_folder = ctx.vault_folder.borrow();
+    let vault_display = std::path::Path::new(vault_folder.as_str())
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("Vault");
+    ctx.vault_name_label
+        .set_label(&format!("{vault_display} (read-only)"));
 }
 
 // ---------------------------------------------------------------------------
@@ -705,8 +1482,8 @@ pub fn wire_close_request(ctx: &EditorCtx) {
     win.connect_close_request(move |_window| {
         gtk::prelude::GtkWindowExt::set_focus(_window, gtk::Widget::NONE);
         let has_vault = ctx.cached_key.borrow().is_some() && !ctx.vault_folder.borrow().is_empty();
-        if !has_vault {
-            // No vault configured — just close
+        if !has_vault || ctx.state.borrow().read_only {
+            // No vault configured, or nothing to save — just close
             return glib::Propagation::Proceed;
         }
 
@@ -1,7 +1,5 @@
 use crate::*;
-use pithos_core::crypto;
 use pithos_core::vault;
-use std::fs;
 
 /// Generate a random base64 nonce for Content-Security-Policy script-src.
 fn generate_csp_nonce() -> String {
@@ -76,25 +74,26 @@ fn resolve_single_asset(asset_id: &str, ctx: &EditorCtx) -> String {
     }
 
     let vault_folder = ctx.vault_folder.borrow().clone();
-    let mime_type = ctx
-        .state
-        .borrow()
-        .assets
-        .get(asset_id)
+    let state = ctx.state.borrow();
+    let meta = state.assets.get(asset_id);
+    let mime_type = meta
         .map(|m| m.mime_type.clone())
         .unwrap_or_else(|| "image/png".to_string());
 
-    let asset_path = vault::assets_dir(&vault_folder).join(asset_id);
-    let Ok(raw_data) = fs::read(&asset_path) else {
+    let cached_key_ref = ctx.cached_key.borrow();
+    let Some(main_key) = cached_key_ref.as_ref() else {
         return String::new();
     };
-
-    let cached_key_ref = ctx.cached_key.borrow();
-    let Some(cached_key) = cached_key_ref.as_ref() else {
+    let stored_in = meta.and_then(|m| m.stored_in.as_deref());
+    let Ok((folder, cached_key)) =
+        vault::blob_location(&vault_folder, &state.folder_keys, main_key, stored_in)
+    else {
         return String::new();
     };
 
-    match crypto::decrypt_asset(&raw_data, cached_key) {
+    // Unlisted assets have nothing to check their binding against
+    let binding = meta.and_then(|m| vault::asset_binding(&state.vault_id, m));
+    match vault::read_asset(&folder, asset_id, cached_key, binding.as_ref()) {
         Ok(decrypted) => {
             let b64 =
                 base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &decrypted);
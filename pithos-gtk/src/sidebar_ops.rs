@@ -1,8 +1,13 @@
 use crate::*;
 use adw::prelude::*;
-use pithos_core::search::note_matches_query;
+use pithos_core::bundle::ShareTarget;
+use pithos_core::search::{
+    ArchivedId, ArchivedIndex, ArchivedText, BodyTerms, Highlighter, Query, QueryError, SearchHit,
+};
 use pithos_core::state::*;
+use pithos_core::vault;
 use std::collections::HashMap;
+use std::ops::Range;
 
 // ---------------------------------------------------------------------------
 // Sidebar signal wiring
@@ -53,6 +58,10 @@ pub fn wire_sidebar_signals(
                     switch_to_note(&ctx, &note_id);
                 }
                 Some(SidebarRowKind::Folder(folder_id)) => {
+                    if !ctx.state.borrow().is_folder_accessible(Some(&folder_id)) {
+                        show_unlock_folder_dialog(&ctx, &folder_id);
+                        return;
+                    }
                     {
                         let mut state = ctx.state.borrow_mut();
                         if let Some(f) = state.folders.iter_mut().find(|f| f.id == folder_id) {
@@ -63,6 +72,21 @@ pub fn wire_sidebar_signals(
                     refresh_note_list(&ctx);
                     trigger_vault_save(&ctx);
                 }
+                Some(SidebarRowKind::SavedSearch(search_id)) => {
+                    {
+                        let mut state = ctx.state.borrow_mut();
+                        if let Some(s) = state.saved_searches.iter_mut().find(|s| s.id == search_id)
+                        {
+                            s.expanded = !s.expanded;
+                        }
+                    }
+                    ctx.saved_search_hits.borrow_mut().remove(&search_id);
+                    refresh_note_list(&ctx);
+                    trigger_vault_save(&ctx);
+                }
+                Some(SidebarRowKind::Archived(source)) => {
+                    show_archived_text(&ctx, &source);
+                }
                 None => {}
             }
         });
@@ -74,8 +98,8 @@ pub fn wire_sidebar_signals(
             .button(3) // right-click
             .build();
         click.connect_released(move |gesture, _, x, y| {
-            let viewing_trash = ctx.state.borrow().viewing_trash;
-            if viewing_trash {
+            // Read-only menus still open, with the items that edit disabled
+            if ctx.state.borrow().viewing_trash {
                 return;
             }
             let Some(widget) = gesture.widget() else {
@@ -102,7 +126,10 @@ pub fn wire_sidebar_signals(
                 Some(SidebarRowKind::Folder(folder_id)) => {
                     show_folder_context_menu(&ctx, &folder_id, x, y, &widget);
                 }
-                None => {}
+                Some(SidebarRowKind::SavedSearch(search_id)) => {
+                    show_saved_search_context_menu(&ctx, &search_id, x, y, &widget);
+                }
+                Some(SidebarRowKind::Archived(_)) | None => {}
             }
         });
         notes_list.add_controller(click);
@@ -204,6 +231,9 @@ fn move_note_to_parent(
     new_parent_id: Option<String>,
     target_note_id: Option<&str>,
 ) -> bool {
+    if !state.is_folder_accessible(new_parent_id.as_deref()) {
+        return false;
+    }
     let Some(mut from_idx) = state.notes.iter().position(|n| n.id == note_id) else {
         return false;
     };
@@ -251,7 +281,19 @@ fn move_folder_to_parent(
     new_parent_id: Option<String>,
     target_folder_id: Option<&str>,
 ) -> bool {
-    if would_create_folder_cycle(state, folder_id, new_parent_id.as_deref()) {
+    if would_create_folder_cycle(state, folder_id, new_parent_id.as_deref())
+        || !state.is_folder_accessible(new_parent_id.as_deref())
+    {
+        return false;
+    }
+    // Locked folders don't nest
+    let into_locked = state.locked_root(new_parent_id.as_deref()).is_some();
+    if into_locked
+        && state
+            .folders
+            .iter()
+            .any(|f| f.locked && state.folder_is_within(&f.id, folder_id))
+    {
         return false;
     }
 
@@ -303,7 +345,14 @@ fn apply_sidebar_drop(
 ) -> bool {
     let mut state = ctx.state.borrow_mut();
 
-    if state.viewing_trash || !state.search_query.trim().is_empty() || !state.filter_tags.is_empty()
+    if state.read_only
+        || state.viewing_trash
+        || !state.search_query.trim().is_empty()
+        || !state.filter_tags.is_empty()
+        || matches!(
+            target,
+            Some(SidebarRowKind::SavedSearch(_) | SidebarRowKind::Archived(_))
+        )
     {
         return false;
     }
@@ -320,7 +369,9 @@ fn apply_sidebar_drop(
                         .and_then(|n| n.parent_id.clone());
                     (parent, Some(target_note_id))
                 }
-                None => (None, None),
+                Some(SidebarRowKind::SavedSearch(_) | SidebarRowKind::Archived(_)) | None => {
+                    (None, None)
+                }
             };
             move_note_to_parent(&mut state, &note_id, new_parent, target_note.as_deref())
         }
@@ -337,10 +388,13 @@ fn apply_sidebar_drop(
                         .and_then(|n| n.parent_id.clone());
                     (parent, None)
                 }
-                None => (None, None),
+                Some(SidebarRowKind::SavedSearch(_) | SidebarRowKind::Archived(_)) | None => {
+                    (None, None)
+                }
             };
             move_folder_to_parent(&mut state, &folder_id, new_parent, target_folder.as_deref())
         }
+        SidebarRowKind::SavedSearch(_) | SidebarRowKind::Archived(_) => false,
     };
 
     if changed {
@@ -375,6 +429,9 @@ pub fn show_note_context_menu(
     let move_btn = gtk::Button::with_label("Move to Folder\u{2026}");
     move_btn.add_css_class("flat");
     move_btn.set_halign(gtk::Align::Fill);
+    let share_btn = gtk::Button::with_label("Share as Encrypted Bundle\u{2026}");
+    share_btn.add_css_class("flat");
+    share_btn.set_halign(gtk::Align::Fill);
     let pin_btn = {
         let state = ctx.state.borrow();
         let pinned = find_note_index(&state.notes, note_id)
@@ -388,10 +445,15 @@ pub fn show_note_context_menu(
     };
     pin_btn.add_css_class("flat");
     pin_btn.set_halign(gtk::Align::Fill);
+    let editable = !ctx.state.borrow().read_only;
+    for btn in [&rename_btn, &pin_btn, &move_btn, &delete_btn] {
+        btn.set_sensitive(editable);
+    }
 
     vbox.append(&rename_btn);
     vbox.append(&pin_btn);
     vbox.append(&move_btn);
+    vbox.append(&share_btn);
     vbox.append(&delete_btn);
 
     let popover = gtk::Popover::new();
@@ -430,6 +492,15 @@ pub fn show_note_context_menu(
             move_note_to_folder(&ctx);
         });
     }
+    {
+        let ctx = ctx.clone();
+        let popover = popover.clone();
+        let note_id = note_id.to_string();
+        share_btn.connect_clicked(move |_| {
+            popover.popdown();
+            share_as_bundle(&ctx, ShareTarget::Note(note_id.clone()));
+        });
+    }
     {
         let ctx = ctx.clone();
         let popover = popover.clone();
@@ -476,15 +547,66 @@ pub fn show_folder_context_menu(
     new_subfolder_btn.add_css_class("flat");
     new_subfolder_btn.set_halign(gtk::Align::Fill);
 
+    let share_btn = gtk::Button::with_label("Share as Encrypted Bundle\u{2026}");
+    share_btn.add_css_class("flat");
+    share_btn.set_halign(gtk::Align::Fill);
+
     let delete_btn = gtk::Button::with_label("Delete Folder");
     delete_btn.add_css_class("flat");
     delete_btn.add_css_class("destructive-action");
     delete_btn.set_halign(gtk::Align::Fill);
 
-    vbox.append(&rename_btn);
-    vbox.append(&new_note_btn);
-    vbox.append(&new_subfolder_btn);
-    vbox.append(&delete_btn);
+    let (locked, unlocked, can_lock) = {
+        let state = ctx.state.borrow();
+        let locked = state.folders.iter().any(|f| f.id == folder_id && f.locked);
+        let can_lock = !locked
+            && state.locked_root(Some(folder_id)).is_none()
+            && !state
+                .folders
+                .iter()
+                .any(|f| f.locked && state.folder_is_within(&f.id, folder_id));
+        (locked, state.is_folder_unlocked(folder_id), can_lock)
+    };
+    let lock_btn = gtk::Button::with_label(match (locked, unlocked) {
+        (false, _) => "Lock Folder…",
+        (true, false) => "Unlock Folder…",
+        (true, true) => "Lock Folder Now",
+    });
+    lock_btn.add_css_class("flat");
+    lock_btn.set_halign(gtk::Align::Fill);
+
+    let remove_lock_btn = gtk::Button::with_label("Remove Lock…");
+    remove_lock_btn.add_css_class("flat");
+    remove_lock_btn.set_halign(gtk::Align::Fill);
+    // Unlocking and locking an unlocked folder again don't change the vault
+    let editable = !ctx.state.borrow().read_only;
+    for btn in [
+        &rename_btn,
+        &new_note_btn,
+        &new_subfolder_btn,
+        &remove_lock_btn,
+        &delete_btn,
+    ] {
+        btn.set_sensitive(editable);
+    }
+    lock_btn.set_sensitive(editable || locked);
+
+    if locked && !unlocked {
+        vbox.append(&lock_btn);
+        vbox.append(&rename_btn);
+    } else {
+        vbox.append(&rename_btn);
+        vbox.append(&new_note_btn);
+        vbox.append(&new_subfolder_btn);
+        if locked {
+            vbox.append(&lock_btn);
+            vbox.append(&remove_lock_btn);
+        } else if can_lock {
+            vbox.append(&lock_btn);
+        }
+        vbox.append(&share_btn);
+        vbox.append(&delete_btn);
+    }
 
     let popover = gtk::Popover::new();
     popover.set_child(Some(&vbox));
@@ -529,6 +651,15 @@ pub fn show_folder_context_menu(
             create_folder(&ctx, Some(folder_id.clone()));
         });
     }
+    {
+        let ctx = ctx.clone();
+        let popover = popover.clone();
+        let folder_id = folder_id.to_string();
+        share_btn.connect_clicked(move |_| {
+            popover.popdown();
+            share_as_bundle(&ctx, ShareTarget::Folder(folder_id.clone()));
+        });
+    }
     {
         let ctx = ctx.clone();
         let popover = popover.clone();
@@ -538,6 +669,28 @@ pub fn show_folder_context_menu(
             delete_folder(&ctx, &folder_id);
         });
     }
+    {
+        let ctx = ctx.clone();
+        let popover = popover.clone();
+        let folder_id = folder_id.to_string();
+        lock_btn.connect_clicked(move |_| {
+            popover.popdown();
+            match (locked, unlocked) {
+                (false, _) => show_lock_folder_dialog(&ctx, &folder_id),
+                (true, false) => show_unlock_folder_dialog(&ctx, &folder_id),
+                (true, true) => lock_folder_now(&ctx, &folder_id),
+            }
+        });
+    }
+    {
+        let ctx = ctx.clone();
+        let popover = popover.clone();
+        let folder_id = folder_id.to_string();
+        remove_lock_btn.connect_clicked(move |_| {
+            popover.popdown();
+            remove_folder_lock(&ctx, &folder_id);
+        });
+    }
 
     popover.popup();
 }
@@ -608,6 +761,10 @@ pub fn rename_folder_dialog(ctx: &EditorCtx, folder_id: &str) {
 }
 
 pub fn delete_folder(ctx: &EditorCtx, folder_id: &str) {
+    if !ctx.state.borrow().is_folder_accessible(Some(folder_id)) {
+        send_toast(ctx, "Unlock the folder first");
+        return;
+    }
     let dialog = adw::AlertDialog::new(
         Some("Delete Folder?"),
         Some("Notes inside this folder will be moved to the root level"),
@@ -726,47 +883,79 @@ pub fn refresh_note_list(ctx: &EditorCtx) {
         ctx.notes_list.remove(&row);
     }
 
-    let (mut visible, active_id, sort_order, search_query, highlight_term, folders, filter_active) = {
+    // The search and the tag filter as one query; `None` if neither is set
+    let filter = {
         let state = ctx.state.borrow();
-        let raw_query = state.search_query.clone();
-        let filter_tags = state.filter_tags.clone();
-        let tag_filter_and = state.tag_filter_and;
-        let has_search = !raw_query.trim().is_empty();
-        let filter_active = has_search || !filter_tags.is_empty();
-
-        let highlight_term = if has_search {
-            raw_query.trim().to_lowercase()
+        if state.search_query.trim().is_empty() && state.filter_tags.is_empty() {
+            None
         } else {
-            String::new()
-        };
+            Some(Query::parse(&state.search_query).map(|mut query| {
+                query.add_tag_filter(&state.filter_tags, state.tag_filter_and);
+                query
+            }))
+        }
+    };
+    let query_error = filter
+        .as_ref()
+        .and_then(|filter| filter.as_ref().err())
+        .map(ToString::to_string);
+    ctx.search_entry.set_tooltip_text(query_error.as_deref());
+    if query_error.is_some() {
+        ctx.search_entry.add_css_class("error");
+    } else {
+        ctx.search_entry.remove_css_class("error");
+    }
+    // A history or trash search lists the snapshots or trashed notes it finds
+    let scope = ctx.state.borrow().search_scope;
+    if let (SearchScope::History | SearchScope::Trash, Some(Ok(query))) = (scope, &filter) {
+        render_archived_hits(ctx, query, scope);
+        return;
+    }
+    let (scores, highlighter): (HashMap<String, f64>, Option<Highlighter>) = match &filter {
+        Some(Ok(query)) => {
+            let scores = search_notes(ctx, query)
+                .into_iter()
+                .map(|hit| (hit.id, hit.score))
+                .collect();
+            let highlighter = ctx.state.borrow().search_index.highlighter(query);
+            (scores, Some(highlighter).filter(|h| !h.is_empty()))
+        }
+        _ => (HashMap::new(), None),
+    };
+
+    let (mut visible, active_id, sort_order, folders, filter_active) = {
+        let state = ctx.state.borrow();
+        let filter_active = filter.is_some();
 
         let visible: Vec<NoteSummary> = state
             .notes
             .iter()
-            .filter(|note| {
-                let matches_search = if has_search {
-                    note_matches_query(note, &raw_query)
-                } else {
-                    true
-                };
-                let matches_tags = if filter_tags.is_empty() {
-                    true
-                } else if tag_filter_and {
-                    filter_tags.iter().all(|ft| note.tags.contains(ft))
-                } else {
-                    filter_tags.iter().any(|ft| note.tags.contains(ft))
-                };
-                matches_search && matches_tags
-            })
-            .map(|note| NoteSummary {
-                id: note.id.clone(),
-                name: note.name.clone(),
-                content_snippet: make_search_snippet(&note.content, &highlight_term),
-                tags: note.tags.clone(),
-                created_at: note.created_at,
-                updated_at: note.updated_at,
-                pinned: note.pinned,
-                parent_id: note.parent_id.clone(),
+            .filter_map(|note| {
+                if filter_active && !scores.contains_key(&note.id) {
+                    return None;
+                }
+                // A body not in memory is decrypted for its snippet alone, and
+                // wiped as `stored` drops
+                let snippet = highlighter.as_ref().and_then(|h| {
+                    let stored;
+                    let content = if note.loaded {
+                        note.content.as_str()
+                    } else {
+                        stored = read_stored_body(ctx, &note.id, note.stored_in.as_deref())?;
+                        stored.content.as_str()
+                    };
+                    make_search_snippet(content, &h.ranges(content))
+                });
+                Some(NoteSummary {
+                    id: note.id.clone(),
+                    name: note.name.clone(),
+                    content_snippet: snippet,
+                    tags: note.tags.clone(),
+                    created_at: note.created_at,
+                    updated_at: note.updated_at,
+                    pinned: note.pinned,
+                    parent_id: note.parent_id.clone(),
+                })
             })
             .collect();
 
@@ -774,21 +963,23 @@ pub fn refresh_note_list(ctx: &EditorCtx) {
             visible,
             state.active_note_id.clone(),
             state.sort_order,
-            raw_query,
-            highlight_term,
             state.folders.clone(),
             filter_active,
         )
     };
 
     apply_note_sort(&mut visible, sort_order);
+    // Best match first; notes only filters match stay in the sort order
+    if scores.values().any(|score| *score > 0.0) {
+        visible.sort_by(|a, b| scores[&b.id].total_cmp(&scores[&a.id]));
+    }
 
     let mut row_items: Vec<SidebarRowKind> = Vec::new();
 
     if filter_active {
         // Flat mode during search — no folder hierarchy
         for note in &visible {
-            let row = build_note_row(ctx, note, &active_id, &highlight_term, 0);
+            let row = build_note_row(ctx, note, &active_id, highlighter.as_ref(), 0);
             ctx.notes_list.append(&row);
             row_items.push(SidebarRowKind::Note(note.id.clone()));
         }
@@ -820,6 +1011,8 @@ pub fn refresh_note_list(ctx: &EditorCtx) {
             }
         }
 
+        // Smart folders above the folder tree
+        render_saved_searches(ctx, &visible, &active_id, &mut row_items);
         render_tree_level(
             ctx,
             None,
@@ -827,7 +1020,7 @@ pub fn refresh_note_list(ctx: &EditorCtx) {
             &folders_by_parent,
             &notes_by_parent,
             &active_id,
-            &search_query,
+            None,
             &mut row_items,
         );
     }
@@ -841,53 +1034,675 @@ pub fn refresh_note_list(ctx: &EditorCtx) {
 }
 
 // ---------------------------------------------------------------------------
-// Search highlighting
+// Smart folders
 // ---------------------------------------------------------------------------
 
-pub fn highlight_search(text: &str, query: &str) -> String {
-    if query.is_empty() {
-        return glib::markup_escape_text(text).to_string();
-    }
-    let lower = text.to_lowercase();
-    // Map each byte offset in `lower` back to the corresponding byte offset in `text`.
-    let mut lower_to_orig: Vec<usize> = Vec::with_capacity(lower.len() + 1);
-    for (orig_byte, orig_ch) in text.char_indices() {
-        let lc: String = orig_ch.to_lowercase().collect();
-        for _ in 0..lc.len() {
-            lower_to_orig.push(orig_byte);
+/// Sort orders a smart folder can have, as the sidebar menu names them.
+const SMART_FOLDER_SORTS: [(SortOrder, &str); 7] = [
+    (SortOrder::Manual, "Manual"),
+    (SortOrder::ModifiedDesc, "Modified \u{2193}"),
+    (SortOrder::ModifiedAsc, "Modified \u{2191}"),
+    (SortOrder::NameAsc, "Name A\u{2192}Z"),
+    (SortOrder::NameDesc, "Name Z\u{2192}A"),
+    (SortOrder::CreatedDesc, "Created \u{2193}"),
+    (SortOrder::CreatedAsc, "Created \u{2191}"),
+];
+
+/// A row for each smart folder, followed by the notes its search finds now
+/// if it is expanded. Collapsed ones aren't searched, as a search may have
+/// to decrypt note bodies.
+fn render_saved_searches(
+    ctx: &EditorCtx,
+    notes: &[NoteSummary],
+    active_id: &str,
+    row_items: &mut Vec<SidebarRowKind>,
+) {
+    let searches = ctx.state.borrow().saved_searches.clone();
+    let by_id: HashMap<&str, &NoteSummary> = notes.iter().map(|n| (n.id.as_str(), n)).collect();
+    for search in &searches {
+        let found = search.expanded.then(|| saved_search_hits(ctx, search));
+        ctx.notes_list
+            .append(&build_saved_search_row(search, found.as_ref()));
+        row_items.push(SidebarRowKind::SavedSearch(search.id.clone()));
+
+        let Some(Ok(hits)) = found else {
+            continue;
+        };
+        let scores: HashMap<&str, f64> = hits.iter().map(|h| (h.id.as_str(), h.score)).collect();
+        let mut found_notes: Vec<&NoteSummary> = hits
+            .iter()
+            .filter_map(|hit| by_id.get(hit.id.as_str()).copied())
+            .collect();
+        apply_note_sort_refs(&mut found_notes, search.sort_order);
+        if scores.values().any(|score| *score > 0.0) {
+            found_notes.sort_by(|a, b| scores[b.id.as_str()].total_cmp(&scores[a.id.as_str()]));
+        }
+        for note in found_notes {
+            ctx.notes_list
+                .append(&build_note_row(ctx, note, active_id, None, 1));
+            row_items.push(SidebarRowKind::Note(note.id.clone()));
+        }
+    }
+}
+
+/// Notes `search` finds, kept from its last lookup until the next save
+/// starts. Notes deleted since are left out by the caller.
+fn saved_search_hits(ctx: &EditorCtx, search: &SavedSearch) -> Result<Vec<SearchHit>, QueryError> {
+    if let Some(found) = ctx.saved_search_hits.borrow().get(&search.id) {
+        return found.clone();
+    }
+    let found = search.to_query().map(|query| search_notes(ctx, &query));
+    ctx.saved_search_hits
+        .borrow_mut()
+        .insert(search.id.clone(), found.clone());
+    found
+}
+
+/// Look smart folders up again on the next refresh. Returns true if any
+/// were expanded, so the note list needs that refresh.
+pub fn forget_saved_search_hits(ctx: &EditorCtx) -> bool {
+    let mut hits = ctx.saved_search_hits.borrow_mut();
+    let any = !hits.is_empty();
+    hits.clear();
+    any
+}
+
+/// `found` is `None` for a collapsed smart folder.
+fn build_saved_search_row(
+    search: &SavedSearch,
+    found: Option<&Result<Vec<SearchHit>, QueryError>>,
+) -> gtk::ListBoxRow {
+    let row = gtk::ListBoxRow::new();
+    row.set_widget_name(&format!("saved-search:{}", search.id));
+    let mut summary = search.query.clone();
+    for tag in &search.filter_tags {
+        summary.push_str(&format!(" #{tag}"));
+    }
+    row.set_tooltip_text(Some(summary.trim()));
+
+    let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+    row_box.set_margin_start(8);
+    row_box.set_margin_end(8);
+    row_box.set_margin_top(4);
+    row_box.set_margin_bottom(4);
+
+    let chevron = gtk::Image::from_icon_name(if search.expanded {
+        "pan-down-symbolic"
+    } else {
+        "pan-end-symbolic"
+    });
+    chevron.set_pixel_size(16);
+    chevron.add_css_class("folder-row-chevron");
+    row_box.append(&chevron);
+
+    let icon = gtk::Image::from_icon_name("folder-saved-search-symbolic");
+    icon.set_pixel_size(16);
+    icon.add_css_class("folder-row-icon");
+    row_box.append(&icon);
+
+    let name_label = gtk::Label::new(Some(&search.name));
+    name_label.set_xalign(0.0);
+    name_label.set_hexpand(true);
+    name_label.add_css_class("folder-row");
+    name_label.set_ellipsize(gtk::pango::EllipsizeMode::End);
+    row_box.append(&name_label);
+
+    match found {
+        Some(Ok(hits)) => {
+            let count_label = gtk::Label::new(Some(&hits.len().to_string()));
+            count_label.add_css_class("folder-row-count");
+            count_label.add_css_class("dim-label");
+            count_label.add_css_class("caption");
+            row_box.append(&count_label);
+        }
+        Some(Err(e)) => {
+            let warning = gtk::Image::from_icon_name("dialog-warning-symbolic");
+            warning.set_pixel_size(14);
+            warning.set_tooltip_text(Some(&e.to_string()));
+            row_box.append(&warning);
         }
+        None => {}
     }
-    lower_to_orig.push(text.len());
 
-    let lower_query = query.to_lowercase();
+    row.set_child(Some(&row_box));
+    row
+}
+
+pub fn show_saved_search_context_menu(
+    ctx: &EditorCtx,
+    search_id: &str,
+    x: f64,
+    y: f64,
+    widget: &gtk::Widget,
+) {
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    vbox.set_margin_start(6);
+    vbox.set_margin_end(6);
+    vbox.set_margin_top(6);
+    vbox.set_margin_bottom(6);
+
+    let edit_btn = gtk::Button::with_label("Edit Smart Folder\u{2026}");
+    edit_btn.add_css_class("flat");
+    edit_btn.set_halign(gtk::Align::Fill);
+    vbox.append(&edit_btn);
+
+    let delete_btn = gtk::Button::with_label("Delete Smart Folder");
+    delete_btn.add_css_class("flat");
+    delete_btn.add_css_class("destructive-action");
+    delete_btn.set_halign(gtk::Align::Fill);
+    vbox.append(&delete_btn);
+    let editable = !ctx.state.borrow().read_only;
+    edit_btn.set_sensitive(editable);
+    delete_btn.set_sensitive(editable);
+
+    let popover = gtk::Popover::new();
+    popover.set_child(Some(&vbox));
+    popover.set_parent(widget);
+    popover.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+    popover.connect_closed(|p| p.unparent());
+
+    {
+        let ctx = ctx.clone();
+        let popover = popover.clone();
+        let search_id = search_id.to_string();
+        edit_btn.connect_clicked(move |_| {
+            popover.popdown();
+            show_saved_search_dialog(&ctx, Some(&search_id));
+        });
+    }
+    {
+        let ctx = ctx.clone();
+        let popover = popover.clone();
+        let search_id = search_id.to_string();
+        delete_btn.connect_clicked(move |_| {
+            popover.popdown();
+            delete_saved_search(&ctx, &search_id);
+        });
+    }
+
+    popover.popup();
+}
+
+/// Edit the smart folder `search_id`, or with `None`, save the current
+/// search, tag filter and sort order as a new one.
+pub fn show_saved_search_dialog(ctx: &EditorCtx, search_id: Option<&str>) {
+    let search = {
+        let state = ctx.state.borrow();
+        search_id
+            .and_then(|id| state.saved_searches.iter().find(|s| s.id == id).cloned())
+            .unwrap_or_else(|| SavedSearch {
+                id: String::new(),
+                name: String::new(),
+                query: state.search_query.trim().to_string(),
+                filter_tags: state.filter_tags.clone(),
+                tag_filter_and: state.tag_filter_and,
+                sort_order: state.sort_order,
+                expanded: true,
+            })
+    };
+    let editing = !search.id.is_empty();
+
+    let dialog = adw::AlertDialog::new(
+        Some(if editing {
+            "Edit Smart Folder"
+        } else {
+            "New Smart Folder"
+        }),
+        Some("Notes matching the search are listed above your folders and kept up to date as notes change"),
+    );
+
+    let vbox = gtk::Box::new(gtk::Orientation::Vertical, 8);
+    let name_entry = gtk::Entry::new();
+    name_entry.set_placeholder_text(Some("Name"));
+    name_entry.set_text(&search.name);
+    name_entry.set_activates_default(true);
+    vbox.append(&name_entry);
+
+    let query_entry = gtk::Entry::new();
+    query_entry.set_placeholder_text(Some("Search, e.g. tag:threat-model is:todo"));
+    query_entry.set_text(&search.query);
+    query_entry.set_activates_default(true);
+    vbox.append(&query_entry);
+
+    let tags_entry = gtk::Entry::new();
+    tags_entry.set_placeholder_text(Some("Tags, separated by commas"));
+    tags_entry.set_text(&search.filter_tags.join(", "));
+    tags_entry.set_activates_default(true);
+    vbox.append(&tags_entry);
+
+    let all_tags_check = gtk::CheckButton::with_label("Notes need all of the tags");
+    all_tags_check.set_active(search.tag_filter_and);
+    vbox.append(&all_tags_check);
+
+    let sort_labels: Vec<&str> = SMART_FOLDER_SORTS.iter().map(|(_, label)| *label).collect();
+    let sort_dropdown = gtk::DropDown::from_strings(&sort_labels);
+    let sort_index = SMART_FOLDER_SORTS
+        .iter()
+        .position(|(order, _)| *order == search.sort_order)
+        .unwrap_or(0);
+    sort_dropdown.set_selected(sort_index as u32);
+    sort_dropdown.set_tooltip_text(Some("Sort Order"));
+    vbox.append(&sort_dropdown);
+    dialog.set_extra_child(Some(&vbox));
+
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("save", if editing { "Save" } else { "Create" });
+    dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("save"));
+    dialog.set_close_response("cancel");
+
+    let window = ctx.window.clone();
+    let ctx = ctx.clone();
+    dialog.connect_response(None, move |dlg, response| {
+        dlg.set_extra_child(gtk::Widget::NONE);
+        if response != "save" {
+            return;
+        }
+        let mut search = search.clone();
+        search.name = name_entry.text().trim().to_string();
+        search.query = query_entry.text().trim().to_string();
+        search.filter_tags = tags_entry
+            .text()
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches('#').to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        search.tag_filter_and = all_tags_check.is_active();
+        search.sort_order = SMART_FOLDER_SORTS
+            .get(sort_dropdown.selected() as usize)
+            .map_or(SortOrder::ModifiedDesc, |(order, _)| *order);
+        if search.name.is_empty() {
+            send_toast(&ctx, "Give the smart folder a name");
+            return;
+        }
+        if let Err(e) = search.to_query() {
+            send_toast(&ctx, &e.to_string());
+            return;
+        }
+        {
+            let mut state = ctx.state.borrow_mut();
+            if let Some(existing) = state.saved_searches.iter_mut().find(|s| s.id == search.id) {
+                *existing = search;
+                ctx.saved_search_hits.borrow_mut().remove(&existing.id);
+            } else {
+                search.id = SavedSearch::next_id(&state.saved_searches);
+                state.saved_searches.push(search);
+            }
+        }
+        refresh_note_list(&ctx);
+        trigger_vault_save(&ctx);
+    });
+    dialog.present(Some(&window));
+}
+
+pub fn delete_saved_search(ctx: &EditorCtx, search_id: &str) {
+    let dialog = adw::AlertDialog::new(
+        Some("Delete Smart Folder?"),
+        Some("Only the saved search is deleted; its notes stay where they are"),
+    );
+
+    dialog.add_response("cancel", "Cancel");
+    dialog.add_response("delete", "Delete");
+    dialog.set_response_appearance("delete", adw::ResponseAppearance::Destructive);
+    dialog.set_default_response(Some("cancel"));
+    dialog.set_close_response("cancel");
+
+    let window = ctx.window.clone();
+    let ctx = ctx.clone();
+    let search_id = search_id.to_string();
+    dialog.connect_response(None, move |_, response| {
+        if response == "delete" {
+            ctx.state
+                .borrow_mut()
+                .saved_searches
+                .retain(|s| s.id != search_id);
+            refresh_note_list(&ctx);
+            trigger_vault_save(&ctx);
+        }
+    });
+    dialog.present(Some(&window));
+}
+
+// ---------------------------------------------------------------------------
+// Search index
+// ---------------------------------------------------------------------------
+
+/// Bring the search index up to date with the notes in memory. Notes that
+/// are not loaded are left to [`index_unloaded_notes`], so this never
+/// decrypts.
+pub fn sync_search_index(ctx: &EditorCtx) {
+    let mut state = ctx.state.borrow_mut();
+    let DocState {
+        notes,
+        search_index,
+        ..
+    } = &mut *state;
+    search_index.sync(notes, |_| None);
+}
+
+/// Index the notes that are not loaded and changed since they were indexed.
+/// A worker thread decrypts each body, counts its words and wipes it, so only
+/// the word counts reach the index. Run once after unlocking the vault or a
+/// locked folder; searches meanwhile use what is indexed.
+pub fn index_unloaded_notes(ctx: &EditorCtx) {
+    let Some(key) = ctx.cached_key.borrow().clone() else {
+        return;
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    // ID, `updated_at`, where the body is and its key
+    let stale: Vec<_> = {
+        let state = ctx.state.borrow();
+        state
+            .notes
+            .iter()
+            .filter(|note| !note.loaded && state.search_index.is_stale(note))
+            .filter_map(|note| {
+                let stored_in = note.stored_in.as_deref();
+                let (dir, key) =
+                    vault::blob_location(&vault_folder, &state.folder_keys, &key, stored_in)
+                        .ok()?;
+                let owner = vault::blob_owner(&state.vault_id, stored_in);
+                Some((note.id.clone(), note.updated_at, dir, owner, key.clone()))
+            })
+            .collect()
+    };
+    if stale.is_empty() {
+        return;
+    }
+
+    let (tx, rx) = std::sync::mpsc::channel::<(String, i64, BodyTerms)>();
+    std::thread::spawn(move || {
+        for (id, updated_at, dir, owner, key) in stale {
+            let Ok(body) = vault::read_note_body(&dir, &owner, &id, &key) else {
+                continue;
+            };
+            let terms = BodyTerms::new(&body.content);
+            drop(body); // Wiped as it drops
+            if tx.send((id, updated_at, terms)).is_err() {
+                return;
+            }
+        }
+    });
+
+    let ctx = ctx.clone();
+    glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
+        // Locked meanwhile: dropping `rx` stops the worker
+        if ctx.cached_key.borrow().is_none() {
+            return glib::ControlFlow::Break;
+        }
+        let mut state = ctx.state.borrow_mut();
+        let DocState {
+            notes,
+            search_index,
+            ..
+        } = &mut *state;
+        loop {
+            match rx.try_recv() {
+                Ok((id, updated_at, terms)) => {
+                    // Skip notes loaded or edited since the body was read
+                    let note = notes
+                        .iter()
+                        .find(|n| n.id == id && !n.loaded && n.updated_at == updated_at);
+                    if let Some(note) = note {
+                        search_index.index_body_terms(note, terms);
+                    }
+                }
+                Err(std::sync::mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => break,
+            }
+        }
+        drop(state);
+        // Smart folders may find the notes just indexed
+        if forget_saved_search_hits(&ctx) {
+            refresh_note_list(&ctx);
+        }
+        glib::ControlFlow::Break
+    });
+}
+
+/// Notes matching `query`, best match first. Bodies that are not loaded are
+/// decrypted only if the query has to look at the text, like a phrase does.
+pub fn search_notes(ctx: &EditorCtx, query: &Query) -> Vec<SearchHit> {
+    sync_search_index(ctx);
+    let key = ctx.cached_key.borrow();
+    let Some(key) = key.as_ref() else {
+        return Vec::new();
+    };
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let state = ctx.state.borrow();
+    state
+        .search_index
+        .query(query, &state.notes, &state.folders, |note| {
+            let (dir, key) = vault::blob_location(
+                &vault_folder,
+                &state.folder_keys,
+                key,
+                note.stored_in.as_deref(),
+            )
+            .ok()?;
+            let owner = vault::blob_owner(&state.vault_id, note.stored_in.as_deref());
+            vault::read_note_body(&dir, &owner, &note.id, key).ok()
+        })
+}
+
+// ---------------------------------------------------------------------------
+// History and trash search
+// ---------------------------------------------------------------------------
+
+/// Decrypt the body of a note or trashed note that is not loaded.
+fn read_stored_body(
+    ctx: &EditorCtx,
+    note_id: &str,
+    stored_in: Option<&str>,
+) -> Option<vault::NoteBody> {
+    let key = ctx.cached_key.borrow();
+    let vault_folder = ctx.vault_folder.borrow().clone();
+    let state = ctx.state.borrow();
+    let (dir, key) =
+        vault::blob_location(&vault_folder, &state.folder_keys, key.as_ref()?, stored_in).ok()?;
+    let owner = vault::blob_owner(&state.vault_id, stored_in);
+    vault::read_note_body(&dir, &owner, note_id, key).ok()
+}
+
+/// The snapshots of every note, newest first, or every trashed note. Bodies
+/// that are not loaded are decrypted for this, so a history search reads
+/// every note's blob; it does so once, on its first search.
+pub fn archived_texts(ctx: &EditorCtx, scope: SearchScope) -> Vec<ArchivedText> {
+    let state = ctx.state.borrow();
+    let mut texts = Vec::new();
+    match scope {
+        SearchScope::Notes => {}
+        SearchScope::History => {
+            for note in &state.notes {
+                // Newest first
+                if note.loaded {
+                    texts.extend(
+                        note.versions
+                            .iter()
+                            .enumerate()
+                            .rev()
+                            .map(|(index, v)| ArchivedText::version(note, index, v.ts, &v.content)),
+                    );
+                } else if let Some(body) =
+                    read_stored_body(ctx, &note.id, note.stored_in.as_deref())
+                {
+                    texts.extend(
+                        body.versions
+                            .iter()
+                            .enumerate()
+                            .rev()
+                            .map(|(index, v)| ArchivedText::version(note, index, v.ts, &v.content)),
+                    );
+                }
+            }
+        }
+        SearchScope::Trash => {
+            for item in &state.trash {
+                if item.loaded {
+                    texts.push(ArchivedText::trashed(item, &item.content));
+                } else if let Some(body) =
+                    read_stored_body(ctx, &item.id, item.stored_in.as_deref())
+                {
+                    texts.push(ArchivedText::trashed(item, &body.content));
+                }
+            }
+        }
+    }
+    texts
+}
+
+/// The snapshot or trashed note `source`, if it is still there.
+pub fn archived_text(ctx: &EditorCtx, source: &ArchivedId) -> Option<ArchivedText> {
+    let state = ctx.state.borrow();
+    match source {
+        ArchivedId::Version { note_id, index } => {
+            let note = state.notes.iter().find(|n| n.id == *note_id)?;
+            let body;
+            let version = if note.loaded {
+                note.versions
+                    .get(*index)
+                    .map(|v| (v.ts, v.content.as_str()))
+            } else {
+                body = read_stored_body(ctx, &note.id, note.stored_in.as_deref())?;
+                body.versions
+                    .get(*index)
+                    .map(|v| (v.ts, v.content.as_str()))
+            };
+            let (ts, content) = version?;
+            Some(ArchivedText::version(note, *index, ts, content))
+        }
+        ArchivedId::Trash(trash_id) => {
+            let item = state.trash.iter().find(|t| t.id == *trash_id)?;
+            if item.loaded {
+                Some(ArchivedText::trashed(item, &item.content))
+            } else {
+                let body = read_stored_body(ctx, &item.id, item.stored_in.as_deref())?;
+                Some(ArchivedText::trashed(item, &body.content))
+            }
+        }
+    }
+}
+
+/// List the snapshots or trashed notes `query` finds, best match first.
+fn render_archived_hits(ctx: &EditorCtx, query: &Query, scope: SearchScope) {
+    // Indexed on the first search in this scope, not on every keystroke
+    let built = matches!(&*ctx.archived_index.borrow(), Some((built, _)) if *built == scope);
+    if !built {
+        let archive = ArchivedIndex::new(archived_texts(ctx, scope));
+        *ctx.archived_index.borrow_mut() = Some((scope, archive));
+    }
+    let cached = ctx.archived_index.borrow();
+    let Some((_, archive)) = cached.as_ref() else {
+        return;
+    };
+    let texts = archive.texts();
+    let folders = ctx.state.borrow().folders.clone();
+    let (hits, highlighter) = archive.search(query, &folders);
+
+    if hits.is_empty() {
+        let empty = gtk::Label::new(Some(if scope == SearchScope::Trash {
+            "Nothing in the trash matches"
+        } else {
+            "No snapshot matches"
+        }));
+        empty.add_css_class("dim-label");
+        empty.set_margin_top(24);
+        let row = gtk::ListBoxRow::new();
+        row.set_child(Some(&empty));
+        row.set_selectable(false);
+        row.set_activatable(false);
+        ctx.notes_list.append(&row);
+    }
+
+    let mut row_items = Vec::new();
+    for (index, _) in hits {
+        let text = &texts[index];
+        ctx.notes_list
+            .append(&build_archived_row(text, &highlighter));
+        row_items.push(SidebarRowKind::Archived(text.source.clone()));
+    }
+    ctx.state.borrow_mut().visible_row_items = row_items;
+}
+
+fn build_archived_row(text: &ArchivedText, highlighter: &Highlighter) -> gtk::ListBoxRow {
+    let row = gtk::ListBoxRow::new();
+    let row_box = gtk::Box::new(gtk::Orientation::Vertical, 2);
+    row_box.set_margin_start(8);
+    row_box.set_margin_end(8);
+    row_box.set_margin_top(6);
+    row_box.set_margin_bottom(6);
+
+    let title_row = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+    let icon = gtk::Image::from_icon_name(match text.source {
+        ArchivedId::Version { .. } => "document-open-recent-symbolic",
+        ArchivedId::Trash(_) => "user-trash-symbolic",
+    });
+    icon.set_pixel_size(14);
+    icon.set_opacity(0.5);
+    title_row.append(&icon);
+
+    let title = gtk::Label::new(None);
+    title.set_xalign(0.0);
+    title.set_hexpand(true);
+    title.add_css_class("note-row-title");
+    title.set_ellipsize(gtk::pango::EllipsizeMode::End);
+    title.set_markup(&highlight_search(
+        text.name(),
+        &highlighter.ranges(text.name()),
+    ));
+    title_row.append(&title);
+
+    let when = gtk::Label::new(Some(&match text.source {
+        ArchivedId::Version { .. } => format!("Snapshot {}", format_ts(text.ts())),
+        ArchivedId::Trash(_) => format!("Deleted {}", format_ts(text.ts())),
+    }));
+    when.add_css_class("dim-label");
+    when.add_css_class("caption");
+    title_row.append(&when);
+    row_box.append(&title_row);
+
+    let content = text.content();
+    if let Some(snippet) = make_search_snippet(content, &highlighter.ranges(content)) {
+        let subtitle = gtk::Label::new(None);
+        subtitle.set_xalign(0.0);
+        subtitle.add_css_class("dim-label");
+        subtitle.add_css_class("caption");
+        subtitle.set_ellipsize(gtk::pango::EllipsizeMode::End);
+        subtitle.set_markup(&highlight_search(&snippet, &highlighter.ranges(&snippet)));
+        row_box.append(&subtitle);
+    }
+
+    row.set_child(Some(&row_box));
+    row
+}
+
+// ---------------------------------------------------------------------------
+// Search highlighting
+// ---------------------------------------------------------------------------
+
+/// Markup for `text` with the byte `ranges` of search matches in bold.
+pub fn highlight_search(text: &str, ranges: &[Range<usize>]) -> String {
     let mut result = String::new();
-    let mut pos = 0usize; // byte position in `lower`
-    let mut orig_pos = 0usize; // byte position in `text`
-    while let Some(idx) = lower[pos..].find(&lower_query) {
-        let abs = pos + idx;
-        let match_end = abs + lower_query.len();
-        let orig_start = lower_to_orig[abs];
-        let orig_end = lower_to_orig[match_end.min(lower_to_orig.len() - 1)];
-        result.push_str(&glib::markup_escape_text(&text[orig_pos..orig_start]));
+    let mut pos = 0usize;
+    for range in ranges {
+        let (Some(before), Some(matched)) = (text.get(pos..range.start), text.get(range.clone()))
+        else {
+            continue;
+        };
+        result.push_str(&glib::markup_escape_text(before));
         result.push_str("<b>");
-        result.push_str(&glib::markup_escape_text(&text[orig_start..orig_end]));
+        result.push_str(&glib::markup_escape_text(matched));
         result.push_str("</b>");
-        orig_pos = orig_end;
-        pos = match_end;
+        pos = range.end;
     }
-    result.push_str(&glib::markup_escape_text(&text[orig_pos..]));
+    result.push_str(&glib::markup_escape_text(&text[pos..]));
     result
 }
 
-pub fn make_search_snippet(content: &str, query: &str) -> Option<String> {
-    let query = query.trim();
-    if query.is_empty() {
-        return None;
-    }
-
-    let lower = content.to_lowercase();
-    let lower_query = query.to_lowercase();
-    let match_pos = lower.find(&lower_query)?;
+/// A line of `content` around the first of the match `ranges`.
+pub fn make_search_snippet(content: &str, ranges: &[Range<usize>]) -> Option<String> {
+    let first = ranges.first()?;
+    let match_pos = first.start;
 
     // Show context around first match while preserving UTF-8 boundaries.
     let mut start = match_pos.saturating_sub(64);
@@ -895,7 +1710,7 @@ pub fn make_search_snippet(content: &str, query: &str) -> Option<String> {
         start += 1;
     }
 
-    let mut end = (match_pos + lower_query.len() + 96).min(content.len());
+    let mut end = (first.end + 96).min(content.len());
     while end > start && !content.is_char_boundary(end) {
         end -= 1;
     }
@@ -924,7 +1739,7 @@ pub fn build_note_row(
     ctx: &EditorCtx,
     note: &NoteSummary,
     active_id: &str,
-    search_query: &str,
+    highlighter: Option<&Highlighter>,
     depth: u32,
 ) -> gtk::ListBoxRow {
     let row = gtk::ListBoxRow::new();
@@ -960,8 +1775,8 @@ pub fn build_note_row(
     if note.id == active_id {
         title.add_css_class("heading");
     }
-    if !search_query.is_empty() {
-        let highlighted = highlight_search(&note.name, search_query);
+    if let Some(highlighter) = highlighter {
+        let highlighted = highlight_search(&note.name, &highlighter.ranges(&note.name));
         title.set_markup(&highlighted);
     } else {
         title.set_text(&note.name);
@@ -975,9 +1790,9 @@ pub fn build_note_row(
     subtitle.add_css_class("caption");
     subtitle.set_ellipsize(gtk::pango::EllipsizeMode::End);
     subtitle.set_wrap(false);
-    if !search_query.is_empty() {
+    if let Some(highlighter) = highlighter {
         if let Some(snippet) = note.content_snippet.as_ref() {
-            let highlighted = highlight_search(snippet, search_query);
+            let highlighted = highlight_search(snippet, &highlighter.ranges(snippet));
             subtitle.set_markup(&highlighted);
         } else {
             let meta_text = format!(
@@ -1026,7 +1841,12 @@ pub fn build_note_row(
     row
 }
 
-pub fn build_folder_row(folder: &FolderItem, depth: u32, note_count: usize) -> gtk::ListBoxRow {
+pub fn build_folder_row(
+    folder: &FolderItem,
+    depth: u32,
+    note_count: usize,
+    unlocked: bool,
+) -> gtk::ListBoxRow {
     let row = gtk::ListBoxRow::new();
     row.set_widget_name(&format!("folder:{}", folder.id));
     let row_box = gtk::Box::new(gtk::Orientation::Horizontal, 4);
@@ -1037,7 +1857,8 @@ pub fn build_folder_row(folder: &FolderItem, depth: u32, note_count: usize) -> g
     row_box.set_margin_bottom(4);
 
     // Chevron icon
-    let chevron_name = if folder.expanded {
+    let sealed = folder.locked && !unlocked;
+    let chevron_name = if folder.expanded && !sealed {
         "pan-down-symbolic"
     } else {
         "pan-end-symbolic"
@@ -1047,8 +1868,20 @@ pub fn build_folder_row(folder: &FolderItem, depth: u32, note_count: usize) -> g
     chevron.add_css_class("folder-row-chevron");
     row_box.append(&chevron);
 
-    // Folder icon
-    let folder_icon = gtk::Image::from_icon_name("folder-symbolic");
+    // Folder icon, a padlock for locked folders
+    let icon_name = match (folder.locked, unlocked) {
+        (false, _) => "folder-symbolic",
+        (true, false) => "system-lock-screen-symbolic",
+        (true, true) => "changes-allow-symbolic",
+    };
+    let folder_icon = gtk::Image::from_icon_name(icon_name);
+    if folder.locked {
+        folder_icon.set_tooltip_text(Some(if unlocked {
+            "Locked folder (unlocked)"
+        } else {
+            "Locked folder"
+        }));
+    }
     folder_icon.set_pixel_size(16);
     folder_icon.add_css_class("folder-row-icon");
     row_box.append(&folder_icon);
@@ -1105,7 +1938,7 @@ pub fn render_tree_level(
     folders_by_parent: &HashMap<Option<String>, Vec<&FolderItem>>,
     notes_by_parent: &HashMap<Option<String>, Vec<&NoteSummary>>,
     active_id: &str,
-    search_query: &str,
+    highlighter: Option<&Highlighter>,
     row_items: &mut Vec<SidebarRowKind>,
 ) {
     let key = parent_id.map(|s| s.to_string());
@@ -1114,7 +1947,8 @@ pub fn render_tree_level(
     if let Some(child_folders) = folders_by_parent.get(&key) {
         for folder in child_folders {
             let note_count = count_descendant_notes(&folder.id, folders_by_parent, notes_by_parent);
-            let row = build_folder_row(folder, depth, note_count);
+            let unlocked = ctx.state.borrow().is_folder_unlocked(&folder.id);
+            let row = build_folder_row(folder, depth, note_count, unlocked);
             ctx.notes_list.append(&row);
             row_items.push(SidebarRowKind::Folder(folder.id.clone()));
 
@@ -1126,7 +1960,7 @@ pub fn render_tree_level(
                     folders_by_parent,
                     notes_by_parent,
                     active_id,
-                    search_query,
+                    highlighter,
                     row_items,
                 );
             }
@@ -1136,7 +1970,7 @@ pub fn render_tree_level(
     // Then render notes at this level
     if let Some(child_notes) = notes_by_parent.get(&key) {
         for note in child_notes {
-            let row = build_note_row(ctx, note, active_id, search_query, depth);
+            let row = build_note_row(ctx, note, active_id, highlighter, depth);
             ctx.notes_list.append(&row);
             row_items.push(SidebarRowKind::Note(note.id.clone()));
         }
@@ -1247,6 +2081,7 @@ pub fn toggle_tag_filter(ctx: &EditorCtx, tag: &str) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pithos_core::search::note_matches_query;
 
     fn make_note(name: &str, content: &str) -> NoteItem {
         NoteItem {
@@ -1260,6 +2095,8 @@ mod tests {
             file_path: None,
             parent_id: None,
             pinned: false,
+            loaded: true,
+            stored_in: None,
         }
     }
 
@@ -1294,13 +2131,13 @@ mod tests {
 
     #[test]
     fn test_highlight_search_basic() {
-        let result = highlight_search("Hello World", "world");
-        assert!(result.contains("<b>World</b>"));
+        let result = highlight_search("Hello <World>", &[7..12]);
+        assert_eq!(result, "Hello &lt;<b>World</b>&gt;");
     }
 
     #[test]
     fn test_make_search_snippet() {
-        let snippet = make_search_snippet("some long content with rust keyword here", "rust");
+        let snippet = make_search_snippet("some long content with rust keyword here", &[23..27]);
         assert!(snippet.is_some());
         assert!(snippet.unwrap().contains("rust"));
     }
@@ -10,6 +10,8 @@ use sourceview5::prelude::*;
 
 pub fn initialize_state(ctx: &EditorCtx) {
     // Load the active note's content into the source buffer
+    let active_id = ctx.state.borrow().active_note_id.clone();
+    ensure_note_loaded(ctx, &active_id);
     let active_content = {
         let state = ctx.state.borrow();
         find_note_index(&state.notes, &state.active_note_id)
@@ -362,9 +362,11 @@ fn build_vault_page() -> adw::NavigationPage {
     list.add_css_class("boxed-list");
     list.set_selection_mode(gtk::SelectionMode::None);
     for desc in [
-        "Your password is used for AES-256 encryption via PBKDF2 key derivation",
+        "Your password is used for AES-256 encryption via Argon2id key derivation",
         "The vault file is saved to your chosen folder as an encrypted .mdvault file",
         "Nobody can read your notes without the password \u{2014} not even with direct file access",
+        "Optionally require a key file (for example on a USB stick) as a second factor",
+        "Generate a recovery code under Unlock Methods in case you forget your password",
     ] {
         list.append(&adw::ActionRow::builder().title(desc).build());
     }
@@ -588,11 +590,54 @@ fn build_search_page() -> adw::NavigationPage {
 
     c.append(&help_section("Search"));
     c.append(&help_paragraph(
-        "Quickly find notes by title using the search bar in the sidebar.",
+        "Quickly find notes by title and content using the search bar in the sidebar.",
     ));
     c.append(&shortcut_list(&[("Ctrl+Shift+F", "Focus the search bar")]));
     c.append(&help_paragraph(
-        "Start typing to filter the notes list. The search matches note titles.",
+        "Start typing to filter the notes list. Notes with every word you type \
+         are listed best match first: title matches, then recently updated notes. \
+         A word found nowhere as typed also matches words a typo or two away. \
+         Add filters to narrow the list down.",
+    ));
+
+    let syntax_list = gtk::ListBox::new();
+    syntax_list.add_css_class("boxed-list");
+    syntax_list.set_selection_mode(gtk::SelectionMode::None);
+    for (syntax, meaning) in [
+        ("\"exact phrase\"", "Notes containing the text as typed"),
+        ("-word", "Notes without the word"),
+        ("word OR other", "Notes with either word"),
+        ("/regex/", "Notes matching a regular expression"),
+        ("tag:security", "Notes with the tag"),
+        ("folder:\"Client A\"", "Notes in the folder or one inside it"),
+        (
+            "updated:&gt;2026-01-01",
+            "By date; also created:, &lt;, &lt;=, &gt;=, a month like 2026-01, or 7d for the last week",
+        ),
+        ("pinned:true", "Pinned notes"),
+        ("has:image, has:task", "Notes with an image or a task list"),
+        ("is:todo", "Notes with an unchecked task"),
+    ] {
+        syntax_list.append(
+            &adw::ActionRow::builder()
+                .title(syntax)
+                .subtitle(meaning)
+                .build(),
+        );
+    }
+    c.append(&syntax_list);
+    c.append(&help_paragraph(
+        "Tag filters chosen in the sidebar are added to the search the same way.",
+    ));
+    c.append(&help_paragraph(
+        "The menu next to the search bar switches between searching your notes, \
+         their version history and the trash. Click a snapshot or trashed note \
+         found to read it with the matches highlighted and restore it.",
+    ));
+
+    c.append(&help_section("Smart Folders"));
+    c.append(&help_paragraph(
+        "A smart folder is a saved search listed above your folders. Choose          \u{201c}New Smart Folder\u{2026}\u{201d} in the sidebar menu to save the          current search, tag filter and sort order under a name. Expand it to see          the notes it finds now; the list follows as notes change. Right-click it          to edit or delete it.",
     ));
 
     c.append(&help_section("Daily Notes"));
@@ -671,6 +716,14 @@ fn build_import_export_page() -> adw::NavigationPage {
     }
     c.append(&list);
 
+    c.append(&help_section("Sharing"));
+    c.append(&help_paragraph(
+        "Right-click a note or folder and choose Share as Encrypted Bundle to \
+         save it, with its tags, history and images, as one encrypted file. \
+         Send the passphrase shown afterwards separately; the recipient opens \
+         the file with Import Shared Bundle.",
+    ));
+
     help_page("Import &amp; Export", &c)
 }
 
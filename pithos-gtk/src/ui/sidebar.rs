@@ -56,8 +56,23 @@ pub fn build_sidebar() -> (
     search_entry.set_placeholder_text(Some("Search notes\u{2026}"));
     search_entry.set_hexpand(true);
 
+    let scope_menu = gtk::gio::Menu::new();
+    scope_menu.append(Some("Notes"), Some("win.search-scope::notes"));
+    scope_menu.append(Some("Version History"), Some("win.search-scope::history"));
+    scope_menu.append(Some("Trash"), Some("win.search-scope::trash"));
+    let scope_btn = gtk::MenuButton::new();
+    scope_btn.set_icon_name("view-more-symbolic");
+    scope_btn.set_tooltip_text(Some("Search In"));
+    scope_btn.add_css_class("flat");
+    scope_btn.set_menu_model(Some(&scope_menu));
+    set_accessible_label(&scope_btn, "Search In");
+
+    let search_box = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+    search_box.append(&search_entry);
+    search_box.append(&scope_btn);
+
     let search_bar = gtk::SearchBar::builder()
-        .child(&search_entry)
+        .child(&search_box)
         .search_mode_enabled(false)
         .show_close_button(true)
         .build();
@@ -135,6 +150,10 @@ pub fn build_sidebar_menu() -> gtk::gio::Menu {
 
     let section1 = gtk::gio::Menu::new();
     section1.append(Some("New Folder"), Some("win.new-folder"));
+    section1.append(
+        Some("New Smart Folder\u{2026}"),
+        Some("win.new-smart-folder"),
+    );
     section1.append(
         Some("New from Template\u{2026}"),
         Some("win.new-from-template"),
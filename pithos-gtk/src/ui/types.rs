@@ -1,9 +1,10 @@
 use gtk::glib;
 use sourceview5 as sourceview;
-use std::{cell::Cell, cell::RefCell, rc::Rc};
+use std::{cell::Cell, cell::RefCell, collections::HashMap, rc::Rc};
 
 use pithos_core::crypto;
-use pithos_core::state::DocState;
+use pithos_core::search::{ArchivedIndex, QueryError, SearchHit};
+use pithos_core::state::{DocState, SearchScope};
 
 pub const CODE_LANGUAGES: &[&str] = &[
     "text",
@@ -53,6 +54,8 @@ pub struct EditorCtx {
     pub saving: Rc<Cell<bool>>,
     pub close_requested: Rc<Cell<bool>>,
     pub vault_file_monitor: Rc<RefCell<Option<gtk::gio::FileMonitor>>>,
+    // Pending merge of changes another writer made to the vault
+    pub external_merge_id: Rc<Cell<Option<glib::SourceId>>>,
     // HIG layout widgets
     pub split_view: adw::OverlaySplitView,
     pub toast_overlay: adw::ToastOverlay,
@@ -67,6 +70,10 @@ pub struct EditorCtx {
     pub content_stack: gtk::Stack,
     pub sync_timeout_id: Rc<Cell<Option<glib::SourceId>>>,
     pub search_timeout_id: Rc<Cell<Option<glib::SourceId>>>,
+    // Notes each expanded smart folder found, kept until the next save starts
+    pub saved_search_hits: Rc<RefCell<HashMap<String, Result<Vec<SearchHit>, QueryError>>>>,
+    // What a history or trash search looks through, kept while its scope is
+    pub archived_index: Rc<RefCell<Option<(SearchScope, ArchivedIndex)>>>,
     // Content chrome
     pub content_header: adw::HeaderBar,
     // Find/replace bar
@@ -79,10 +86,14 @@ pub struct EditorCtx {
     pub search_settings: sourceview::SearchSettings,
     // Vault name display
     pub vault_name_label: gtk::Label,
-    // Timestamp of last completed vault save (used by file monitor to suppress own-write toasts)
-    pub last_save_completed: Rc<Cell<std::time::Instant>>,
     // Split pane position saved before entering zen mode, restored on exit
     pub pre_zen_split_pos: Rc<Cell<i32>>,
+    // Auto-lock: last keyboard/pointer input, idle check timer, screen-lock handler
+    pub last_activity: Rc<Cell<std::time::Instant>>,
+    pub auto_lock_timeout_id: Rc<Cell<Option<glib::SourceId>>>,
+    pub screensaver_handler: Rc<RefCell<Option<glib::SignalHandlerId>>>,
+    // Cursor offset of each open tab that isn't the active note
+    pub tab_cursors: Rc<RefCell<HashMap<String, i32>>>,
 }
 
 pub struct ContentPaneWidgets {
@@ -1,7 +1,7 @@
 use adw::prelude::*;
 use sourceview5 as sourceview;
 use sourceview5::prelude::*;
-use std::{cell::Cell, cell::RefCell, rc::Rc};
+use std::{cell::Cell, cell::RefCell, collections::HashMap, rc::Rc};
 
 use crate::ui::types::*;
 use crate::*;
@@ -63,7 +63,7 @@ const SIDEBAR_COLLAPSE_BREAKPOINT: &str = "max-width: 1260sp";
 
 pub fn build_editor(
     window: &adw::ApplicationWindow,
-    initial_state: DocState,
+    mut initial_state: DocState,
     vault_folder: String,
     cached_key: crypto::CachedKey,
 ) {
@@ -142,6 +142,23 @@ pub fn build_editor(
     apply_theme(&initial_state.theme);
     apply_sourceview_theme(&source_view, is_dark_active());
 
+    vault::mark_loaded(&mut initial_state, &vault_folder);
+    // Notes changed since it was stored are re-indexed by index_unloaded_notes
+    if initial_state.search_index.is_empty() {
+        initial_state.search_index =
+            vault::read_search_index(&vault_folder, &initial_state.vault_id, &cached_key);
+    }
+    if read_only_session() || !vault::folder_writable(&vault_folder) {
+        initial_state.read_only = true;
+    }
+    if !initial_state.read_only {
+        match take_vault_lock(&vault_folder) {
+            Ok(()) => {}
+            // Another instance got there first
+            Err(vault::VaultError::InUse(_)) => initial_state.read_only = true,
+            Err(e) => eprintln!("Failed to lock the vault: {e}"),
+        }
+    }
     let state = Rc::new(RefCell::new(initial_state));
 
     let ctx = EditorCtx {
@@ -168,6 +185,7 @@ pub fn build_editor(
         saving: Rc::new(Cell::new(false)),
         close_requested: Rc::new(Cell::new(false)),
         vault_file_monitor: Rc::new(RefCell::new(None)),
+        external_merge_id: Rc::new(Cell::new(None)),
         split_view: split_view.clone(),
         toast_overlay,
         toolbar: toolbar_scroll.clone(),
@@ -181,6 +199,8 @@ pub fn build_editor(
         content_stack,
         sync_timeout_id: Rc::new(Cell::new(None)),
         search_timeout_id: Rc::new(Cell::new(None)),
+        saved_search_hits: Rc::new(RefCell::new(HashMap::new())),
+        archived_index: Rc::new(RefCell::new(None)),
         content_header,
         find_bar,
         find_entry,
@@ -190,8 +210,11 @@ pub fn build_editor(
         search_context,
         search_settings,
         vault_name_label,
-        last_save_completed: Rc::new(Cell::new(std::time::Instant::now())),
         pre_zen_split_pos: Rc::new(Cell::new(0)),
+        last_activity: Rc::new(Cell::new(std::time::Instant::now())),
+        auto_lock_timeout_id: Rc::new(Cell::new(None)),
+        screensaver_handler: Rc::new(RefCell::new(None)),
+        tab_cursors: Rc::new(RefCell::new(HashMap::new())),
     };
 
     // Show vault folder name in the header
@@ -205,8 +228,14 @@ pub fn build_editor(
     }
 
     initialize_state(&ctx);
+    restore_lock_resume(&ctx);
 
-    let purged = pithos_core::notes::purge_old_trash(&mut ctx.state.borrow_mut());
+    let read_only = ctx.state.borrow().read_only;
+    let purged = if read_only {
+        0
+    } else {
+        pithos_core::notes::purge_old_trash(&mut ctx.state.borrow_mut())
+    };
 
     wire_menu_actions(&ctx);
     wire_toolbar_signals(&ctx, &tb);
@@ -234,8 +263,14 @@ pub fn build_editor(
     }
 
     wire_close_request(&ctx);
-    setup_auto_save(&ctx);
+    if read_only {
+        apply_read_only(&ctx);
+    } else {
+        setup_auto_save(&ctx);
+    }
+    setup_auto_lock(&ctx, &split_view);
     watch_vault_file(&ctx);
+    index_unloaded_notes(&ctx);
 
     // Wire AdwTabView signals
     {
@@ -340,6 +375,11 @@ pub fn build_editor(
     }
 
     window.set_content(Some(&split_view));
+
+    {
+        let ctx = ctx.clone();
+        glib::idle_add_local_once(move || offer_encryption_upgrade(&ctx));
+    }
 }
 
 pub fn build_content_pane() -> ContentPaneWidgets {